@@ -0,0 +1,223 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rsdos::{io_loose, io_packs, maintain, Config, Container};
+use tempfile::TempDir;
+
+/// Objects per dataset, so each iteration touches enough objects to show seeks across packs.
+const N_OBJECTS: usize = 100;
+/// Object sizes of the synthetic datasets.
+const OBJECT_SIZES: [usize; 3] = [1024, 64 * 1024, 1024 * 1024];
+const PACK_SIZE_TARGET: u64 = 4 * 1024 * 1024;
+
+fn new_container(compression: &str) -> (TempDir, Container) {
+    let tmp_dir = tempfile::tempdir().expect("fail to create temp dir");
+    let cnt = Container::new(tmp_dir.path());
+    cnt.initialize(&Config::new(PACK_SIZE_TARGET, compression))
+        .expect("fail to initialize container");
+    (tmp_dir, cnt)
+}
+
+/// ``n`` distinct objects of ``size`` bytes, half random and half zeros so they compress
+/// about as well as typical data files.
+fn dataset(n: usize, size: usize, seed: u64) -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| {
+            let mut content = vec![0u8; size];
+            rng.fill(&mut content[..size / 2]);
+            content
+        })
+        .collect()
+}
+
+/// ``n`` distinct text objects of ``size`` bytes, random lowercase words so they are compressed
+/// and compress about as well as text data files.
+fn text_dataset(n: usize, size: usize, seed: u64) -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| {
+            (0..size)
+                .map(|_| match rng.gen_range(0..32u8) {
+                    26..=30 => b' ',
+                    31 => b'\n',
+                    letter => b'a' + letter,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+    for size in OBJECT_SIZES {
+        group.throughput(Throughput::Bytes((N_OBJECTS * size) as u64));
+        let mut seed = 0;
+        let mut next_dataset = || {
+            seed += 1;
+            dataset(N_OBJECTS, size, seed)
+        };
+
+        let (_tmp_dir, cnt) = new_container("none");
+        group.bench_function(BenchmarkId::new("loose", size), |b| {
+            b.iter_batched(
+                &mut next_dataset,
+                |objs| io_loose::insert_many(objs, &cnt).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+
+        for compression in ["none", "zlib:+1", "zstd:+1"] {
+            let (_tmp_dir, cnt) = new_container(compression);
+            group.bench_function(
+                BenchmarkId::new(format!("packs/{compression}"), size),
+                |b| {
+                    b.iter_batched(
+                        &mut next_dataset,
+                        |objs| io_packs::insert_many(objs, &cnt).unwrap(),
+                        BatchSize::LargeInput,
+                    );
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Objects inserted per iteration of the pipeline bench, enough for every core to get some.
+const N_PIPELINE_OBJECTS: usize = 1000;
+const PIPELINE_OBJECT_SIZE: usize = 64 * 1024;
+
+/// Pack inserts compressed on the inserting thread (``0``) against compression threads, one per
+/// core for ``default``.
+fn bench_insert_pipeline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_pipeline");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(
+        (N_PIPELINE_OBJECTS * PIPELINE_OBJECT_SIZE) as u64,
+    ));
+    for compressors in [Some(0), Some(1), Some(4), None] {
+        let tmp_dir = tempfile::tempdir().expect("fail to create temp dir");
+        let cnt = Container::new(tmp_dir.path());
+        let mut config = Config::new(PACK_SIZE_TARGET, "zstd:3");
+        config.pack_compressors = compressors;
+        cnt.initialize(&config)
+            .expect("fail to initialize container");
+
+        let label = compressors.map_or("default".to_string(), |n| n.to_string());
+        let mut seed = 0;
+        group.bench_function(BenchmarkId::new("compressors", label), |b| {
+            b.iter_batched(
+                || {
+                    seed += 1;
+                    text_dataset(N_PIPELINE_OBJECTS, PIPELINE_OBJECT_SIZE, seed)
+                },
+                |objs| io_packs::insert_many(objs, &cnt).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_extract(c: &mut Criterion) {
+    let mut group = c.benchmark_group("extract");
+    for size in OBJECT_SIZES {
+        for compression in ["none", "zstd:+1"] {
+            let (_tmp_dir, cnt) = new_container(compression);
+            let hashkeys: Vec<_> = io_packs::insert_many(dataset(N_OBJECTS, size, 0), &cnt)
+                .unwrap()
+                .into_iter()
+                .map(|inserted| inserted.hashkey)
+                .collect();
+
+            group.throughput(Throughput::Bytes(size as u64));
+            let mut i = 0;
+            group.bench_function(
+                BenchmarkId::new(format!("single/{compression}"), size),
+                |b| {
+                    b.iter(|| {
+                        i = (i + 1) % hashkeys.len();
+                        let obj = io_packs::extract(&hashkeys[i], &cnt).unwrap().unwrap();
+                        Vec::try_from(obj).unwrap()
+                    });
+                },
+            );
+
+            group.throughput(Throughput::Bytes((N_OBJECTS * size) as u64));
+            group.bench_function(BenchmarkId::new(format!("many/{compression}"), size), |b| {
+                b.iter(|| {
+                    io_packs::extract_many(&hashkeys, &cnt)
+                        .unwrap()
+                        .for_each(|obj| {
+                            black_box(Vec::try_from(obj.unwrap()).unwrap());
+                        });
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_pack_loose(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pack_loose");
+    group.sample_size(10);
+    for size in OBJECT_SIZES {
+        group.throughput(Throughput::Bytes((N_OBJECTS * size) as u64));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_batched(
+                || {
+                    let (tmp_dir, cnt) = new_container("zstd:+1");
+                    io_loose::insert_many(dataset(N_OBJECTS, size, 0), &cnt).unwrap();
+                    (tmp_dir, cnt)
+                },
+                // return the container so it is removed outside of the measurement
+                |(tmp_dir, cnt)| {
+                    maintain::pack_loose(&cnt).unwrap();
+                    (tmp_dir, cnt)
+                },
+                BatchSize::PerIteration,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// Loose objects that are all already in packs, so ``pack_loose`` only runs its skip check.
+const N_LOOSE_PACKED: usize = 10_000;
+/// Objects in the packs of the container the skip check runs against.
+const PACKED_SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+fn bench_pack_loose_skip_packed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pack_loose_skip_packed");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(N_LOOSE_PACKED as u64));
+    for n_packed in PACKED_SIZES {
+        let (_tmp_dir, cnt) = new_container("none");
+        let contents = || (0..n_packed).map(|i| format!("object {i}").into_bytes());
+        io_packs::insert_many(contents(), &cnt).unwrap();
+        io_loose::insert_many(contents().take(N_LOOSE_PACKED), &cnt).unwrap();
+
+        // loose objects are not cleaned by ``pack_loose``, every iteration skips all of them
+        group.bench_function(BenchmarkId::from_parameter(n_packed), |b| {
+            b.iter(|| {
+                let report = maintain::pack_loose(&cnt).unwrap();
+                assert_eq!(report.skipped_already_packed, N_LOOSE_PACKED as u64);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_insert,
+    bench_insert_pipeline,
+    bench_extract,
+    bench_pack_loose,
+    bench_pack_loose_skip_packed
+);
+criterion_main!(benches);
@@ -9,7 +9,7 @@ fn main() -> anyhow::Result<()> {
     let pack_target_size = 4 * 1024;
     let config = rsdos::Config::new(pack_target_size, "none");
 
-    let cnt = rsdos::Container::new(cnt_path);
+    let mut cnt = rsdos::Container::new(cnt_path);
     let args: Vec<String> = std::env::args().collect();
 
     if args.len() > 1 {
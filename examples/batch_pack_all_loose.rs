@@ -9,7 +9,7 @@ fn main() -> anyhow::Result<()> {
     let single_obj_size = 1024 * 1024; // 1MiB
     let config = rsdos::Config::new(pack_target_size, "zlib+1");
 
-    let cnt = rsdos::Container::new(cnt_path);
+    let mut cnt = rsdos::Container::new(cnt_path);
     let args: Vec<String> = std::env::args().collect();
     let arg1 = args.get(1).unwrap();
 
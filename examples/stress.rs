@@ -0,0 +1,97 @@
+//! Stress a container with several processes writing and packing at once, then check it.
+//!
+//!     cargo run --release --example stress -- [PROCESSES] [OBJECTS]
+//!
+//! Each process re-runs this binary as a `worker`: even ones insert ``OBJECTS`` objects
+//! alternately to loose and packs, odd ones pack loose objects until all writers are done.
+use rsdos::{maintain, Config, Container};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::{env, fs};
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("worker") {
+        let (cnt_path, id, n, writers) = (
+            Path::new(&args[2]),
+            args[3].parse()?,
+            args[4].parse()?,
+            args[5].parse()?,
+        );
+        return worker(cnt_path, id, n, writers);
+    }
+
+    let processes: usize = args.get(1).map_or(Ok(8), |n| n.parse())?;
+    let n: usize = args.get(2).map_or(Ok(2000), |n| n.parse())?;
+    let cnt_path = env::current_dir()?.join("sample_stress");
+    for dir in [&cnt_path, &done_dir(&cnt_path)] {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::create_dir_all(dir)?;
+    }
+    Container::new(&cnt_path).initialize(&Config::new(64 * 1024, "zstd:+1"))?;
+
+    let exe = env::current_exe()?;
+    let children: Vec<Child> = (0..processes)
+        .map(|id| {
+            Command::new(&exe)
+                .arg("worker")
+                .arg(&cnt_path)
+                .arg(id.to_string())
+                .arg(n.to_string())
+                .arg(processes.div_ceil(2).to_string())
+                .spawn()
+        })
+        .collect::<Result<_, _>>()?;
+    for mut child in children {
+        anyhow::ensure!(child.wait()?.success(), "a worker failed");
+    }
+
+    let cnt = Container::open(&cnt_path)?;
+    maintain::pack_loose(&cnt)?;
+    let report = maintain::validate(&cnt)?;
+    println!(
+        "{} objects in packs, {} in loose",
+        report.packs_checked, report.loose_checked
+    );
+    anyhow::ensure!(report.is_valid(), "container corrupted: {report:?}");
+
+    Ok(())
+}
+
+/// Where writers leave a file once they inserted all their objects.
+fn done_dir(cnt_path: &Path) -> PathBuf {
+    cnt_path.with_extension("done")
+}
+
+fn worker(cnt_path: &Path, id: usize, n: usize, writers: usize) -> anyhow::Result<()> {
+    let cnt = Container::open(cnt_path)?;
+    if id % 2 == 1 {
+        while fs::read_dir(done_dir(cnt_path))?.count() < writers {
+            maintain::pack_loose(&cnt)?;
+        }
+        return Ok(());
+    }
+
+    for i in 0..n {
+        // one object out of ten is shared by all writers
+        let content = if i % 10 == 0 {
+            format!("shared {i} ").repeat(20)
+        } else {
+            format!("worker {id} object {i} ").repeat(i % 100 + 1)
+        };
+        let inserted = if i % 2 == 0 {
+            rsdos::io_loose::insert(content.into_bytes(), &cnt)?
+        } else {
+            rsdos::io_packs::insert(content.into_bytes(), &cnt)?
+        };
+        anyhow::ensure!(
+            cnt.get(&inserted.hashkey)?.is_some(),
+            "{} not readable after insert",
+            inserted.hashkey
+        );
+    }
+    fs::write(done_dir(cnt_path).join(id.to_string()), b"")?;
+    Ok(())
+}
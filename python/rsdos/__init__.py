@@ -5,10 +5,10 @@ from contextlib import contextmanager
 import typing as t
 import io
 from pathlib import Path
-from .rsdos import _Container, run_cli
+from .rsdos import _Container, CancellationToken, MaintenanceInProgressError, run_cli
 from enum import Enum
 
-__all__ = ("Container", "run_cli")
+__all__ = ("CancellationToken", "Container", "MaintenanceInProgressError", "run_cli")
 
 # StreamReadBytesType = t.Union[
 #     t.BinaryIO,
@@ -43,6 +43,17 @@ class Container:
     def __init__(self, folder: t.Union[str, Path]):
         self.cnt = _Container(folder)
 
+    def __enter__(self) -> Container:
+        return self
+
+    def __exit__(self, exc_type, exc_value, traceback) -> None:
+        self.close()
+
+    def close(self) -> None:
+        """Release the index connections and pack files kept open. The container is still
+        usable afterwards, they are opened again when needed."""
+        self.cnt.close()
+
     def _init_db(self):
         self.cnt._init_db()
 
@@ -94,16 +105,10 @@ class Container:
     def get_object_stream(self, hashkey: str) -> Iterator[StreamReadBytesType | None]:
         stream = io.BytesIO()
         try:
-            # try fetch from loose
-            self._fetch_from_loose(hashkey, stream)
+            # looked up in loose first, then in packs
+            self.cnt.write_stream(hashkey, stream)
         except ValueError:
-            try:
-                # not found in loose, try fetch from packs
-                self._fetch_from_packs(hashkey, stream)
-            except ValueError:
-                yield None
-            else:
-                yield stream
+            yield None
         else:
             yield stream
 
@@ -114,11 +119,23 @@ class Container:
 
         # what not found in loose, try to find in packs
         # packs XXX: large speed overhead even no object in packs
-        for k, v in self.cnt.extract_many_from_packs(not_found).items():
-            d[k] = bytes(v)
+        d.update(self.cnt.extract_many_from_packs(not_found))
 
         return d
 
+    def stream_many(self, hashkeys: t.Iterable[str]) -> Iterator[t.Tuple[str, bytes]]:
+        """Lazily yield ``(hashkey, content)`` of packed objects, hashkeys not in packs are skipped.
+        Unlike ``get_objects_content`` only one batch of objects is kept in memory."""
+        return self.cnt.stream_many(list(hashkeys))
+
+    def iter_objects_content_packs(
+        self, hashkeys: t.Iterable[str], max_memory: int
+    ) -> Iterator[t.Dict[str, bytes]]:
+        """Lazily yield dicts of the content of packed objects, each holding at most
+        ``max_memory`` bytes of content (an object larger than that comes alone). Hashkeys not
+        in packs are skipped."""
+        return self.cnt.extract_many_from_packs(list(hashkeys), max_memory)
+
     def get_loose_objects_content_raw_rs(
         self, hashkeys: t.List[str], skip_if_missing: bool = True
     ) -> t.Tuple[t.Dict[str, t.Optional[bytes]], t.List[str]]:
@@ -126,7 +143,7 @@ class Container:
         not_found = []
         for k, v in self.cnt.extract_many_from_loose(hashkeys).items():
             if v is not None:
-                d[k] = bytes(v)
+                d[k] = v
             else:
                 not_found.append(k)
                 if skip_if_missing:
@@ -136,9 +153,26 @@ class Container:
 
         return d, not_found
 
+    def compute_hash(self, stream: StreamReadBytesType) -> str:
+        """Hashkey the content of ``stream`` would get in the container, without storing it."""
+        return self.cnt.compute_hash(stream)
+
+    def verify_object(self, hashkey: str) -> bool | None:
+        """Re-hash the object in every store it is in and compare with its hashkey, ``None`` if
+        the object is not in the container."""
+        return self.cnt.verify_object(hashkey)
+
     def add_object(self, content: bytes) -> str:
-        stream = io.BytesIO(content)
-        return self.add_streamed_object(stream)
+        """Add an object, to packs when it is larger than the ``auto_pack_threshold`` of the
+        container and to loose otherwise."""
+        return self.cnt.insert(content).hashkey
+
+    def add_objects_to_loose(
+        self, content_list: t.Sequence[t.Union[bytes, StreamReadBytesType]]
+    ) -> t.List[str]:
+        """Add many objects (bytes or binary streams) to loose in one call, return the hashkeys
+        in the same order."""
+        return [r.hashkey for r in self.cnt.insert_many_to_loose(list(content_list))]
 
     def add_object_to_packs(self, content: bytes) -> str:
         stream = io.BytesIO(content)
@@ -149,7 +183,7 @@ class Container:
     # XXX: I prefer name `add_objects_to_packs`
     def add_objects_to_pack(
         self,
-        content_list: t.Union[t.List[bytes], t.Tuple[bytes, ...]],
+        content_list: t.Iterable[t.Union[bytes, StreamReadBytesType]],
         compress: bool | CompressMode = CompressMode.NO,
         no_holes: bool = False,
         no_holes_read_twice: bool = True,
@@ -157,6 +191,8 @@ class Container:
         do_fsync: bool = True,
         do_commit: bool = True,
     ) -> t.List[str]:
+        """Add many objects (bytes or binary streams) to packs, return the hashkeys in the same
+        order. ``content_list`` can be a generator, it is consumed lazily by batch."""
         # To compatible with legacy dos
         if isinstance(compress, bool):
             if compress:
@@ -166,20 +202,16 @@ class Container:
         else:
             compress_mode = compress
         hkey_lst = [
-            i[2]
-            for i in self.cnt.insert_many_to_packs(content_list, compress_mode.value)
+            r.hashkey
+            for r in self.cnt.insert_many_to_packs(content_list, compress_mode.value)
         ]
         return hkey_lst
 
     def add_streamed_object(self, stream: StreamReadBytesType) -> str:
-        _, hashkey = self.cnt.insert_to_loose(stream)
-
-        return hashkey
+        return self.cnt.insert(stream).hashkey
 
     def add_streamed_object_to_packs(self, stream: StreamReadBytesType) -> str:
-        _, _, hashkey = self.cnt.insert_to_packs(stream)
-
-        return hashkey
+        return self.cnt.insert_to_packs(stream).hashkey
 
     def init_container(
         self,
@@ -223,7 +255,18 @@ class Container:
         compress: bool | CompressMode = CompressMode.NO,
         validate_objects: bool = True,
         do_fsync: bool = True,
+        progress: t.Optional[t.Callable[[int, int], None]] = None,
+        cancel: t.Optional[CancellationToken] = None,
     ):
+        """Pack the loose objects not yet in packs, return a ``PackReport`` with the number of
+        objects packed, the bytes read and written, the packs created, the loose objects
+        skipped since already packed and the already compressed objects stored as they are (by
+        algorithm, in ``precompressed``).
+
+        ``progress`` is called from time to time with ``(done, total)`` the number of objects
+        packed so far, and once more when packing is over. Once ``cancel`` is cancelled (e.g.
+        from another thread) packing stops at the next object, the objects packed so far are
+        kept and the report has ``interrupted`` set."""
         # To compatible with legacy dos
         if isinstance(compress, bool):
             if compress:
@@ -232,4 +275,25 @@ class Container:
                 compress_mode = CompressMode.NO
         else:
             compress_mode = compress
-        return self.cnt.pack_all_loose(compress_mode.value)
+        return self.cnt.pack_all_loose(compress_mode.value, progress, cancel)
+
+    def validate(
+        self,
+        progress: t.Optional[t.Callable[[int, int], None]] = None,
+        cancel: t.Optional[CancellationToken] = None,
+    ):
+        """Re-hash every loose and packed object, return a ``ValidationReport`` with the number of
+        objects checked and the hashkeys of those whose content does not match.
+
+        ``progress`` is called from time to time with ``(done, total)`` the number of objects
+        checked so far, and once more when validation is over. Once ``cancel`` is cancelled
+        validation stops at the next object and the report has ``interrupted`` set."""
+        return self.cnt.validate(progress, cancel)
+
+    def clean_storage(self, vacuum: bool = False) -> None:
+        """Delete the loose objects that are already packed. The index is always vacuumed,
+        ``vacuum`` is only kept for compatibility with legacy disk-objectstore.
+
+        Raises ``MaintenanceInProgressError`` if another maintenance operation (e.g. a repack)
+        is running on the container."""
+        self.cnt.clean_storage()
@@ -1,23 +1,285 @@
 use std::{
-    collections::HashMap,
-    io::{Cursor, Read, Seek},
+    collections::BTreeMap,
+    io::{Read, Seek},
+    iter::Peekable,
     path::PathBuf,
-    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use pyo3::{
+    create_exception,
     exceptions::{PyRuntimeError, PyValueError},
     prelude::*,
-    types::PyBytes,
+    types::{PyBytes, PyDict, PyTuple},
 };
 use pyo3_file::PyFileLikeObject;
 use rsdos::{
-    container::{Compression, PACKS_DB},
+    cancel::CancellationToken,
+    config::CompressionPolicy,
+    container::{Compression, LOOKUP_AUTO, PACKS_DB},
     db,
-    io::{ByteString, ReaderMaker},
+    io::{ByteString, HashWriter, InsertResult, MaybeContentFormat, ReaderMaker, Store},
+    io_packs::{ExtractManyOptions, PObject},
+    maintain::{PackReport, ProgressObserver, ValidationReport},
     Config, Container,
 };
 
+/// Number of Python sources converted under the GIL before it is released for the Rust I/O.
+const INSERT_BATCH_SIZE: usize = 1024;
+
+/// Number of objects read from packs per batch by ``PyContainer.stream_many``.
+const STREAM_BATCH_SIZE: usize = 1024;
+
+/// Least time between two calls of a Python progress callback, the GIL is only taken that often.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+create_exception!(
+    rsdos,
+    MaintenanceInProgressError,
+    PyRuntimeError,
+    "Another maintenance operation holds the maintenance lock of the container."
+);
+
+/// ``MaintenanceInProgressError`` if another maintenance operation holds the lock, ``Exception``
+/// for the other errors of a maintenance operation.
+fn maintain_err(err: rsdos::Error) -> PyErr {
+    match err {
+        rsdos::Error::MaintenanceInProgress { .. } => {
+            MaintenanceInProgressError::new_err(err.to_string())
+        }
+        _ => PyErr::new::<pyo3::exceptions::PyException, _>(err.to_string()),
+    }
+}
+
+/// ``ValueError`` for a malformed hashkey, ``IOError`` for the other errors of reading objects.
+fn read_err(err: rsdos::Error) -> PyErr {
+    match err {
+        rsdos::Error::InvalidHashKey { .. } => PyValueError::new_err(err.to_string()),
+        _ => PyErr::new::<pyo3::exceptions::PyIOError, _>(err.to_string()),
+    }
+}
+
+/// ``bytes`` of the ``size`` bytes of content of ``rmaker``, read straight into the buffer of the
+/// Python object instead of through an intermediate ``Vec``. The GIL is released while reading.
+fn read_bytes<'py, R>(py: Python<'py>, rmaker: &R, size: u64) -> PyResult<Bound<'py, PyBytes>>
+where
+    R: ReaderMaker + Sync,
+{
+    let len = usize::try_from(size).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    PyBytes::new_bound_with(py, len, |buf| {
+        py.allow_threads(|| {
+            rmaker.make_reader()?.read_exact(buf)?;
+            Ok::<_, rsdos::Error>(())
+        })
+        .map_err(read_err)
+    })
+}
+
+/// An inserted object, see ``rsdos::io::InsertResult``.
+#[pyclass(name = "InsertResult", frozen, get_all)]
+struct PyInsertResult {
+    hashkey: String,
+    raw_size: u64,
+    stored_size: u64,
+    compressed: bool,
+    /// ``"loose"``, ``"packs"`` or ``"chunked"``.
+    store: &'static str,
+}
+
+impl From<InsertResult> for PyInsertResult {
+    fn from(inserted: InsertResult) -> Self {
+        Self {
+            hashkey: inserted.hashkey,
+            raw_size: inserted.raw_size,
+            stored_size: inserted.stored_size,
+            compressed: inserted.compressed,
+            store: match inserted.store {
+                Store::Loose => "loose",
+                Store::Packs => "packs",
+                Store::Chunked => "chunked",
+            },
+        }
+    }
+}
+
+#[pymethods]
+impl PyInsertResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "InsertResult(hashkey='{}', raw_size={}, stored_size={}, compressed={}, store='{}')",
+            self.hashkey,
+            self.raw_size,
+            self.stored_size,
+            if self.compressed { "True" } else { "False" },
+            self.store
+        )
+    }
+}
+
+/// What ``pack_all_loose`` packed, see ``rsdos::maintain::PackReport``.
+#[pyclass(name = "PackReport", frozen, get_all)]
+struct PyPackReport {
+    objects_packed: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+    packs_created: u64,
+    skipped_already_packed: u64,
+    precompressed: BTreeMap<String, u64>,
+    interrupted: bool,
+}
+
+impl From<PackReport> for PyPackReport {
+    fn from(report: PackReport) -> Self {
+        Self {
+            objects_packed: report.objects_packed,
+            bytes_read: report.bytes_read,
+            bytes_written: report.bytes_written,
+            packs_created: report.packs_created,
+            skipped_already_packed: report.skipped_already_packed,
+            precompressed: report.precompressed,
+            interrupted: report.interrupted,
+        }
+    }
+}
+
+#[pymethods]
+impl PyPackReport {
+    fn __repr__(&self) -> String {
+        format!(
+            "PackReport(objects_packed={}, bytes_read={}, bytes_written={}, packs_created={}, skipped_already_packed={}, precompressed={:?}, interrupted={})",
+            self.objects_packed,
+            self.bytes_read,
+            self.bytes_written,
+            self.packs_created,
+            self.skipped_already_packed,
+            self.precompressed,
+            self.interrupted
+        )
+    }
+}
+
+/// What ``validate`` found, see ``rsdos::maintain::ValidationReport``.
+#[pyclass(name = "ValidationReport", frozen, get_all)]
+struct PyValidationReport {
+    loose_checked: u64,
+    packs_checked: u64,
+    invalid_loose: Vec<String>,
+    invalid_packs: Vec<String>,
+    interrupted: bool,
+}
+
+impl From<ValidationReport> for PyValidationReport {
+    fn from(report: ValidationReport) -> Self {
+        Self {
+            loose_checked: report.loose_checked,
+            packs_checked: report.packs_checked,
+            invalid_loose: report.invalid_loose,
+            invalid_packs: report.invalid_packs,
+            interrupted: report.interrupted,
+        }
+    }
+}
+
+#[pymethods]
+impl PyValidationReport {
+    fn is_valid(&self) -> bool {
+        self.invalid_loose.is_empty() && self.invalid_packs.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ValidationReport(loose_checked={}, packs_checked={}, invalid_loose={:?}, invalid_packs={:?}, interrupted={})",
+            self.loose_checked, self.packs_checked, self.invalid_loose, self.invalid_packs, self.interrupted
+        )
+    }
+}
+
+/// Stops ``pack_all_loose`` or ``validate`` running in another thread, see
+/// ``rsdos::cancel::CancellationToken``.
+#[pyclass(name = "CancellationToken", frozen)]
+#[derive(Default)]
+struct PyCancellationToken(CancellationToken);
+
+#[pymethods]
+impl PyCancellationToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.0.cancel();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+/// ``ProgressObserver`` calling a Python callable with ``(done, total)``.
+///
+/// The operation runs without the GIL, it is only taken to call ``callback`` at most once per
+/// ``PROGRESS_INTERVAL`` (and always at the end). The first exception raised by ``callback`` is
+/// kept to be raised once the operation is over, the callback is not called anymore after it.
+struct PyProgress {
+    callback: PyObject,
+    last: Mutex<Option<Instant>>,
+    error: Mutex<Option<PyErr>>,
+}
+
+impl PyProgress {
+    fn new(callback: PyObject) -> Self {
+        Self {
+            callback,
+            last: Mutex::new(None),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Run ``f`` reporting to ``progress`` if given, the error raised by the callback if any
+    /// takes precedence over the result of ``f``.
+    fn observe<T, F>(py: Python, progress: Option<PyObject>, f: F) -> PyResult<T>
+    where
+        T: Send,
+        F: FnOnce(&dyn ProgressObserver) -> PyResult<T> + Send,
+    {
+        let Some(callback) = progress else {
+            return py.allow_threads(|| f(&()));
+        };
+        let progress = Self::new(callback);
+        let res = py.allow_threads(|| f(&progress));
+        match progress.error.into_inner().ok().flatten() {
+            Some(err) => Err(err),
+            None => res,
+        }
+    }
+}
+
+impl ProgressObserver for PyProgress {
+    fn progress(&self, done: u64, total: u64) {
+        {
+            let Ok(mut last) = self.last.lock() else {
+                return;
+            };
+            let now = Instant::now();
+            if done < total && last.is_some_and(|t| now.duration_since(t) < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last = Some(now);
+        }
+        let Ok(mut error) = self.error.lock() else {
+            return;
+        };
+        if error.is_some() {
+            return;
+        }
+        if let Err(err) = Python::with_gil(|py| self.callback.call1(py, (done, total))) {
+            *error = Some(err);
+        }
+    }
+}
+
 #[pyclass(name = "_Container")]
 struct PyContainer {
     inner: Container,
@@ -34,11 +296,27 @@ impl PyContainer {
 
     fn _init_db(&self) -> PyResult<()> {
         let db = self.inner.path.join(PACKS_DB);
-        db::create(&db)?;
+        db::create(&db).map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
 
         Ok(())
     }
 
+    /// Release the index connections and pack files kept open, see ``Container::close``. The
+    /// container can still be used afterwards, they are opened again on demand.
+    fn close(&self) {
+        self.inner.close();
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> bool {
+        self.inner.close();
+        false
+    }
+
     fn get_folder(&self) -> PathBuf {
         self.inner.path.clone()
     }
@@ -46,7 +324,9 @@ impl PyContainer {
     #[pyo3(signature = (pack_size_target=4 * 1024 * 1024, compression_algorithm="zlib:+1"))]
     fn init_container(&self, pack_size_target: u64, compression_algorithm: &str) -> PyResult<()> {
         let config = Config::new(pack_size_target, compression_algorithm);
-        self.inner.initialize(&config)?;
+        self.inner
+            .initialize(&config)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
         Ok(())
     }
 
@@ -55,200 +335,443 @@ impl PyContainer {
         self.inner.valid().is_ok()
     }
 
-    fn insert_to_loose(&self, stream: Py<PyAny>) -> PyResult<(u64, String)> {
+    /// Insert ``bytes`` or a binary file-like object to the store picked by its size, see
+    /// ``rsdos::Container::insert``. A file-like object has no known size, it goes to loose unless
+    /// the container stores objects chunked.
+    fn insert(&self, py: Python, source: Bound<'_, PyAny>) -> PyResult<PyInsertResult> {
+        let source = PySource::try_from(&source)?;
+        let cnt = &self.inner;
+        py.allow_threads(|| cnt.insert(source))
+            .map(PyInsertResult::from)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
+    }
+
+    fn insert_to_loose(&self, py: Python, stream: Py<PyAny>) -> PyResult<PyInsertResult> {
         let file_like = PyFileLikeObject::with_requirements(stream, true, false, false, false)?;
         let stream = Stream { fl: file_like };
 
-        rsdos::io_loose::insert(stream, &self.inner)
+        let cnt = &self.inner;
+        py.allow_threads(|| rsdos::io_loose::insert(stream, cnt))
+            .map(PyInsertResult::from)
             .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
     }
 
-    fn insert_to_packs(&self, stream: Py<PyAny>) -> PyResult<(u64, u64, String)> {
+    /// Insert ``bytes`` or binary file-like objects to loose, return an ``InsertResult`` per
+    /// source in the order of ``sources``.
+    fn insert_many_to_loose(
+        &self,
+        py: Python,
+        sources: Vec<Bound<'_, PyAny>>,
+    ) -> PyResult<Vec<PyInsertResult>> {
+        let mut res = Vec::with_capacity(sources.len());
+        for batch in sources.chunks(INSERT_BATCH_SIZE) {
+            let batch = batch
+                .iter()
+                .map(PySource::try_from)
+                .collect::<PyResult<Vec<_>>>()?;
+            let cnt = &self.inner;
+            let inserted = py
+                .allow_threads(|| rsdos::io_loose::insert_many(batch, cnt))
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            res.extend(inserted.into_iter().map(PyInsertResult::from));
+        }
+        Ok(res)
+    }
+
+    fn insert_to_packs(&self, py: Python, stream: Py<PyAny>) -> PyResult<PyInsertResult> {
         let file_like = PyFileLikeObject::with_requirements(stream, true, false, false, false)?;
         let stream = Stream { fl: file_like };
 
-        rsdos::io_packs::insert(stream, &self.inner)
+        let cnt = &self.inner;
+        py.allow_threads(|| rsdos::io_packs::insert(stream, cnt))
+            .map(PyInsertResult::from)
             .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
     }
 
+    /// Insert ``bytes`` or binary file-like objects to packs, return an ``InsertResult`` per
+    /// source in the order of ``sources``.
+    ///
+    /// ``sources`` can be any iterable, e.g. a generator. It is pulled lazily by batch of
+    /// ``INSERT_BATCH_SIZE``, so only one batch of sources is held in memory at a time.
     fn insert_many_to_packs(
         &self,
         py: Python,
-        sources: Vec<Py<PyBytes>>,
+        sources: &Bound<'_, PyAny>,
         compress_mode: &str,
-    ) -> PyResult<Vec<(u64, u64, String)>> {
-        let sources = sources.iter().map(|s| {
-            let b = s.bind(py);
-            b.as_bytes().to_vec()
-        });
-
-        let compression = match compress_mode {
-            "no" | "keep" => Compression::from_str("none").unwrap(),
-            "yes" => {
-                let algo = self
-                    .inner
-                    .config()
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyException, _>(e.to_string()))?
-                    .compression_algorithm;
-                Compression::from_str(&algo)
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?
-            }
-            _ => {
-                todo!()
+    ) -> PyResult<Vec<PyInsertResult>> {
+        let compression = self.compression(compress_mode)?;
+
+        let mut sources = sources.iter()?;
+        let mut res = Vec::new();
+        loop {
+            let batch = sources
+                .by_ref()
+                .take(INSERT_BATCH_SIZE)
+                .map(|source| PySource::try_from(&source?))
+                .collect::<PyResult<Vec<_>>>()?;
+            if batch.is_empty() {
+                break;
             }
-        };
-
-        rsdos::io_packs::_insert_many_internal(sources, &self.inner, &compression)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
+            let cnt = &self.inner;
+            let inserted = py
+                .allow_threads(|| rsdos::io_packs::_insert_many_internal(batch, cnt, &compression))
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            res.extend(inserted.into_iter().map(PyInsertResult::from));
+        }
+        Ok(res)
     }
 
     // This is 2 times fast than write to writer from py world since there is no overhead to cross
     // boundary for every py object.
-    fn extract_many_from_loose(
+    fn extract_many_from_loose<'py>(
         &self,
+        py: Python<'py>,
         hashkeys: Vec<String>,
-    ) -> HashMap<String, Option<ByteString>> {
-        let mut buf = Vec::new();
-        hashkeys
-            .iter()
-            .map(|hashkey| {
-                let content = match rsdos::io_loose::extract(hashkey, &self.inner).unwrap() {
-                    Some(obj) => {
-                        buf.clear();
-                        // XXX: no need of using Cursor should use buffer reader
-                        let mut cursor = Cursor::new(&mut buf);
-                        let mut rdr = obj.make_reader().unwrap();
-
-                        std::io::copy(&mut rdr, &mut cursor).unwrap();
-                        Some(buf.clone())
-                    }
-                    _ => None,
-                };
-                (hashkey.to_owned(), content)
-            })
-            .collect()
-    }
-
-    fn pack_all_loose(&self, compress_mode: &str) -> PyResult<()> {
-        // NOTE: compress_mode passed to here are: "no", "yes", "keep", "auto".
-        // In legacy dos, "keep" is equivelant to "no" when pack from loose.
-        let compression = match compress_mode {
-            "no" | "keep" => Compression::from_str("none").unwrap(),
-            "yes" => {
-                let algo = self
-                    .inner
-                    .config()
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyException, _>(e.to_string()))?
-                    .compression_algorithm;
-                Compression::from_str(&algo)
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let objs = py.allow_threads(|| {
+            hashkeys
+                .iter()
+                .map(|hashkey| rsdos::io_loose::extract(hashkey, &self.inner).map_err(read_err))
+                .collect::<PyResult<Vec<_>>>()
+        })?;
+
+        let dict = PyDict::new_bound(py);
+        for (hashkey, obj) in hashkeys.into_iter().zip(objs) {
+            match obj {
+                Some(obj) => dict.set_item(hashkey, read_bytes(py, &obj, obj.expected_size)?)?,
+                None => dict.set_item(hashkey, py.None())?,
             }
-            _ => {
-                todo!()
-            }
-        };
-        rsdos::maintain::_pack_loose_internal(&self.inner, &compression)
+        }
+        Ok(dict)
+    }
+
+    /// Pack the loose objects not yet in packs and return a ``PackReport`` of what was packed.
+    /// ``progress`` is called with ``(done, total)`` the number of objects packed, packing stops
+    /// at the next object once ``cancel`` is cancelled.
+    #[pyo3(signature = (compress_mode, progress=None, cancel=None))]
+    fn pack_all_loose(
+        &self,
+        py: Python,
+        compress_mode: &str,
+        progress: Option<PyObject>,
+        cancel: Option<PyRef<PyCancellationToken>>,
+    ) -> PyResult<PyPackReport> {
+        let compression = self.compression(compress_mode)?;
+        let cnt = &self.inner;
+        let cancel = cancel.map(|token| token.0.clone());
+        PyProgress::observe(py, progress, |progress| {
+            rsdos::maintain::_pack_loose_internal(
+                cnt,
+                &compression,
+                None,
+                false,
+                progress,
+                None,
+                cancel.as_ref(),
+            )
+            .map(PyPackReport::from)
             .map_err(|e| PyErr::new::<pyo3::exceptions::PyException, _>(e.to_string()))
+        })
     }
 
+    /// Re-hash every object and return a ``ValidationReport`` of those that do not match their
+    /// hashkey. ``progress`` is called with ``(done, total)`` the number of objects checked,
+    /// validation stops at the next object once ``cancel`` is cancelled.
+    #[pyo3(signature = (progress=None, cancel=None))]
+    fn validate(
+        &self,
+        py: Python,
+        progress: Option<PyObject>,
+        cancel: Option<PyRef<PyCancellationToken>>,
+    ) -> PyResult<PyValidationReport> {
+        let cnt = &self.inner;
+        let cancel = cancel.map(|token| token.0.clone());
+        PyProgress::observe(py, progress, |progress| {
+            rsdos::maintain::validate_with_progress(cnt, progress, None, cancel.as_ref())
+                .map(PyValidationReport::from)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyException, _>(e.to_string()))
+        })
+    }
+
+    /// Delete the loose objects already packed and vacuum the index,
+    /// ``MaintenanceInProgressError`` if another maintenance operation is running.
+    fn clean_storage(&self, py: Python) -> PyResult<()> {
+        py.allow_threads(|| rsdos::maintain::clean_loose(&self.inner, false))
+            .map(|_| ())
+            .map_err(maintain_err)
+    }
+
+    /// Content of the packed objects of ``hashkeys`` by hashkey, hashkeys not found in packs are
+    /// skipped.
+    ///
+    /// With ``max_memory`` (bytes) an ``ObjectBatches`` iterator is returned instead, yielding
+    /// dicts that hold at most ``max_memory`` bytes of content each, so requesting many large
+    /// objects does not need them all in memory at once.
+    #[pyo3(signature = (hashkeys, max_memory=None))]
     fn extract_many_from_packs(
         &self,
+        py: Python,
         hashkeys: Vec<String>,
-    ) -> PyResult<HashMap<String, ByteString>> {
-        let objs = rsdos::io_packs::extract_many(&hashkeys, &self.inner)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+        max_memory: Option<u64>,
+    ) -> PyResult<PyObject> {
+        if let Some(max_memory) = max_memory {
+            let cnt = Arc::new(Container::new(&self.inner.path));
+            let objs =
+                rsdos::io_packs::extract_many_owned(hashkeys, cnt, ExtractManyOptions::default())
+                    .map_err(read_err)?;
+            let batches = ObjectBatches {
+                objs: (Box::new(objs) as Box<dyn Iterator<Item = Result<PObject, rsdos::Error>> + Send>)
+                    .peekable(),
+                max_memory,
+            };
+            return Ok(batches.into_py(py));
+        }
 
-        let res = objs
-            .map(|obj| {
-                let hashkey = &obj.id.clone();
-                let Ok(b) = obj.try_into() else {
-                    panic!("Failed to convert `obj` into bytes.");
-                };
-                (hashkey.to_owned(), b)
-            })
-            .collect();
+        let cnt = &self.inner;
+        let objs = py.allow_threads(|| {
+            rsdos::io_packs::extract_many(&hashkeys, cnt)
+                .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+                .map_err(read_err)
+        })?;
 
-        Ok(res)
+        let dict = PyDict::new_bound(py);
+        for obj in objs {
+            dict.set_item(&obj.id, read_bytes(py, &obj, obj.raw_size)?)?;
+        }
+        Ok(dict.into_py(py))
     }
 
-    fn write_stream_from_loose(&self, hash: &str, py_filelike: Py<PyAny>) -> PyResult<()> {
-        Stream::write_from_loose(&self.inner, hash, py_filelike)
+    /// Iterate over ``(hashkey, bytes)`` of the packed objects, reading them lazily by batch.
+    /// Hashkeys not found in packs are skipped.
+    fn stream_many(&self, hashkeys: Vec<String>) -> ObjectStream {
+        ObjectStream {
+            cnt: Container::new(&self.inner.path),
+            pending: hashkeys.into_iter(),
+            batch: Vec::new().into_iter(),
+        }
     }
 
-    fn write_stream_from_packs(&self, hash: &str, py_filelike: Py<PyAny>) -> PyResult<()> {
-        Stream::write_from_packs(&self.inner, hash, py_filelike)
+    /// Hashkey of the content of ``stream`` (a binary file-like object) with the container's
+    /// ``hash_type``, the content is not stored.
+    fn compute_hash(&self, py: Python, stream: Py<PyAny>) -> PyResult<String> {
+        let mut fl = PyFileLikeObject::with_requirements(stream, true, false, false, false)?;
+        let algorithm = self
+            .inner
+            .config()
+            .and_then(|config| config.hash_algorithm())
+            .map_err(read_err)?;
+        py.allow_threads(|| {
+            let mut hwriter = HashWriter::new(std::io::sink(), algorithm);
+            std::io::copy(&mut fl, &mut hwriter)?;
+            Ok(hex::encode(hwriter.finish()))
+        })
+    }
+
+    /// Re-hash the object ``hashkey`` in every store it is in and compare with its hashkey, see
+    /// ``rsdos::maintain::verify``. ``None`` if the object is not in the container.
+    fn verify_object(&self, py: Python, hashkey: &str) -> PyResult<Option<bool>> {
+        let cnt = &self.inner;
+        py.allow_threads(|| rsdos::maintain::verify(cnt, hashkey))
+            .map(|verification| verification.map(|v| v.is_valid()))
+            .map_err(read_err)
+    }
+
+    /// Write the content of ``hash`` to ``py_filelike``, looked up in loose first and then in
+    /// packs (see ``rsdos::Container::get``).
+    fn write_stream(&self, py: Python, hash: &str, py_filelike: Py<PyAny>) -> PyResult<()> {
+        Stream::write_from(py, &self.inner, hash, LOOKUP_AUTO, py_filelike)
+    }
+
+    fn write_stream_from_loose(
+        &self,
+        py: Python,
+        hash: &str,
+        py_filelike: Py<PyAny>,
+    ) -> PyResult<()> {
+        Stream::write_from(py, &self.inner, hash, &[Store::Loose], py_filelike)
+    }
+
+    fn write_stream_from_packs(
+        &self,
+        py: Python,
+        hash: &str,
+        py_filelike: Py<PyAny>,
+    ) -> PyResult<()> {
+        Stream::write_from(py, &self.inner, hash, &[Store::Packs], py_filelike)
     }
 
     // XXX: combine with get_n_objs and return dicts
-    fn get_total_size(&self) -> PyResult<u64> {
-        let info = rsdos::cli::stat(&self.inner)?;
+    fn get_total_size(&self, py: Python) -> PyResult<u64> {
+        let info = py
+            .allow_threads(|| rsdos::cli::stat_fast(&self.inner))
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
         Ok(info.size.loose)
     }
 
     // FIXME: rename count_loose
-    fn get_n_objs(&self) -> PyResult<u64> {
-        let info = rsdos::cli::stat(&self.inner)?;
+    fn get_n_objs(&self, py: Python) -> PyResult<u64> {
+        let info = py
+            .allow_threads(|| rsdos::cli::stat_fast(&self.inner))
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
         Ok(info.count.loose)
     }
 
-    fn get_count_pack(&self) -> PyResult<u64> {
-        let info = rsdos::cli::stat(&self.inner)?;
+    fn get_count_pack(&self, py: Python) -> PyResult<u64> {
+        let info = py
+            .allow_threads(|| rsdos::cli::stat_fast(&self.inner))
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
         Ok(info.count.packs)
     }
 
-    fn get_count_pack_file(&self) -> PyResult<u64> {
-        let info = rsdos::cli::stat(&self.inner)?;
+    fn get_count_pack_file(&self, py: Python) -> PyResult<u64> {
+        let info = py
+            .allow_threads(|| rsdos::cli::stat_fast(&self.inner))
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
         Ok(info.count.packs_file)
     }
 }
 
-#[derive(Debug)]
+impl PyContainer {
+    /// Compression to pack with for a ``compress_mode`` of legacy disk-objectstore: ``"no"``,
+    /// ``"keep"`` (the same as ``"no"`` when packing from loose), ``"yes"`` or ``"auto"``.
+    ///
+    /// NOTE: with ``"yes"`` and ``"auto"`` objects are given the container's algorithm, then the
+    /// container's compression policy guesses per object (``maybe_content_format``) whether it is
+    /// worth compressing, as legacy ``"auto"`` does.
+    fn compression(&self, compress_mode: &str) -> PyResult<Compression> {
+        match compress_mode {
+            "no" | "keep" => Ok(Compression::Uncompressed),
+            "yes" | "auto" => self
+                .inner
+                .compression()
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyException, _>(e.to_string())),
+            _ => Err(PyValueError::new_err(format!(
+                "Unknown compress mode '{compress_mode}', expect 'no', 'yes', 'keep' or 'auto'"
+            ))),
+        }
+    }
+}
+
+/// Iterator returned by ``PyContainer.stream_many``.
+///
+/// Only one batch of at most ``STREAM_BATCH_SIZE`` objects is held in memory at a time.
 #[pyclass]
-struct Stream {
-    fl: PyFileLikeObject,
+struct ObjectStream {
+    cnt: Container,
+    pending: std::vec::IntoIter<String>,
+    batch: std::vec::IntoIter<PObject>,
 }
 
-impl Stream {
-    fn write_from_loose(cnt: &Container, hash: &str, py_filelike: Py<PyAny>) -> PyResult<()> {
-        if let Some(obj) = rsdos::io_loose::extract(hash, cnt)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?
-        {
-            match PyFileLikeObject::with_requirements(py_filelike, true, false, false, false) {
-                Ok(mut fl) => {
-                    // copy from reader to writer
-                    let mut rdr = obj
-                        .make_reader()
-                        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
-                    std::io::copy(&mut rdr, &mut fl)?;
-                    fl.rewind().unwrap();
-                    Ok(())
-                }
-                Err(e) => Err(e),
+#[pymethods]
+impl ObjectStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python) -> PyResult<Option<(String, PyObject)>> {
+        loop {
+            if let Some(obj) = slf.batch.next() {
+                let content = read_bytes(py, &obj, obj.raw_size)?;
+                return Ok(Some((obj.id, content.into())));
+            }
+
+            let hashkeys: Vec<String> = slf.pending.by_ref().take(STREAM_BATCH_SIZE).collect();
+            if hashkeys.is_empty() {
+                return Ok(None);
             }
-        } else {
-            Err(PyErr::new::<PyValueError, _>(format!("{hash} not found")))
+
+            let cnt = &slf.cnt;
+            let batch = py.allow_threads(|| {
+                rsdos::io_packs::extract_many(&hashkeys, cnt)
+                    .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+                    .map_err(read_err)
+            })?;
+            slf.batch = batch.into_iter();
         }
     }
+}
 
-    fn write_from_packs(cnt: &Container, hash: &str, py_filelike: Py<PyAny>) -> PyResult<()> {
-        if let Some(obj) = rsdos::io_packs::extract(hash, cnt)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?
-        {
-            match PyFileLikeObject::with_requirements(py_filelike, true, false, false, false) {
-                Ok(mut fl) => {
-                    // copy from reader to writer
-                    let mut rdr = obj
-                        .make_reader()
-                        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
-                    std::io::copy(&mut rdr, &mut fl)?;
-                    fl.rewind().unwrap();
-                    Ok(())
-                }
-                Err(e) => Err(e),
+/// Iterator returned by ``PyContainer.extract_many_from_packs`` with ``max_memory``.
+///
+/// Every batch is a dict of at most ``max_memory`` bytes of content, objects are added to it
+/// until the next one would exceed the cap. An object larger than the cap is yielded alone.
+#[pyclass]
+struct ObjectBatches {
+    objs: Peekable<Box<dyn Iterator<Item = Result<PObject, rsdos::Error>> + Send>>,
+    max_memory: u64,
+}
+
+#[pymethods]
+impl ObjectBatches {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python) -> PyResult<Option<Py<PyDict>>> {
+        let max_memory = slf.max_memory;
+        let objs = &mut slf.objs;
+        let batch: Result<Vec<PObject>, rsdos::Error> = py.allow_threads(|| {
+            let mut batch = Vec::new();
+            let mut size = 0;
+            // an error ends the batch, it is raised once the objects before it are returned
+            while let Some(obj) = objs.next_if(|obj| match obj {
+                Ok(obj) => batch.is_empty() || size + obj.raw_size <= max_memory,
+                Err(_) => batch.is_empty(),
+            }) {
+                let obj = obj?;
+                size += obj.raw_size;
+                batch.push(obj);
             }
-        } else {
-            Err(PyErr::new::<PyValueError, _>(format!("{hash} not found")))
+            Ok(batch)
+        });
+        let batch = batch.map_err(read_err)?;
+        if batch.is_empty() {
+            return Ok(None);
+        }
+
+        let dict = PyDict::new_bound(py);
+        for obj in batch {
+            dict.set_item(&obj.id, read_bytes(py, &obj, obj.raw_size)?)?;
         }
+        Ok(Some(dict.unbind()))
+    }
+}
+
+/// A Python binary file-like object used as a Rust reader/writer.
+///
+/// It is safe to use without holding the GIL: every ``read``/``write`` call on the wrapped
+/// ``PyFileLikeObject`` acquires the GIL only for the duration of that call. Since data is copied
+/// by chunk, the GIL is taken once per chunk and other Python threads run in between.
+#[derive(Debug)]
+#[pyclass]
+struct Stream {
+    fl: PyFileLikeObject,
+}
+
+impl Stream {
+    /// Copy the content of ``hash``, looked up in ``stores`` in order, to ``py_filelike``.
+    /// ``ValueError`` if it is in none of them.
+    fn write_from(
+        py: Python,
+        cnt: &Container,
+        hash: &str,
+        stores: &[Store],
+        py_filelike: Py<PyAny>,
+    ) -> PyResult<()> {
+        let mut fl = PyFileLikeObject::with_requirements(py_filelike, true, false, false, false)?;
+        py.allow_threads(|| {
+            if let Some(obj) = cnt.lookup(hash, stores).map_err(read_err)? {
+                // copy from reader to writer
+                let mut rdr = obj
+                    .make_reader()
+                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+                std::io::copy(&mut rdr, &mut fl)?;
+                fl.rewind().unwrap();
+                Ok(())
+            } else {
+                Err(PyErr::new::<PyValueError, _>(format!("{hash} not found")))
+            }
+        })
     }
 }
 
@@ -258,6 +781,53 @@ impl ReaderMaker for Stream {
     }
 }
 
+/// A source handed over from Python, either in-memory ``bytes`` or a binary file-like object.
+enum PySource {
+    Bytes(ByteString),
+    Stream(Stream),
+}
+
+impl TryFrom<&Bound<'_, PyAny>> for PySource {
+    type Error = PyErr;
+
+    fn try_from(obj: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(b) = obj.downcast::<PyBytes>() {
+            return Ok(PySource::Bytes(b.as_bytes().to_vec()));
+        }
+        let fl =
+            PyFileLikeObject::with_requirements(obj.clone().unbind(), true, false, false, false)?;
+        Ok(PySource::Stream(Stream { fl }))
+    }
+}
+
+impl ReaderMaker for PySource {
+    fn make_reader(&self) -> Result<impl Read, rsdos::Error> {
+        let rdr: Box<dyn Read + '_> = match self {
+            PySource::Bytes(b) => Box::new(b.as_slice()),
+            PySource::Stream(s) => Box::new(s.fl.clone()),
+        };
+        Ok(rdr)
+    }
+    /// ``bytes`` are sniffed like in Rust, a stream can only be read once so it is compressed.
+    fn maybe_content_format(
+        &self,
+        policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, rsdos::Error> {
+        match self {
+            PySource::Bytes(b) => b.maybe_content_format(policy),
+            PySource::Stream(_) => Ok(MaybeContentFormat::MaybeLargeText),
+        }
+    }
+
+    /// ``bytes`` are read and compressed ahead of the pack writer, see ``io_packs::insert_many``.
+    fn size_hint(&self) -> Option<u64> {
+        match self {
+            PySource::Bytes(b) => b.size_hint(),
+            PySource::Stream(_) => None,
+        }
+    }
+}
+
 #[pyfunction]
 // TODO: remove after https://github.com/PyO3/maturin/issues/368 is resolved
 fn run_cli(_py: Python) -> PyResult<()> {
@@ -273,6 +843,16 @@ fn run_cli(_py: Python) -> PyResult<()> {
 #[pyo3(name = "rsdos")]
 fn pyrsdos(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyContainer>()?;
+    m.add_class::<PyInsertResult>()?;
+    m.add_class::<PyPackReport>()?;
+    m.add_class::<PyValidationReport>()?;
+    m.add_class::<PyCancellationToken>()?;
+    m.add_class::<ObjectStream>()?;
+    m.add_class::<ObjectBatches>()?;
     m.add_function(wrap_pyfunction!(run_cli, m)?)?;
+    m.add(
+        "MaintenanceInProgressError",
+        m.py().get_type_bound::<MaintenanceInProgressError>(),
+    )?;
     Ok(())
 }
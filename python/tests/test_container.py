@@ -1,6 +1,8 @@
 from rsdos import Container
 import tempfile
 import os
+import io
+import hashlib
 
 
 def test_initialisation(tmp_path):
@@ -28,3 +30,32 @@ def test_add_loose_from_stream(rs_container):
     assert read_content == content
 
     os.remove(temp_handle.name)
+
+
+def test_context_manager_close(tmp_path):
+    """Handles are released on exit, the container is still usable afterwards."""
+    with Container(tmp_path) as container:
+        container.init_container()
+        hashkey = container.add_object_to_packs(b"test 0")
+        assert container.get_object_content(hashkey) == b"test 0"
+
+    assert container.get_object_content(hashkey) == b"test 0"
+    container.close()
+    container.close()
+
+
+def test_compute_hash_and_verify_object(rs_container):
+    """Hashes computed from python match the stored hashkeys, corruption is detected."""
+    content = b"0123456789" * 1000
+    assert rs_container.compute_hash(io.BytesIO(content)) == hashlib.sha256(content).hexdigest()
+
+    loose = rs_container.add_object(content)
+    packed = rs_container.add_object_to_packs(b"packed")
+    assert rs_container.compute_hash(io.BytesIO(content)) == loose
+    assert rs_container.verify_object(loose) is True
+    assert rs_container.verify_object(packed) is True
+    assert rs_container.verify_object(hashlib.sha256(b"missing").hexdigest()) is None
+
+    path = rs_container.get_folder() / "loose" / loose[:2] / loose[2:]
+    path.write_bytes(b"corrupted")
+    assert rs_container.verify_object(loose) is False
@@ -1,4 +1,8 @@
 import hashlib
+import io
+from concurrent.futures import ThreadPoolExecutor
+
+import pytest
 
 from rsdos import Container
 
@@ -38,6 +42,33 @@ def test_write_1000_files(rs_container):
     assert expected_hashkeys == hashkeys
 
 
+def test_write_many_bytes_and_streams(rs_container):
+    """Add bytes and streams to loose in one batch, hashkeys keep the input order"""
+    num_files = 3000
+    contents = [str(i).encode("ascii") for i in range(num_files)]
+    expected_hashkeys = [hashlib.sha256(content).hexdigest() for content in contents]
+    sources = [c if i % 2 else io.BytesIO(c) for i, c in enumerate(contents)]
+
+    hashkeys = rs_container.add_objects_to_loose(sources)
+
+    assert hashkeys == expected_hashkeys
+    assert rs_container.count_objects() == num_files
+    assert rs_container.get_object_content(hashkeys[1]) == contents[1]
+
+
+def test_write_from_threads(rs_container):
+    """Add objects from several Python threads, the GIL is released during Rust I/O"""
+    num_files = 400
+    contents = [str(i).encode("ascii") * 1000 for i in range(num_files)]
+    expected_hashkeys = [hashlib.sha256(content).hexdigest() for content in contents]
+
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        hashkeys = list(pool.map(rs_container.add_object, contents))
+
+    assert hashkeys == expected_hashkeys
+    assert rs_container.count_objects() == num_files
+
+
 def test_count_1000(tmp_path):
     rs_container = Container(tmp_path)
     rs_container.init_container()
@@ -61,3 +92,12 @@ def test_get_total_size_10000(rs_container):
     total_size = rs_container.get_total_size()
 
     print(total_size)
+
+
+def test_read_invalid_hashkey(rs_container):
+    """A malformed hashkey raises ValueError instead of being looked up"""
+    rs_container.add_object(b"test")
+
+    with pytest.raises(ValueError):
+        rs_container.get_objects_content(["a"])
+    assert rs_container.get_object_content("a") is None
@@ -1,5 +1,8 @@
+import os
+import time
+
 import pytest
-from rsdos import Container, CompressMode
+from rsdos import CancellationToken, Container, CompressMode, MaintenanceInProgressError
 
 
 @pytest.mark.parametrize(
@@ -73,3 +76,94 @@ def test_pack_loose_over_many_packs(
 
     if pack_size == "s":
         assert cnt.count_pack_file() > 1
+
+
+def test_pack_loose_auto(tmp_path):
+    """With ``AUTO`` only the objects worth compressing are compressed, a report is returned."""
+    cnt = Container(tmp_path)
+    cnt.init_container(compression_algorithm="zlib:+1")
+
+    text = ("8bytes0" * 1024).encode("ascii")
+    # starts with the zlib magic, taken as already compressed
+    compressed = b"\x78\x9c" + text
+    small = b"tiny"
+    hashkeys = [cnt.add_object(c) for c in (text, compressed, small)]
+
+    report = cnt.pack_all_loose(CompressMode.AUTO)
+
+    assert report.objects_packed == 3
+    assert report.bytes_read == len(text) + len(compressed) + len(small)
+    # only ``text`` is compressed
+    assert report.bytes_written < len(compressed) + len(small) + 100
+    assert report.packs_created == 1
+    assert report.skipped_already_packed == 0
+    for h, c in zip(hashkeys, (text, compressed, small)):
+        assert cnt.get_object_content(h) == c
+
+    report = cnt.pack_all_loose(CompressMode.AUTO)
+    assert (report.objects_packed, report.skipped_already_packed) == (0, 3)
+
+
+def test_pack_loose_and_validate_progress(tmp_path):
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    for i in range(10):
+        cnt.add_object(f"content {i}".encode("ascii"))
+
+    calls = []
+    report = cnt.pack_all_loose(progress=lambda done, total: calls.append((done, total)))
+    assert report.objects_packed == 10
+    assert calls[0] == (0, 10)
+    assert calls[-1] == (10, 10)
+
+    calls = []
+    report = cnt.validate(progress=lambda done, total: calls.append((done, total)))
+    assert report.is_valid()
+    assert (report.loose_checked, report.packs_checked) == (10, 10)
+    assert calls[-1] == (20, 20)
+
+    def fail(done, total):
+        raise KeyError("stop")
+
+    with pytest.raises(KeyError):
+        cnt.validate(progress=fail)
+
+
+def test_pack_loose_and_validate_cancelled(tmp_path):
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    for i in range(10):
+        cnt.add_object(f"content {i}".encode("ascii"))
+
+    # cancelled as soon as packing starts, nothing is packed
+    token = CancellationToken()
+    report = cnt.pack_all_loose(progress=lambda done, total: token.cancel(), cancel=token)
+    assert token.is_cancelled()
+    assert report.interrupted
+    assert report.objects_packed == 0
+
+    report = cnt.validate(cancel=token)
+    assert report.interrupted
+    assert report.loose_checked == 0
+
+    report = cnt.pack_all_loose(cancel=CancellationToken())
+    assert not report.interrupted
+    assert report.objects_packed == 10
+
+
+def test_clean_storage_maintenance_in_progress(tmp_path):
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    cnt.add_object(b"content")
+    cnt.pack_all_loose()
+
+    # held by this (running) process
+    lock = tmp_path / ".maintenance.lock"
+    lock.write_text(f"{os.getpid()} {int(time.time())} repack")
+    with pytest.raises(MaintenanceInProgressError):
+        cnt.clean_storage()
+    assert cnt.count_objects() == 1
+
+    lock.unlink()
+    cnt.clean_storage()
+    assert cnt.count_objects() == 0
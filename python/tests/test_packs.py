@@ -1,6 +1,7 @@
 from rsdos import Container, CompressMode
 import pytest
 import hashlib
+import io
 import random
 
 
@@ -50,6 +51,32 @@ def test_packs_read_many(tmp_path, compress_mode):
     assert results == expected_results_dict
 
 
+@pytest.mark.parametrize(
+    "compress_mode",
+    [CompressMode.YES, CompressMode.NO],
+)
+def test_packs_stream_many(tmp_path, compress_mode):
+    """Stream 3'000 packed objects over several batches, missing hashkeys are skipped."""
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    num_files = 3000
+    data_content = [str(i).encode("ascii") for i in range(num_files)]
+    expected_hashkeys = [
+        hashlib.sha256(content).hexdigest() for content in data_content
+    ]
+    expected_results_dict = dict(zip(expected_hashkeys, data_content))
+
+    hashkeys = cnt.add_objects_to_pack(data_content, compress=compress_mode)
+    stream = cnt.stream_many(hashkeys + [hashlib.sha256(b"missing").hexdigest()])
+
+    assert next(stream)[0] in expected_results_dict
+    assert len(list(stream)) == num_files - 1
+
+    results = dict(cnt.stream_many(hashkeys))
+
+    assert results == expected_results_dict
+
+
 @pytest.mark.parametrize(
     "compress_mode",
     [CompressMode.YES, CompressMode.NO],
@@ -114,3 +141,55 @@ def test_packs_write_different_size(tmp_path, compress_mode, nrepeat):
 
     assert len(hashkeys) == len(data_content)
     assert expected_hashkeys == hashkeys
+
+
+def test_packs_write_from_generator(tmp_path):
+    """Add 3'000 objects from a generator over several batches, bytes and streams mixed."""
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    num_files = 3000
+    data_content = [str(i).encode("ascii") for i in range(num_files)]
+    expected_hashkeys = [
+        hashlib.sha256(content).hexdigest() for content in data_content
+    ]
+
+    def sources():
+        for i, content in enumerate(data_content):
+            yield io.BytesIO(content) if i % 2 else content
+
+    hashkeys = cnt.add_objects_to_pack(sources(), compress=CompressMode.YES)
+
+    assert hashkeys == expected_hashkeys
+    assert cnt.get_objects_content(hashkeys) == dict(zip(hashkeys, data_content))
+
+
+def test_packs_extract_many_max_memory(tmp_path):
+    """Request 100 objects of 1 KiB with a cap of 10 KiB, they come in batches under the cap."""
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    data_content = [f"{i:04}".encode("ascii") * 256 for i in range(100)]
+    hashkeys = cnt.add_objects_to_pack(data_content)
+
+    batches = list(cnt.iter_objects_content_packs(hashkeys, max_memory=10 * 1024))
+
+    assert len(batches) == 10
+    assert all(sum(len(v) for v in batch.values()) <= 10 * 1024 for batch in batches)
+    results = {k: v for batch in batches for k, v in batch.items()}
+    assert results == dict(zip(hashkeys, data_content))
+
+    # an object larger than the cap is yielded alone
+    batches = list(cnt.iter_objects_content_packs(hashkeys[:3], max_memory=10))
+    assert [len(batch) for batch in batches] == [1, 1, 1]
+
+
+def test_packs_extract_many_bytes(tmp_path):
+    """Contents come back as ``bytes`` built by rust, from packs as well as from loose."""
+    cnt = Container(tmp_path)
+    cnt.init_container()
+    packed = cnt.add_objects_to_pack([b"packed 0", b"packed 1"])
+    loose = cnt.add_object(b"loose 0")
+
+    results = cnt.get_objects_content(packed + [loose])
+
+    assert results == {packed[0]: b"packed 0", packed[1]: b"packed 1", loose: b"loose 0"}
+    assert all(type(v) is bytes for v in results.values())
@@ -5,9 +5,18 @@ pub use crate::config::Config;
 #[path = "libs/db.rs"]
 pub mod db;
 
+#[path = "libs/index.rs"]
+pub mod index;
+
 #[path = "libs/utils.rs"]
 pub mod utils;
 
+#[path = "libs/throttle.rs"]
+pub mod throttle;
+
+#[path = "libs/cancel.rs"]
+pub mod cancel;
+
 #[path = "libs/error.rs"]
 pub mod error;
 pub use crate::error::Error;
@@ -16,6 +25,7 @@ pub use crate::error::Error;
 pub mod cli;
 pub use crate::cli::add_file;
 pub use crate::cli::stat;
+pub use crate::cli::StoreType;
 
 #[path = "libs/io.rs"]
 pub mod io;
@@ -26,6 +36,33 @@ pub mod io_packs;
 #[path = "libs/io_loose.rs"]
 pub mod io_loose;
 
+#[path = "libs/pipeline.rs"]
+pub mod pipeline;
+
+#[path = "libs/bloom.rs"]
+pub mod bloom;
+
+#[path = "libs/cache.rs"]
+pub mod cache;
+
+#[path = "libs/dictionary.rs"]
+pub mod dictionary;
+
+#[path = "libs/chunked.rs"]
+pub mod chunked;
+
+#[path = "libs/journal.rs"]
+pub mod journal;
+
+#[path = "libs/lock.rs"]
+pub mod lock;
+
+#[path = "libs/namespace.rs"]
+pub mod namespace;
+
+#[path = "libs/expiry.rs"]
+pub mod expiry;
+
 #[path = "libs/container.rs"]
 pub mod container;
 pub use crate::container::Container;
@@ -34,5 +71,16 @@ pub use crate::container::Container;
 #[cfg(test)]
 pub mod test_utils;
 
+#[path = "libs/pack_storage.rs"]
+pub mod pack_storage;
+
 #[path = "libs/maintain.rs"]
 pub mod maintain;
+
+#[path = "libs/server.rs"]
+#[cfg(feature = "server")]
+pub mod server;
+
+#[path = "libs/mount.rs"]
+#[cfg(feature = "fuse")]
+pub mod mount;
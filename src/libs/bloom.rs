@@ -0,0 +1,98 @@
+/// Bits per expected item, with ``N_HASHES`` this gives about 1% false positives at capacity.
+const BITS_PER_ITEM: u64 = 10;
+const N_HASHES: u64 = 7;
+/// Smallest capacity, so small containers do not rebuild the filter on every few inserts.
+const MIN_CAPACITY: u64 = 4096;
+
+/// Bloom filter over hashkeys: ``may_contain`` is never false for an inserted hashkey and only
+/// true for about 1% of the others while ``len`` stays below ``capacity``.
+///
+/// Hashkeys are already uniform (hex of a SHA-256), the bit positions are derived from their
+/// first 32 hex digits by double hashing instead of hashing them again.
+#[derive(Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    n_bits: u64,
+    len: u64,
+    capacity: u64,
+}
+
+impl BloomFilter {
+    #[must_use]
+    pub fn with_capacity(capacity: u64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let n_bits = capacity * BITS_PER_ITEM;
+        Self {
+            bits: vec![0; usize::try_from(n_bits.div_ceil(64)).unwrap_or(usize::MAX)],
+            n_bits,
+            len: 0,
+            capacity,
+        }
+    }
+
+    /// Bit positions of ``hashkey``, ``None`` if it does not start with 32 hex digits.
+    fn positions(&self, hashkey: &str) -> Option<impl Iterator<Item = u64>> {
+        let h1 = u64::from_str_radix(hashkey.get(..16)?, 16).ok()?;
+        let h2 = u64::from_str_radix(hashkey.get(16..32)?, 16).ok()?;
+        let n_bits = self.n_bits;
+        Some((0..N_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % n_bits))
+    }
+
+    pub fn insert(&mut self, hashkey: &str) {
+        let Some(positions) = self.positions(hashkey) else {
+            return;
+        };
+        for pos in positions {
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+        self.len += 1;
+    }
+
+    /// False if ``hashkey`` was never inserted, true if it probably was. Anything that is not a
+    /// hashkey may be contained.
+    #[must_use]
+    pub fn may_contain(&self, hashkey: &str) -> bool {
+        let Some(mut positions) = self.positions(hashkey) else {
+            return true;
+        };
+        positions.all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+
+    /// Whether more items were inserted than the filter was sized for, its false positive rate
+    /// then grows and it is better rebuilt with a larger capacity.
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::digest;
+
+    use super::*;
+
+    fn hashkey(i: u64) -> String {
+        hex::encode(digest::digest(&digest::SHA256, &i.to_le_bytes()))
+    }
+
+    #[test]
+    fn bloom_no_false_negatives() {
+        let mut bloom = BloomFilter::with_capacity(10_000);
+        for i in 0..10_000 {
+            bloom.insert(&hashkey(i));
+        }
+        assert!(!bloom.is_full());
+        assert!((0..10_000).all(|i| bloom.may_contain(&hashkey(i))));
+
+        let false_positives = (10_000..20_000)
+            .filter(|i| bloom.may_contain(&hashkey(*i)))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+
+        assert!(bloom.may_contain("not a hashkey"));
+        bloom.insert(&hashkey(10_000));
+        bloom.insert(&hashkey(10_001));
+        assert!(bloom.is_full());
+    }
+}
@@ -0,0 +1,136 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Objects larger than this fraction of the cache are never cached, so one large object can not
+/// evict everything else.
+const MAX_OBJECT_FRACTION: u64 = 8;
+
+/// Hit and miss counters and the content of an ``ObjectCache``.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    /// Content and last use of every cached object.
+    entries: HashMap<String, (Arc<[u8]>, u64)>,
+    /// Cached hashkeys by last use, the first one is evicted first.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+/// Size-bounded LRU cache of decompressed object contents by hashkey, shared by the readers of a
+/// ``Container``.
+#[derive(Debug)]
+pub struct ObjectCache {
+    capacity: u64,
+    lru: Mutex<Lru>,
+}
+
+impl ObjectCache {
+    /// A cache holding at most ``capacity`` bytes of content.
+    #[must_use]
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// Whether an object of ``size`` bytes is small enough to be cached.
+    #[must_use]
+    pub fn admits(&self, size: u64) -> bool {
+        size <= self.capacity / MAX_OBJECT_FRACTION
+    }
+
+    /// Content of ``hashkey`` if it is cached, counted as a hit or a miss.
+    pub fn get(&self, hashkey: &str) -> Option<Arc<[u8]>> {
+        let mut lru = self.lru.lock().ok()?;
+        let lru = &mut *lru;
+        lru.tick += 1;
+        let Some((content, last_use)) = lru.entries.get_mut(hashkey) else {
+            lru.stats.misses += 1;
+            return None;
+        };
+        let key = lru.recency.remove(last_use).unwrap_or_default();
+        *last_use = lru.tick;
+        lru.recency.insert(lru.tick, key);
+        lru.stats.hits += 1;
+        Some(content.clone())
+    }
+
+    /// Cache ``content`` of ``hashkey``, evicting the least recently used objects to make room.
+    pub fn put(&self, hashkey: &str, content: Arc<[u8]>) {
+        let size = content.len() as u64;
+        if !self.admits(size) {
+            return;
+        }
+        let Ok(mut lru) = self.lru.lock() else {
+            return;
+        };
+        let lru = &mut *lru;
+        if lru.entries.contains_key(hashkey) {
+            return;
+        }
+
+        while lru.stats.bytes + size > self.capacity {
+            let Some((_, evicted)) = lru.recency.pop_first() else {
+                break;
+            };
+            if let Some((content, _)) = lru.entries.remove(&evicted) {
+                lru.stats.bytes -= content.len() as u64;
+                lru.stats.objects -= 1;
+            }
+        }
+
+        lru.tick += 1;
+        lru.recency.insert(lru.tick, hashkey.to_string());
+        lru.entries.insert(hashkey.to_string(), (content, lru.tick));
+        lru.stats.bytes += size;
+        lru.stats.objects += 1;
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        self.lru.lock().map(|lru| lru.stats).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let cache = ObjectCache::new(80);
+        let content = |n: usize| Arc::from(vec![0u8; n]);
+
+        cache.put("a", content(10));
+        cache.put("b", content(10));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_none());
+
+        // too large for the cache
+        cache.put("large", content(11));
+        assert!(cache.get("large").is_none());
+
+        // "b" is the least recently used
+        for key in ["c", "d", "e", "f", "g", "h", "i"] {
+            cache.put(key, content(10));
+        }
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.objects, 8);
+        assert_eq!(stats.bytes, 80);
+    }
+}
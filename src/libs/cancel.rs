@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Asks a long maintenance operation (e.g. ``maintain::_pack_loose_internal``) to stop.
+///
+/// The token is cloned to whoever may cancel, another thread or a signal handler, and checked by
+/// the operation between objects: the objects done so far are committed, the container is left
+/// consistent and the operation returns what it did up to then instead of an error.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Whether the operation given ``cancel`` is to stop, never without a token.
+pub(crate) fn is_cancelled(cancel: Option<&CancellationToken>) -> bool {
+    cancel.is_some_and(CancellationToken::is_cancelled)
+}
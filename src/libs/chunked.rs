@@ -0,0 +1,206 @@
+use fastcdc::v2020::StreamCDC;
+use ring::digest;
+use std::io::{self, Cursor, Read};
+
+use crate::db;
+use crate::index::ObjectIndex;
+use crate::io::{check_hashkey, ByteString, HashReader, InsertResult, ReaderMaker, Store};
+use crate::namespace::{self, Scope};
+use crate::{io_loose, Container, Error};
+
+// FastCDC bounds of a chunk, tuned for large near-identical files.
+const MIN_CHUNK_SIZE: u32 = 16_384; // 16 KiB
+const AVG_CHUNK_SIZE: u32 = 65_536; // 64 KiB
+const MAX_CHUNK_SIZE: u32 = 262_144; // 256 KiB
+
+/// First line of a manifest object, followed by one chunk hashkey per line.
+const MANIFEST_HEADER: &str = "rsdos-chunked-manifest v1";
+
+/// An object stored in chunks, reassembled from its manifest.
+#[derive(Debug)]
+pub struct CObject {
+    pub id: String,
+    pub raw_size: u64,
+    pub manifest: String,
+    pub chunks: Vec<String>,
+}
+
+impl CObject {
+    /// Reader over the reassembled content, chunks are read one at a time.
+    pub fn reader<'a>(&self, cnt: &'a Container) -> ChunkedReader<'a> {
+        ChunkedReader {
+            cnt,
+            chunks: self.chunks.clone().into_iter(),
+            current: Cursor::new(vec![]),
+        }
+    }
+}
+
+pub struct ChunkedReader<'a> {
+    cnt: &'a Container,
+    chunks: std::vec::IntoIter<String>,
+    current: Cursor<ByteString>,
+}
+
+impl Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+            let content = read_object(&chunk, self.cnt)
+                .and_then(|content| {
+                    content.ok_or_else(|| Error::MissingObject {
+                        hashkey: chunk.clone(),
+                    })
+                })
+                .map_err(io::Error::other)?;
+            self.current = Cursor::new(content);
+        }
+    }
+}
+
+/// Split the source into content-defined chunks, store every chunk and the manifest listing them
+/// as loose objects, and record the manifest in the index.
+///
+/// Return the hashkey and size of the whole content, so a chunked object is looked up by the
+/// same hashkey as if it was stored plain. Chunks shared with other objects are stored once.
+pub fn insert<T>(source: T, cnt: &Container) -> Result<InsertResult, Error>
+where
+    T: ReaderMaker,
+{
+    insert_in(source, cnt, Scope::Unscoped)
+}
+
+/// Insert in chunks for ``scope``, see ``namespace::Scope``. The chunks and the manifest are
+/// kept with the chunked object, they are not recorded themselves.
+pub(crate) fn insert_in<T>(source: T, cnt: &Container, scope: Scope) -> Result<InsertResult, Error>
+where
+    T: ReaderMaker,
+{
+    cnt.valid()?;
+
+    // hash the whole content while chunking
+    let mut hreader = HashReader::new(source.make_reader()?, &digest::SHA256);
+
+    let mut manifest = format!("{MANIFEST_HEADER}\n");
+    let mut raw_size = 0;
+    for chunk in StreamCDC::new(&mut hreader, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE) {
+        let chunk = chunk.map_err(|err| Error::ChunkCopyError { source: err.into() })?;
+        raw_size += chunk.length as u64;
+        let chunk = io_loose::insert_in(chunk.data, cnt, Scope::Internal)?;
+        manifest.push_str(&chunk.hashkey);
+        manifest.push('\n');
+    }
+    let hash_hex = hex::encode(hreader.finish());
+
+    let manifest = io_loose::insert_in(manifest.into_bytes(), cnt, Scope::Internal)?;
+
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    tx.insert_chunked(&hash_hex, raw_size, &manifest.hashkey)?;
+    namespace::record(&*tx, scope, std::slice::from_ref(&hash_hex))?;
+    tx.commit()?;
+    cnt.note_indexed(&hash_hex);
+
+    Ok(InsertResult {
+        hashkey: hash_hex,
+        raw_size,
+        stored_size: raw_size,
+        compressed: false,
+        store: Store::Chunked,
+    })
+}
+
+/// Look up a chunked object and read its manifest, ``None`` if the object is not chunked.
+pub fn extract(hashkey: &str, cnt: &Container) -> Result<Option<CObject>, Error> {
+    cnt.valid()?;
+    check_hashkey(hashkey)?;
+
+    let Some((raw_size, manifest)) = cnt.index()?.select_chunked(hashkey)? else {
+        return Ok(None);
+    };
+
+    let content = read_object(&manifest, cnt)?.ok_or_else(|| Error::MissingObject {
+        hashkey: manifest.clone(),
+    })?;
+    let content = String::from_utf8(content).map_err(|_| Error::IntegrityError {
+        expected: MANIFEST_HEADER.to_string(),
+        got: "non utf-8 content".to_string(),
+    })?;
+    let mut lines = content.lines();
+    match lines.next() {
+        Some(MANIFEST_HEADER) => {}
+        got => {
+            return Err(Error::IntegrityError {
+                expected: MANIFEST_HEADER.to_string(),
+                got: got.unwrap_or_default().to_string(),
+            })
+        }
+    }
+
+    Ok(Some(CObject {
+        id: hashkey.to_string(),
+        raw_size,
+        manifest,
+        chunks: lines.map(ToString::to_string).collect(),
+    }))
+}
+
+/// Read a plain object from loose, or from packs once it has been packed.
+fn read_object(hashkey: &str, cnt: &Container) -> Result<Option<ByteString>, Error> {
+    cnt.get(hashkey)?.map(ByteString::try_from).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        maintain::pack_loose,
+        stat,
+        test_utils::{new_container, PACK_TARGET_SIZE},
+    };
+
+    use super::*;
+
+    #[test]
+    fn chunked_insert_and_extract_share_chunks() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut content: ByteString = (0..1_000_000).map(|_| rng.gen()).collect();
+        let inserted = insert(content.clone(), &cnt).unwrap();
+        assert_eq!(inserted.raw_size, content.len() as u64);
+        assert_eq!(inserted.store, Store::Chunked);
+        let hashkey = inserted.hashkey;
+        assert_eq!(
+            hashkey,
+            hex::encode(digest::digest(&digest::SHA256, &content))
+        );
+        let n_objects = stat(&cnt).unwrap().count.loose;
+
+        // a near-identical file only adds the chunks around the change and its manifest
+        content[500_000] ^= 0xFF;
+        let hashkey_changed = insert(content.clone(), &cnt).unwrap().hashkey;
+        assert_ne!(hashkey, hashkey_changed);
+        let n_added = stat(&cnt).unwrap().count.loose - n_objects;
+        assert!(n_added <= 3, "{n_added} objects added");
+
+        // reassembled from packs as well
+        pack_loose(&cnt).unwrap();
+        crate::maintain::clean_loose(&cnt, false).unwrap();
+        let obj = extract(&hashkey_changed, &cnt).unwrap().unwrap();
+        assert_eq!(obj.raw_size, content.len() as u64);
+        let mut buf = vec![];
+        obj.reader(&cnt).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+
+        assert!(extract(&"0".repeat(64), &cnt).unwrap().is_none());
+    }
+}
@@ -1,23 +1,37 @@
 use anyhow::Context;
 use std::ffi::OsString;
-use std::{fs, io::BufReader, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
 
+use crate::cancel::CancellationToken;
 use crate::container::{
-    traverse_loose, traverse_packs, Container, ContainerInfo, CountInfo, SizeInfo,
+    loose_hashkey, traverse_loose, traverse_packs, Container, ContainerInfo, CountInfo, SizeInfo,
+    LOOKUP_AUTO, STALE_TMP_TTL,
 };
-use crate::io::ReaderMaker;
-use crate::io_loose::insert as loose_insert;
-use crate::io_packs::insert as packs_insert;
+use crate::io::{ExtractOptions, InsertResult, Object, Store, VerifyingReader};
+use crate::io_loose::insert_by_link_in as loose_insert_by_link;
+use crate::io_loose::insert_in as loose_insert;
+use crate::io_packs::insert_in as packs_insert;
+use crate::io_packs::PObject;
+use crate::maintain::{IndexAnomaly, MaintainReport, PackReport, RepackStrategy};
+use crate::namespace::Scope;
+use crate::throttle::{parse_rate, IoThrottle};
 use crate::Error;
 
-use crate::config::Config;
-use crate::db::{self, print_table};
+use crate::config::{ByteSize, Config, SETTABLE_KEYS};
+use crate::db::{EntryFilter, PackEntry, PackStats};
 
 use crate::container::Compression;
 use crate::utils::create_dir;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use human_bytes::human_bytes;
+use serde::Serialize;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 use std::{env, fmt::Debug};
 
 use std::io::{self, Write};
@@ -27,14 +41,28 @@ pub const DEFAULT_COMPRESSION_ALGORITHM: &str = "zstd:-1";
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to the repository where container locate, <cwd> if not specified
+    /// Path to the container. If not specified, $RSDOS_CONTAINER, then the container the working
+    /// directory is in (or the `container` folder of it or of a parent), then <cwd>/container
     #[arg(short, long, value_name = "FOLDER")]
     path: Option<PathBuf>,
 
+    /// Output format of `status`, `info`, `list`, `namespaces`, `validate`, `show`,
+    /// `fsck --index-only` and `debug dump-index`
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     #[command(subcommand)]
     cmd: Commands,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// Human readable text
+    Text,
+    /// Machine readable JSON
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 enum OptimizeCommands {
     /// pack objects from loose to packed store
@@ -47,13 +75,141 @@ enum OptimizeCommands {
         /// the DB.
         #[arg(long, default_value_t = false)]
         no_clean: bool,
+
+        /// Only pack the loose objects written at least this long ago, in seconds or with a unit
+        /// ("90s", "30m", "12h", "1d"). Recently written objects are the most read.
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<Duration>,
+
+        /// Read the loose objects at most at this rate, bytes per second or with a unit
+        /// ("50MB/s")
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        throttle: Option<u64>,
+
+        /// Only report what would be packed and cleaned, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
     },
 
     /// repack objects in packs
     Repack {
-        /// Compression algorithm for repack
-        #[arg(short, long, default_value = DEFAULT_COMPRESSION_ALGORITHM, value_name = "COMPRESSION")]
-        compression: String,
+        /// Compression algorithm entries are rewritten with by `--recompress`, the one of the
+        /// container if not given
+        #[arg(short, long, value_name = "COMPRESSION")]
+        compression: Option<String>,
+
+        /// Re-evaluate the compression of every entry and rewrite the ones not stored with the
+        /// algorithm it gives, e.g. after changing the compression of the container
+        #[arg(long, default_value_t = false)]
+        recompress: bool,
+
+        /// Order objects are rewritten in within their pack: `preserve`, `sort-by-hash` or
+        /// `sort-by-size`
+        #[arg(short, long, default_value = "preserve", value_name = "STRATEGY")]
+        strategy: RepackStrategy,
+
+        /// Read the packed objects at most at this rate, bytes per second or with a unit
+        /// ("50MB/s")
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        throttle: Option<u64>,
+
+        /// Only report what would be rewritten, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// move objects out of sparse packs into the current pack and delete the old packs
+    Prune {
+        /// Prune packs whose live bytes are below this fraction of their size.
+        #[arg(long, default_value_t = 0.5, value_name = "RATIO")]
+        min_live_ratio: f64,
+
+        /// Only report what would be moved, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// split packs far over the pack size target and merge those far under it, e.g. after
+    /// `rsdos info --config --set pack_size_target=...`
+    Rebalance {
+        /// Only report what would be moved, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// upload sealed packs to the pack storage and delete the local copies
+    Offload {
+        /// Only report what would be offloaded, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// delete loose objects that are already packed and vacuum the DB
+    Clean {
+        /// Only report what would be deleted, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// delete the objects whose expiry has passed
+    Expire,
+
+    /// delete a namespace and its objects that are in no other namespace
+    GcNamespace {
+        #[arg(required = true, value_name = "NAMESPACE")]
+        namespace: String,
+
+        /// Only report what would be deleted, the container is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// train a zstd dictionary on small objects and compress new pack entries with it
+    TrainDictionary {
+        /// Largest number of objects to train on
+        #[arg(long, default_value_t = 1000, value_name = "N")]
+        samples: usize,
+
+        /// Size of the dictionary, bytes or with a unit ("110KiB")
+        #[arg(long, default_value = "110KiB", value_name = "SIZE")]
+        size: ByteSize,
+    },
+}
+
+impl OptimizeCommands {
+    fn dry_run(&self) -> bool {
+        match self {
+            OptimizeCommands::Pack { dry_run, .. }
+            | OptimizeCommands::Repack { dry_run, .. }
+            | OptimizeCommands::Prune { dry_run, .. }
+            | OptimizeCommands::Rebalance { dry_run }
+            | OptimizeCommands::Offload { dry_run }
+            | OptimizeCommands::Clean { dry_run }
+            | OptimizeCommands::GcNamespace { dry_run, .. } => *dry_run,
+            OptimizeCommands::TrainDictionary { .. } | OptimizeCommands::Expire => false,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum DebugCommands {
+    /// Print the entries of the pack index in the order they were inserted
+    DumpIndex {
+        /// Only the entries of this pack
+        #[arg(long, value_name = "PACK_ID")]
+        pack_id: Option<u64>,
+
+        /// Print at most this many entries
+        #[arg(long, value_name = "N")]
+        limit: Option<u64>,
+
+        /// Only the entries whose hashkey starts with this hex prefix
+        #[arg(long = "where", value_name = "HASH_PREFIX", value_parser = parse_hash_prefix)]
+        hash_prefix: Option<String>,
+
+        /// Print CSV, whatever `--format`
+        #[arg(long)]
+        csv: bool,
     },
 }
 
@@ -61,24 +217,177 @@ enum OptimizeCommands {
 enum Commands {
     /// Initialize container folder to store objects
     Init {
-        /// Pack size (in GiB)
-        #[arg(short, long, default_value_t = 4, value_name = "PACK_SIZE")]
-        pack_size_gb: u64,
+        /// Pack size, bytes or with a unit ("4GiB", "512MB"), at least 1 MiB
+        #[arg(short, long, default_value = "4GiB", value_name = "PACK_SIZE", value_parser = parse_pack_size)]
+        pack_size: ByteSize,
 
         /// Compression algorithm none for not compressing data or
         #[arg(short, long, default_value = DEFAULT_COMPRESSION_ALGORITHM, value_name = "COMPRESSION")]
         compression: String,
+
+        /// Write a header in front of every pack entry, so the index can be rebuilt from packs
+        #[arg(long, default_value_t = false)]
+        entry_header: bool,
+
+        /// Keep the pack files in shards of 256 packs instead of flat in `packs`
+        #[arg(long, default_value_t = false)]
+        sharded_packs: bool,
+
+        /// Objects larger than this (in MiB) added with `--to auto` go straight to packs
+        #[arg(long, value_name = "SIZE_MB")]
+        auto_pack_threshold_mb: Option<u64>,
+
+        /// Refuse inserts once loose objects and pack files take more than this (in MiB)
+        #[arg(long, value_name = "SIZE_MB")]
+        max_total_size_mb: Option<u64>,
+
+        /// Refuse objects larger than this (in MiB)
+        #[arg(long, value_name = "SIZE_MB")]
+        max_object_size_mb: Option<u64>,
+
+        /// Store files added with `--to auto` in content-defined chunks shared between objects
+        #[arg(long, default_value_t = false)]
+        chunked: bool,
+
+        /// Offload sealed packs to this storage, `file:///<dir>`, `s3://<bucket>/<prefix>` or
+        /// `gs://<bucket>/<prefix>` (the latter two need the `s3` feature)
+        #[arg(long, value_name = "URL")]
+        pack_storage: Option<String>,
+
+        /// Write objects smaller than this ("4KiB") uncompressed to packs of their own
+        #[arg(long, value_name = "SIZE")]
+        small_object_threshold: Option<ByteSize>,
+
+        /// Size of the packs of small objects, `--pack-size` if not specified
+        #[arg(long, value_name = "PACK_SIZE", value_parser = parse_pack_size)]
+        small_pack_size: Option<ByteSize>,
+    },
+
+    /// Upgrade the container to the latest format version
+    Upgrade {
+        /// Also enable pack entry headers for packs written from now on
+        #[arg(long, default_value_t = false)]
+        entry_header: bool,
+
+        /// Also move the pack files into shards of 256 packs
+        #[arg(long, default_value_t = false)]
+        sharded_packs: bool,
+    },
+
+    /// Change the compression algorithm of the objects packed from now on
+    SetCompression {
+        /// Compression algorithm, `none`, `zlib:<level>` or `zstd:<level>`
+        #[arg(required = true, value_name = "COMPRESSION")]
+        compression: String,
+
+        /// Also rewrite the packed objects with it, see `rsdos optimize repack --recompress`
+        #[arg(long, default_value_t = false)]
+        recompress: bool,
     },
 
     /// Get the status of container
-    Status,
+    Status {
+        /// Also list the objects and bytes of every pack file, to spot packs worth repacking
+        #[arg(long, default_value_t = false)]
+        per_pack: bool,
+
+        /// Do not walk the loose store, take its counts from the counters kept in the index
+        #[arg(long, default_value_t = false, conflicts_with = "full")]
+        fast: bool,
+
+        /// Walk the loose store (the default) and reset the counters kept in the index to it
+        #[arg(long, default_value_t = false)]
+        full: bool,
+    },
+
+    /// Show the container id, version and capabilities
+    Info {
+        /// Print the whole config instead
+        #[arg(long)]
+        config: bool,
+
+        /// Change a key of the config, e.g. `compression_algorithm=zstd:3` or
+        /// `pack_size_target=1GiB`, may be repeated. Sizes, limits and thread counts take `none` to
+        /// unset them.
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_setting, requires = "config")]
+        set: Vec<(String, String)>,
+    },
+
+    /// List hashkeys of all objects with the store they are in
+    List {
+        /// Only list the objects of this namespace
+        #[arg(long, value_name = "NAMESPACE")]
+        namespace: Option<String>,
+    },
+
+    /// List the namespaces with the count and size of their objects
+    Namespaces,
+
+    /// Re-hash all objects and report those that are corrupted
+    Validate {
+        /// Read the objects at most at this rate, bytes per second or with a unit ("50MB/s")
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        throttle: Option<u64>,
+    },
+
+    /// Re-hash a single object and exit with 1 if it is corrupted or not found
+    Verify {
+        #[arg(required = true)]
+        id: String,
+    },
+
+    /// Serve the container over HTTP
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000", value_name = "ADDR")]
+        addr: std::net::SocketAddr,
+
+        /// Refuse inserts
+        #[arg(long, default_value_t = false)]
+        read_only: bool,
+
+        /// Refuse inserted objects larger than this ("1GiB")
+        #[arg(long, value_name = "SIZE")]
+        max_object_size: Option<ByteSize>,
+    },
+
+    /// Copy the objects missing from another container into it
+    Sync {
+        /// Path of the destination container
+        #[arg(required = true, value_name = "DST")]
+        dst: PathBuf,
+
+        /// Only report what would be copied, the destination is not touched.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Copy the container to a new folder, consistent even while it is written to
+    Backup {
+        /// Folder of the copy, must not exist or be empty
+        #[arg(required = true, value_name = "DEST")]
+        dest: PathBuf,
+    },
+
+    /// Mount the container read-only, one file per object named by its hashkey
+    #[cfg(feature = "fuse")]
+    Mount {
+        #[arg(required = true, value_name = "MOUNTPOINT")]
+        mountpoint: PathBuf,
+    },
+
+    /// Show where an object is stored and its sizes, without reading its content
+    Show {
+        #[arg(required = true)]
+        id: String,
+    },
 
     /// Inspect loose and pack storages
     Inspect {
-        /// Target store type, `loose`/`packs` to add to loose/packs.
-        /// Use `auto` (default) if you don't know.
-        #[arg(short, long, default_value = "auto")]
-        storage_type: String,
+        /// Store to inspect, `auto` (default) for both.
+        #[arg(short, long, value_enum, default_value_t = StoreType::Auto)]
+        storage_type: StoreType,
     },
 
     /// Add files to container
@@ -89,8 +398,22 @@ enum Commands {
 
         /// Target store type, `loose`/`packs` to add to loose/packs.
         /// Use `auto` (default) if you don't know.
-        #[arg(short, long, default_value = "auto", value_name = "TO")]
-        to: String,
+        #[arg(short, long, value_enum, default_value_t = StoreType::Auto, value_name = "TO")]
+        to: StoreType,
+
+        /// Hard-link files into loose instead of copying them, falls back to copy across
+        /// filesystems. The source files must not be modified afterwards.
+        #[arg(long)]
+        link: bool,
+
+        /// Also put the added objects in this namespace, e.g. `raw` or `processed`
+        #[arg(long, value_name = "NAMESPACE")]
+        namespace: Option<String>,
+
+        /// Expire the added objects this long from now, in seconds or with a unit ("12h", "7d"),
+        /// see `rsdos optimize expire`
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        expire_after: Option<Duration>,
     },
 
     /// Optimize the storage
@@ -99,114 +422,525 @@ enum Commands {
         cmd: OptimizeCommands,
     },
 
+    /// Check pack files and rebuild the pack index from them
+    Fsck {
+        /// Only check the index against the pack file sizes and report the inconsistent entries,
+        /// nothing is rebuilt. Exits with 1 if any is found.
+        #[arg(long)]
+        index_only: bool,
+    },
+
+    /// Walk the loose store and reset the loose counters kept in the index
+    Recount,
+
     CatFile {
         #[arg(required = true)]
         id: String,
 
         /// Target store type, `loose`/`packs` to add to loose/packs.
         /// Use `auto` (default) if you don't know.
-        #[arg(short, long, default_value = "auto", value_name = "FROM")]
-        from: String,
+        #[arg(short, long, value_enum, default_value_t = StoreType::Auto, value_name = "FROM")]
+        from: StoreType,
+
+        /// Re-hash the content while writing it, fail if it does not match the id
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Look into the internals of the container, for support and debugging
+    Debug {
+        #[command(subcommand)]
+        cmd: DebugCommands,
     },
+
+    /// Print the completion script of a shell to stdout
+    Completions {
+        #[arg(required = true, value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the man page (roff) to stdout
+    Man,
 }
 
-fn extract(
-    id: &str,
-    cnt: &Container,
-    st: &StoreType,
-    mut to: impl Write,
-) -> anyhow::Result<Option<u64>> {
-    let n = match st {
-        StoreType::Loose => _extract_l(id, cnt, to)?,
-        StoreType::Packs => _extract_p(id, cnt, to)?,
-        StoreType::Auto => {
-            // first lookup in loose, if not found lookup in packed
-            _extract_l(id, cnt, &mut to)?.or_else(|| _extract_p(id, cnt, &mut to).ok()?)
+/// The clap ``Command`` of `rsdos`, what the CLI parses and what completions and the man page are
+/// generated from.
+#[must_use]
+pub fn command() -> clap::Command {
+    Args::command()
+}
+
+/// An object and the store it is in, as printed by `rsdos list`.
+#[derive(Debug, Serialize)]
+pub struct ObjectListing {
+    pub hashkey: String,
+    pub store: &'static str,
+}
+
+/// List all objects of loose and packs, an object in both stores is listed twice.
+pub fn list_objects(cnt: &Container) -> Result<Vec<ObjectListing>, Error> {
+    let mut objs = vec![];
+    for path in traverse_loose(cnt)? {
+        if let Some(hashkey) = loose_hashkey(&path?) {
+            objs.push(ObjectListing {
+                hashkey,
+                store: "loose",
+            });
         }
+    }
+
+    cnt.index()?.iterate(&mut |entry| {
+        objs.push(ObjectListing {
+            hashkey: entry.hashkey,
+            store: "packs",
+        });
+        Ok(())
+    })?;
+
+    Ok(objs)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn print_maintain_report(report: &MaintainReport, dry_run: bool, verb: &str, verb_done: &str) {
+    let action = if dry_run {
+        format!("Would {verb}")
+    } else {
+        verb_done.to_string()
     };
-    Ok(n)
+    println!(
+        "{action} {} objects ({})",
+        report.objects,
+        human_bytes(report.bytes as f64)
+    );
 }
 
-fn _extract_l(id: &str, cnt: &Container, mut to: impl Write) -> anyhow::Result<Option<u64>> {
-    let obj = crate::io_loose::extract(id, cnt)?;
-    if let Some(obj) = obj {
-        let rdr = obj.make_reader()?;
-        let mut buf_rdr = BufReader::new(rdr);
-        let n = std::io::copy(&mut buf_rdr, &mut to).with_context(|| "write object to stdout")?;
-
-        // TODO: (v2) checksum
-        anyhow::ensure!(
-            n == obj.expected_size,
-            "object has wrong size, expected: {}, got: {}, usually caused by data corruption",
-            obj.expected_size,
-            n
+#[allow(clippy::cast_precision_loss)]
+/// Token cancelled by Ctrl-C (or SIGTERM), so a long operation stops between two objects and
+/// leaves the container consistent. A second Ctrl-C exits right away.
+fn interrupt_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    let handler_token = token.clone();
+    let installed = ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("interrupting after the current object, Ctrl-C again to exit right away");
+        handler_token.cancel();
+    });
+    if let Err(err) = installed {
+        eprintln!("Ctrl-C will not stop cleanly: {err}");
+    }
+    token
+}
+
+/// Exit as killed by Ctrl-C once an interrupted operation reported what it did, ``rerun`` is the
+/// command that carries on.
+fn exit_interrupted(rerun: &str) -> ! {
+    eprintln!("interrupted, container consistent: run `{rerun}` again to finish");
+    std::process::exit(130);
+}
+
+fn print_pack_report(report: &PackReport, dry_run: bool) {
+    if !report.precompressed.is_empty() {
+        let counts: Vec<_> = report
+            .precompressed
+            .iter()
+            .map(|(algorithm, n)| format!("{n} {algorithm}"))
+            .collect();
+        let verb = if dry_run { "would be" } else { "were" };
+        println!(
+            "Already compressed objects ({}) {verb} stored as they are",
+            counts.join(", ")
         );
-        Ok(Some(n))
-    } else {
-        Ok(None)
+    }
+    if dry_run {
+        println!(
+            "Would pack {} objects ({}), {} already packed",
+            report.objects_packed,
+            human_bytes(report.bytes_read as f64),
+            report.skipped_already_packed
+        );
+        return;
+    }
+    println!(
+        "Packed {} objects ({} read, {} written) into {} new packs, {} already packed",
+        report.objects_packed,
+        human_bytes(report.bytes_read as f64),
+        human_bytes(report.bytes_written as f64),
+        report.packs_created,
+        report.skipped_already_packed
+    );
+}
+
+/// Storage details of an object, as printed by `rsdos show`.
+#[derive(Debug, Serialize)]
+pub struct ObjectInfo {
+    pub hashkey: String,
+    pub loose: Option<LooseObjectInfo>,
+    pub packs: Option<PackedObjectInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LooseObjectInfo {
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackedObjectInfo {
+    pub raw_size: u64,
+    pub stored_size: u64,
+    pub compression: String,
+    pub pack_id: u64,
+    pub offset: u64,
+}
+
+/// Look up an object in loose and packs, ``None`` if it is in neither.
+pub fn show_object(id: &str, cnt: &Container) -> Result<Option<ObjectInfo>, Error> {
+    let loose = crate::io_loose::extract(id, cnt)?.map(|obj| LooseObjectInfo {
+        size: obj.expected_size,
+    });
+
+    let packs = match cnt.index()?.select(id)? {
+        Some(entry) => {
+            let obj = PObject::from_entry(&entry, cnt.pack_path(entry.pack_id)?)
+                .with_storage(entry.pack_id, cnt.pack_storage()?);
+            Some(PackedObjectInfo {
+                raw_size: entry.raw_size,
+                stored_size: entry.size,
+                compression: obj.compression()?.to_string(),
+                pack_id: entry.pack_id,
+                offset: entry.offset,
+            })
+        }
+        None => None,
+    };
+
+    if loose.is_none() && packs.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(ObjectInfo {
+        hashkey: id.to_string(),
+        loose,
+        packs,
+    }))
+}
+
+/// Raw size of an object wherever it is stored, ``None`` if it is not in the container.
+#[cfg(any(feature = "server", feature = "fuse"))]
+pub(crate) fn object_size(id: &str, cnt: &Container) -> Result<Option<u64>, Error> {
+    if let Some(info) = show_object(id, cnt)? {
+        let size = info
+            .loose
+            .map(|loose| loose.size)
+            .or(info.packs.map(|packs| packs.raw_size));
+        return Ok(size);
+    }
+    Ok(crate::chunked::extract(id, cnt)?.map(|obj| obj.raw_size))
+}
+
+pub(crate) fn extract(
+    id: &str,
+    cnt: &Container,
+    st: &StoreType,
+    options: ExtractOptions,
+    to: impl Write,
+) -> Result<Option<u64>, Error> {
+    match cnt.lookup(id, st.lookup_order())? {
+        Some(obj) => _extract_o(&obj, options, to).map(Some),
+        // not found in loose nor in packed, lookup in chunked
+        None if *st == StoreType::Auto => _extract_c(id, cnt, options, to),
+        None => Ok(None),
     }
 }
 
-fn _extract_p(id: &str, cnt: &Container, mut to: impl Write) -> anyhow::Result<Option<u64>> {
-    let obj = crate::io_packs::extract(id, cnt)?;
+fn _extract_o(obj: &Object, options: ExtractOptions, mut to: impl Write) -> Result<u64, Error> {
+    let rdr = obj.make_reader_with(options)?;
+    let mut buf_rdr = BufReader::new(rdr);
+    let n = std::io::copy(&mut buf_rdr, &mut to)?;
+    // TODO: (v2) checksum
+    // wrong size is usually caused by data corruption
+    if n != obj.size() {
+        return Err(Error::UnexpectedCopySize {
+            expected: obj.size(),
+            got: n,
+        });
+    }
+    Ok(n)
+}
+
+fn _extract_c(
+    id: &str,
+    cnt: &Container,
+    options: ExtractOptions,
+    mut to: impl Write,
+) -> Result<Option<u64>, Error> {
+    let obj = crate::chunked::extract(id, cnt)?;
     if let Some(obj) = obj {
-        let rdr = obj.make_reader()?;
+        let rdr = if options.verify {
+            VerifyingReader::new(obj.reader(cnt), id)
+        } else {
+            VerifyingReader::unchecked(obj.reader(cnt))
+        };
         let mut buf_rdr = BufReader::new(rdr);
-        let n = std::io::copy(&mut buf_rdr, &mut to).with_context(|| "write object to stdout")?;
-        // TODO: (v2) checksum
-        anyhow::ensure!(
-            n == obj.raw_size,
-            "object has wrong size, expected: {}, got: {}, usually caused by data corruption",
-            obj.raw_size,
-            n
-        );
+        let n = std::io::copy(&mut buf_rdr, &mut to)?;
+        // wrong size is usually caused by data corruption
+        if n != obj.raw_size {
+            return Err(Error::UnexpectedCopySize {
+                expected: obj.raw_size,
+                got: n,
+            });
+        }
         Ok(Some(n))
     } else {
         Ok(None)
     }
 }
 
+/// Store an object is added to or read from, parsed from ``"auto"``, ``"loose"`` or ``"packs"``.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StoreType {
+    /// Pick the store: by size when adding, the first one having the object when reading
     Auto,
     Loose,
+    #[value(alias = "pack")]
     Packs,
 }
 
+impl StoreType {
+    /// Stores an object is looked up in when reading, see ``Container::lookup``.
+    #[must_use]
+    pub fn lookup_order(self) -> &'static [Store] {
+        match self {
+            StoreType::Auto => LOOKUP_AUTO,
+            StoreType::Loose => &[Store::Loose],
+            StoreType::Packs => &[Store::Packs],
+        }
+    }
+}
+
+impl FromStr for StoreType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(StoreType::Auto),
+            "loose" => Ok(StoreType::Loose),
+            "packs" | "pack" => Ok(StoreType::Packs),
+            _ => Err(Error::ParseStoreTypeError { s: s.to_string() }),
+        }
+    }
+}
+
+/// Smallest pack size accepted by `rsdos init`, smaller packs only add open files.
+const MIN_PACK_SIZE: ByteSize = ByteSize::mib(1);
+
+fn parse_pack_size(s: &str) -> Result<ByteSize, String> {
+    let size = ByteSize::from_str(s).map_err(|err| err.to_string())?;
+    if size < MIN_PACK_SIZE {
+        return Err(format!(
+            "pack size must be at least {} bytes (1 MiB), got {}",
+            MIN_PACK_SIZE.as_u64(),
+            size.as_u64()
+        ));
+    }
+    Ok(size)
+}
+
+/// Parse a `KEY=VALUE` config edit of `rsdos info --set`, the value is checked by
+/// ``Config::set``.
+fn parse_setting(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid setting '{s}', expect KEY=VALUE"))?;
+    let (key, value) = (key.trim(), value.trim());
+    if !SETTABLE_KEYS.contains(&key) {
+        return Err(format!(
+            "'{key}' can not be set, expect one of {}",
+            SETTABLE_KEYS.join(", ")
+        ));
+    }
+    if key == "pack_size_target" {
+        parse_pack_size(value)?;
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse the hashkey prefix of `rsdos debug dump-index --where`, hex and lowercased.
+fn parse_hash_prefix(s: &str) -> Result<String, String> {
+    let prefix = s.trim();
+    if prefix.is_empty() || prefix.len() > 64 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!(
+            "invalid hashkey prefix '{s}', expect up to 64 hex digits"
+        ));
+    }
+    Ok(prefix.to_ascii_lowercase())
+}
+
+/// Parse an age, a plain number of seconds or a number with a unit ``s``, ``m``, ``h`` or ``d``.
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let err = || format!("invalid age '{s}', expect seconds or a number with a unit like '1d'");
+    let s_trim = s.trim();
+    let split = s_trim
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(s_trim.len());
+    let (n, unit) = s_trim.split_at(split);
+    let n = n.parse::<u64>().map_err(|_| err())?;
+    let factor = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(err()),
+    };
+    n.checked_mul(factor)
+        .map(Duration::from_secs)
+        .ok_or_else(err)
+}
+
+/// Insert ``file`` to the store ``to``, to ``namespace`` if given (see
+/// ``Container::insert_to_namespace``). With ``link`` a loose object is hard-linked to the file.
 pub fn add_file(
     file: &PathBuf,
     cnt: &Container,
     to: &StoreType,
-) -> anyhow::Result<(String, String, u64)> {
+    link: bool,
+    namespace: Option<&str>,
+) -> Result<InsertResult, Error> {
     // Race here if file changes in between stat and push, the source may changed
     // in the end of add check, the size from stat and copied should be identical.
     // that is why we do streamed size check in the end.
-    let stat = fs::metadata(file).with_context(|| format!("stat {}", file.display()))?;
+    let stat = fs::metadata(file).map_err(|err| Error::IoOpen {
+        source: err,
+        path: file.clone(),
+    })?;
     let expected_size = stat.len();
 
-    let (bytes_read, _bytes_write, hash_hex) = match to {
-        StoreType::Loose | StoreType::Auto => {
-            let (b_r, hash_hex) = loose_insert(file.clone(), cnt)?;
-            (b_r, b_r, hash_hex)
-        },
-        // TODO: for single pack insert, if run twice it will insert duplicate entries
-        // Should write to sandbox first as loose and then to pack
-        StoreType::Packs => packs_insert(file.clone(), cnt)?,
+    let scope = match namespace {
+        Some(namespace) => {
+            crate::namespace::check_namespace(namespace)?;
+            Scope::Namespace(namespace)
+        }
+        None => Scope::Unscoped,
+    };
+    let store = match to {
+        StoreType::Packs => Store::Packs,
+        StoreType::Auto => cnt.auto_store(Some(expected_size))?,
+        StoreType::Loose => Store::Loose,
+    };
+    let inserted = match store {
+        Store::Loose if link => loose_insert_by_link(file, cnt, scope)?,
+        Store::Loose => loose_insert(file.clone(), cnt, scope)?,
+        Store::Packs => packs_insert(file.clone(), cnt, scope)?,
+        Store::Chunked => crate::chunked::insert_in(file.clone(), cnt, scope)?,
     };
 
-    anyhow::ensure!(
-        bytes_read == expected_size,
-        format!(
-            "bytes streamed: {}, bytes source: {}",
-            bytes_read, expected_size
-        )
-    );
+    if inserted.raw_size != expected_size {
+        return Err(Error::UnexpectedCopySize {
+            expected: expected_size,
+            got: inserted.raw_size,
+        });
+    }
+
+    Ok(inserted)
+}
 
-    Ok((hash_hex, file.display().to_string(), expected_size))
+/// How `rsdos debug dump-index` and `rsdos inspect` print the entries of the pack index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DumpFormat {
+    Table,
+    Csv,
+    Json,
 }
 
-pub fn stat(cnt: &Container) -> anyhow::Result<ContainerInfo> {
+const DUMP_COLUMNS: [&str; 6] = [
+    "hashkey",
+    "compressed",
+    "raw_size",
+    "size",
+    "offset",
+    "pack_id",
+];
+
+/// Write the entries of the pack index of ``cnt`` matching ``filter`` to ``out``, one at a time.
+fn dump_index(
+    cnt: &Container,
+    filter: &EntryFilter,
+    format: DumpFormat,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let index = cnt.index()?;
+    let row = |entry: &PackEntry, sep: &str| {
+        [
+            entry.hashkey.clone(),
+            entry.compressed.to_string(),
+            entry.raw_size.to_string(),
+            entry.size.to_string(),
+            entry.offset.to_string(),
+            entry.pack_id.to_string(),
+        ]
+        .join(sep)
+    };
+    match format {
+        DumpFormat::Table => writeln!(out, "{}", DUMP_COLUMNS.join(" | "))?,
+        DumpFormat::Csv => writeln!(out, "{}", DUMP_COLUMNS.join(","))?,
+        DumpFormat::Json => write!(out, "[")?,
+    }
+    let mut first = true;
+    index.iterate_filtered(filter, &mut |entry| {
+        match format {
+            DumpFormat::Table => writeln!(out, "{}", row(&entry, " | "))?,
+            DumpFormat::Csv => writeln!(out, "{}", row(&entry, ","))?,
+            DumpFormat::Json => {
+                let sep = if first { "\n  " } else { ",\n  " };
+                let json = serde_json::to_string(&entry).map_err(io::Error::from)?;
+                write!(out, "{sep}{json}")?;
+            }
+        }
+        first = false;
+        Ok(())
+    })?;
+    if format == DumpFormat::Json {
+        writeln!(out, "{}]", if first { "" } else { "\n" })?;
+    }
+    Ok(())
+}
+
+pub fn stat(cnt: &Container) -> Result<ContainerInfo, Error> {
     cnt.valid()?;
 
+    // traverse loose and compute number of objects and total size, split by whether they are
+    // also in packs
+    let (mut loose_files_count, mut loose_files_size, mut duplicates) = (0, 0, 0);
+    crate::maintain::for_each_loose_packed(cnt, &*cnt.index()?, |path, packed| {
+        if let Ok(stat) = fs::metadata(path) {
+            loose_files_count += 1;
+            loose_files_size += stat.len();
+            if packed {
+                duplicates += stat.len();
+            }
+        }
+        Ok(())
+    })?;
+    _stat(cnt, loose_files_count, loose_files_size, Some(duplicates))
+}
+
+/// Like ``stat`` without walking the loose store, which is what takes long on big containers:
+/// loose objects are taken from the counters kept in the index (see
+/// ``Container::loose_counters``) and packed objects from the index. Pack files are only listed.
+pub fn stat_fast(cnt: &Container) -> Result<ContainerInfo, Error> {
+    cnt.valid()?;
+
+    let (loose_files_count, loose_files_size) = cnt.loose_counters()?;
+    _stat(cnt, loose_files_count, loose_files_size, None)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn _stat(
+    cnt: &Container,
+    loose_files_count: u64,
+    loose_files_size: u64,
+    loose_duplicates: Option<u64>,
+) -> Result<ContainerInfo, Error> {
     // Read config.json
     let config_path = cnt.config_file();
     let config = fs::File::open(&config_path).map_err(|err| Error::ConfigFileError {
@@ -221,23 +955,13 @@ pub fn stat(cnt: &Container) -> anyhow::Result<ContainerInfo> {
         path: config_path.clone(),
     })?;
 
-    // traverse loose and compute number of objects and total size
-    let iter_loose = traverse_loose(cnt).with_context(|| "traverse loose by iter")?;
-    let (loose_files_count, loose_files_size) =
-        iter_loose
-            .into_iter()
-            .fold((0, 0), |(count, size), path| match fs::metadata(path) {
-                Ok(stat) => (count + 1, size + stat.len()),
-                Err(_) => (count, size),
-            });
-
     // packs info from db
     let packs_db = cnt.packs_db();
     let packs_db_size = fs::metadata(&packs_db)?.len();
-    let (packs_count, packs_size) = db::stat(&packs_db)?;
+    let packs_stats = cnt.index()?.stats()?;
 
     // traverse packs and compute
-    let iter_packs = traverse_packs(cnt).with_context(|| "traverse packs by iter")?;
+    let iter_packs = traverse_packs(cnt)?;
     let (packs_file_count, packs_file_size) =
         iter_packs
             .into_iter()
@@ -255,7 +979,7 @@ pub fn stat(cnt: &Container) -> anyhow::Result<ContainerInfo> {
             loose: loose_files_count,
             // number of pack objs
             // FIXME: rename -> pack
-            packs: packs_count,
+            packs: packs_stats.count,
             // number of pack files
             packs_file: packs_file_count,
         },
@@ -263,49 +987,184 @@ pub fn stat(cnt: &Container) -> anyhow::Result<ContainerInfo> {
             // total size of all loose objs
             loose: loose_files_size,
             // total size of all pack objs
-            packs: packs_size,
+            packs: packs_stats.raw_size,
             // total size of all pack files
             packs_file: packs_file_size,
             // size of pack index db file
             packs_db: packs_db_size,
+            packs_raw: packs_stats.raw_size,
+            packs_stored: packs_stats.size,
+            compression_ratio: if packs_stats.size == 0 {
+                1.0
+            } else {
+                packs_stats.raw_size as f64 / packs_stats.size as f64
+            },
+            loose_unique: loose_duplicates.map(|duplicates| loose_files_size - duplicates),
+            loose_duplicates,
         },
+        cache: cnt.object_cache()?.map(|cache| cache.stats()),
     })
 }
 
+/// Indexed objects of a pack file together with the size of the file and the bytes of it no
+/// indexed object covers, the space a repack would reclaim.
+#[derive(Debug, Serialize)]
+pub struct PackInfo {
+    #[serde(flatten)]
+    pub stats: PackStats,
+    /// ``None`` if the pack is not in the local ``packs`` folder (e.g. offloaded).
+    pub file_size: Option<u64>,
+    /// ``None`` if the pack is not in the local ``packs`` folder.
+    pub dead_bytes: Option<u64>,
+}
+
+/// ``PackInfo`` of every pack, indexed or in the local ``packs`` folder, ordered by ``pack_id``.
+/// A pack file no indexed object refers to is listed with no object.
+pub fn stat_per_pack(cnt: &Container) -> Result<Vec<PackInfo>, Error> {
+    let mut packs: BTreeMap<u64, PackInfo> = cnt
+        .index()?
+        .pack_stats()?
+        .into_iter()
+        .map(|stats| {
+            let info = PackInfo {
+                stats,
+                file_size: None,
+                dead_bytes: None,
+            };
+            (info.stats.pack_id, info)
+        })
+        .collect();
+    for frag in crate::maintain::fragmentation(cnt)? {
+        let info = packs.entry(frag.pack_id).or_insert_with(|| PackInfo {
+            stats: PackStats {
+                pack_id: frag.pack_id,
+                count: 0,
+                size: 0,
+                raw_size: 0,
+            },
+            file_size: None,
+            dead_bytes: None,
+        });
+        info.file_size = Some(frag.file_size);
+        info.dead_bytes = Some(frag.dead_bytes);
+    }
+    Ok(packs.into_values().collect())
+}
+
+/// Environment variable giving the container path when `--path` is not passed.
+pub const CONTAINER_ENV: &str = "RSDOS_CONTAINER";
+
+/// Container the CLI works on: ``path`` if given, else ``env`` (``$RSDOS_CONTAINER``), else (with
+/// ``discover``) the container ``cwd`` is in, else ``<cwd>/container``. Relative paths are
+/// resolved against ``cwd``.
+fn container_path(
+    path: Option<PathBuf>,
+    env: Option<OsString>,
+    cwd: &Path,
+    discover: bool,
+) -> PathBuf {
+    let path = path
+        .or_else(|| env.filter(|p| !p.is_empty()).map(PathBuf::from))
+        .or_else(|| discover.then(|| crate::container::discover(cwd)).flatten())
+        .unwrap_or_else(|| PathBuf::from("container"));
+    cwd.join(path)
+}
+
+/// Entry point of the `rsdos` binary (and of the Python `rsdos` command).
+///
+/// NOTE: this is the only function returning ``anyhow::Result``, errors are given the context of
+/// the command for printing. Library functions return ``Error`` so callers can match on them.
 #[allow(clippy::too_many_lines)]
 pub fn run_cli(args: &[OsString]) -> anyhow::Result<()> {
     let args = Args::parse_from(args);
-    // If container path provided, using it
-    // otherwise assume the `container` folder of cwd
-    let cnt_path = args.path.unwrap_or(env::current_dir()?.join("container"));
+    let cwd = env::current_dir()?;
+    // `init` creates a new container, do not look for an existing one around
+    let discover = !matches!(args.cmd, Commands::Init { .. });
+    let cnt_path = container_path(
+        args.path.clone(),
+        env::var_os(CONTAINER_ENV),
+        &cwd,
+        discover,
+    );
 
     match args.cmd {
         Commands::Init {
-            pack_size_gb,
+            pack_size,
             compression,
+            entry_header,
+            sharded_packs,
+            auto_pack_threshold_mb,
+            max_total_size_mb,
+            max_object_size_mb,
+            chunked,
+            pack_storage,
+            small_object_threshold,
+            small_pack_size,
         } => {
             // if target not exist create folder
             if !cnt_path.exists() {
                 create_dir(&cnt_path)?;
             }
 
-            let config = Config::new(pack_size_gb * 1024 * 1024 * 1024, &compression);
+            let config = Config::builder(pack_size, Compression::from_str(&compression)?)
+                .pack_entry_header(entry_header)
+                .sharded_packs(sharded_packs)
+                .auto_pack_threshold(auto_pack_threshold_mb.map(ByteSize::mib))
+                .max_total_size(max_total_size_mb.map(ByteSize::mib))
+                .max_object_size(max_object_size_mb.map(ByteSize::mib))
+                .chunked_storage(chunked)
+                .pack_storage_url(pack_storage)
+                .small_object_threshold(small_object_threshold)
+                .small_pack_size_target(small_pack_size)
+                .build()?;
             let cnt = Container::new(&cnt_path);
             cnt.initialize(&config).with_context(|| {
                 format!("unable to initialize container at {}", cnt.path.display())
             })?;
         }
         #[allow(clippy::cast_precision_loss)]
-        Commands::Status => {
+        Commands::Status {
+            per_pack,
+            fast,
+            full,
+        } => {
             let cnt = Container::new(&cnt_path);
             let cnt = match cnt.valid() {
                 Ok(cnt) => cnt,
                 Err(e) => anyhow::bail!(e),
             };
 
-            let info = crate::stat(cnt).with_context(|| "unable to get container stat")?;
+            let info = if fast {
+                stat_fast(cnt)
+            } else {
+                crate::stat(cnt)
+            }
+            .with_context(|| "unable to get container stat")?;
+            if full {
+                cnt.index()
+                    .and_then(|index| index.set_loose_counters(info.count.loose, info.size.loose))
+                    .with_context(|| "unable to reset the loose counters")?;
+            }
+            let packs = if per_pack {
+                Some(stat_per_pack(cnt).with_context(|| "unable to get pack stat")?)
+            } else {
+                None
+            };
+            if args.format == OutputFormat::Json {
+                #[derive(Serialize)]
+                struct Status {
+                    #[serde(flatten)]
+                    info: ContainerInfo,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    packs: Option<Vec<PackInfo>>,
+                }
+                let status = Status { info, packs };
+                println!("{}", serde_json::to_string_pretty(&status)?);
+                return Ok(());
+            }
+
             // print status to stdout
-            let state = String::new()
+            let mut state = String::new()
                         // container info
                         + "[container]\n"
                         + &format!("Location = {}\n", info.location)
@@ -321,17 +1180,280 @@ pub fn run_cli(args: &[OsString]) -> anyhow::Result<()> {
                         + &format!("Loose objects (raw) = {}\n", human_bytes(info.size.loose as f64))
                         + &format!("Pack objects (raw) = {}\n", human_bytes(info.size.packs as f64))
                         + &format!("Pack Files = {}\n", human_bytes(info.size.packs_file as f64))
-                        + &format!("Pack DB file = {}\n", human_bytes(info.size.packs_db as f64));
+                        + &format!("Pack DB file = {}\n", human_bytes(info.size.packs_db as f64))
+                        + &format!("Pack objects (stored) = {}\n", human_bytes(info.size.packs_stored as f64))
+                        + &format!("Compression ratio = {:.2}\n", info.size.compression_ratio);
+            if let (Some(unique), Some(duplicates)) =
+                (info.size.loose_unique, info.size.loose_duplicates)
+            {
+                state = state
+                    + &format!(
+                        "Loose objects (not packed) = {}\n",
+                        human_bytes(unique as f64)
+                    )
+                    + &format!(
+                        "Loose objects (also packed) = {}\n",
+                        human_bytes(duplicates as f64)
+                    );
+            }
+            if let Some(cache) = info.cache {
+                state = state
+                    + "\n[container.cache]\n"
+                    + &format!("Hits = {}\n", cache.hits)
+                    + &format!("Misses = {}\n", cache.misses)
+                    + &format!("Objects = {}\n", cache.objects)
+                    + &format!("Size = {}\n", human_bytes(cache.bytes as f64));
+            }
+            for PackInfo {
+                stats,
+                file_size,
+                dead_bytes,
+            } in packs.unwrap_or_default()
+            {
+                let file_size =
+                    file_size.map_or("offloaded".to_string(), |n| human_bytes(n as f64));
+                let dead_bytes =
+                    dead_bytes.map_or("offloaded".to_string(), |n| human_bytes(n as f64));
+                state = state
+                    + &format!("\n[container.packs.{}]\n", stats.pack_id)
+                    + &format!("Objects = {}\n", stats.count)
+                    + &format!("Objects (raw) = {}\n", human_bytes(stats.raw_size as f64))
+                    + &format!("Objects (stored) = {}\n", human_bytes(stats.size as f64))
+                    + &format!("File = {file_size}\n")
+                    + &format!("Dead = {dead_bytes}\n");
+            }
 
             io::stdout().write_all(state.as_bytes())?;
         }
+        Commands::List { namespace } => {
+            let cnt = Container::new(&cnt_path);
+            cnt.valid()?;
+
+            let mut objs = list_objects(&cnt)?;
+            if let Some(namespace) = namespace {
+                let hashkeys: HashSet<_> = crate::namespace::list(&cnt, &namespace)?
+                    .into_iter()
+                    .collect();
+                objs.retain(|obj| hashkeys.contains(&obj.hashkey));
+            }
+            match args.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&objs)?),
+                OutputFormat::Text => {
+                    let mut stdout = io::stdout().lock();
+                    for obj in objs {
+                        writeln!(stdout, "{} {}", obj.hashkey, obj.store)?;
+                    }
+                }
+            }
+        }
+        #[allow(clippy::cast_precision_loss)]
+        Commands::Info { config, set } => {
+            let cnt = Container::new(&cnt_path);
+            cnt.valid()?;
+            let cfg = if set.is_empty() {
+                cnt.config()?
+            } else {
+                cnt.update_config(|cfg| set.iter().try_for_each(|(key, value)| cfg.set(key, value)))
+                    .with_context(|| {
+                        format!("unable to update the config of {}", cnt.path.display())
+                    })?
+            };
+            if config {
+                println!("{}", serde_json::to_string_pretty(&cfg)?);
+            } else {
+                let caps = cfg.capabilities();
+                match args.format {
+                    OutputFormat::Json => {
+                        #[derive(Serialize)]
+                        struct Info {
+                            path: PathBuf,
+                            container_id: String,
+                            container_version: u32,
+                            zstd: bool,
+                            entry_header: bool,
+                            checksum: bool,
+                            chunked: bool,
+                            sharded: bool,
+                        }
+                        let info = Info {
+                            path: cnt.path.clone(),
+                            container_id: cfg.container_id.to_string(),
+                            container_version: cfg.container_version,
+                            zstd: caps.zstd,
+                            entry_header: caps.entry_header,
+                            checksum: caps.checksum,
+                            chunked: caps.chunked,
+                            sharded: caps.sharded,
+                        };
+                        println!("{}", serde_json::to_string_pretty(&info)?);
+                    }
+                    OutputFormat::Text => {
+                        let state = String::new()
+                            + "[container]\n"
+                            + &format!("Path = {}\n", cnt.path.display())
+                            + &format!("Id = {}\n", cfg.container_id)
+                            + &format!("Version = {}\n", cfg.container_version)
+                            + "[capabilities]\n"
+                            + &format!("Zstd = {}\n", caps.zstd)
+                            + &format!("Entry header = {}\n", caps.entry_header)
+                            + &format!("Checksum = {}\n", caps.checksum)
+                            + &format!("Chunked = {}\n", caps.chunked)
+                            + &format!("Sharded = {}", caps.sharded);
+                        println!("{state}");
+                    }
+                }
+            }
+        }
+        Commands::Namespaces => {
+            let cnt = Container::new(&cnt_path);
+            let stats = crate::namespace::stats(&cnt)?;
+            match args.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+                OutputFormat::Text => {
+                    for ns in stats {
+                        println!(
+                            "{} {} objects ({})",
+                            ns.namespace,
+                            ns.count,
+                            human_bytes(ns.size as f64)
+                        );
+                    }
+                }
+            }
+        }
+        Commands::Validate { throttle } => {
+            let cnt = Container::new(&cnt_path);
+            let throttle = throttle.map(IoThrottle::new);
+            let cancel = interrupt_token();
+            let report = crate::maintain::validate_with_progress(
+                &cnt,
+                &(),
+                throttle.as_ref(),
+                Some(&cancel),
+            )
+            .with_context(|| format!("unable to validate {}", cnt.path.display()))?;
+
+            match args.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                OutputFormat::Text => {
+                    let mut state = String::new()
+                        + "[validate]\n"
+                        + &format!("Loose objects checked = {}\n", report.loose_checked)
+                        + &format!("Pack objects checked = {}\n", report.packs_checked)
+                        + &format!("Invalid loose objects = {}\n", report.invalid_loose.len())
+                        + &format!("Invalid pack objects = {}\n", report.invalid_packs.len());
+                    for hashkey in &report.invalid_loose {
+                        state += &format!("invalid loose: {hashkey}\n");
+                    }
+                    for hashkey in &report.invalid_packs {
+                        state += &format!("invalid packs: {hashkey}\n");
+                    }
+                    io::stdout().write_all(state.as_bytes())?;
+                }
+            }
+
+            if !report.is_valid() {
+                std::process::exit(1);
+            }
+            if report.interrupted {
+                exit_interrupted("rsdos validate");
+            }
+        }
+        #[cfg(feature = "server")]
+        Commands::Serve {
+            addr,
+            read_only,
+            max_object_size,
+        } => {
+            let options = crate::server::ServeOptions {
+                read_only,
+                max_object_size: max_object_size.map(ByteSize::as_u64),
+            };
+            crate::server::serve(Container::new(&cnt_path), addr, options)?;
+        }
+        Commands::Sync { dst, dry_run } => {
+            let cnt = Container::new(&cnt_path);
+            let dst = Container::new(&dst);
+            let cancel = interrupt_token();
+            let report =
+                crate::maintain::sync(&cnt, &dst, dry_run, Some(&cancel)).with_context(|| {
+                    format!(
+                        "unable to sync {} to {}",
+                        cnt.path.display(),
+                        dst.path.display()
+                    )
+                })?;
+            print_maintain_report(&report, dry_run, "copy", "Copied");
+            if report.interrupted {
+                exit_interrupted("rsdos sync");
+            }
+        }
+        #[allow(clippy::cast_precision_loss)]
+        Commands::Backup { dest } => {
+            let cnt = Container::new(&cnt_path);
+            let report = crate::maintain::backup(&cnt, &dest)
+                .with_context(|| format!("unable to back up to {}", dest.display()))?;
+            println!(
+                "Backed up {} objects ({}) to {}",
+                report.objects,
+                human_bytes(report.bytes as f64),
+                dest.display()
+            );
+        }
+        #[cfg(feature = "fuse")]
+        Commands::Mount { mountpoint } => {
+            let cnt = Container::new(&cnt_path);
+            crate::mount::mount(&cnt, &mountpoint)
+                .with_context(|| format!("unable to mount at {}", mountpoint.display()))?;
+        }
+        Commands::Verify { id } => {
+            let cnt = Container::new(&cnt_path);
+            let Some(verification) = crate::maintain::verify(&cnt, &id)
+                .with_context(|| format!("unable to verify {id}"))?
+            else {
+                eprintln!("Error: object {id} not found");
+                std::process::exit(1);
+            };
+
+            match args.format {
+                OutputFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&verification)?);
+                }
+                OutputFormat::Text => {
+                    let status = |valid: Option<bool>| match valid {
+                        Some(true) => "ok",
+                        Some(false) => "corrupted",
+                        None => "absent",
+                    };
+                    let state = String::new()
+                        + "[verify]\n"
+                        + &format!("Hashkey = {}\n", verification.hashkey)
+                        + &format!("Loose = {}\n", status(verification.loose))
+                        + &format!("Packs = {}\n", status(verification.packs));
+                    io::stdout().write_all(state.as_bytes())?;
+                }
+            }
+
+            if !verification.is_valid() {
+                std::process::exit(1);
+            }
+        }
         #[allow(clippy::cast_precision_loss)]
-        Commands::AddFiles { paths, to } => {
+        Commands::AddFiles {
+            paths,
+            to,
+            link,
+            namespace,
+            expire_after,
+        } => {
             let cnt = Container::new(&cnt_path);
             let cnt = match cnt.valid() {
                 Ok(cnt) => cnt,
                 Err(e) => anyhow::bail!(e),
             };
+            if let Some(namespace) = &namespace {
+                crate::namespace::check_namespace(namespace)?;
+            }
 
             for path in paths {
                 if !path.is_file() {
@@ -339,52 +1461,84 @@ pub fn run_cli(args: &[OsString]) -> anyhow::Result<()> {
                     continue;
                 }
 
-                let to = match to.as_str() {
-                    "auto" => StoreType::Auto,
-                    "loose" => StoreType::Loose,
-                    "packs" => StoreType::Packs,
-                    _ => {
-                        eprintln!("unknown store '{to}', expect 'auto', 'loose' or 'packs'");
-                        std::process::exit(1);
-                    }
-                };
-                let (hash_hex, filename, expected_size) = add_file(&path, cnt, &to)?;
+                let inserted = add_file(&path, cnt, &to, link, namespace.as_deref())?;
+                if let Some(ttl) = expire_after {
+                    let at = SystemTime::now() + ttl;
+                    crate::expiry::set_expiry(cnt, &[&inserted.hashkey], at)?;
+                }
                 println!(
                     "{} - {}: {}",
-                    hash_hex,
-                    filename,
-                    human_bytes(expected_size as f64)
+                    inserted.hashkey,
+                    path.display(),
+                    human_bytes(inserted.raw_size as f64)
                 );
             }
         }
-        Commands::Inspect { storage_type } => match storage_type.as_str() {
-            "loose" => {
+        #[allow(clippy::cast_precision_loss)]
+        Commands::Show { id } => {
+            let cnt = Container::new(&cnt_path);
+            cnt.valid()?;
+
+            let Some(info) = show_object(&id, &cnt)? else {
+                eprintln!("object {id} not found");
+                std::process::exit(1)
+            };
+            if args.format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+                return Ok(());
+            }
+
+            let mut state = String::new() + "[object]\n" + &format!("Hashkey = {}\n", info.hashkey);
+            if let Some(loose) = &info.loose {
+                state = state
+                    + "\n[object.loose]\n"
+                    + &format!("Size = {}\n", human_bytes(loose.size as f64));
+            }
+            if let Some(packs) = &info.packs {
+                state = state
+                    + "\n[object.packs]\n"
+                    + &format!("Raw size = {}\n", human_bytes(packs.raw_size as f64))
+                    + &format!("Stored size = {}\n", human_bytes(packs.stored_size as f64))
+                    + &format!("Compression = {}\n", packs.compression)
+                    + &format!("Pack id = {}\n", packs.pack_id)
+                    + &format!("Offset = {}\n", packs.offset);
+            }
+            io::stdout().write_all(state.as_bytes())?;
+        }
+        Commands::Inspect { storage_type } => {
+            let cnt = Container::new(&cnt_path);
+            if matches!(storage_type, StoreType::Loose | StoreType::Auto) {
                 // traverse loose and print path (hash) with size
-                let cnt = Container::new(&cnt_path);
                 cnt.valid()?;
                 println!(" hash | size ");
                 for p in traverse_loose(&cnt).with_context(|| "traverse loose by iter")? {
+                    let p = p?;
                     let stat = fs::metadata(&p)?;
                     let size = stat.len();
 
                     println!("{} | {}", &p.display(), size);
                 }
             }
-            "pack" => {
-                let cnt = Container::new(&cnt_path);
-                let db = cnt.packs_db();
-                let _ = print_table(&db);
-            }
-            _ => {
-                eprintln!("unknown store '{storage_type}', expect 'auto', 'loose' or 'packs'");
-                std::process::exit(1);
+            if matches!(storage_type, StoreType::Packs | StoreType::Auto) {
+                dump_index(
+                    &cnt,
+                    &EntryFilter::default(),
+                    DumpFormat::Table,
+                    &mut io::stdout().lock(),
+                )
+                .with_context(|| "dump the pack index")?;
             }
-        },
+        }
+        #[allow(clippy::cast_precision_loss)]
         Commands::Optimize { cmd } => {
+            let dry_run = cmd.dry_run();
             match cmd {
                 OptimizeCommands::Pack {
                     no_compress,
                     no_clean,
+                    older_than,
+                    throttle,
+                    dry_run,
                 } => {
                     let cnt = Container::new(&cnt_path);
                     let cnt = match cnt.valid() {
@@ -399,42 +1553,283 @@ pub fn run_cli(args: &[OsString]) -> anyhow::Result<()> {
                         Compression::from_str(DEFAULT_COMPRESSION_ALGORITHM)?
                     };
 
-                    crate::maintain::_pack_loose_internal(cnt, &compression).unwrap_or_else(
-                        |err| {
-                            eprintln!("failed on pack loose {err}");
-                            std::process::exit(1);
-                        },
-                    );
+                    let throttle = throttle.map(IoThrottle::new);
+                    let cancel = interrupt_token();
+                    let report = crate::maintain::_pack_loose_internal(
+                        cnt,
+                        &compression,
+                        older_than,
+                        dry_run,
+                        &(),
+                        throttle.as_ref(),
+                        Some(&cancel),
+                    )
+                    .unwrap_or_else(|err| {
+                        eprintln!("failed on pack loose {err}");
+                        std::process::exit(1);
+                    });
+                    print_pack_report(&report, dry_run);
+                    if report.interrupted {
+                        // the objects packed so far are committed, the loose ones are still there
+                        exit_interrupted("rsdos optimize pack");
+                    }
 
-                    // TODO: clean loose that already packed
+                    // NOTE: in dry run nothing is packed, so the clean reports only the objects
+                    // that were packed before.
                     if !no_clean {
-                        todo!()
+                        let report = crate::maintain::clean_loose(cnt, dry_run)
+                            .with_context(|| "clean loose objects that are packed")?;
+                        print_maintain_report(&report, dry_run, "delete", "Deleted");
+                    }
+                }
+                OptimizeCommands::Repack {
+                    compression,
+                    recompress,
+                    strategy,
+                    throttle,
+                    dry_run,
+                } => {
+                    let cnt = Container::new(&cnt_path);
+                    let compression = match compression {
+                        Some(compression) => compression.parse::<Compression>()?,
+                        None => cnt.compression()?,
+                    };
+                    let recompress = recompress.then_some(&compression);
+                    let throttle = throttle.map(IoThrottle::new);
+                    let cancel = interrupt_token();
+                    let report = crate::maintain::repack_with(
+                        &cnt,
+                        strategy,
+                        recompress,
+                        dry_run,
+                        throttle.as_ref(),
+                        Some(&cancel),
+                    )
+                    .with_context(|| format!("unable to repack {}", cnt.path.display()))?;
+                    print_maintain_report(&report, dry_run, "rewrite", "Rewrote");
+                    if report.interrupted {
+                        exit_interrupted("rsdos optimize repack");
                     }
                 }
-                OptimizeCommands::Repack { compression } => {
-                    todo!()
+                OptimizeCommands::Prune {
+                    min_live_ratio,
+                    dry_run,
+                } => {
+                    let cnt = Container::new(&cnt_path);
+                    let report = crate::maintain::prune_packs(&cnt, min_live_ratio, dry_run)
+                        .with_context(|| format!("unable to prune {}", cnt.path.display()))?;
+                    print_maintain_report(&report, dry_run, "move", "Moved");
+                }
+                OptimizeCommands::Rebalance { dry_run } => {
+                    let cnt = Container::new(&cnt_path);
+                    let report = crate::maintain::rebalance_packs(&cnt, dry_run)
+                        .with_context(|| format!("unable to rebalance {}", cnt.path.display()))?;
+                    print_maintain_report(&report, dry_run, "move", "Moved");
+                }
+                OptimizeCommands::Offload { dry_run } => {
+                    let cnt = Container::new(&cnt_path);
+                    let report = crate::maintain::offload_packs(&cnt, dry_run)
+                        .with_context(|| format!("unable to offload {}", cnt.path.display()))?;
+                    print_maintain_report(&report, dry_run, "offload", "Offloaded");
+                }
+                OptimizeCommands::Clean { dry_run } => {
+                    let cnt = Container::new(&cnt_path);
+                    let report = crate::maintain::clean_loose(&cnt, dry_run)
+                        .with_context(|| "clean loose objects that are packed")?;
+                    print_maintain_report(&report, dry_run, "delete", "Deleted");
+                }
+                OptimizeCommands::Expire => {
+                    let cnt = Container::new(&cnt_path);
+                    let report = crate::maintain::expire(&cnt, SystemTime::now())
+                        .with_context(|| format!("unable to expire {}", cnt.path.display()))?;
+                    print_maintain_report(&report, false, "delete", "Deleted expired");
+                }
+                OptimizeCommands::GcNamespace { namespace, dry_run } => {
+                    let cnt = Container::new(&cnt_path);
+                    let report = crate::maintain::gc_namespace(&cnt, &namespace, dry_run)
+                        .with_context(|| format!("unable to delete namespace {namespace}"))?;
+                    print_maintain_report(&report, dry_run, "delete", "Deleted");
+                }
+                OptimizeCommands::TrainDictionary { samples, size } => {
+                    let cnt = Container::new(&cnt_path);
+                    let size = usize::try_from(size.as_u64())?;
+                    let trained = crate::maintain::train_dictionary(&cnt, samples, size)
+                        .with_context(|| {
+                            format!("unable to train a dictionary for {}", cnt.path.display())
+                        })?;
+                    println!(
+                        "Trained zstd dictionary {} ({} bytes) on {} objects",
+                        trained.id, trained.size, trained.samples
+                    );
+                }
+            }
+
+            // opportunistic, temp files of crashed inserts are not worth failing the optimize
+            if !dry_run {
+                let cnt = Container::new(&cnt_path);
+                match crate::maintain::clean_sandbox(&cnt, STALE_TMP_TTL) {
+                    Ok(report) if report.objects > 0 => println!(
+                        "Removed {} stale temp files ({})",
+                        report.objects,
+                        human_bytes(report.bytes as f64)
+                    ),
+                    Ok(_) => {}
+                    Err(err) => eprintln!("failed on clean sandbox {err}"),
                 }
             }
         }
-        Commands::CatFile { id, from } => {
-            let cnt = crate::Container::new(&cnt_path);
-            let from = match from.as_str() {
-                "auto" => StoreType::Auto,
-                "loose" => StoreType::Loose,
-                "packs" => StoreType::Packs,
-                _ => {
-                    eprintln!("unknown store '{from}', expect 'auto', 'loose' or 'packs'");
-                    std::process::exit(1);
+        Commands::Upgrade {
+            entry_header,
+            sharded_packs,
+        } => {
+            let cnt = Container::new(&cnt_path);
+            let from_version = crate::maintain::upgrade(&cnt, entry_header, sharded_packs)
+                .with_context(|| format!("unable to upgrade {}", cnt.path.display()))?;
+            let config = cnt.config()?;
+            let to_version = config.container_version;
+            if from_version == to_version {
+                println!("Container at version {to_version}");
+            } else {
+                println!("Container upgraded from version {from_version} to {to_version}");
+            }
+            if config.pack_entry_header {
+                println!("Pack entry headers enabled");
+            }
+            if config.sharded_packs {
+                println!("Packs sharded");
+            }
+        }
+        Commands::SetCompression {
+            compression,
+            recompress,
+        } => {
+            let cnt = Container::new(&cnt_path);
+            let report = cnt
+                .set_compression(&compression, recompress)
+                .with_context(|| {
+                    format!("unable to set the compression of {}", cnt.path.display())
+                })?;
+            println!("Compression set to {}", cnt.compression()?);
+            if let Some(report) = report {
+                print_maintain_report(&report, false, "rewrite", "Rewrote");
+            }
+        }
+        Commands::Fsck { index_only: true } => {
+            let cnt = Container::new(&cnt_path);
+            let report = crate::maintain::check_index(&cnt)
+                .with_context(|| format!("unable to check the index of {}", cnt.path.display()))?;
+
+            match args.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                OutputFormat::Text => {
+                    let mut state = String::new()
+                        + "[fsck.index]\n"
+                        + &format!("Entries checked = {}\n", report.entries_checked)
+                        + &format!("Packs checked = {}\n", report.packs_checked)
+                        + &format!("Anomalies = {}\n", report.anomalies.len());
+                    for anomaly in &report.anomalies {
+                        state += &match anomaly {
+                            IndexAnomaly::MissingPack { hashkey, pack_id } => {
+                                format!("missing pack: {hashkey} in pack {pack_id}\n")
+                            }
+                            IndexAnomaly::OutOfRange {
+                                hashkey,
+                                pack_id,
+                                offset,
+                                length,
+                                pack_size,
+                            } => format!(
+                                "out of range: {hashkey} at {offset}+{length} of pack {pack_id} ({pack_size} bytes)\n"
+                            ),
+                            IndexAnomaly::Overlap {
+                                hashkey,
+                                other,
+                                pack_id,
+                                offset,
+                                other_end,
+                            } => format!(
+                                "overlap: {hashkey} at {offset} of pack {pack_id}, {other} ends at {other_end}\n"
+                            ),
+                        };
+                    }
+                    io::stdout().write_all(state.as_bytes())?;
                 }
-            };
+            }
+
+            if !report.is_consistent() {
+                std::process::exit(1);
+            }
+        }
+        #[allow(clippy::cast_precision_loss)]
+        Commands::Fsck { index_only: false } => {
+            let cnt = Container::new(&cnt_path);
+            let report = crate::maintain::rebuild_index(&cnt)
+                .with_context(|| format!("unable to rebuild index of {}", cnt.path.display()))?;
+
+            let state = String::new()
+                + "[fsck]\n"
+                + &format!("Index readable = {}\n", report.index_readable)
+                + &format!("Entries kept = {}\n", report.entries_kept)
+                + &format!("Entries dropped = {}\n", report.entries_dropped)
+                + &format!("Entries recovered = {}\n", report.entries_recovered)
+                + &format!("Rows copied = {}\n", report.rows_copied)
+                + &format!(
+                    "Unreferenced pack bytes = {}\n",
+                    human_bytes(report.unreferenced_bytes as f64)
+                );
+            io::stdout().write_all(state.as_bytes())?;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        Commands::Recount => {
+            let cnt = Container::new(&cnt_path);
+            let report = crate::maintain::recount(&cnt)
+                .with_context(|| format!("unable to recount loose of {}", cnt.path.display()))?;
+            println!(
+                "Counted {} loose objects ({})",
+                report.objects,
+                human_bytes(report.bytes as f64)
+            );
+        }
+        Commands::CatFile { id, from, verify } => {
+            let cnt = crate::Container::new(&cnt_path);
             let mut to = std::io::stdout();
-            let n = extract(&id, &cnt, &from, &mut to)?;
+            let n = extract(&id, &cnt, &from, ExtractOptions { verify }, &mut to)?;
 
             if n.is_none() {
                 eprintln!("object {id} not found");
                 std::process::exit(1)
             }
-        } // TODO: validate/backup subcommands
+        }
+        Commands::Debug {
+            cmd:
+                DebugCommands::DumpIndex {
+                    pack_id,
+                    limit,
+                    hash_prefix,
+                    csv,
+                },
+        } => {
+            let cnt = Container::new(&cnt_path);
+            cnt.valid()?;
+            let format = match (csv, args.format) {
+                (true, _) => DumpFormat::Csv,
+                (false, OutputFormat::Json) => DumpFormat::Json,
+                (false, OutputFormat::Text) => DumpFormat::Table,
+            };
+            let filter = EntryFilter {
+                pack_id,
+                hash_prefix,
+                limit,
+            };
+            dump_index(&cnt, &filter, format, &mut io::stdout().lock())
+                .with_context(|| format!("unable to dump the index of {}", cnt.path.display()))?;
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut command(), "rsdos", &mut io::stdout());
+        }
+        Commands::Man => {
+            clap_mangen::Man::new(command()).render(&mut io::stdout())?;
+        }
     };
 
     Ok(())
@@ -452,6 +1847,335 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn parse_store_type() {
+        assert_eq!("auto".parse::<StoreType>().unwrap(), StoreType::Auto);
+        assert_eq!(" loose".parse::<StoreType>().unwrap(), StoreType::Loose);
+        assert_eq!("pack".parse::<StoreType>().unwrap(), StoreType::Packs);
+        assert!(matches!(
+            "packed".parse::<StoreType>(),
+            Err(Error::ParseStoreTypeError { .. })
+        ));
+
+        // clap parses the same names
+        let args = Args::try_parse_from(["rsdos", "cat-file", "abc", "--from", "packs"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::CatFile {
+                from: StoreType::Packs,
+                ..
+            }
+        ));
+        assert!(Args::try_parse_from(["rsdos", "add-files", "f", "--to", "packed"]).is_err());
+    }
+
+    #[test]
+    fn parse_info_set() {
+        let args = Args::try_parse_from([
+            "rsdos",
+            "info",
+            "--config",
+            "--set",
+            "compression_algorithm=zstd:3",
+            "--set",
+            "pack_size_target = 1GiB",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Info { config: true, ref set } if set == &[
+                ("compression_algorithm".to_string(), "zstd:3".to_string()),
+                ("pack_size_target".to_string(), "1GiB".to_string()),
+            ]
+        ));
+
+        for setting in [
+            "compression_algorithm",
+            "hash_type=sha1",
+            "pack_size_target=1KiB",
+        ] {
+            assert!(
+                Args::try_parse_from(["rsdos", "info", "--config", "--set", setting]).is_err(),
+                "{setting} should not parse"
+            );
+        }
+        // edits are only made with --config
+        assert!(Args::try_parse_from(["rsdos", "info", "--set", "bloom_filter=false"]).is_err());
+    }
+
+    #[test]
+    fn cli_dump_index() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let a = io_packs::insert(b"a".to_vec(), &cnt).unwrap().hashkey;
+        io_packs::insert(b"b".to_vec(), &cnt).unwrap();
+
+        let dump = |filter: &EntryFilter, format| {
+            let mut out = vec![];
+            dump_index(&cnt, filter, format, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let csv = dump(&EntryFilter::default(), DumpFormat::Csv);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "hashkey,compressed,raw_size,size,offset,pack_id");
+        assert_eq!(lines[1], format!("{a},false,1,1,0,0"));
+        assert_eq!(lines.len(), 3);
+
+        let filter = EntryFilter {
+            hash_prefix: Some(a[..6].to_string()),
+            ..EntryFilter::default()
+        };
+        let json: Vec<serde_json::Value> =
+            serde_json::from_str(&dump(&filter, DumpFormat::Json)).unwrap();
+        assert_eq!(json.len(), 1);
+        assert_eq!(json[0]["hashkey"], a.as_str());
+        let filter = EntryFilter {
+            pack_id: Some(1),
+            ..EntryFilter::default()
+        };
+        let json: Vec<serde_json::Value> =
+            serde_json::from_str(&dump(&filter, DumpFormat::Json)).unwrap();
+        assert!(json.is_empty());
+
+        let args = Args::try_parse_from([
+            "rsdos",
+            "debug",
+            "dump-index",
+            "--where",
+            "ABc1",
+            "--limit",
+            "5",
+            "--csv",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Debug {
+                cmd: DebugCommands::DumpIndex { hash_prefix: Some(ref prefix), limit: Some(5), csv: true, .. }
+            } if prefix == "abc1"
+        ));
+        assert!(Args::try_parse_from(["rsdos", "debug", "dump-index", "--where", "xyz"]).is_err());
+    }
+
+    #[test]
+    fn parse_fsck_index_only() {
+        let args = Args::try_parse_from(["rsdos", "fsck"]).unwrap();
+        assert!(matches!(args.cmd, Commands::Fsck { index_only: false }));
+        let args = Args::try_parse_from(["rsdos", "fsck", "--index-only"]).unwrap();
+        assert!(matches!(args.cmd, Commands::Fsck { index_only: true }));
+    }
+
+    #[test]
+    fn parse_namespace_flags() {
+        let args = Args::try_parse_from(["rsdos", "add-files", "f", "--namespace", "raw"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::AddFiles { namespace: Some(ns), .. } if ns == "raw"
+        ));
+        let args = Args::try_parse_from(["rsdos", "list", "--namespace", "raw"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::List { namespace: Some(ns) } if ns == "raw"
+        ));
+        let args = Args::try_parse_from(["rsdos", "optimize", "gc-namespace", "raw", "--dry-run"])
+            .unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Optimize {
+                cmd: OptimizeCommands::GcNamespace { namespace, dry_run: true }
+            } if namespace == "raw"
+        ));
+    }
+
+    #[test]
+    fn parse_pack_older_than() {
+        assert_eq!(parse_age("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_age("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_age(" 1d ").unwrap(), Duration::from_secs(24 * 3600));
+        for s in ["", "d", "1.5h", "1w", "-1s"] {
+            assert!(parse_age(s).is_err(), "{s} should not parse");
+        }
+
+        let args =
+            Args::try_parse_from(["rsdos", "optimize", "pack", "--older-than", "12h"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Optimize {
+                cmd: OptimizeCommands::Pack {
+                    older_than: Some(age),
+                    ..
+                }
+            } if age == Duration::from_secs(12 * 3600)
+        ));
+    }
+
+    #[test]
+    fn parse_throttle_flag() {
+        let args = Args::try_parse_from(["rsdos", "validate", "--throttle", "50MB/s"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Validate {
+                throttle: Some(50_000_000)
+            }
+        ));
+        let args =
+            Args::try_parse_from(["rsdos", "optimize", "repack", "--throttle", "1KiB"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Commands::Optimize {
+                cmd: OptimizeCommands::Repack {
+                    throttle: Some(1024),
+                    ..
+                }
+            }
+        ));
+        assert!(Args::try_parse_from(["rsdos", "optimize", "pack", "--throttle", "fast"]).is_err());
+    }
+
+    #[test]
+    fn cli_command_generates_completions_and_man() {
+        command().debug_assert();
+
+        let mut buf = vec![];
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut command(),
+            "rsdos",
+            &mut buf,
+        );
+        assert!(String::from_utf8(buf).unwrap().contains("add-files"));
+
+        let mut buf = vec![];
+        clap_mangen::Man::new(command()).render(&mut buf).unwrap();
+        assert!(String::from_utf8(buf).unwrap().contains("rsdos"));
+    }
+
+    #[test]
+    fn parse_init_pack_size() {
+        let pack_size = |args: &[&str]| -> Result<ByteSize, clap::Error> {
+            let args = Args::try_parse_from([&["rsdos", "init"][..], args].concat())?;
+            match args.cmd {
+                Commands::Init { pack_size, .. } => Ok(pack_size),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(pack_size(&[]).unwrap(), ByteSize::gib(4));
+        assert_eq!(
+            pack_size(&["--pack-size", "512MB"]).unwrap(),
+            ByteSize(512_000_000)
+        );
+        assert_eq!(pack_size(&["-p", "1048576"]).unwrap(), ByteSize::mib(1));
+        assert!(pack_size(&["--pack-size", "100000"]).is_err());
+        assert!(pack_size(&["--pack-size", "4 parsecs"]).is_err());
+    }
+
+    #[test]
+    fn resolve_container_path() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let nested = cnt.loose();
+        let other = tempfile::tempdir().unwrap();
+
+        // --path first, relative to the working dir
+        assert_eq!(
+            container_path(Some("cnt".into()), Some("env".into()), other.path(), true),
+            other.path().join("cnt")
+        );
+        // then $RSDOS_CONTAINER
+        assert_eq!(
+            container_path(None, Some("/env".into()), &nested, true),
+            PathBuf::from("/env")
+        );
+        // then the container the working dir is in
+        assert_eq!(
+            container_path(None, Some("".into()), &nested, true),
+            cnt.path
+        );
+        // not discovered for `init`
+        assert_eq!(
+            container_path(None, None, &nested, false),
+            nested.join("container")
+        );
+        assert_eq!(
+            container_path(None, None, other.path(), true),
+            other.path().join("container")
+        );
+    }
+
+    #[test]
+    fn cli_stat_fast() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        for i in 0..10 {
+            crate::io_loose::insert(format!("test {i}").into_bytes(), &cnt).unwrap();
+        }
+        // a duplicate is not counted twice
+        crate::io_loose::insert(b"test 0".to_vec(), &cnt).unwrap();
+        io_packs::insert(b"packed".to_vec(), &cnt).unwrap();
+
+        let fast = stat_fast(&cnt).unwrap();
+        let full = stat(&cnt).unwrap();
+        assert_eq!(fast.count.loose, 10);
+        assert_eq!(fast.size.loose, 60);
+        assert_eq!(
+            (fast.count.loose, fast.size.loose, fast.count.packs),
+            (full.count.loose, full.size.loose, full.count.packs)
+        );
+
+        crate::maintain::pack_loose(&cnt).unwrap();
+        crate::maintain::clean_loose(&cnt, false).unwrap();
+        let fast = stat_fast(&cnt).unwrap();
+        assert_eq!((fast.count.loose, fast.size.loose), (0, 0));
+        assert_eq!(fast.count.packs, 11);
+    }
+
+    #[test]
+    fn cli_stat_compression_and_duplicates() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        crate::io_loose::insert(b"a".repeat(2000), &cnt).unwrap();
+        crate::io_loose::insert(b"small".to_vec(), &cnt).unwrap();
+        crate::maintain::pack_loose(&cnt).unwrap();
+        crate::io_loose::insert(b"only loose".to_vec(), &cnt).unwrap();
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.size.packs_raw, 2005);
+        assert_eq!(info.size.packs, info.size.packs_raw);
+        assert!(info.size.packs_stored < info.size.packs_raw);
+        assert!(info.size.compression_ratio > 1.0);
+        assert_eq!(info.size.loose_duplicates, Some(2005));
+        assert_eq!(info.size.loose_unique, Some(10));
+
+        // the loose store is not walked
+        let fast = stat_fast(&cnt).unwrap();
+        assert_eq!(fast.size.packs_stored, info.size.packs_stored);
+        assert_eq!(
+            (fast.size.loose_unique, fast.size.loose_duplicates),
+            (None, None)
+        );
+
+        let (_tmp_dir, empty) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        assert!((stat(&empty).unwrap().size.compression_ratio - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn cli_stat_per_pack() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let sources: Vec<_> = (0..10).map(|i| format!("test {i}").into_bytes()).collect();
+        io_packs::insert_many(sources, &cnt).unwrap();
+
+        let packs = stat_per_pack(&cnt).unwrap();
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].stats.pack_id, 0);
+        assert_eq!(packs[0].stats.count, 10);
+        assert_eq!(packs[0].stats.raw_size, 60);
+        assert!(packs[0].file_size.unwrap() >= packs[0].stats.size);
+        assert_eq!(packs[0].dead_bytes, Some(0));
+
+        // a pack file nothing refers to is all dead
+        fs::write(cnt.packs().join("7"), b"orphan").unwrap();
+        let packs = stat_per_pack(&cnt).unwrap();
+        assert_eq!(packs.len(), 2);
+        assert_eq!(packs[1].stats.count, 0);
+        assert_eq!(packs[1].dead_bytes, Some(6));
+    }
+
     #[test]
     fn cli_add_ten_diff_objs_to_loose() {
         let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
@@ -463,7 +2187,8 @@ mod tests {
             write!(tf, "test {i}").unwrap();
 
             let fp = tf.into_temp_path();
-            add_file(&fp.to_path_buf(), &cnt, &StoreType::Loose).expect("unable to add file {i}");
+            add_file(&fp.to_path_buf(), &cnt, &StoreType::Loose, false, None)
+                .expect("unable to add file {i}");
         }
 
         // status audit
@@ -485,7 +2210,7 @@ mod tests {
             write!(tf, "test x").unwrap();
 
             let fp = tf.into_temp_path();
-            let _ = add_file(&fp.to_path_buf(), &cnt, &StoreType::Loose)
+            let _ = add_file(&fp.to_path_buf(), &cnt, &StoreType::Loose, false, None)
                 .expect("unable to add file {i}");
         }
 
@@ -494,6 +2219,57 @@ mod tests {
         assert_eq!(info.count.loose, 1);
     }
 
+    #[test]
+    fn cli_add_auto_route_by_size() -> anyhow::Result<()> {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let mut config = cnt.config()?;
+        config.auto_pack_threshold = Some(16);
+        cnt.write_config(&config)?;
+
+        for content in ["small", "larger than the threshold"] {
+            let mut tf = NamedTempFile::new()?;
+            write!(tf, "{content}")?;
+            add_file(
+                &tf.into_temp_path().to_path_buf(),
+                &cnt,
+                &StoreType::Auto,
+                false,
+                None,
+            )?;
+        }
+
+        let info = stat(&cnt)?;
+        assert_eq!(info.count.loose, 1);
+        assert_eq!(info.count.packs, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cli_show_object() -> anyhow::Result<()> {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+3");
+
+        let mut tf = NamedTempFile::new()?;
+        write!(tf, "{}", "test ".repeat(200))?;
+        let fp = tf.into_temp_path();
+        let hash_hex = add_file(&fp.to_path_buf(), &cnt, &StoreType::Loose, false, None)?.hashkey;
+
+        let info = show_object(&hash_hex, &cnt)?.unwrap();
+        assert_eq!(info.loose.unwrap().size, 1000);
+        assert!(info.packs.is_none());
+
+        crate::maintain::pack_loose(&cnt)?;
+        let packs = show_object(&hash_hex, &cnt)?.unwrap().packs.unwrap();
+        assert_eq!(packs.raw_size, 1000);
+        assert!(packs.stored_size < 1000);
+        assert_eq!(packs.compression, "zstd");
+        assert_eq!((packs.pack_id, packs.offset), (0, 0));
+
+        assert!(show_object(&"0".repeat(64), &cnt)?.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn cli_add_ten_diff_objs_to_packs() -> anyhow::Result<()> {
         let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
@@ -505,8 +2281,9 @@ mod tests {
                 write!(tf, "test {i}").unwrap();
 
                 let fp = tf.into_temp_path();
-                let (hash_hex, _, _) = add_file(&fp.to_path_buf(), &cnt, &StoreType::Packs)
-                    .expect("add file to pack failed");
+                let hash_hex = add_file(&fp.to_path_buf(), &cnt, &StoreType::Packs, false, None)
+                    .expect("add file to pack failed")
+                    .hashkey;
 
                 (hash_hex, content)
             })
@@ -541,8 +2318,9 @@ mod tests {
                 write!(tf, "{content}").unwrap();
 
                 let fp = tf.into_temp_path();
-                let (hash_hex, _, _) = add_file(&fp.to_path_buf(), &cnt, &StoreType::Packs)
-                    .expect("add file to pack failed");
+                let hash_hex = add_file(&fp.to_path_buf(), &cnt, &StoreType::Packs, false, None)
+                    .expect("add file to pack failed")
+                    .hashkey;
 
                 (hash_hex, content)
             })
@@ -1,10 +1,166 @@
+use ring::digest;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
 use uuid::Uuid;
 
-const CONTAINER_VERSION: u32 = 1;
+use crate::container::Compression;
+use crate::index::IndexBackend;
+use crate::io::SNIFF_LEN;
+use crate::Error;
+
+/// Newest container format understood by this build.
+///
+/// - 1: layout of legacy disk-objectstore. Entries are compressed with zlib or zstd, ``rsdos
+///   init`` defaulted to zstd before version 2.
+/// - 2: adds optional pack entry headers (with CRC32 checksum) and optional sharded packs.
+pub const CONTAINER_VERSION: u32 = 2;
 const LOOSE_PREFIX_LEN: u32 = 2;
+const HASH_TYPE: &str = "sha256";
+/// Reader threads of a pack insert when ``Config::pack_readers`` is not set, reading is rarely
+/// what a pack insert waits for.
+pub const DEFAULT_PACK_READERS: u64 = 2;
+
+/// Keys of the config ``Config::set`` may change on an existing container. The others are fixed
+/// at init or changed by the command that rewrites what depends on them (``upgrade``,
+/// ``train-dictionary``).
+pub const SETTABLE_KEYS: &[&str] = &[
+    "compression_algorithm",
+    "pack_size_target",
+    "auto_pack_threshold",
+    "max_total_size",
+    "max_object_size",
+    "chunked_storage",
+    "bloom_filter",
+    "object_cache_size",
+    "small_object_threshold",
+    "small_pack_size_target",
+    "pack_readers",
+    "pack_compressors",
+];
+
+/// A size in bytes, so the unit of a size is never left to the call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    #[must_use]
+    pub const fn b(n: u64) -> Self {
+        ByteSize(n)
+    }
+
+    #[must_use]
+    pub const fn kib(n: u64) -> Self {
+        ByteSize(n * 1024)
+    }
+
+    #[must_use]
+    pub const fn mib(n: u64) -> Self {
+        ByteSize(n * 1024 * 1024)
+    }
+
+    #[must_use]
+    pub const fn gib(n: u64) -> Self {
+        ByteSize(n * 1024 * 1024 * 1024)
+    }
+
+    #[must_use]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// Parse a plain number of bytes or a number with a unit, ``"100000"``, ``"512MB"``, ``"4GiB"``.
+/// Units are case-insensitive, ``KB``/``MB``/``GB`` are powers of 1000 and ``KiB``/``MiB``/``GiB``
+/// powers of 1024.
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::ParseByteSizeError { s: s.to_string() };
+        let s_trim = s.trim();
+        let split = s_trim
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(s_trim.len());
+        let (n, unit) = s_trim.split_at(split);
+        let n = n.parse::<u64>().map_err(|_| err())?;
+        let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "kb" => 1000,
+            "mb" => 1000 * 1000,
+            "gb" => 1000 * 1000 * 1000,
+            "kib" => 1024,
+            "mib" => 1024 * 1024,
+            "gib" => 1024 * 1024 * 1024,
+            _ => return Err(err()),
+        };
+        n.checked_mul(factor).map(ByteSize).ok_or_else(err)
+    }
+}
+
+/// How pack entries are guessed to be worth compressing, see
+/// ``ReaderMaker::maybe_content_format``. Only contents guessed as large text are compressed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct CompressionPolicy {
+    /// Contents up to this size (bytes) are stored uncompressed, too small to gain from it. For
+    /// the default of 850 bytes see:
+    /// https://developer.att.com/video-optimizer/docs/best-practices/text-file-compression
+    pub min_compress_size: u64,
+    /// Hex of the leading bytes of contents already compressed (default zlib and zstd), these are
+    /// stored as they are.
+    pub skip_magic_prefixes: Vec<String>,
+    /// Extensions (without the dot, case-insensitive) of the files always compressed whatever
+    /// their size and content.
+    pub force_compress_extensions: Vec<String>,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        CompressionPolicy {
+            min_compress_size: 850,
+            skip_magic_prefixes: vec!["78".to_string(), "28b52ffd".to_string()],
+            force_compress_extensions: vec![],
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Whether ``head``, the first bytes of a content, starts with one of the
+    /// ``skip_magic_prefixes``. Prefixes that are not valid hex never match.
+    #[must_use]
+    pub fn skips_magic(&self, head: &[u8]) -> bool {
+        self.skip_magic_prefixes
+            .iter()
+            .filter_map(|prefix| hex::decode(prefix).ok())
+            .any(|prefix| !prefix.is_empty() && head.starts_with(&prefix))
+    }
+
+    /// Whether a file with ``extension`` is always compressed.
+    #[must_use]
+    pub fn forces_extension(&self, extension: &str) -> bool {
+        self.force_compress_extensions
+            .iter()
+            .any(|ext| ext.trim_start_matches('.').eq_ignore_ascii_case(extension))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for prefix in &self.skip_magic_prefixes {
+            match hex::decode(prefix) {
+                Ok(bytes) if !bytes.is_empty() && bytes.len() <= SNIFF_LEN => {}
+                Ok(_) => {
+                    return Err(format!(
+                        "magic prefix '{prefix}' must be 1 to {SNIFF_LEN} bytes"
+                    ))
+                }
+                Err(_) => return Err(format!("magic prefix '{prefix}' is not hex")),
+            }
+        }
+        Ok(())
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     pub container_id: Uuid,
     pub container_version: u32,
@@ -12,19 +168,596 @@ pub struct Config {
     pub pack_size_target: u64, // bytes
     pub hash_type: String,
     pub compression_algorithm: String,
+    /// Write a header (hash, sizes, CRC32) in front of every pack entry, so the pack index can be
+    /// rebuilt from the pack files alone. Containers created before this option default to off.
+    #[serde(default)]
+    pub pack_entry_header: bool,
+    /// Objects larger than this (bytes) added with the ``auto`` store go straight to packs instead
+    /// of loose, so huge files are not written twice. ``None`` keeps all of them in loose.
+    #[serde(default)]
+    pub auto_pack_threshold: Option<u64>,
+    /// Largest size (bytes) of the files the container may take on disk, loose objects and pack
+    /// files together. ``None`` for no limit.
+    #[serde(default)]
+    pub max_total_size: Option<u64>,
+    /// Largest raw size (bytes) of a single object. ``None`` for no limit.
+    #[serde(default)]
+    pub max_object_size: Option<u64>,
+    /// Store objects added with the ``auto`` store in content-defined chunks plus a manifest, so
+    /// near-identical large files share their common chunks. Off stores every object plain.
+    #[serde(default)]
+    pub chunked_storage: bool,
+    /// Where sealed packs are offloaded to, see ``pack_storage::open`` for the supported URLs.
+    /// ``None`` keeps all packs in the local ``packs`` folder.
+    #[serde(default)]
+    pub pack_storage_url: Option<String>,
+    /// Keep a bloom filter of the indexed hashkeys in memory, so existence checks and the dedup
+    /// of pack inserts skip the index query for most missing objects.
+    #[serde(default = "default_bloom_filter")]
+    pub bloom_filter: bool,
+    /// Bytes of recently read small packed objects kept decompressed in memory, so repeated
+    /// reads skip decompression. ``None`` disables the cache.
+    #[serde(default)]
+    pub object_cache_size: Option<u64>,
+    /// Directory the temporary files of inserts are written to before they are moved into the
+    /// container, e.g. on a tmpfs. A relative path is taken from the container folder. ``None``
+    /// uses the ``sandbox`` folder of the container.
+    #[serde(default)]
+    pub sandbox_dir: Option<PathBuf>,
+    /// Objects smaller than this (bytes) are written uncompressed to packs of their own, the
+    /// small-object tier, instead of the regular packs. ``None`` writes all objects to the
+    /// regular packs.
+    #[serde(default)]
+    pub small_object_threshold: Option<u64>,
+    /// Size (bytes) the packs of the small-object tier are rolled over at. ``None`` uses
+    /// ``pack_size_target``.
+    #[serde(default)]
+    pub small_pack_size_target: Option<u64>,
+    /// Id of the zstd dictionary pack entries are compressed with from now on, see
+    /// ``maintain::train_dictionary``. ``None`` compresses without dictionary.
+    #[serde(default)]
+    pub zstd_dictionary: Option<u32>,
+    /// When pack entries are compressed, the defaults skip small and already compressed contents.
+    #[serde(default)]
+    pub compression_policy: CompressionPolicy,
+    /// Store of the pack index, containers created before this option use SQLite.
+    #[serde(default)]
+    pub index_backend: IndexBackend,
+    /// Threads reading the sources of a pack insert ahead of the pack writer. ``None`` uses
+    /// ``DEFAULT_PACK_READERS``.
+    #[serde(default)]
+    pub pack_readers: Option<u64>,
+    /// Threads compressing the sources of a pack insert, see ``io_packs::insert_many``. ``None``
+    /// uses one per core, ``0`` reads, compresses and writes every source in turn on the
+    /// inserting thread.
+    #[serde(default)]
+    pub pack_compressors: Option<u64>,
+    /// Keep the pack files in shards of ``container::PACKS_PER_SHARD`` packs, see
+    /// ``container::PackLayout``. Containers created before this option keep them flat.
+    #[serde(default)]
+    pub sharded_packs: bool,
+    /// Bumped by every ``Container::update_config``, tells whether the config changed since it was
+    /// read.
+    #[serde(default)]
+    pub revision: u64,
+}
+
+fn default_bloom_filter() -> bool {
+    true
+}
+
+/// Features a container may use, negotiated from its ``container_version`` and config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub zstd: bool,
+    pub entry_header: bool,
+    pub checksum: bool,
+    pub chunked: bool,
+    pub sharded: bool,
+}
+
+/// Digest algorithm of the ``hash_type`` of a config, ``Error::InvalidConfig`` if it is not
+/// supported.
+pub fn hash_algorithm(hash_type: &str) -> Result<&'static digest::Algorithm, Error> {
+    match hash_type {
+        HASH_TYPE => Ok(&digest::SHA256),
+        other => Err(Error::InvalidConfig {
+            field: "hash_type".to_string(),
+            cause: format!("only '{HASH_TYPE}' is supported, got '{other}'"),
+        }),
+    }
 }
 
 impl Config {
+    /// ``pack_size_target`` is in bytes and ``compression`` is not checked, use ``Config::builder``
+    /// for a validated config.
     #[must_use]
     pub fn new(pack_size_target: u64, compression: &str) -> Self {
-        // TODO: use parse-size crate to parse size_target that accept GiB, MiB ...
         Config {
             container_id: uuid::Uuid::new_v4(),
             container_version: CONTAINER_VERSION,
             loose_prefix_len: LOOSE_PREFIX_LEN,
             pack_size_target,
-            hash_type: "sha256".to_string(),
+            hash_type: HASH_TYPE.to_string(),
             compression_algorithm: compression.to_string(),
+            pack_entry_header: false,
+            auto_pack_threshold: None,
+            max_total_size: None,
+            max_object_size: None,
+            chunked_storage: false,
+            pack_storage_url: None,
+            bloom_filter: true,
+            object_cache_size: None,
+            sandbox_dir: None,
+            small_object_threshold: None,
+            small_pack_size_target: None,
+            zstd_dictionary: None,
+            compression_policy: CompressionPolicy::default(),
+            index_backend: IndexBackend::default(),
+            pack_readers: None,
+            pack_compressors: None,
+            sharded_packs: false,
+            revision: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn builder(pack_size_target: ByteSize, compression: Compression) -> ConfigBuilder {
+        ConfigBuilder {
+            config: Config::new(pack_size_target.as_u64(), &compression.to_string()),
+        }
+    }
+
+    /// Check the values this build can not work with, returns ``Error::InvalidConfig`` naming the
+    /// first invalid field.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |field: &str, cause: String| Error::InvalidConfig {
+            field: field.to_string(),
+            cause,
+        };
+        self.check_version()?;
+        // loose objects are always stored under ``<hash[..2]>/<hash[2..]>``
+        if self.loose_prefix_len != LOOSE_PREFIX_LEN {
+            return Err(invalid(
+                "loose_prefix_len",
+                format!(
+                    "only {LOOSE_PREFIX_LEN} is supported, got {}",
+                    self.loose_prefix_len
+                ),
+            ));
+        }
+        self.hash_algorithm()?;
+        // a version 1 build would look for the packs flat in ``packs``
+        if self.sharded_packs && self.container_version < 2 {
+            return Err(Error::FeatureNotSupported {
+                feature: "sharded packs".to_string(),
+                required: 2,
+                found: self.container_version,
+            });
         }
+        if self.pack_size_target == 0 {
+            return Err(invalid("pack_size_target", "must be positive".to_string()));
+        }
+        if self.pack_readers == Some(0) {
+            return Err(invalid("pack_readers", "must be positive".to_string()));
+        }
+        if self.small_pack_size_target == Some(0) {
+            return Err(invalid(
+                "small_pack_size_target",
+                "must be positive".to_string(),
+            ));
+        }
+        self.compression_policy
+            .validate()
+            .map_err(|cause| invalid("compression_policy", cause))?;
+        match self.compression_algorithm.parse::<Compression>()? {
+            Compression::Zlib(level) if level > 9 => Err(invalid(
+                "compression_algorithm",
+                format!("zlib level must be in 0..=9, got {level}"),
+            )),
+            Compression::Zstd(level) if level > 22 => Err(invalid(
+                "compression_algorithm",
+                format!("zstd level must be at most 22, got {level}"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether an object of ``size`` bytes added with the ``auto`` store goes to packs.
+    #[must_use]
+    pub fn auto_to_packs(&self, size: u64) -> bool {
+        self.auto_pack_threshold
+            .is_some_and(|threshold| size > threshold)
+    }
+
+    /// Check an object of ``object_size`` raw bytes against the quotas, ``total_size`` is what the
+    /// container takes on disk once the object is stored. Returns ``Error::QuotaExceeded`` naming
+    /// the first quota the object goes over.
+    pub fn check_quota(&self, object_size: u64, total_size: u64) -> Result<(), Error> {
+        let exceeded = |quota: &str, limit: u64, requested: u64| Error::QuotaExceeded {
+            quota: quota.to_string(),
+            limit,
+            requested,
+        };
+        match (self.max_object_size, self.max_total_size) {
+            (Some(limit), _) if object_size > limit => {
+                Err(exceeded("max_object_size", limit, object_size))
+            }
+            (_, Some(limit)) if total_size > limit => {
+                Err(exceeded("max_total_size", limit, total_size))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Digest algorithm of ``hash_type``, the hashkeys of the container are computed with it.
+    pub fn hash_algorithm(&self) -> Result<&'static digest::Algorithm, Error> {
+        hash_algorithm(&self.hash_type)
+    }
+
+    /// ``Error::UnsupportedContainerVersion`` unless this build understands ``container_version``,
+    /// from 1 (legacy disk-objectstore) to ``CONTAINER_VERSION``. A container of another version
+    /// may be laid out differently, writing to it could corrupt it.
+    pub fn check_version(&self) -> Result<(), Error> {
+        if (1..=CONTAINER_VERSION).contains(&self.container_version) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedContainerVersion {
+                found: self.container_version,
+                supported: CONTAINER_VERSION,
+            })
+        }
+    }
+
+    /// Set ``key`` (one of ``SETTABLE_KEYS``) from its text ``value``, a size is bytes or with a
+    /// unit (``"4GiB"``) and an optional size or limit is ``none`` to unset it. Returns
+    /// ``Error::InvalidConfig`` for another key or a value that does not parse, the config is
+    /// then unchanged. ``validate`` the config once all the keys are set, e.g. through
+    /// ``Container::update_config``.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let invalid = |cause: String| Error::InvalidConfig {
+            field: key.to_string(),
+            cause,
+        };
+        let size = |value: &str| {
+            value
+                .parse::<ByteSize>()
+                .map(ByteSize::as_u64)
+                .map_err(|err| invalid(err.to_string()))
+        };
+        let optional_size = |value: &str| match value {
+            "none" => Ok(None),
+            value => size(value).map(Some),
+        };
+        let optional_count = |value: &str| match value {
+            "none" => Ok(None),
+            value => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| invalid(format!("expect a number or 'none', got '{value}'"))),
+        };
+        let flag = |value: &str| {
+            value
+                .parse::<bool>()
+                .map_err(|_| invalid(format!("expect 'true' or 'false', got '{value}'")))
+        };
+        match key {
+            "compression_algorithm" => {
+                self.compression_algorithm = value.parse::<Compression>()?.to_string();
+            }
+            "pack_size_target" => self.pack_size_target = size(value)?,
+            "auto_pack_threshold" => self.auto_pack_threshold = optional_size(value)?,
+            "max_total_size" => self.max_total_size = optional_size(value)?,
+            "max_object_size" => self.max_object_size = optional_size(value)?,
+            "chunked_storage" => self.chunked_storage = flag(value)?,
+            "bloom_filter" => self.bloom_filter = flag(value)?,
+            "object_cache_size" => self.object_cache_size = optional_size(value)?,
+            "small_object_threshold" => self.small_object_threshold = optional_size(value)?,
+            "small_pack_size_target" => self.small_pack_size_target = optional_size(value)?,
+            "pack_readers" => self.pack_readers = optional_count(value)?,
+            "pack_compressors" => self.pack_compressors = optional_count(value)?,
+            _ => {
+                return Err(invalid(format!(
+                    "can not be set, settable keys are {}",
+                    SETTABLE_KEYS.join(", ")
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Reader and compression threads of a pack insert, ``0`` compression threads to insert on the
+    /// calling thread alone.
+    #[must_use]
+    pub fn pack_workers(&self) -> (usize, usize) {
+        let readers = self.pack_readers.unwrap_or(DEFAULT_PACK_READERS);
+        let compressors = match self.pack_compressors {
+            Some(compressors) => compressors,
+            None => std::thread::available_parallelism().map_or(1, |n| n.get() as u64),
+        };
+        (
+            usize::try_from(readers).unwrap_or(usize::MAX),
+            usize::try_from(compressors).unwrap_or(usize::MAX),
+        )
+    }
+
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        let v2 = self.container_version >= 2;
+        Capabilities {
+            // version 1 containers made by ``rsdos init`` already hold zstd entries
+            zstd: true,
+            entry_header: v2 && self.pack_entry_header,
+            // the CRC32 checksum is carried by the entry header
+            checksum: v2 && self.pack_entry_header,
+            chunked: v2 && self.chunked_storage,
+            sharded: v2 && self.sharded_packs,
+        }
+    }
+}
+
+/// Build a ``Config`` from typed values, ``build`` validates it.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    #[must_use]
+    pub fn loose_prefix_len(mut self, len: u32) -> Self {
+        self.config.loose_prefix_len = len;
+        self
+    }
+
+    #[must_use]
+    pub fn hash_type(mut self, hash_type: &str) -> Self {
+        self.config.hash_type = hash_type.to_string();
+        self
+    }
+
+    #[must_use]
+    pub fn pack_entry_header(mut self, on: bool) -> Self {
+        self.config.pack_entry_header = on;
+        self
+    }
+
+    #[must_use]
+    pub fn sharded_packs(mut self, on: bool) -> Self {
+        self.config.sharded_packs = on;
+        self
+    }
+
+    #[must_use]
+    pub fn auto_pack_threshold(mut self, threshold: Option<ByteSize>) -> Self {
+        self.config.auto_pack_threshold = threshold.map(ByteSize::as_u64);
+        self
+    }
+
+    #[must_use]
+    pub fn max_total_size(mut self, size: Option<ByteSize>) -> Self {
+        self.config.max_total_size = size.map(ByteSize::as_u64);
+        self
+    }
+
+    #[must_use]
+    pub fn max_object_size(mut self, size: Option<ByteSize>) -> Self {
+        self.config.max_object_size = size.map(ByteSize::as_u64);
+        self
+    }
+
+    #[must_use]
+    pub fn chunked_storage(mut self, on: bool) -> Self {
+        self.config.chunked_storage = on;
+        self
+    }
+
+    #[must_use]
+    pub fn pack_storage_url(mut self, url: Option<String>) -> Self {
+        self.config.pack_storage_url = url;
+        self
+    }
+
+    #[must_use]
+    pub fn bloom_filter(mut self, on: bool) -> Self {
+        self.config.bloom_filter = on;
+        self
+    }
+
+    #[must_use]
+    pub fn object_cache_size(mut self, size: Option<ByteSize>) -> Self {
+        self.config.object_cache_size = size.map(ByteSize::as_u64);
+        self
+    }
+
+    #[must_use]
+    pub fn sandbox_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.config.sandbox_dir = dir;
+        self
+    }
+
+    #[must_use]
+    pub fn small_object_threshold(mut self, threshold: Option<ByteSize>) -> Self {
+        self.config.small_object_threshold = threshold.map(ByteSize::as_u64);
+        self
+    }
+
+    #[must_use]
+    pub fn small_pack_size_target(mut self, size: Option<ByteSize>) -> Self {
+        self.config.small_pack_size_target = size.map(ByteSize::as_u64);
+        self
+    }
+
+    #[must_use]
+    pub fn compression_policy(mut self, policy: CompressionPolicy) -> Self {
+        self.config.compression_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<Config, Error> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_byte_size() {
+        assert_eq!("100000".parse::<ByteSize>().unwrap(), ByteSize(100_000));
+        assert_eq!("512MB".parse::<ByteSize>().unwrap(), ByteSize(512_000_000));
+        assert_eq!("4GiB".parse::<ByteSize>().unwrap(), ByteSize::gib(4));
+        assert_eq!(" 16 kib ".parse::<ByteSize>().unwrap(), ByteSize::kib(16));
+        assert_eq!("1b".parse::<ByteSize>().unwrap(), ByteSize(1));
+
+        for s in ["", "GiB", "4.5GiB", "-1", "4TiB", "99999999999GiB"] {
+            assert!(
+                matches!(s.parse::<ByteSize>(), Err(Error::ParseByteSizeError { .. })),
+                "{s} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn config_builder() {
+        let config = Config::builder(ByteSize::mib(4), Compression::Zstd(-1))
+            .pack_entry_header(true)
+            .max_object_size(Some(ByteSize::kib(1)))
+            .build()
+            .unwrap();
+        assert_eq!(config.pack_size_target, 4 * 1024 * 1024);
+        assert_eq!(config.compression_algorithm, "zstd:-1");
+        assert_eq!(config.max_object_size, Some(1024));
+        assert!(config.pack_entry_header);
+        assert_eq!(config.loose_prefix_len, LOOSE_PREFIX_LEN);
+        assert_eq!(config.hash_type, HASH_TYPE);
+    }
+
+    #[test]
+    fn config_builder_invalid() {
+        let builder = Config::builder(ByteSize::mib(4), Compression::Uncompressed);
+
+        let err = builder.clone().loose_prefix_len(3).build().unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidConfig { ref field, .. } if field == "loose_prefix_len")
+        );
+
+        let err = builder.clone().hash_type("md5").build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { ref field, .. } if field == "hash_type"));
+
+        let err = Config::builder(ByteSize::b(0), Compression::Uncompressed)
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidConfig { ref field, .. } if field == "pack_size_target")
+        );
+
+        let err = Config::builder(ByteSize::mib(4), Compression::Zlib(10))
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidConfig { ref field, .. } if field == "compression_algorithm")
+        );
+
+        let err = builder
+            .clone()
+            .compression_policy(CompressionPolicy {
+                skip_magic_prefixes: vec!["zz".to_string()],
+                ..CompressionPolicy::default()
+            })
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidConfig { ref field, .. } if field == "compression_policy")
+        );
+
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn config_set() {
+        let mut config = Config::builder(ByteSize::mib(4), Compression::Uncompressed)
+            .build()
+            .unwrap();
+        config.set("compression_algorithm", "zstd:3").unwrap();
+        config.set("pack_size_target", "1GiB").unwrap();
+        config.set("max_object_size", "10MB").unwrap();
+        config.set("bloom_filter", "false").unwrap();
+        config.set("pack_compressors", "0").unwrap();
+        assert_eq!(config.compression_algorithm, "zstd:+3");
+        assert_eq!(config.pack_size_target, ByteSize::gib(1).as_u64());
+        assert_eq!(config.max_object_size, Some(10_000_000));
+        assert!(!config.bloom_filter);
+        assert_eq!(config.pack_compressors, Some(0));
+        config.set("max_object_size", "none").unwrap();
+        assert_eq!(config.max_object_size, None);
+
+        for (key, value) in [
+            ("hash_type", "sha1"),
+            ("container_version", "3"),
+            ("pack_size_targt", "1GiB"),
+            ("pack_size_target", "big"),
+            ("bloom_filter", "yes"),
+            ("pack_readers", "4GiB"),
+        ] {
+            let err = config.set(key, value).unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidConfig { ref field, .. } if field == key),
+                "{key}={value}: {err}"
+            );
+        }
+        assert!(config.set("compression_algorithm", "lz4").is_err());
+
+        config.container_version = 1;
+        config.set("compression_algorithm", "zstd:1").unwrap();
+        assert_eq!(config.compression_algorithm, "zstd:+1");
+    }
+
+    #[test]
+    fn config_serde_roundtrip() {
+        let config = Config::builder(ByteSize::gib(4), Compression::Zlib(1))
+            .auto_pack_threshold(Some(ByteSize::mib(100)))
+            .pack_storage_url(Some("s3://bucket/packs".to_string()))
+            .object_cache_size(Some(ByteSize::mib(64)))
+            .small_object_threshold(Some(ByteSize::kib(4)))
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&config).unwrap();
+        let back: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, config);
+        back.validate().unwrap();
+    }
+
+    #[test]
+    fn config_serde_defaults() {
+        // a config written before the optional fields existed
+        let json = r#"{
+            "container_id": "6f9cc4a4-7c3b-4e4e-a8a4-6a5b0b2f6f0d",
+            "container_version": 1,
+            "loose_prefix_len": 2,
+            "pack_size_target": 4294967296,
+            "hash_type": "sha256",
+            "compression_algorithm": "zlib+1"
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(!config.pack_entry_header);
+        assert!(config.bloom_filter);
+        assert_eq!(config.auto_pack_threshold, None);
+        assert_eq!(config.compression_policy, CompressionPolicy::default());
+        assert_eq!(config.revision, 0);
+        assert!(!config.sharded_packs);
+        config.validate().unwrap();
+
+        // version 1 builds do not find sharded packs
+        let mut config = config;
+        config.sharded_packs = true;
+        assert!(matches!(
+            config.validate(),
+            Err(Error::FeatureNotSupported { .. })
+        ));
+        config.container_version = 2;
+        config.validate().unwrap();
+        assert!(config.capabilities().sharded);
     }
 }
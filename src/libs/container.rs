@@ -1,53 +1,165 @@
-use anyhow::Context;
-use serde_json::to_string_pretty;
+use serde::Serialize;
 
+use crate::bloom::BloomFilter;
+use crate::cache::{CacheStats, ObjectCache};
+use crate::config::Capabilities;
+use crate::dictionary::Dictionaries;
+use crate::index::{IndexBackend, ObjectIndex};
+use crate::io::{is_hashkey, InsertResult, Object, ReaderMaker, Store};
+use crate::io_loose::LooseWriter;
+use crate::maintain::{self, MaintainReport, RepackStrategy};
+use crate::namespace::{self, Scope};
+use crate::pack_storage::{self, PackStorage};
+use crate::utils::{create_dir, open_shared, rename_replace, Dir};
 use crate::Error;
-use crate::{config::Config, db, utils::Dir};
+use crate::{chunked, io_loose, io_packs};
+use crate::{config::Config, db};
 use core::panic;
+use fs4::fs_std::FileExt;
 use indicatif::{ProgressBar, ProgressIterator};
+use std::collections::HashMap;
+use std::fmt;
 use std::result;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 use std::{
     fs,
-    io::Write,
+    io::{self, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 
 pub const PACKS_DB: &str = "packs.idx";
+const JOURNAL: &str = "packs.journal";
 const CONFIG_FILE: &str = "config.json";
+/// Locked by ``Container::update_config``, so concurrent config updates do not overwrite each
+/// other.
+const CONFIG_LOCK: &str = "config.lock";
+/// Held by a ``MaintenanceLock`` while a maintenance operation runs.
+const MAINTENANCE_LOCK: &str = ".maintenance.lock";
 const LOOSE: &str = "loose";
 const PACKS: &str = "packs";
 const DUPLICATES: &str = "duplicates";
 const SANDBOX: &str = "sandbox";
+pub(crate) const DICTIONARIES: &str = "dictionaries";
+/// Pack files kept open by a ``Container``, all handles are dropped when more are needed.
+const MAX_OPEN_PACKS: usize = 64;
+/// First bytes of an SQLite database file, what the pack index starts with.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+/// Temporary files of the sandboxes not modified for this long are taken as left by crashed
+/// writers and removed at ``Container::open``.
+pub const STALE_TMP_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Order ``Container::get`` looks an object up in: loose, where it is written first, then packs.
+pub const LOOKUP_AUTO: &[Store] = &[Store::Loose, Store::Packs];
 
+/// A container is shared between threads as ``Arc<Container>``: all caches sit behind locks and
+/// are filled on first use, ``reload`` empties them through a shared reference.
 #[derive(Debug)]
 pub struct Container {
     pub path: PathBuf,
+    pool: db::Pool,
+    /// ``config.json`` parsed on first use, see ``Container::reload``.
+    config: Cached<Config>,
+    /// Pack storage opened from the config on first use.
+    pack_storage: Cached<Option<Arc<dyn PackStorage>>>,
+    /// Hashkeys of the index, built from it on first use, see ``Container::may_be_indexed``.
+    bloom: RwLock<Option<BloomFilter>>,
+    /// Cache of packed objects, created from the config on first use.
+    object_cache: Cached<Option<Arc<ObjectCache>>>,
+    /// Open pack files by pack id with the identity of the file they were opened from, see
+    /// ``Container::pack_file``.
+    pack_files: Mutex<HashMap<u64, (Arc<fs::File>, FileId)>>,
+    /// zstd dictionaries, loaded on first use.
+    dictionaries: Arc<Dictionaries>,
+    /// Handle of ``packs.idx`` the header is checked through, see ``Container::index_header``.
+    index_file: Mutex<Option<(fs::File, FileId)>>,
 }
 
+/// Value computed on first use and shared by all threads until it is cleared.
 #[derive(Debug)]
+struct Cached<T>(RwLock<Option<T>>);
+
+impl<T: Clone> Cached<T> {
+    fn new() -> Self {
+        Cached(RwLock::new(None))
+    }
+
+    /// The cached value, ``init`` computes it when there is none. Threads racing on an empty
+    /// cache may all run ``init``, the first value stored is kept and returned to all of them.
+    fn get_or_try_init<F>(&self, init: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        if let Some(value) = self.0.read().ok().and_then(|value| value.clone()) {
+            return Ok(value);
+        }
+
+        let value = init()?;
+        let Ok(mut cached) = self.0.write() else {
+            return Ok(value);
+        };
+        Ok(cached.get_or_insert(value).clone())
+    }
+
+    fn clear(&self) {
+        if let Ok(mut cached) = self.0.write() {
+            *cached = None;
+        }
+    }
+}
+
+/// Device and inode of a file, to tell that a pack file was replaced since it was opened.
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+#[derive(Debug, Serialize)]
 pub struct ContainerInfo {
     pub location: String,
     pub id: String,
     pub compression_algorithm: String,
     pub count: CountInfo,
     pub size: SizeInfo,
+    /// Counters of the object cache of this ``Container``, ``None`` if it has no cache.
+    pub cache: Option<CacheStats>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct CountInfo {
     pub loose: u64,
     pub packs: u64,
     pub packs_file: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct SizeInfo {
     pub loose: u64,
+    /// Raw bytes of the packed objects, the same as ``packs_raw``.
     pub packs: u64,
     pub packs_file: u64,
     pub packs_db: u64,
+    /// Bytes of the packed objects when not compressed.
+    pub packs_raw: u64,
+    /// Bytes the packed objects take in packs once compressed, without entry headers and the
+    /// bytes no object refers to.
+    pub packs_stored: u64,
+    /// ``packs_raw`` over ``packs_stored``, how many times compression shrinks the packed objects.
+    /// 1 when nothing is packed.
+    pub compression_ratio: f64,
+    /// Bytes of the loose objects not in packs. ``None`` when the loose store was not walked.
+    pub loose_unique: Option<u64>,
+    /// Bytes of the loose objects also in packs, what ``maintain::clean_loose`` frees. ``None``
+    /// when the loose store was not walked.
+    pub loose_duplicates: Option<u64>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -91,11 +203,294 @@ impl FromStr for Compression {
     }
 }
 
+impl Compression {
+    /// Name of the algorithm without its level, as ``PObject::compression`` reports it.
+    #[must_use]
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Compression::Uncompressed => "none",
+            Compression::Zlib(_) => "zlib",
+            Compression::Zstd(_) => "zstd",
+        }
+    }
+}
+
+/// Written the way it is parsed, so it round-trips through ``Config::compression_algorithm``.
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Uncompressed => write!(f, "none"),
+            Compression::Zlib(level) => write!(f, "zlib:{level:+}"),
+            Compression::Zstd(level) => write!(f, "zstd:{level:+}"),
+        }
+    }
+}
+
 impl Container {
     pub fn new<P: AsRef<Path>>(path: P) -> Container {
         Container {
             path: path.as_ref().to_owned(),
+            pool: db::Pool::default(),
+            config: Cached::new(),
+            pack_storage: Cached::new(),
+            bloom: RwLock::new(None),
+            object_cache: Cached::new(),
+            pack_files: Mutex::new(HashMap::new()),
+            dictionaries: Arc::new(Dictionaries::new(path.as_ref().join(DICTIONARIES))),
+            index_file: Mutex::new(None),
+        }
+    }
+
+    /// Open an existing container: validate its layout and detect its format version, refusing
+    /// versions newer than this build understands. Stale temporary files are removed from the
+    /// sandboxes.
+    ///
+    /// NOTE: bytes left at the end of packs by interrupted writes are truncated by the next writer,
+    /// see ``journal::recover``, not here: that takes the index write lock, which a reader (or a
+    /// read-only container) must not wait for.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Container, Error> {
+        let cnt = Container::new(path);
+        cnt.valid()?;
+        let config = cnt.config()?;
+
+        // opportunistic, a sandbox that can not be cleaned does not prevent opening
+        let _ = remove_stale_tmp(&cnt.sandbox(), STALE_TMP_TTL);
+        if config.sandbox_dir.is_some() {
+            if let Ok(dir) = cnt.insert_sandbox() {
+                let _ = remove_stale_tmp(&dir, STALE_TMP_TTL);
+            }
+        }
+        Ok(cnt)
+    }
+
+    /// Features enabled for this container, derived from its format version and config.
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        Ok(self.config()?.capabilities())
+    }
+
+    /// Connection to the pack index, taken from the container's connection pool.
+    pub fn db(&self) -> Result<db::PooledConnection<'_>, Error> {
+        self.pool.get(&self.packs_db())
+    }
+
+    /// The pack index, in the store of ``Config::index_backend``.
+    pub fn index(&self) -> Result<Box<dyn ObjectIndex + '_>, Error> {
+        match self.config()?.index_backend {
+            IndexBackend::Sqlite => Ok(Box::new(self.db()?)),
+        }
+    }
+
+    /// The pack index with its own connection instead of one borrowed from the pool, so it can be
+    /// kept by an iterator that outlives the borrow of the container.
+    pub(crate) fn owned_index(&self) -> Result<Box<dyn ObjectIndex>, Error> {
+        match self.config()?.index_backend {
+            IndexBackend::Sqlite => Ok(Box::new(db::open(&self.packs_db())?)),
+        }
+    }
+
+    /// Close the pooled index connections, needed after ``packs.idx`` is replaced.
+    pub(crate) fn close_db(&self) {
+        self.pool.clear();
+        if let Ok(mut bloom) = self.bloom.write() {
+            *bloom = None;
+        }
+        if let Ok(mut pack_files) = self.pack_files.lock() {
+            pack_files.clear();
+        }
+    }
+
+    /// Open handle of pack ``pack_id``, ``None`` if the pack file is not in ``packs`` (e.g.
+    /// offloaded). Handles are reused while the pack file is still the one they were opened
+    /// from, a pack file replaced under the same id is opened again.
+    ///
+    /// NOTE: the handle is shared, read it at an offset (``FileExt::read_at``) instead of seeking.
+    /// Handles are only reused on unix, where the file identity can be checked.
+    pub(crate) fn pack_file(&self, pack_id: u64) -> Result<Option<Arc<fs::File>>, Error> {
+        let path = self.pack_path(pack_id)?;
+        let meta = match fs::metadata(&path) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let id = file_id(&meta);
+
+        let Ok(mut pack_files) = self.pack_files.lock() else {
+            return Ok(Some(Arc::new(open_shared(&path)?)));
+        };
+        if let Some((file, _)) = pack_files
+            .get(&pack_id)
+            .filter(|(_, cached)| Some(*cached) == id)
+        {
+            return Ok(Some(file.clone()));
+        }
+
+        let file = Arc::new(open_shared(&path)?);
+        if let Some(id) = id {
+            if pack_files.len() >= MAX_OPEN_PACKS {
+                pack_files.clear();
+            }
+            pack_files.insert(pack_id, (file.clone(), id));
+        }
+        Ok(Some(file))
+    }
+
+    /// False if ``hashkey`` is certainly not in the index (packs or chunked), so the query can be
+    /// skipped. Always true with ``Config::bloom_filter`` off.
+    ///
+    /// NOTE: the filter only learns about the objects indexed through this ``Container``, objects
+    /// indexed by another process after it was built are missed until ``reload``.
+    pub(crate) fn may_be_indexed(&self, hashkey: &str) -> Result<bool, Error> {
+        if !self.config()?.bloom_filter {
+            return Ok(true);
+        }
+        if let Ok(bloom) = self.bloom.read() {
+            if let Some(bloom) = bloom.as_ref().filter(|bloom| !bloom.is_full()) {
+                return Ok(bloom.may_contain(hashkey));
+            }
+        }
+
+        // only the thread building the filter holds it exclusively
+        let Ok(mut bloom) = self.bloom.write() else {
+            return Ok(true);
+        };
+        if bloom.as_ref().map_or(true, BloomFilter::is_full) {
+            *bloom = Some(self.index()?.bloom()?);
+        }
+        Ok(bloom
+            .as_ref()
+            .map_or(true, |bloom| bloom.may_contain(hashkey)))
+    }
+
+    /// Record that ``hashkey`` was added to the index, once the filter is built.
+    pub(crate) fn note_indexed(&self, hashkey: &str) {
+        if let Ok(mut bloom) = self.bloom.write() {
+            if let Some(bloom) = bloom.as_mut() {
+                bloom.insert(hashkey);
+            }
+        }
+    }
+
+    /// Store an object of ``size`` bytes goes to when inserted with ``Container::insert``: chunked
+    /// when the container has the capability, packs when it is above
+    /// ``Config::auto_pack_threshold``, loose otherwise. An object of unknown size goes to loose.
+    pub fn auto_store(&self, size: Option<u64>) -> Result<Store, Error> {
+        let config = self.config()?;
+        let store = if config.capabilities().chunked {
+            Store::Chunked
+        } else if size.is_some_and(|size| config.auto_to_packs(size)) {
+            Store::Packs
+        } else {
+            Store::Loose
+        };
+        Ok(store)
+    }
+
+    /// Insert an object to the store picked by ``auto_store`` from its ``size_hint``, so large
+    /// objects are written to packs once instead of through loose.
+    pub fn insert<T: ReaderMaker>(&self, source: T) -> Result<InsertResult, Error> {
+        self.insert_in(source, Scope::Unscoped)
+    }
+
+    /// Insert an object to ``namespace``, to the store picked as by ``insert``. Unlike an object
+    /// inserted with ``insert`` and tagged afterwards, it is deleted by
+    /// ``maintain::gc_namespace`` of ``namespace`` unless it is also inserted otherwise.
+    pub fn insert_to_namespace<T: ReaderMaker>(
+        &self,
+        source: T,
+        namespace: &str,
+    ) -> Result<InsertResult, Error> {
+        namespace::check_namespace(namespace)?;
+        self.insert_in(source, Scope::Namespace(namespace))
+    }
+
+    fn insert_in<T: ReaderMaker>(&self, source: T, scope: Scope) -> Result<InsertResult, Error> {
+        match self.auto_store(source.size_hint())? {
+            Store::Loose => io_loose::insert_in(source, self, scope),
+            Store::Packs => io_packs::insert_in(source, self, scope),
+            Store::Chunked => chunked::insert_in(source, self, scope),
+        }
+    }
+
+    /// Writer of a new loose object, for a content produced piece by piece rather than read from
+    /// a file or a buffer. Nothing is stored until ``LooseWriter::commit``, see ``LooseWriter``.
+    pub fn loose_writer(&self) -> Result<LooseWriter<'_>, Error> {
+        LooseWriter::new(self)
+    }
+
+    /// Hashkey the content of ``reader`` would be stored under in this container, see
+    /// ``io::compute_hashkey``. Nothing is stored.
+    pub fn compute_hashkey<R: Read>(&self, reader: R) -> Result<String, Error> {
+        crate::io::compute_hashkey(reader, &self.config()?.hash_type)
+    }
+
+    /// Whether the object is in the container, in loose, packs or chunked. Most missing objects
+    /// are told apart by the bloom filter without querying the index.
+    pub fn has_object(&self, hashkey: &str) -> Result<bool, Error> {
+        if !is_hashkey(hashkey) {
+            return Ok(false);
+        }
+        let loc = self.loose().join(&hashkey[..2]).join(&hashkey[2..]);
+        if loc.exists() {
+            return Ok(true);
         }
+        if !self.may_be_indexed(hashkey)? {
+            return Ok(false);
+        }
+        let index = self.index()?;
+        Ok(index.select(hashkey)?.is_some() || index.select_chunked(hashkey)?.is_some())
+    }
+
+    /// Number and total size of the loose objects, from the counters kept in the index. The loose
+    /// store is walked (``scan_loose``) only when the index has no counters yet, e.g. for a
+    /// container created by an older rsdos.
+    ///
+    /// NOTE: the counters are updated after each loose insert and clean, they may drift when
+    /// loose files are changed by hand or when an insert is interrupted in between.
+    pub fn loose_counters(&self) -> Result<(u64, u64), Error> {
+        let index = self.index()?;
+        if let Some(counters) = index.loose_counters()? {
+            return Ok(counters);
+        }
+        let (count, size) = self.scan_loose()?;
+        index.set_loose_counters(count, size)?;
+        Ok((count, size))
+    }
+
+    /// Number and total size of the loose objects, by walking the loose store.
+    pub fn scan_loose(&self) -> Result<(u64, u64), Error> {
+        let (mut count, mut size) = (0, 0);
+        for path in traverse_loose(self)? {
+            if let Ok(stat) = fs::metadata(path?) {
+                count += 1;
+                size += stat.len();
+            }
+        }
+        Ok((count, size))
+    }
+
+    /// Look up an object in loose first and then in packs, ``None`` if it is in neither.
+    ///
+    /// NOTE: chunked objects are not returned, they are read with ``chunked::extract``.
+    pub fn get(&self, hashkey: &str) -> Result<Option<Object>, Error> {
+        self.lookup(hashkey, LOOKUP_AUTO)
+    }
+
+    /// Look up an object in ``stores`` in order, stopping at the first one having it. ``None`` if
+    /// it is in none of them.
+    ///
+    /// NOTE: ``Store::Chunked`` is skipped, chunked objects are read with ``chunked::extract``.
+    pub fn lookup(&self, hashkey: &str, stores: &[Store]) -> Result<Option<Object>, Error> {
+        for store in stores {
+            let obj = match store {
+                Store::Loose => io_loose::extract(hashkey, self)?.map(Object::from),
+                Store::Packs => io_packs::extract(hashkey, self)?.map(Object::from),
+                Store::Chunked => None,
+            };
+            if obj.is_some() {
+                return Ok(obj);
+            }
+        }
+        Ok(None)
     }
 
     pub fn compression(&self) -> Result<Compression, Error> {
@@ -103,12 +498,45 @@ impl Container {
         Compression::from_str(&algo)
     }
 
+    /// Change the compression algorithm of the pack entries written from now on, ``algo`` is
+    /// written as in ``Config::compression_algorithm`` (e.g. ``"zstd:+3"``). The config is changed
+    /// with ``update_config``, existing entries are left as they are.
+    ///
+    /// With ``recompress`` the current packs are sealed and the entries of all packs are
+    /// rewritten with the new algorithm where the compression policy picks it, see
+    /// ``maintain::repack_with``. Returns the report of that repack.
+    pub fn set_compression(
+        &self,
+        algo: &str,
+        recompress: bool,
+    ) -> Result<Option<MaintainReport>, Error> {
+        self.valid()?;
+        let compression = Compression::from_str(algo)?;
+        self.update_config(|config| config.set("compression_algorithm", algo))?;
+
+        if !recompress {
+            return Ok(None);
+        }
+        io_packs::seal_current_pack(&self.pack_layout()?)?;
+        let report = maintain::repack_with(
+            self,
+            RepackStrategy::Preserve,
+            Some(&compression),
+            false,
+            None,
+            None,
+        )?;
+        Ok(Some(report))
+    }
+
     /// This will remove everything in the container folder. Use carefully!
     ///
     /// # Panics
     ///
     /// If the `remove_dir_all` or `create_dir_all` failed it will panic.
-    pub fn reset(&self) {
+    pub fn reset(&mut self) {
+        self.close_db();
+        self.reload();
         fs::remove_dir_all(&self.path)
             .unwrap_or_else(|err| panic!("not able to purge {}: {}", self.path.display(), err));
         fs::create_dir_all(&self.path).unwrap_or_else(|err| {
@@ -120,54 +548,212 @@ impl Container {
         });
     }
 
-    pub fn initialize(&self, config: &Config) -> anyhow::Result<&Self> {
+    /// Create the layout of a new container at its path, which must be empty, with ``config``.
+    pub fn initialize(&self, config: &Config) -> Result<&Self, Error> {
         if Dir(&self.path).is_empty()? {
-            let json_string = to_string_pretty(&config)?;
-            let config = self.path.join(CONFIG_FILE);
-            fs::File::create(config.clone())?
-                .write_all(json_string.as_bytes())
-                .map_err(|err| Error::IoWrite {
-                    source: err,
-                    path: config.clone(),
-                })?;
+            // the config is written through the sandbox, so it is never seen half written
+            Dir(&self.path).new_folder(SANDBOX)?;
+            self.write_config(config)?;
 
-            // Create loose/pack/duplicates/sandbox folders
+            // Create loose/pack/duplicates folders
             Dir(&self.path).new_folder(LOOSE)?;
             Dir(&self.path).new_folder(PACKS)?;
             Dir(&self.path).new_folder(DUPLICATES)?;
-            Dir(&self.path).new_folder(SANDBOX)?;
 
             // Create Sqlite DB for pack->idx mapping
             let db = self.path.join(PACKS_DB);
 
-            db::create(&db).with_context(|| format!("create db at {}", db.display()))?;
+            db::create(&db)?;
+            // an empty loose store, counted from now on
+            self.index()?.set_loose_counters(0, 0)?;
         } else {
             // is not empty, check if it is properly initialized
             let cnt = self.valid()?;
-            anyhow::bail!("{} already initialized", cnt.path.display())
+            return Err(Error::AlreadyInitialized {
+                path: cnt.path.clone(),
+            });
         }
 
         Ok(self)
     }
 
+    /// The container config. ``config.json`` is read once and cached, use ``reload`` to pick up
+    /// changes made to the file by someone else.
     pub fn config(&self) -> Result<Config, Error> {
+        self.config.get_or_try_init(|| self.read_config())
+    }
+
+    fn read_config(&self) -> Result<Config, Error> {
         let config_path = self.config_file();
         let config = fs::read_to_string(&config_path)?;
-        let config = serde_json::from_str(&config).map_err(|err| Error::ConfigFileError {
+        serde_json::from_str(&config).map_err(|err| Error::ConfigFileError {
             source: err.into(),
-            path: config_path.clone(),
-        })?;
+            path: config_path,
+        })
+    }
+
+    /// Change ``config.json`` with ``update`` and return the config written. Updaters (threads or
+    /// processes) are serialized by an exclusive lock of ``config.lock``, the config is read from
+    /// disk once the lock is held so no update is lost. The updated config is validated and its
+    /// ``revision`` bumped before it replaces the old one with ``write_config``.
+    pub fn update_config<F>(&self, update: F) -> Result<Config, Error>
+    where
+        F: FnOnce(&mut Config) -> Result<(), Error>,
+    {
+        let lock_path = self.path.join(CONFIG_LOCK);
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|err| Error::IoOpen {
+                source: err,
+                path: lock_path,
+            })?;
+        lock.lock_exclusive()?;
 
+        let mut config = self.read_config()?;
+        update(&mut config)?;
+        config.validate()?;
+        config.revision += 1;
+        self.write_config(&config)?;
+
+        // NOTE: the lock is released when the file is closed
+        drop(lock);
         Ok(config)
     }
 
+    /// Drop the cached config and everything derived from it, the next ``config`` call re-reads
+    /// ``config.json``. Threads holding the previous pack storage or object cache keep using it.
+    pub fn reload(&self) {
+        self.config.clear();
+        self.pack_storage.clear();
+        self.object_cache.clear();
+        if let Ok(mut bloom) = self.bloom.write() {
+            *bloom = None;
+        }
+        if let Ok(mut pack_files) = self.pack_files.lock() {
+            pack_files.clear();
+        }
+    }
+
+    /// Release the index connections and pack files this container keeps open and drop its
+    /// caches, as ``reload`` does. The container stays usable, they are opened again on next use.
+    /// Connections in use by other threads are only closed once given back.
+    pub fn close(&self) {
+        self.reload();
+        self.pool.clear();
+    }
+
+    /// Cache of decompressed packed objects, ``None`` if ``Config::object_cache_size`` is unset.
+    pub fn object_cache(&self) -> Result<Option<Arc<ObjectCache>>, Error> {
+        self.object_cache.get_or_try_init(|| {
+            Ok(self
+                .config()?
+                .object_cache_size
+                .map(|size| Arc::new(ObjectCache::new(size))))
+        })
+    }
+
+    /// Storage packs are offloaded to, ``None`` if the container keeps all packs locally.
+    pub fn pack_storage(&self) -> Result<Option<Arc<dyn PackStorage>>, Error> {
+        self.pack_storage
+            .get_or_try_init(|| match self.config()?.pack_storage_url {
+                Some(url) => Ok(Some(pack_storage::open(&url)?)),
+                None => Ok(None),
+            })
+    }
+
+    /// Replace ``config.json`` atomically: the new content is written to the sandbox (same
+    /// filesystem) and renamed over the old file, so readers never see a partial config. Use
+    /// ``update_config`` to change the config of a container that may be in use.
+    pub(crate) fn write_config(&self, config: &Config) -> Result<(), Error> {
+        let json_string =
+            serde_json::to_string_pretty(config).map_err(|err| Error::ConfigFileError {
+                source: err.into(),
+                path: self.config_file(),
+            })?;
+        let tmp = self.sandbox().join(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(json_string.as_bytes())
+            .and_then(|()| f.sync_all())
+            .map_err(|err| Error::IoWrite {
+                source: err,
+                path: tmp.clone(),
+            })?;
+        drop(f);
+        rename_replace(&tmp, &self.config_file())?;
+        self.reload();
+        Ok(())
+    }
+
     /// The method validate if it is a valid container (means properly initialized from empty dir), return itself if valid.
     /// This function is supposed to be called before heavy operation such as repack and
     /// ``extract_many`` to avoid the container folder is malfunctional. This can also be called at
     /// very begining of every CLI commands to make sure that operation are ready to proceed.
     /// On the contrary, this should not be called for dense small operations (e.g. inside
     /// ``insert_many`` or ``extract_many``) just for a tiny performance save (which matters).
+    ///
+    /// Besides the layout (see ``valid_layout``), the pack index must be there and be an SQLite
+    /// database, ``Error::IndexMissing`` otherwise, instead of failing later deep in the queries.
+    /// The format version of the container must be one this build understands, see
+    /// ``Config::check_version``.
     pub fn valid(&self) -> Result<&Self, Error> {
+        self.valid_layout()?;
+
+        let db = self.packs_db();
+        let cause = match self.index_header(&db) {
+            Ok(header) if &header == SQLITE_HEADER => {
+                self.config()?.check_version()?;
+                return Ok(self);
+            }
+            Ok(_) => "not an SQLite database",
+            Err(err) if err.kind() == io::ErrorKind::NotFound => "missing",
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => "truncated",
+            Err(err) => {
+                return Err(Error::IoOpen {
+                    source: err,
+                    path: db,
+                })
+            }
+        };
+        Err(Error::IndexMissing {
+            path: db,
+            cause: cause.to_string(),
+        })
+    }
+
+    /// The first bytes of the index at ``db``, read through a handle kept open as long as ``db`` is
+    /// the same file.
+    ///
+    /// NOTE: SQLite locks the index with POSIX advisory locks, which a process loses on all its
+    /// handles of a file once any of them is closed. Opening and closing ``packs.idx`` for every
+    /// check would drop the locks of the pooled connections and let other processes write under
+    /// them, which corrupts the index.
+    fn index_header(&self, db: &Path) -> io::Result<[u8; SQLITE_HEADER.len()]> {
+        let mut header = [0; SQLITE_HEADER.len()];
+        let Some(id) = file_id(&fs::metadata(db)?) else {
+            // no advisory locks to lose where the file identity is unknown
+            fs::File::open(db)?.read_exact(&mut header)?;
+            return Ok(header);
+        };
+        let mut index_file = self
+            .index_file
+            .lock()
+            .map_err(|_| io::Error::other("index handle poisoned"))?;
+        let (file, _) = match &mut *index_file {
+            Some(cached) if cached.1 == id => cached,
+            // replaced (e.g. by ``rsdos fsck``), or not opened yet
+            slot => slot.insert((fs::File::open(db)?, id)),
+        };
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        Ok(header)
+    }
+
+    /// Check the folders and files of the container are the ones it is initialized with,
+    /// whatever their content. Enough for ``maintain::rebuild_index``, which replaces the index.
+    pub fn valid_layout(&self) -> Result<&Self, Error> {
         if !self.path.exists() || Dir(&self.path).is_empty()? {
             return Err(Error::Uninitialized {
                 path: self.path.clone(),
@@ -182,10 +768,14 @@ impl Container {
 
         for entry in self.path.read_dir()? {
             let path = entry?.path();
+            // a lock or journal of the index released by another process meanwhile
+            if !path.exists() {
+                continue;
+            }
             if let Some(filename) = path.file_name() {
-                let filename = filename.to_string_lossy();
-                match filename.as_ref() {
-                    LOOSE | PACKS | DUPLICATES | SANDBOX => {
+                let filename = component_name(&filename.to_string_lossy());
+                match filename.as_str() {
+                    LOOSE | PACKS | DUPLICATES | SANDBOX | DICTIONARIES => {
                         if !path.is_dir() {
                             return Err(Error::StoreComponentError {
                                 path: self.path.clone(),
@@ -193,7 +783,7 @@ impl Container {
                             });
                         }
                     }
-                    CONFIG_FILE => {
+                    CONFIG_FILE | CONFIG_LOCK | JOURNAL | MAINTENANCE_LOCK => {
                         if !path.is_file() {
                             return Err(Error::StoreComponentError {
                                 path: self.path.clone(),
@@ -217,67 +807,314 @@ impl Container {
         Ok(self)
     }
 
+    /// Bytes taken on disk by loose objects and pack files, what ``Config::max_total_size`` limits.
+    pub fn disk_usage(&self) -> Result<u64, Error> {
+        let mut used = 0;
+        for path in traverse_loose(self)? {
+            used += fs::metadata(path?).map_or(0, |stat| stat.len());
+        }
+        used += traverse_packs(self)?
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|stat| stat.len())
+            .sum::<u64>();
+        Ok(used)
+    }
+
     #[must_use]
     pub fn loose(&self) -> PathBuf {
         Dir(&self.path).at_path(LOOSE)
     }
 
+    /// The ``sandbox`` folder of the container, on the same filesystem as the loose and packs
+    /// folders so its files can be renamed into them.
     #[must_use]
     pub fn sandbox(&self) -> PathBuf {
         Dir(&self.path).at_path(SANDBOX)
     }
 
+    /// Where inserts write their temporary files: ``Config::sandbox_dir`` (created if missing) or
+    /// else ``sandbox``. It may be on another filesystem, see ``io_loose::insert``.
+    pub fn insert_sandbox(&self) -> Result<PathBuf, Error> {
+        match self.config()?.sandbox_dir {
+            Some(dir) => {
+                let dir = self.path.join(dir);
+                fs::create_dir_all(&dir)?;
+                Ok(dir)
+            }
+            None => Ok(self.sandbox()),
+        }
+    }
+
     #[must_use]
     pub fn packs(&self) -> PathBuf {
         Dir(&self.path).at_path(PACKS)
     }
 
+    /// Where the pack files are, flat in ``packs`` or in shards, see ``PackLayout``.
+    pub fn pack_layout(&self) -> Result<PackLayout, Error> {
+        Ok(PackLayout {
+            root: self.packs(),
+            sharded: self.config()?.capabilities().sharded,
+        })
+    }
+
+    /// Path of pack ``pack_id`` in the ``pack_layout``.
+    pub fn pack_path(&self, pack_id: u64) -> Result<PathBuf, Error> {
+        Ok(self.pack_layout()?.path(pack_id))
+    }
+
     #[must_use]
     pub fn packs_db(&self) -> PathBuf {
         Dir(&self.path).at_path(PACKS_DB)
     }
 
+    /// zstd dictionaries of the container, see ``maintain::train_dictionary``.
+    #[must_use]
+    pub fn dictionaries(&self) -> Arc<Dictionaries> {
+        self.dictionaries.clone()
+    }
+
+    /// Write-ahead intent log of the pack appends, see ``journal::Journal``.
+    #[must_use]
+    pub fn journal(&self) -> PathBuf {
+        Dir(&self.path).at_path(JOURNAL)
+    }
+
+    /// Lock file of the maintenance operations, see ``lock::MaintenanceLock``.
+    #[must_use]
+    pub fn maintenance_lock(&self) -> PathBuf {
+        Dir(&self.path).at_path(MAINTENANCE_LOCK)
+    }
+
     #[must_use]
     pub fn config_file(&self) -> PathBuf {
         Dir(&self.path).at_path(CONFIG_FILE)
     }
 }
 
-pub fn traverse_loose(cnt: &Container) -> Result<impl Iterator<Item = PathBuf>, Error> {
+/// Name of a file of the container folder as it is compared to the store components. The
+/// default filesystems of windows and macOS ignore case, there ``Loose`` is the ``loose`` folder.
+fn component_name(filename: &str) -> String {
+    if cfg!(any(windows, target_os = "macos")) {
+        filename.to_lowercase()
+    } else {
+        filename.to_string()
+    }
+}
+
+/// Remove the ``*.tmp`` files of ``dir`` not modified for ``older_than``, returns the number of
+/// files and bytes removed.
+pub(crate) fn remove_stale_tmp(dir: &Path, older_than: Duration) -> Result<(u64, u64), Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(err) => return Err(err.into()),
+    };
+
+    let now = SystemTime::now();
+    let (mut count, mut size) = (0, 0);
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "tmp") {
+            continue;
+        }
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if !meta.is_file() || age.map_or(true, |age| age < older_than) {
+            continue;
+        }
+        // removed by another process in between
+        if fs::remove_file(&path).is_ok() {
+            count += 1;
+            size += meta.len();
+        }
+    }
+    Ok((count, size))
+}
+
+/// Paths of the loose objects, yielded lazily shard by shard (``loose/<2 chars>``) in ascending
+/// order of the shard name, so objects come grouped by the first two chars of their hashkey. The
+/// order inside a shard is the one of the filesystem and is not specified.
+///
+/// A shard or an object removed while traversing (e.g. by a concurrent ``clean``) is skipped,
+/// other failures to read the loose store are yielded as ``Err`` and the traversal goes on.
+pub fn traverse_loose(cnt: &Container) -> Result<LooseTraversal, Error> {
     // TODO: using Dependency Injection mode to notify and handle progress by outside func
     // let spinnner = ProgressBar::new_spinner().with_message("Auditing container stat ...");
     // spinnner.enable_steady_tick(Duration::from_millis(500));
 
     let loose = cnt.loose();
-    Ok(loose
-        .read_dir()?
+    let mut shards: Vec<_> = loose
+        .read_dir()
+        .map_err(|err| Error::IoOpen {
+            source: err,
+            path: loose.clone(),
+        })?
         .filter_map(result::Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
         .map(|entry| entry.path())
-        .flat_map(|path| {
-            path.read_dir()
-                .unwrap_or_else(|_| panic!("unable to read {}", path.display()))
-        })
-        .filter_map(result::Result::ok)
-        .map(|entry| entry.path()))
-    // .progress_with(spinnner))
+        .collect();
+    shards.sort();
+
+    Ok(LooseTraversal {
+        shards: shards.into_iter(),
+        entries: None,
+    })
+}
+
+/// Iterator over the paths of the loose objects, see ``traverse_loose``.
+#[derive(Debug)]
+pub struct LooseTraversal {
+    shards: std::vec::IntoIter<PathBuf>,
+    entries: Option<fs::ReadDir>,
+}
+
+impl Iterator for LooseTraversal {
+    type Item = Result<PathBuf, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entries) = &mut self.entries {
+                match entries.next() {
+                    Some(Ok(entry)) => return Some(Ok(entry.path())),
+                    Some(Err(err)) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Some(Err(err)) => return Some(Err(err.into())),
+                    None => self.entries = None,
+                }
+            }
+
+            let shard = self.shards.next()?;
+            match shard.read_dir() {
+                Ok(entries) => self.entries = Some(entries),
+                // removed since the shards were listed
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Some(Err(Error::IoOpen {
+                        source: err,
+                        path: shard,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+/// Whether ``path`` looks like an initialized container, i.e. holds a config and a pack index.
+#[must_use]
+pub fn is_container(path: &Path) -> bool {
+    path.join(CONFIG_FILE).is_file() && path.join(PACKS_DB).is_file()
+}
+
+/// Find the container ``start`` is in, like git finds its repository: ``start`` and then each of
+/// its parents is checked for being a container or for holding one in a ``container`` folder.
+#[must_use]
+pub fn discover(start: &Path) -> Option<PathBuf> {
+    start.ancestors().find_map(|dir| {
+        [dir.to_path_buf(), dir.join("container")]
+            .into_iter()
+            .find(|path| is_container(path))
+    })
+}
+
+/// Hashkey of a loose object from its path ``loose/<2 chars>/<rest>``.
+pub fn loose_hashkey(path: &Path) -> Option<String> {
+    let parent = path.parent()?.file_name()?.to_str()?;
+    let filename = path.file_name()?.to_str()?;
+    Some(format!("{parent}{filename}"))
 }
 
 pub fn traverse_packs(cnt: &Container) -> Result<impl Iterator<Item = PathBuf>, Error> {
     let spinnner = ProgressBar::new_spinner().with_message("Auditing container stat ...");
     spinnner.enable_steady_tick(Duration::from_millis(500));
 
-    let packs = cnt.packs();
-    Ok(packs
-        .read_dir()?
-        .filter_map(result::Result::ok)
-        .map(|entry| entry.path())
+    Ok(cnt
+        .pack_layout()?
+        .files()?
+        .into_iter()
         .progress_with(spinnner))
 }
 
+/// Packs in a shard of a sharded ``PackLayout``.
+pub const PACKS_PER_SHARD: u64 = 256;
+
+/// Where the pack files of a container are: flat in ``packs``, or with ``Config::sharded_packs``
+/// in shards ``packs/shard-<pack_id / PACKS_PER_SHARD>/<pack_id>`` so no folder holds more than
+/// ``PACKS_PER_SHARD`` packs. Shards are never named like a pack, so both layouts can be in
+/// ``packs`` at once while ``maintain::upgrade`` moves the packs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackLayout {
+    pub root: PathBuf,
+    pub sharded: bool,
+}
+
+impl PackLayout {
+    /// Path of pack ``pack_id``, whether it exists or not.
+    #[must_use]
+    pub fn path(&self, pack_id: u64) -> PathBuf {
+        if self.sharded {
+            self.root
+                .join(format!("shard-{}", pack_id / PACKS_PER_SHARD))
+                .join(format!("{pack_id}"))
+        } else {
+            self.root.join(format!("{pack_id}"))
+        }
+    }
+
+    /// Path of pack ``pack_id`` to create, its shard is created if missing.
+    pub fn new_path(&self, pack_id: u64) -> Result<PathBuf, Error> {
+        let path = self.path(pack_id);
+        if self.sharded {
+            if let Some(shard) = path.parent() {
+                create_dir(shard)?;
+            }
+        }
+        Ok(path)
+    }
+
+    /// Paths of the pack files. A flat layout skips folders and a sharded one the files outside
+    /// of shards, what ``maintain::upgrade`` may leave when it is interrupted.
+    pub fn files(&self) -> Result<Vec<PathBuf>, Error> {
+        let entries = |dir: &Path| -> Result<Vec<fs::DirEntry>, Error> {
+            Ok(dir
+                .read_dir()
+                .map_err(|err| Error::IoOpen {
+                    source: err,
+                    path: dir.to_path_buf(),
+                })?
+                .filter_map(result::Result::ok)
+                .collect())
+        };
+        let is_dir = |entry: &fs::DirEntry| entry.file_type().is_ok_and(|t| t.is_dir());
+
+        let mut files = Vec::new();
+        for entry in entries(&self.root)? {
+            match (self.sharded, is_dir(&entry)) {
+                (false, false) => files.push(entry.path()),
+                (true, true) => files.extend(
+                    entries(&entry.path())?
+                        .into_iter()
+                        .filter(|entry| !is_dir(entry))
+                        .map(|entry| entry.path()),
+                ),
+                _ => {}
+            }
+        }
+        Ok(files)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use rstest::rstest;
     use tempfile::tempdir;
 
+    use crate::config::CONTAINER_VERSION;
+    use crate::io::ByteString;
     use crate::test_utils::{new_container, PACK_TARGET_SIZE};
 
     use super::*;
@@ -297,9 +1134,10 @@ mod tests {
             .initialize(&Config::new(4 * 1024 * 1024, "none"))
             .unwrap_err();
         assert!(
-            err.to_string().contains("already initialized"),
+            matches!(err, Error::AlreadyInitialized { .. }),
             "got err: {err}"
         );
+        assert!(err.to_string().contains("already initialized"));
     }
 
     #[test]
@@ -318,6 +1156,397 @@ mod tests {
         );
     }
 
+    #[test]
+    fn config_cached_until_reload() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        assert_eq!(cnt.config().unwrap().compression_algorithm, "none");
+
+        // changed behind the container's back
+        let mut config = cnt.config().unwrap();
+        config.compression_algorithm = "zstd:+1".to_string();
+        fs::write(cnt.config_file(), serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(cnt.config().unwrap().compression_algorithm, "none");
+
+        cnt.reload();
+        assert_eq!(cnt.config().unwrap().compression_algorithm, "zstd:+1");
+
+        // written through the container
+        config.compression_algorithm = "zlib:+1".to_string();
+        cnt.write_config(&config).unwrap();
+        assert_eq!(cnt.compression().unwrap(), Compression::Zlib(1));
+    }
+
+    #[test]
+    fn close_releases_handles() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = io_packs::insert(b"test 0".to_vec(), &cnt).unwrap().hashkey;
+        io_packs::extract(&hashkey, &cnt).unwrap().unwrap();
+        assert!(!cnt.pack_files.lock().unwrap().is_empty());
+
+        cnt.close();
+        assert!(cnt.pack_files.lock().unwrap().is_empty());
+
+        // still usable after close
+        let obj = io_packs::extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), b"test 0");
+    }
+
+    #[rstest]
+    fn has_object_with_bloom_filter(#[values(true, false)] bloom_filter: bool) {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let mut config = cnt.config().unwrap();
+        config.bloom_filter = bloom_filter;
+        cnt.write_config(&config).unwrap();
+
+        let missing = "0".repeat(64);
+        assert!(!cnt.has_object(&missing).unwrap());
+        assert!(!cnt.has_object("not a hashkey").unwrap());
+
+        // learnt by the filter built by the lookup above
+        let loose = crate::io_loose::insert(b"loose".to_vec(), &cnt)
+            .unwrap()
+            .hashkey;
+        let packed = crate::io_packs::insert(b"packed".to_vec(), &cnt)
+            .unwrap()
+            .hashkey;
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let chunked = crate::chunked::insert(content, &cnt).unwrap().hashkey;
+        for hashkey in [&loose, &packed, &chunked] {
+            assert!(cnt.has_object(hashkey).unwrap());
+        }
+        assert!(!cnt.has_object(&missing).unwrap());
+
+        // rebuilt from the index
+        cnt.reload();
+        assert!(cnt.has_object(&packed).unwrap());
+        assert!(cnt.has_object(&chunked).unwrap());
+    }
+
+    #[test]
+    fn get_object_from_any_store() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+1");
+        let loose = io_loose::insert(b"loose".to_vec(), &cnt).unwrap().hashkey;
+        let content = "packed ".repeat(200).into_bytes();
+        let packed = io_packs::insert(content.clone(), &cnt).unwrap().hashkey;
+
+        let obj = cnt.get(&loose).unwrap().unwrap();
+        assert_eq!(
+            (obj.hashkey(), obj.size(), obj.store()),
+            (loose.as_str(), 5, Store::Loose)
+        );
+        assert_eq!(ByteString::try_from(obj).unwrap(), b"loose");
+
+        let obj = cnt.get(&packed).unwrap().unwrap();
+        assert_eq!((obj.size(), obj.store()), (1400, Store::Packs));
+        assert_eq!(ByteString::try_from(obj).unwrap(), content);
+
+        assert!(cnt.get(&"0".repeat(64)).unwrap().is_none());
+    }
+
+    #[test]
+    fn valid_detects_missing_index() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        io_packs::insert(b"packed".to_vec(), &cnt).unwrap();
+        cnt.close();
+
+        fs::write(cnt.packs_db(), b"not a sqlite db, but long enough").unwrap();
+        assert!(matches!(
+            cnt.valid(),
+            Err(Error::IndexMissing { cause, .. }) if cause == "not an SQLite database"
+        ));
+        fs::write(cnt.packs_db(), b"SQLite").unwrap();
+        assert!(matches!(
+            cnt.valid(),
+            Err(Error::IndexMissing { cause, .. }) if cause == "truncated"
+        ));
+        fs::remove_file(cnt.packs_db()).unwrap();
+        let err = io_packs::extract(&"0".repeat(64), &cnt).err().unwrap();
+        assert!(matches!(err, Error::IndexMissing { ref cause, .. } if cause == "missing"));
+        assert!(err.to_string().contains("rsdos fsck"));
+        cnt.valid_layout().unwrap();
+    }
+
+    #[test]
+    fn lookup_object_in_order() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = io_loose::insert(b"both".to_vec(), &cnt).unwrap().hashkey;
+        io_packs::insert(b"both".to_vec(), &cnt).unwrap();
+        let loose = io_loose::insert(b"loose".to_vec(), &cnt).unwrap().hashkey;
+
+        let obj = cnt.lookup(&hashkey, &[Store::Packs, Store::Loose]).unwrap();
+        assert_eq!(obj.unwrap().store(), Store::Packs);
+        let obj = cnt.lookup(&hashkey, LOOKUP_AUTO).unwrap();
+        assert_eq!(obj.unwrap().store(), Store::Loose);
+
+        assert!(cnt.lookup(&loose, &[Store::Packs]).unwrap().is_none());
+        assert!(cnt.lookup(&loose, &[]).unwrap().is_none());
+        let obj = cnt.lookup(&loose, &[Store::Chunked, Store::Packs, Store::Loose]);
+        assert_eq!(obj.unwrap().unwrap().store(), Store::Loose);
+    }
+
+    #[test]
+    fn container_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Container>();
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let (_tmp_dir, cnt) = new_container(1024, "zstd:+1");
+        let mut config = cnt.config().unwrap();
+        config.bloom_filter = true;
+        config.object_cache_size = Some(1 << 20);
+        cnt.write_config(&config).unwrap();
+        let cnt = Arc::new(cnt);
+
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let cnt = Arc::clone(&cnt);
+                std::thread::spawn(move || {
+                    let mut hashkeys = Vec::new();
+                    for i in 0..20 {
+                        let content = format!("thread {t} object {i} ").repeat(50).into_bytes();
+                        let hashkey = if i % 2 == 0 {
+                            io_loose::insert(content.clone(), &cnt).unwrap().hashkey
+                        } else {
+                            io_packs::insert(content.clone(), &cnt).unwrap().hashkey
+                        };
+                        // read back right away, while the others keep writing
+                        let obj = cnt.get(&hashkey).unwrap().unwrap();
+                        assert_eq!(ByteString::try_from(obj).unwrap(), content);
+                        if i == 10 {
+                            cnt.reload();
+                        }
+                        hashkeys.push((hashkey, content));
+                    }
+                    hashkeys
+                })
+            })
+            .collect();
+
+        let objects: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(objects.len(), 160);
+        for (hashkey, content) in objects {
+            let obj = cnt.get(&hashkey).unwrap().unwrap();
+            assert_eq!(ByteString::try_from(obj).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn traverse_loose_by_shard() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        for i in 0..20 {
+            io_loose::insert(format!("test {i}").into_bytes(), &cnt).unwrap();
+        }
+        // not a shard, skipped
+        fs::write(cnt.loose().join("README"), b"").unwrap();
+
+        let hashkeys: Vec<_> = traverse_loose(&cnt)
+            .unwrap()
+            .map(|path| loose_hashkey(&path.unwrap()).unwrap())
+            .collect();
+        assert_eq!(hashkeys.len(), 20);
+        assert!(hashkeys.windows(2).all(|w| w[0][..2] <= w[1][..2]));
+    }
+
+    #[test]
+    fn traverse_loose_shard_removed() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let mut hashkeys: Vec<_> = (0..20)
+            .map(|i| {
+                io_loose::insert(format!("test {i}").into_bytes(), &cnt)
+                    .unwrap()
+                    .hashkey
+            })
+            .collect();
+        hashkeys.sort();
+
+        // the last shard disappears once the traversal started
+        let mut iter = traverse_loose(&cnt).unwrap();
+        let first = iter.next().unwrap().unwrap();
+        let last_shard = &hashkeys.last().unwrap()[..2];
+        fs::remove_dir_all(cnt.loose().join(last_shard)).unwrap();
+
+        let rest: Vec<_> = iter.map(Result::unwrap).collect();
+        assert!(!rest.is_empty());
+        assert!(rest
+            .iter()
+            .chain([&first])
+            .all(|path| !loose_hashkey(path).unwrap().starts_with(last_shard)));
+    }
+
+    #[test]
+    fn discover_from_nested_dir() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let nested = cnt.loose().join("ab");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(discover(&nested), Some(cnt.path.clone()));
+        assert_eq!(discover(&cnt.path), Some(cnt.path.clone()));
+
+        // a `container` folder next to the working dir
+        let parent = tempdir().unwrap();
+        let work = parent.path().join("work/data");
+        fs::create_dir_all(&work).unwrap();
+        assert_eq!(discover(&work), None);
+        let cnt = Container::new(parent.path().join("work/container"));
+        fs::create_dir_all(&cnt.path).unwrap();
+        cnt.initialize(&Config::new(PACK_TARGET_SIZE, "none"))
+            .unwrap();
+        assert_eq!(discover(&work), Some(cnt.path.clone()));
+    }
+
+    #[test]
+    fn open_detect_version() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let cnt = Container::open(&cnt.path).unwrap();
+        assert_eq!(cnt.config().unwrap().container_version, CONTAINER_VERSION);
+        assert!(cnt.capabilities().unwrap().zstd);
+        assert!(!cnt.capabilities().unwrap().entry_header);
+
+        let mut config = cnt.config().unwrap();
+        config.container_version = 1;
+        config.pack_entry_header = true;
+        cnt.write_config(&config).unwrap();
+        let caps = Container::open(&cnt.path).unwrap().capabilities().unwrap();
+        assert!(caps.zstd);
+        assert!(!caps.entry_header);
+
+        for version in [0, CONTAINER_VERSION + 1] {
+            config.container_version = version;
+            cnt.write_config(&config).unwrap();
+            let err = Container::open(&cnt.path).unwrap_err();
+            assert!(
+                matches!(err, Error::UnsupportedContainerVersion { found, .. } if found == version),
+                "got err: {err}"
+            );
+
+            // nor read from or written to without opening it
+            let unopened = Container::new(&cnt.path);
+            assert!(matches!(
+                unopened.valid(),
+                Err(Error::UnsupportedContainerVersion { .. })
+            ));
+            assert!(matches!(
+                io_packs::insert(b"test".to_vec(), &unopened),
+                Err(Error::UnsupportedContainerVersion { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn component_name_case() {
+        let name = component_name("Packs.IDX-wal");
+        if cfg!(any(windows, target_os = "macos")) {
+            assert_eq!(name, "packs.idx-wal");
+            assert!(name.contains(PACKS_DB));
+        } else {
+            assert_eq!(name, "Packs.IDX-wal");
+        }
+        assert_eq!(component_name(LOOSE), LOOSE);
+    }
+
+    #[test]
+    fn open_remove_stale_tmp() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let stale = cnt.sandbox().join("stale.tmp");
+        let fresh = cnt.sandbox().join("fresh.tmp");
+        fs::write(&fresh, b"fresh").unwrap();
+        fs::File::create(&stale)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * STALE_TMP_TTL)
+            .unwrap();
+
+        Container::open(&cnt.path).unwrap();
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+    }
+
+    #[test]
+    fn update_config_concurrently() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+
+        // every updater has its own container, as separate processes would
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let cnt = Container::new(&cnt.path);
+                    for _ in 0..10 {
+                        cnt.update_config(|config| {
+                            config.max_total_size = Some(config.max_total_size.unwrap_or(0) + 1);
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        cnt.reload();
+        let config = cnt.config().unwrap();
+        assert_eq!(config.max_total_size, Some(80));
+        assert_eq!(config.revision, 80);
+        cnt.valid().unwrap();
+
+        // an invalid update is not written
+        let err = cnt
+            .update_config(|config| {
+                config.pack_size_target = 0;
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { .. }));
+        assert_eq!(Container::new(&cnt.path).config().unwrap(), config);
+    }
+
+    #[test]
+    fn set_compression_recompress() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        let content = "a text worth compressing\n".repeat(100).into_bytes();
+        let hashkey = io_packs::insert_many(vec![content.clone()], &cnt).unwrap()[0]
+            .hashkey
+            .clone();
+
+        assert!(matches!(
+            cnt.set_compression("lz4:+1", false),
+            Err(Error::ParseCompressionError { .. })
+        ));
+        assert!(matches!(
+            cnt.set_compression("zstd:+23", false),
+            Err(Error::InvalidConfig { .. })
+        ));
+        assert_eq!(cnt.compression().unwrap(), Compression::Zlib(1));
+
+        assert!(cnt.set_compression("zstd:+3", false).unwrap().is_none());
+        assert_eq!(cnt.compression().unwrap(), Compression::Zstd(3));
+        let obj = io_packs::extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(obj.compression().unwrap(), "zlib");
+
+        // also the entries of what was the current pack
+        let report = cnt.set_compression("zstd:+3", true).unwrap().unwrap();
+        assert_eq!(report.objects, 1);
+        let obj = io_packs::extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(obj.compression().unwrap(), "zstd");
+        assert_eq!(ByteString::try_from(obj).unwrap(), content);
+    }
+
+    #[test]
+    fn insert_routes_by_size() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        cnt.update_config(|config| config.set("auto_pack_threshold", "16"))
+            .unwrap();
+
+        let small = cnt.insert(b"small".to_vec()).unwrap();
+        assert_eq!(small.store, Store::Loose);
+        let large = cnt.insert(b"larger than the threshold".to_vec()).unwrap();
+        assert_eq!(large.store, Store::Packs);
+        assert!(io_packs::extract(&large.hashkey, &cnt).unwrap().is_some());
+        assert!(io_loose::extract(&large.hashkey, &cnt).unwrap().is_none());
+    }
+
     #[test]
     fn parse_compression() {
         assert_eq!(
@@ -355,5 +1584,10 @@ mod tests {
 
         // unable to parse
         assert!(Compression::from_str("zzzz").is_err());
+
+        // displayed the way it is parsed
+        for algo in ["none", "zlib:+1", "zstd:-7"] {
+            assert_eq!(Compression::from_str(algo).unwrap().to_string(), algo);
+        }
     }
 }
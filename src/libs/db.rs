@@ -1,100 +1,267 @@
-use anyhow::Context;
-use rusqlite::{params, Connection, OptionalExtension};
-use std::{path::PathBuf, u64};
+use rusqlite::{
+    params, params_from_iter, Connection, ErrorCode, OptionalExtension, Row, ToSql, Transaction,
+    TransactionBehavior,
+};
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::bloom::BloomFilter;
+use crate::index::ObjectIndex;
+use crate::namespace::UNSCOPED;
 use crate::Error;
 
-pub fn create(db: &PathBuf) -> anyhow::Result<()> {
+/// Version of the index schema understood by this build. Bump it together with appending a new
+/// entry to ``MIGRATIONS``.
+pub const SCHEMA_VERSION: u32 = 7;
+
+/// Ordered migrations, the n-th entry brings the schema from version n to n + 1.
+/// Statements must be idempotent so that indexes created by legacy disk-objectstore (which has
+/// ``db_object`` but no ``schema_version`` table) can be adopted as version 1.
+const MIGRATIONS: &[&str] = &[
+    // 0 -> 1: the pack index table
+    "CREATE TABLE IF NOT EXISTS db_object (
+        id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        hashkey VARCHAR NOT NULL,
+        compressed BOOLEAN NOT NULL,
+        size INTEGER NOT NULL,
+        offset INTEGER NOT NULL,
+        length INTEGER NOT NULL,
+        pack_id INTEGER NOT NULL
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS ix_db_object_hashkey ON db_object (hashkey);",
+    // 1 -> 2: manifests of the objects stored in chunks
+    "CREATE TABLE IF NOT EXISTS db_chunked (
+        hashkey VARCHAR NOT NULL PRIMARY KEY,
+        size INTEGER NOT NULL,
+        manifest VARCHAR NOT NULL
+    );",
+    // 2 -> 3: count and size of the loose objects, a single row once they are counted
+    "CREATE TABLE IF NOT EXISTS loose_counters (
+        id INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+        count INTEGER NOT NULL,
+        size INTEGER NOT NULL
+    );",
+    // 3 -> 4: entries of a pack in pack order, to find where the indexed data of a pack ends
+    "CREATE INDEX IF NOT EXISTS ix_db_object_pack_id_offset ON db_object (pack_id, offset);",
+    // 4 -> 5: namespaces objects are grouped in, an object can be in several
+    "CREATE TABLE IF NOT EXISTS db_namespace (
+        namespace VARCHAR NOT NULL,
+        hashkey VARCHAR NOT NULL,
+        PRIMARY KEY (namespace, hashkey)
+    );
+    CREATE INDEX IF NOT EXISTS ix_db_namespace_hashkey ON db_namespace (hashkey);",
+    // 5 -> 6: when objects expire, in seconds since the epoch, objects without one never do
+    "CREATE TABLE IF NOT EXISTS db_expiry (
+        hashkey VARCHAR NOT NULL PRIMARY KEY,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS ix_db_expiry_expires_at ON db_expiry (expires_at);",
+    // 6 -> 7: plain inserts are recorded in the reserved '' namespace, the objects already in a
+    // namespace were tagged after a plain insert
+    "INSERT OR IGNORE INTO db_namespace (namespace, hashkey)
+        SELECT DISTINCT '', hashkey FROM db_namespace;",
+];
+
+pub fn create(db: &PathBuf) -> Result<(), Error> {
     // Create the table if it doesn't already exist
-    let conn = Connection::open(db).with_context(|| "create db")?;
+    let mut conn = Connection::open(db).map_err(|err| Error::SQLiteOpenError {
+        source: err,
+        path: db.clone(),
+    })?;
     conn.execute_batch("PRAGMA journal_mode = wal;")
         .expect("PRAGMA");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS db_object (
-                    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-                    hashkey VARCHAR NOT NULL,
-                    compressed BOOLEAN NOT NULL,
-                    size INTEGER NOT NULL,
-                    offset INTEGER NOT NULL,
-                    length INTEGER NOT NULL,
-                    pack_id INTEGER NOT NULL
-                )",
+    migrate(&mut conn)?;
+
+    Ok(())
+}
+
+/// How long a connection waits for a lock held by another connection before failing with
+/// ``SQLITE_BUSY``.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts to take the write lock when the DB is still busy after ``BUSY_TIMEOUT``.
+const WRITE_ATTEMPTS: u32 = 3;
+/// Idle connections kept by a ``Pool``.
+const POOL_SIZE: usize = 4;
+
+/// Open the index DB and bring its schema up to date.
+pub fn open(db: &Path) -> Result<Connection, Error> {
+    let mut conn = Connection::open(db)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// Start a write transaction. It is IMMEDIATE, i.e. takes the write lock upfront, so concurrent
+/// writers wait for each other here (up to ``BUSY_TIMEOUT``, then retried) instead of failing
+/// with ``SQLITE_BUSY`` in the middle of the transaction.
+pub fn begin_write(conn: &Connection) -> Result<Transaction<'_>, Error> {
+    let mut attempt = 1;
+    loop {
+        match Transaction::new_unchecked(conn, TransactionBehavior::Immediate) {
+            Err(err) if is_busy(&err) && attempt < WRITE_ATTEMPTS => {
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(50 * u64::from(attempt)));
+            }
+            res => return Ok(res?),
+        }
+    }
+}
+
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// A small pool of idle connections to the index DB, so that operations do not pay for opening
+/// (and migrating) a connection every time.
+#[derive(Debug, Default)]
+pub struct Pool {
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Pool {
+    /// Take an idle connection to ``db``, or open a new one if there is none.
+    pub fn get(&self, db: &Path) -> Result<PooledConnection<'_>, Error> {
+        let idle = self.idle.lock().ok().and_then(|mut idle| idle.pop());
+        let conn = match idle {
+            Some(conn) => conn,
+            None => open(db)?,
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self,
+        })
+    }
+
+    /// Close all idle connections, e.g. after the DB file was replaced.
+    pub fn clear(&self) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.clear();
+        }
+    }
+}
+
+/// A connection taken from a ``Pool``, given back to it on drop.
+#[derive(Debug)]
+pub struct PooledConnection<'a> {
+    conn: Option<Connection>,
+    pool: &'a Pool,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("connection is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("connection is only taken on drop")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let (Some(conn), Ok(mut idle)) = (self.conn.take(), self.pool.idle.lock()) {
+            // a connection left inside a transaction is not reusable
+            if conn.is_autocommit() && idle.len() < POOL_SIZE {
+                idle.push(conn);
+            }
+        }
+    }
+}
+
+/// Schema version recorded in the DB, 0 if the DB predates schema versioning.
+pub fn schema_version(conn: &Connection) -> Result<u32, Error> {
+    let has_table: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
         [],
+        |row| row.get(0),
     )?;
+    if !has_table {
+        return Ok(0);
+    }
 
-    conn.execute(
-        "CREATE UNIQUE INDEX ix_db_object_hashkey ON db_object (hashkey)",
-        [],
-    )
-    .with_context(|| "execute create SQL")?;
-
-    Ok(())
-}
-
-pub fn print_table(db: &PathBuf) -> anyhow::Result<()> {
-    // Open the database connection
-    let conn = Connection::open(db)
-        .with_context(|| format!("Open db {} for printing", db.to_string_lossy()))?;
-    
-    // Query to fetch all rows from the table
-    let mut stmt = conn.prepare("SELECT id, hashkey, compressed, size, offset, length, pack_id FROM db_object")?;
-    let rows = stmt.query_map([], |row| {
-        Ok((
-            row.get::<_, i64>(0)?,   // id
-            row.get::<_, String>(1)?, // hashkey
-            row.get::<_, bool>(2)?,   // compressed
-            row.get::<_, i64>(3)?,   // size
-            row.get::<_, i64>(4)?,   // offset
-            row.get::<_, i64>(5)?,   // length
-            row.get::<_, i64>(6)?,   // pack_id
-        ))
-    })?;
+    let version = conn
+        .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+            row.get::<_, Option<u32>>(0)
+        })?
+        .unwrap_or(0);
+    Ok(version)
+}
 
-    // Print the rows
-    println!("id | hashkey | compressed | size | offset | length | pack_id");
-    println!("-----------------------------------------------------------");
-    for row in rows {
-        let (id, hashkey, compressed, size, offset, length, pack_id) = row?;
-        println!(
-            "{} | {} | {} | {} | {} | {} | {}",
-            id, hashkey, compressed, size, offset, length, pack_id
-        );
+/// Apply pending migrations in order and return the resulting schema version.
+///
+/// A DB with a newer schema than ``SCHEMA_VERSION`` is left untouched, it can still be read but
+/// ``ensure_writable`` will refuse to write to it.
+pub fn migrate(conn: &mut Connection) -> Result<u32, Error> {
+    let version = schema_version(conn)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(version);
+    }
+
+    // IMMEDIATE so that concurrent openers serialize and the version is re-read under the lock.
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = schema_version(&tx)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(version);
+    }
+    tx.execute_batch("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")?;
+    for sql in &MIGRATIONS[version as usize..] {
+        tx.execute_batch(sql)?;
+    }
+    tx.execute("DELETE FROM schema_version", [])?;
+    tx.execute(
+        "INSERT INTO schema_version (version) VALUES (?1)",
+        params![SCHEMA_VERSION],
+    )?;
+    tx.commit()?;
+
+    Ok(SCHEMA_VERSION)
+}
+
+/// Refuse to write to an index created by a newer rsdos, since its layout may not be understood.
+pub fn ensure_writable(conn: &Connection) -> Result<(), Error> {
+    let found = schema_version(conn)?;
+    if found > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew {
+            found,
+            supported: SCHEMA_VERSION,
+        });
     }
     Ok(())
 }
 
 /// Counting number of packed objects and get ``total_size`` of their raw objects (size when not
 /// compressed).
-pub fn stat(db: &PathBuf) -> anyhow::Result<(u64, u64)> {
-    let conn = Connection::open(db)
-        .with_context(|| format!("Open db {} for auditing", db.to_string_lossy()))?;
-    let mut stmt = conn.prepare("SELECT size FROM db_object")?;
-    let rows = stmt
-        .query([])
-        .with_context(|| "query size of objects")?
-        .mapped(|row| row.get::<_, u64>(0));
-
-    let mut count = 0;
-    let mut total_size = 0;
-    for size in rows {
-        total_size += size?;
-        count += 1;
-    }
-    Ok((count, total_size))
+pub fn stat(db: &Path) -> Result<(u64, u64), Error> {
+    let stats = open(db)?.stats()?;
+    Ok((stats.count, stats.raw_size))
 }
 
-pub fn insert_packin(conn: &Connection, packin: &PackEntry) -> anyhow::Result<()> {
+pub fn insert_packin(conn: &Connection, packin: &PackEntry) -> Result<(), Error> {
     // NOTE: I use SQL: `INSERT OR IGNORE` to deal with duplicate keys
     let mut stmt = conn.prepare_cached("INSERT OR IGNORE INTO db_object (hashkey, compressed, size, offset, length, pack_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
     stmt.execute(params![
         packin.hashkey,
         packin.compressed,
-        packin.size,
-        packin.offset,
         packin.raw_size,
+        packin.offset,
+        packin.size,
         packin.pack_id
     ])
-    .with_context(|| format!("insert {packin:?} to db"))?;
+    .map_err(|err| Error::SQLiteInsertError { source: err })?;
 
     Ok(())
 }
@@ -107,17 +274,404 @@ pub fn insert(
     offset: u64,
     length: u64,
     pack_id: u64,
-) -> anyhow::Result<()> {
+) -> Result<(), Error> {
     // NOTE: I use SQL: `INSERT OR IGNORE` to deal with duplicate keys
     let mut stmt = conn.prepare_cached("INSERT OR IGNORE INTO db_object (hashkey, compressed, size, offset, length, pack_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
     stmt.execute(params![hashkey, compressed, size, offset, length, pack_id])
-        .with_context(|| "insert to db")?;
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+
+    Ok(())
+}
+
+/// Entries inserted by one statement of ``insert_batch``, their 6 parameters each stay under the
+/// 999 parameters older SQLite builds allow.
+const INSERT_BATCH_ROWS: usize = 100;
+
+/// Insert ``entries`` with multi-row statements of ``INSERT_BATCH_ROWS`` entries, prepared once
+/// per connection, the entries left over are inserted one by one. Insert in a transaction (``tx``
+/// derefs to the ``Connection``), entries of hashkeys already indexed are ignored.
+pub fn insert_batch(tx: &Connection, entries: &[PackEntry]) -> Result<(), Error> {
+    let sql = |rows: usize| {
+        format!(
+            "INSERT OR IGNORE INTO db_object (hashkey, compressed, size, offset, length, pack_id) VALUES {}",
+            vec!["(?, ?, ?, ?, ?, ?)"; rows].join(", ")
+        )
+    };
+    let execute = |sql: &str, batch: &[PackEntry]| {
+        let params = batch.iter().flat_map(|entry| -> [&dyn ToSql; 6] {
+            [
+                &entry.hashkey,
+                &entry.compressed,
+                &entry.raw_size,
+                &entry.offset,
+                &entry.size,
+                &entry.pack_id,
+            ]
+        });
+        tx.prepare_cached(sql)
+            .and_then(|mut stmt| stmt.execute(params_from_iter(params)))
+            .map_err(|err| Error::SQLiteInsertError { source: err })
+    };
+
+    let mut batches = entries.chunks_exact(INSERT_BATCH_ROWS);
+    let full = sql(INSERT_BATCH_ROWS);
+    for batch in &mut batches {
+        execute(&full, batch)?;
+    }
+    let single = sql(1);
+    for entry in batches.remainder() {
+        execute(&single, std::slice::from_ref(entry))?;
+    }
+    Ok(())
+}
+
+/// Record the manifest object of a chunked object, ``size`` is the raw size of the whole object.
+pub fn insert_chunked(
+    conn: &Connection,
+    hashkey: &str,
+    size: u64,
+    manifest: &str,
+) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare_cached(
+            "INSERT OR IGNORE INTO db_chunked (hashkey, size, manifest) VALUES (?1, ?2, ?3)",
+        )
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    stmt.execute(params![hashkey, size, manifest])
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    Ok(())
+}
+
+/// Size and manifest hashkey of a chunked object.
+pub fn select_chunked(conn: &Connection, hashkey: &str) -> Result<Option<(u64, String)>, Error> {
+    let mut stmt = conn
+        .prepare_cached("SELECT size, manifest FROM db_chunked WHERE hashkey = ?1")
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_row(params![hashkey], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// Bloom filter over the hashkeys of the packed and the chunked objects, sized for twice as many
+/// so it holds the objects inserted next.
+pub fn build_bloom(conn: &Connection) -> Result<BloomFilter, Error> {
+    let n: u64 = conn
+        .query_row(
+            "SELECT (SELECT COUNT(*) FROM db_object) + (SELECT COUNT(*) FROM db_chunked)",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    let mut bloom = BloomFilter::with_capacity(2 * n);
+
+    let mut stmt = conn
+        .prepare("SELECT hashkey FROM db_object UNION ALL SELECT hashkey FROM db_chunked")
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    let mut rows = stmt
+        .query([])
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    while let Some(row) = rows.next()? {
+        bloom.insert(&row.get::<_, String>(0)?);
+    }
+    Ok(bloom)
+}
+
+/// Hashkeys of all chunked objects.
+pub fn list_chunked(conn: &Connection) -> Result<Vec<String>, Error> {
+    let mut stmt = conn
+        .prepare_cached("SELECT hashkey FROM db_chunked")
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_map([], |row| row.get(0))
+        .and_then(Iterator::collect)
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// Count and total size of the loose objects kept in the index, ``None`` until they are counted
+/// with ``set_loose_counters``.
+pub fn loose_counters(conn: &Connection) -> Result<Option<(u64, u64)>, Error> {
+    conn.query_row(
+        "SELECT count, size FROM loose_counters WHERE id = 0",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+pub fn set_loose_counters(conn: &Connection, count: u64, size: u64) -> Result<(), Error> {
+    conn.execute(
+        "INSERT OR REPLACE INTO loose_counters (id, count, size) VALUES (0, ?1, ?2)",
+        params![count, size],
+    )
+    .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    Ok(())
+}
 
+/// Add ``count`` objects of ``size`` bytes (negative when removed) to the loose counters, nothing
+/// is done while they are not counted yet.
+pub fn add_loose(conn: &Connection, count: i64, size: i64) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE loose_counters SET count = MAX(count + ?1, 0), size = MAX(size + ?2, 0) WHERE id = 0",
+        params![count, size],
+    )
+    .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    Ok(())
+}
+
+/// Add the objects of ``hashkeys`` to ``namespace``, the ones already in it are ignored.
+pub fn add_to_namespace(
+    conn: &Connection,
+    namespace: &str,
+    hashkeys: &[String],
+) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare_cached("INSERT OR IGNORE INTO db_namespace (namespace, hashkey) VALUES (?1, ?2)")
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    for hashkey in hashkeys {
+        stmt.execute(params![namespace, hashkey])
+            .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    }
     Ok(())
 }
 
+/// Add the objects of ``hashkeys`` that are in no namespace yet to ``namespace``.
+pub fn add_untagged(conn: &Connection, namespace: &str, hashkeys: &[String]) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare_cached(
+            "INSERT OR IGNORE INTO db_namespace (namespace, hashkey)
+            SELECT ?1, ?2 WHERE NOT EXISTS (SELECT 1 FROM db_namespace WHERE hashkey = ?2)",
+        )
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    for hashkey in hashkeys {
+        stmt.execute(params![namespace, hashkey])
+            .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    }
+    Ok(())
+}
+
+/// Remove the objects of ``hashkeys`` from ``namespace``, returns how many were in it.
+pub fn remove_from_namespace(
+    conn: &Connection,
+    namespace: &str,
+    hashkeys: &[String],
+) -> Result<u64, Error> {
+    let mut stmt = conn
+        .prepare_cached("DELETE FROM db_namespace WHERE namespace = ?1 AND hashkey = ?2")
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    let mut removed = 0;
+    for hashkey in hashkeys {
+        let n = stmt
+            .execute(params![namespace, hashkey])
+            .map_err(|err| Error::SQLiteInsertError { source: err })?;
+        removed += n as u64;
+    }
+    Ok(removed)
+}
+
+pub fn delete_namespace(conn: &Connection, namespace: &str) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM db_namespace WHERE namespace = ?1",
+        params![namespace],
+    )
+    .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    Ok(())
+}
+
+/// Hashkeys of the objects of ``namespace``, sorted.
+pub fn namespace_hashkeys(conn: &Connection, namespace: &str) -> Result<Vec<String>, Error> {
+    let mut stmt = conn
+        .prepare_cached("SELECT hashkey FROM db_namespace WHERE namespace = ?1 ORDER BY hashkey")
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_map(params![namespace], |row| row.get(0))
+        .and_then(Iterator::collect)
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// Hashkeys of the objects of ``namespace`` that are in no other namespace.
+pub fn namespace_only(conn: &Connection, namespace: &str) -> Result<Vec<String>, Error> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT hashkey FROM db_namespace WHERE namespace = ?1 AND hashkey NOT IN (
+                SELECT hashkey FROM db_namespace WHERE namespace != ?1
+            )",
+        )
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_map(params![namespace], |row| row.get(0))
+        .and_then(Iterator::collect)
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// Namespaces the object ``hashkey`` is in, sorted, ``namespace::UNSCOPED`` aside.
+pub fn namespaces_of(conn: &Connection, hashkey: &str) -> Result<Vec<String>, Error> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT namespace FROM db_namespace WHERE hashkey = ?1 AND namespace != ?2
+            ORDER BY namespace",
+        )
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_map(params![hashkey, UNSCOPED], |row| row.get(0))
+        .and_then(Iterator::collect)
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// Call ``f`` with the namespace, the hashkey and the size of every object in a namespace
+/// (``namespace::UNSCOPED`` aside) ordered by namespace, stopping at the first error. The size is
+/// ``None`` for a loose object, only the packed and chunked ones are indexed with theirs.
+pub fn for_each_in_namespaces(
+    conn: &Connection,
+    f: &mut dyn FnMut(String, String, Option<u64>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT n.namespace, n.hashkey, COALESCE(o.size, c.size) FROM db_namespace n
+            LEFT JOIN db_object o ON o.hashkey = n.hashkey
+            LEFT JOIN db_chunked c ON c.hashkey = n.hashkey
+            WHERE n.namespace != ?1
+            ORDER BY n.namespace",
+        )
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    let mut rows = stmt
+        .query(params![UNSCOPED])
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    while let Some(row) = rows.next()? {
+        f(row.get(0)?, row.get(1)?, row.get(2)?)?;
+    }
+    Ok(())
+}
+
+/// Seconds since the epoch of ``time`` the expiries are stored in, 0 before the epoch.
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Expire the objects of ``hashkeys`` at ``at`` (to the second), replacing the expiry they had.
+pub fn set_expiry(conn: &Connection, hashkeys: &[String], at: SystemTime) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare_cached("INSERT OR REPLACE INTO db_expiry (hashkey, expires_at) VALUES (?1, ?2)")
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    for hashkey in hashkeys {
+        stmt.execute(params![hashkey, epoch_secs(at)])
+            .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    }
+    Ok(())
+}
+
+/// Clear the expiry of the objects of ``hashkeys``, returns how many had one.
+pub fn clear_expiry(conn: &Connection, hashkeys: &[String]) -> Result<u64, Error> {
+    let mut stmt = conn
+        .prepare_cached("DELETE FROM db_expiry WHERE hashkey = ?1")
+        .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    let mut cleared = 0;
+    for hashkey in hashkeys {
+        let n = stmt
+            .execute(params![hashkey])
+            .map_err(|err| Error::SQLiteInsertError { source: err })?;
+        cleared += n as u64;
+    }
+    Ok(cleared)
+}
+
+/// When the object ``hashkey`` expires, ``None`` if it has no expiry.
+pub fn expiry(conn: &Connection, hashkey: &str) -> Result<Option<SystemTime>, Error> {
+    let secs: Option<u64> = conn
+        .query_row(
+            "SELECT expires_at FROM db_expiry WHERE hashkey = ?1",
+            params![hashkey],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    Ok(secs.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)))
+}
+
+/// Hashkeys of the objects expired at ``now``.
+pub fn expired(conn: &Connection, now: SystemTime) -> Result<Vec<String>, Error> {
+    let mut stmt = conn
+        .prepare_cached("SELECT hashkey FROM db_expiry WHERE expires_at <= ?1")
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_map(params![epoch_secs(now)], |row| row.get(0))
+        .and_then(Iterator::collect)
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// Point the entry of ``entry.hashkey`` to where ``entry`` is now, its raw size is kept.
+pub fn relocate(conn: &Connection, entry: &PackEntry) -> Result<(), Error> {
+    conn.prepare_cached(
+        "UPDATE db_object SET compressed = ?1, offset = ?2, length = ?3, pack_id = ?4
+        WHERE hashkey = ?5",
+    )
+    .and_then(|mut stmt| {
+        stmt.execute(params![
+            entry.compressed,
+            entry.offset,
+            entry.size,
+            entry.pack_id,
+            entry.hashkey
+        ])
+    })
+    .map_err(|err| Error::SQLiteInsertError { source: err })?;
+    Ok(())
+}
+
+/// Drop the pack entries, chunked records, namespaces and expiry of ``hashkeys``.
+pub fn remove(conn: &Connection, hashkeys: &[String]) -> Result<(), Error> {
+    for table in ["db_object", "db_chunked", "db_namespace", "db_expiry"] {
+        let mut stmt = conn
+            .prepare_cached(&format!("DELETE FROM {table} WHERE hashkey = ?1"))
+            .map_err(|err| Error::SQLiteInsertError { source: err })?;
+        for hashkey in hashkeys {
+            stmt.execute(params![hashkey])
+                .map_err(|err| Error::SQLiteInsertError { source: err })?;
+        }
+    }
+    Ok(())
+}
+
+/// Live objects of a pack file according to the index, bytes no entry refers to are not counted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackStats {
+    pub pack_id: u64,
+    pub count: u64,
+    /// Bytes the objects take in the pack (compressed), without entry headers.
+    pub size: u64,
+    /// Bytes of the objects when not compressed.
+    pub raw_size: u64,
+}
+
+/// Count and sizes of the indexed objects of every pack, ordered by ``pack_id``. Packs without
+/// any indexed object are not listed.
+pub fn stats_per_pack(conn: &Connection) -> Result<Vec<PackStats>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT pack_id, COUNT(*), SUM(length), SUM(size) FROM db_object
+            GROUP BY pack_id ORDER BY pack_id",
+        )
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    stmt.query_map([], |row| {
+        Ok(PackStats {
+            pack_id: row.get(0)?,
+            count: row.get(1)?,
+            size: row.get(2)?,
+            raw_size: row.get(3)?,
+        })
+    })
+    .and_then(Iterator::collect)
+    .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
+/// End of the last indexed entry of pack ``pack_id``, 0 if none of its entries is indexed. The
+/// pack must never be written before it.
+pub fn pack_end(conn: &Connection, pack_id: u64) -> Result<u64, Error> {
+    conn.prepare_cached(
+        "SELECT offset + length FROM db_object WHERE pack_id = ?1 ORDER BY offset DESC LIMIT 1",
+    )
+    .and_then(|mut stmt| stmt.query_row(params![pack_id], |row| row.get(0)).optional())
+    .map(Option::unwrap_or_default)
+    .map_err(|err| Error::SQLiteSelectError { source: err })
+}
+
 // XXX: this is almost duplicate as PObject, merge us
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PackEntry {
     pub hashkey: String,
     pub compressed: bool,
@@ -127,24 +681,404 @@ pub struct PackEntry {
     pub pack_id: u64,
 }
 
-// XXX: sub from select_multiple which only query once
+/// Columns of ``db_object`` read into a ``PackEntry`` by ``pack_entry``, in this order.
+pub(crate) const ENTRY_COLUMNS: &str = "hashkey, compressed, size, offset, length, pack_id";
+
+/// ``PackEntry`` of a row selecting ``ENTRY_COLUMNS``.
+pub(crate) fn pack_entry(row: &Row) -> rusqlite::Result<PackEntry> {
+    pack_entry_at(row, 0)
+}
+
+/// ``PackEntry`` of a row selecting ``ENTRY_COLUMNS`` from column ``first`` on.
+fn pack_entry_at(row: &Row, first: usize) -> rusqlite::Result<PackEntry> {
+    Ok(PackEntry {
+        hashkey: row.get(first)?,
+        compressed: row.get(first + 1)?,
+        raw_size: row.get(first + 2)?,
+        offset: row.get(first + 3)?,
+        size: row.get(first + 4)?,
+        pack_id: row.get(first + 5)?,
+    })
+}
+
+/// Rows of ``db_object`` read by ``iterate`` at a time.
+const ITERATE_BATCH_SIZE: u32 = 1000;
+
+/// Every entry of the index in the order they were inserted, read ``ITERATE_BATCH_SIZE`` rows at
+/// a time so walking a large index does not hold it all in memory. The iterator stops after the
+/// first error.
+///
+/// NOTE: each batch is a query of its own, entries inserted or deleted while iterating may or may
+/// not be seen; iterate in a transaction for a consistent view.
+pub fn iterate(conn: &Connection) -> impl Iterator<Item = Result<PackEntry, Error>> + '_ {
+    let mut last_id = 0;
+    let mut batch = Vec::new().into_iter();
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if let Some(entry) = batch.next() {
+            return Some(Ok(entry));
+        }
+        if done {
+            return None;
+        }
+        let rows = conn
+            .prepare_cached(&format!(
+                "SELECT id, {ENTRY_COLUMNS} FROM db_object WHERE id > ?1 ORDER BY id LIMIT ?2"
+            ))
+            .and_then(|mut stmt| {
+                stmt.query_map(params![last_id, ITERATE_BATCH_SIZE], |row| {
+                    Ok((row.get::<_, i64>(0)?, pack_entry_at(row, 1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+            });
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(err) => {
+                done = true;
+                return Some(Err(Error::SQLiteSelectError { source: err }));
+            }
+        };
+        done = rows.len() < ITERATE_BATCH_SIZE as usize;
+        last_id = rows.last().map_or(last_id, |(id, _)| *id);
+        batch = rows
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>()
+            .into_iter();
+        batch.next().map(Ok)
+    })
+}
+
+/// Entries of the index ``for_each_filtered`` goes through, all of them by default.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    /// Only the entries of this pack.
+    pub pack_id: Option<u64>,
+    /// Only the entries whose hashkey starts with this.
+    pub hash_prefix: Option<String>,
+    /// Stop after this many entries.
+    pub limit: Option<u64>,
+}
+
+/// Call ``f`` with the entries matching ``filter`` in the order they were inserted, stopping at
+/// the first error. The entries are streamed, e.g. to dump a large index.
+pub fn for_each_filtered(
+    conn: &Connection,
+    filter: &EntryFilter,
+    f: &mut dyn FnMut(PackEntry) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {ENTRY_COLUMNS} FROM db_object
+            WHERE (?1 IS NULL OR pack_id = ?1) AND (?2 IS NULL OR substr(hashkey, 1, length(?2)) = ?2)
+            ORDER BY id LIMIT ?3"
+        ))
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    // a negative limit is no limit
+    let limit = filter
+        .limit
+        .map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX));
+    let rows = stmt
+        .query_map(params![filter.pack_id, filter.hash_prefix, limit], pack_entry)
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
+    for entry in rows {
+        f(entry.map_err(|err| Error::SQLiteSelectError { source: err })?)?;
+    }
+    Ok(())
+}
+
 pub fn select(conn: &Connection, hash_hex: &str) -> Result<Option<PackEntry>, Error> {
-    let mut stmt = conn.
-        prepare_cached("SELECT hashkey, compressed, size, offset, length, pack_id FROM db_object WHERE hashkey = ?1").
-        map_err(|err| Error::SQLiteSelectError{source: err})?;
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {ENTRY_COLUMNS} FROM db_object WHERE hashkey = ?1"
+        ))
+        .map_err(|err| Error::SQLiteSelectError { source: err })?;
     let entry = stmt
-        .query_row(params![hash_hex], |row| {
-            Ok(PackEntry {
-                hashkey: row.get(0)?,
-                compressed: row.get(1)?,
-                raw_size: row.get(2)?,
-                offset: row.get(3)?,
-                size: row.get(4)?,
-                pack_id: row.get(5)?,
-            })
-        })
+        .query_row(params![hash_hex], pack_entry)
         .optional()
         .map_err(|err| Error::SQLiteSelectError { source: err })?;
 
     Ok(entry)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        io::ByteString,
+        io_packs,
+        test_utils::{new_container, PACK_TARGET_SIZE},
+    };
+
+    use super::*;
+
+    #[test]
+    fn db_concurrent_writers_wait_for_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    let conn = open(&db).unwrap();
+                    let tx = begin_write(&conn).unwrap();
+                    insert(&tx, &format!("hash{i}"), false, 3, 0, 3, 0).unwrap();
+                    // hold the write lock for a while
+                    std::thread::sleep(Duration::from_millis(20));
+                    tx.commit().unwrap();
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let (count, _) = stat(&db).unwrap();
+        assert_eq!(count, 8);
+    }
+
+    #[test]
+    fn db_pool_reuse_connection() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        let pool = Pool::default();
+        {
+            let conn = pool.get(&db).unwrap();
+            insert(&conn, "abc", false, 3, 0, 3, 0).unwrap();
+        }
+        assert_eq!(pool.idle.lock().unwrap().len(), 1);
+
+        // a connection dropped while in a transaction is not given back
+        let conn = pool.get(&db).unwrap();
+        assert_eq!(pool.idle.lock().unwrap().len(), 0);
+        conn.execute_batch("BEGIN").unwrap();
+        drop(conn);
+        assert_eq!(pool.idle.lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn db_iterate_in_batches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        let conn = open(&db).unwrap();
+        assert_eq!(iterate(&conn).count(), 0);
+
+        let n = u64::from(ITERATE_BATCH_SIZE) * 2 + 1;
+        let tx = begin_write(&conn).unwrap();
+        for i in 0..n {
+            insert(&tx, &format!("hash{i}"), false, i, i, i, 0).unwrap();
+        }
+        tx.commit().unwrap();
+        // a gap in the ids
+        conn.execute("DELETE FROM db_object WHERE hashkey = 'hash1'", [])
+            .unwrap();
+
+        let entries: Vec<PackEntry> = iterate(&conn).collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries.len() as u64, n - 1);
+        assert_eq!(entries[0].hashkey, "hash0");
+        assert_eq!(entries[1].hashkey, "hash2");
+        assert_eq!(entries.last().unwrap().raw_size, n - 1);
+    }
+
+    #[test]
+    fn db_insert_batch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        // full batches and a remainder
+        let n = INSERT_BATCH_ROWS as u64 * 2 + 3;
+        let entries: Vec<_> = (0..n)
+            .map(|i| PackEntry {
+                hashkey: format!("hash{i}"),
+                compressed: i % 2 == 0,
+                raw_size: i + 1,
+                size: i,
+                offset: i * 10,
+                pack_id: i / 50,
+            })
+            .collect();
+        let conn = open(&db).unwrap();
+        let tx = begin_write(&conn).unwrap();
+        insert_batch(&tx, &entries).unwrap();
+        // already indexed, ignored
+        insert_batch(&tx, &entries[..1]).unwrap();
+        tx.commit().unwrap();
+
+        let (count, _) = stat(&db).unwrap();
+        assert_eq!(count, n);
+        let last = select(&conn, &format!("hash{}", n - 1)).unwrap().unwrap();
+        assert!(last.compressed);
+        assert_eq!((last.raw_size, last.size), (n, n - 1));
+        assert_eq!((last.offset, last.pack_id), ((n - 1) * 10, (n - 1) / 50));
+    }
+
+    #[test]
+    fn db_filter_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        let conn = open(&db).unwrap();
+        insert(&conn, "ab01", false, 3, 0, 3, 0).unwrap();
+        insert(&conn, "cd02", false, 3, 3, 3, 0).unwrap();
+        insert(&conn, "ab03", false, 3, 0, 3, 1).unwrap();
+        insert(&conn, "ab04", false, 3, 3, 3, 1).unwrap();
+
+        let hashkeys = |filter: EntryFilter| {
+            let mut hashkeys = vec![];
+            for_each_filtered(&conn, &filter, &mut |entry| {
+                hashkeys.push(entry.hashkey);
+                Ok(())
+            })
+            .unwrap();
+            hashkeys
+        };
+        assert_eq!(hashkeys(EntryFilter::default()).len(), 4);
+        let filter = EntryFilter {
+            pack_id: Some(1),
+            ..EntryFilter::default()
+        };
+        assert_eq!(hashkeys(filter), vec!["ab03", "ab04"]);
+        let filter = EntryFilter {
+            hash_prefix: Some("ab".to_string()),
+            limit: Some(2),
+            ..EntryFilter::default()
+        };
+        assert_eq!(hashkeys(filter), vec!["ab01", "ab03"]);
+        // a LIKE wildcard is matched as it is
+        let filter = EntryFilter {
+            hash_prefix: Some("a%".to_string()),
+            ..EntryFilter::default()
+        };
+        assert!(hashkeys(filter).is_empty());
+    }
+
+    #[test]
+    fn db_stats_per_pack() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        let conn = open(&db).unwrap();
+        insert(&conn, "a", true, 10, 0, 4, 0).unwrap();
+        insert(&conn, "b", false, 3, 4, 3, 0).unwrap();
+        insert(&conn, "c", false, 5, 0, 5, 2).unwrap();
+
+        assert_eq!(
+            stats_per_pack(&conn).unwrap(),
+            vec![
+                PackStats {
+                    pack_id: 0,
+                    count: 2,
+                    size: 7,
+                    raw_size: 13
+                },
+                PackStats {
+                    pack_id: 2,
+                    count: 1,
+                    size: 5,
+                    raw_size: 5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn db_loose_counters() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+        let conn = open(&db).unwrap();
+
+        // not counted yet, nothing to update
+        add_loose(&conn, 1, 10).unwrap();
+        assert_eq!(loose_counters(&conn).unwrap(), None);
+
+        set_loose_counters(&conn, 2, 20).unwrap();
+        add_loose(&conn, 1, 10).unwrap();
+        assert_eq!(loose_counters(&conn).unwrap(), Some((3, 30)));
+        add_loose(&conn, -5, -50).unwrap();
+        assert_eq!(loose_counters(&conn).unwrap(), Some((0, 0)));
+    }
+
+    #[test]
+    fn db_migrate_adopts_legacy_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+
+        // index as created by legacy disk-objectstore, without schema_version table
+        let mut conn = Connection::open(&db).unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        insert(&conn, "abc", false, 3, 0, 3, 0).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        assert_eq!(migrate(&mut conn).unwrap(), SCHEMA_VERSION);
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert!(select(&conn, "abc").unwrap().is_some());
+
+        // migrate is idempotent
+        drop(conn);
+        let conn = open(&db).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn db_refuse_write_to_newer_schema() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+
+        let conn = open(&cnt.packs_db()).unwrap();
+        conn.execute(
+            "UPDATE schema_version SET version = ?1",
+            params![SCHEMA_VERSION + 1],
+        )
+        .unwrap();
+
+        // opening (and reading) still works
+        let conn = open(&cnt.packs_db()).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION + 1);
+        assert!(select(&conn, "abc").unwrap().is_none());
+
+        let bstr: ByteString = b"test 0".to_vec();
+        let err = io_packs::insert(bstr, &cnt).unwrap_err();
+        assert!(
+            matches!(err, Error::SchemaTooNew { found, .. } if found == SCHEMA_VERSION + 1),
+            "got err: {err}"
+        );
+    }
+
+    #[test]
+    fn db_insert_packin_columns() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("packs.idx");
+        create(&db).unwrap();
+
+        // ``size`` column is the raw size and ``length`` the size in the pack
+        let entry = PackEntry {
+            hashkey: "abc".to_string(),
+            compressed: true,
+            raw_size: 100,
+            size: 7,
+            offset: 3,
+            pack_id: 1,
+        };
+        let conn = open(&db).unwrap();
+        insert_packin(&conn, &entry).unwrap();
+
+        let (size, length): (u64, u64) = conn
+            .query_row(
+                "SELECT size, length FROM db_object WHERE hashkey = 'abc'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((size, length), (100, 7));
+        let stored = select(&conn, "abc").unwrap().unwrap();
+        assert_eq!((stored.raw_size, stored.size), (entry.raw_size, entry.size));
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crate::Error;
+
+/// Magic number opening a trained zstd dictionary, followed by its id.
+const DICT_MAGIC: [u8; 4] = [0x37, 0xA4, 0x30, 0xEC];
+/// Bytes of a zstd frame to read to find its dictionary id.
+pub const FRAME_HEAD_LEN: usize = 10;
+
+/// zstd dictionaries of a container, one file named by its id in the ``dictionaries`` folder.
+///
+/// A dictionary is never modified once written: the frames compressed with it record its id (see
+/// ``frame_dictionary_id``) and need the very same bytes to be decompressed. Loaded dictionaries
+/// are kept in memory.
+#[derive(Debug)]
+pub struct Dictionaries {
+    dir: PathBuf,
+    loaded: RwLock<HashMap<u32, Arc<[u8]>>>,
+}
+
+impl Dictionaries {
+    #[must_use]
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            loaded: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The dictionary ``id``, ``Error::DictionaryNotFound`` if the container has none with it.
+    pub fn get(&self, id: u32) -> Result<Arc<[u8]>, Error> {
+        if let Some(dict) = self.loaded.read().ok().and_then(|d| d.get(&id).cloned()) {
+            return Ok(dict);
+        }
+
+        let dict: Arc<[u8]> = match fs::read(self.dir.join(format!("{id}"))) {
+            Ok(dict) => dict.into(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::DictionaryNotFound { id })
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if let Ok(mut loaded) = self.loaded.write() {
+            loaded.insert(id, dict.clone());
+        }
+        Ok(dict)
+    }
+
+    /// Store a trained dictionary and return its id. A dictionary already stored is kept as it
+    /// is.
+    pub fn add(&self, dict: &[u8]) -> Result<u32, Error> {
+        let Some(id) = dictionary_id(dict) else {
+            return Err(Error::InvalidDictionary {
+                cause: "not a trained zstd dictionary".to_string(),
+            });
+        };
+        let path = self.dir.join(format!("{id}"));
+        if path.exists() {
+            return Ok(id);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let tmp = self.dir.join(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(dict)
+            .and_then(|()| f.sync_all())
+            .map_err(|err| Error::IoWrite {
+                source: err,
+                path: tmp.clone(),
+            })?;
+        drop(f);
+        fs::rename(&tmp, &path)?;
+        Ok(id)
+    }
+}
+
+/// Id of a trained zstd dictionary, ``None`` if ``dict`` is not one (or has the reserved id 0).
+#[must_use]
+pub fn dictionary_id(dict: &[u8]) -> Option<u32> {
+    if !dict.starts_with(&DICT_MAGIC) {
+        return None;
+    }
+    let id = u32::from_le_bytes(dict.get(4..8)?.try_into().ok()?);
+    Some(id).filter(|id| *id != 0)
+}
+
+/// Id of the dictionary a zstd frame was compressed with, read from the start of the frame
+/// (``FRAME_HEAD_LEN`` bytes are enough). ``None`` if the frame was compressed without one.
+#[must_use]
+pub fn frame_dictionary_id(head: &[u8]) -> Option<u32> {
+    // magic(4), frame header descriptor(1), window descriptor(0 or 1), dictionary id(0..=4)
+    let descriptor = *head.get(4)?;
+    let id_len = [0, 1, 2, 4][usize::from(descriptor & 0b11)];
+    let single_segment = descriptor & 0b10_0000 != 0;
+    let start = 5 + usize::from(!single_segment);
+    let mut id = [0u8; 4];
+    id[..id_len].copy_from_slice(head.get(start..start + id_len)?);
+    Some(u32::from_le_bytes(id)).filter(|id| *id != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_records_dictionary_id() {
+        let samples: Vec<Vec<u8>> = (0..500)
+            .map(|i| format!(r#"{{"uuid": "{i:08}", "attributes": {{"value": {i}}}}}"#).into())
+            .collect();
+        let dict = zstd::dict::from_samples(&samples, 2048).unwrap();
+        let id = dictionary_id(&dict).unwrap();
+
+        let with = zstd::bulk::Compressor::with_dictionary(3, &dict)
+            .unwrap()
+            .compress(&samples[7])
+            .unwrap();
+        assert_eq!(frame_dictionary_id(&with), Some(id));
+        let without = zstd::encode_all(&samples[7][..], 3).unwrap();
+        assert_eq!(frame_dictionary_id(&without), None);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dictionaries = Dictionaries::new(tmp_dir.path().join("dictionaries"));
+        assert!(matches!(
+            dictionaries.get(id),
+            Err(Error::DictionaryNotFound { .. })
+        ));
+        assert_eq!(dictionaries.add(&dict).unwrap(), id);
+        assert_eq!(&*dictionaries.get(id).unwrap(), &dict[..]);
+        assert!(dictionaries.add(b"raw content").is_err());
+    }
+}
@@ -28,6 +28,16 @@ pub enum Error {
     UnableObtainDir { path: PathBuf },
     #[error("Uninitialized container directory at {}", .path.display())]
     Uninitialized { path: PathBuf },
+    #[error("Pack index at {} is {}, run `rsdos fsck` to rebuild it from the packs, or re-init the container", .path.display(), .cause)]
+    IndexMissing { path: PathBuf, cause: String },
+    #[error("Container at {} is already initialized", .path.display())]
+    AlreadyInitialized { path: PathBuf },
+    #[error("Another maintenance operation ({}, pid {}) is running on the container, wait for it to finish or remove {} if that process is gone", .operation, .pid, .path.display())]
+    MaintenanceInProgress {
+        path: PathBuf,
+        pid: u32,
+        operation: String,
+    },
     #[error("Could not read the container config file at {}: {}", .path.display(), .source)]
     ConfigFileError {
         source: std::io::Error,
@@ -37,6 +47,34 @@ pub enum Error {
     StoreComponentError { path: PathBuf, cause: String },
     #[error("Could not parst {} to compression algorithm", .s)]
     ParseCompressionError { s: String },
+    #[error("Could not parse '{}' to a size, expect bytes or a number with a unit like '512MiB'", .s)]
+    ParseByteSizeError { s: String },
+    #[error("Could not parse '{}' to a rate, expect bytes per second or a size with a unit like '50MB/s'", .s)]
+    ParseRateError { s: String },
+    #[error("Invalid config {}: {}", .field, .cause)]
+    InvalidConfig { field: String, cause: String },
+    #[error("Unknown store '{}', expect 'auto', 'loose' or 'packs'", .s)]
+    ParseStoreTypeError { s: String },
+    #[error("Unknown repack strategy '{}', expect 'preserve', 'sort-by-hash' or 'sort-by-size'", .s)]
+    ParseRepackStrategyError { s: String },
+    #[error("zstd dictionary {} not found in the container", .id)]
+    DictionaryNotFound { id: u32 },
+    #[error("Invalid zstd dictionary: {}", .cause)]
+    InvalidDictionary { cause: String },
+    #[error("Unsupported container version {}, this rsdos supports versions 1 to {}", .found, .supported)]
+    UnsupportedContainerVersion { found: u32, supported: u32 },
+    #[error("{} requires container version {}, got {}, run `rsdos upgrade` first", .feature, .required, .found)]
+    FeatureNotSupported {
+        feature: String,
+        required: u32,
+        found: u32,
+    },
+    #[error("Quota {} exceeded: {} bytes over the limit of {} bytes", .quota, .requested, .limit)]
+    QuotaExceeded {
+        quota: String,
+        limit: u64,
+        requested: u64,
+    },
 
     // io module errors
     #[error("Unexpected size in copy: expect {} got {}", .expected, .got)]
@@ -47,12 +85,35 @@ pub enum Error {
     ParsePackFilenameError { source: std::num::ParseIntError, n: String},
     #[error("Unexpected checksum, expected: '{}' got: '{}'", .expected, .got)]
     IntegrityError { expected: String, got: String},
+    #[error("Invalid namespace '{}', expect letters, digits, '-', '_' or '.'", .namespace)]
+    InvalidNamespace { namespace: String },
+    #[error("Object {} is not in the container", .hashkey)]
+    MissingObject { hashkey: String },
+    #[error("Invalid hashkey '{}', expect 64 hex characters", .hashkey)]
+    InvalidHashKey { hashkey: String },
+    #[error("Refusing to write pack {} at {}, its indexed entries end at {}, packs are append-only", .pack_id, .offset, .indexed_end)]
+    PackNotAppendOnly {
+        pack_id: u64,
+        offset: u64,
+        indexed_end: u64,
+    },
+    #[error("Pack storage {} failed: {}", .url, .cause)]
+    PackStorage { url: String, cause: String },
 
     // db module erors
     #[error("rusqlite error")]
     RusqliteError(#[from] rusqlite::Error),
+    #[error("Could not open the index DB at {}", .path.display())]
+    SQLiteOpenError {
+        source: rusqlite::Error,
+        path: PathBuf,
+    },
     #[error("Could not select from DB")]
     SQLiteSelectError { source: rusqlite::Error },
     #[error("Could not insert to DB")]
     SQLiteInsertError { source: rusqlite::Error },
+    #[error("Object {} was written but its index entry was not committed: {}", .hashkey, .cause)]
+    NotCommitted { hashkey: String, cause: String },
+    #[error("Index schema version {} is newer than the supported version {}, refusing to write", .found, .supported)]
+    SchemaTooNew { found: u32, supported: u32 },
 }
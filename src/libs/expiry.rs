@@ -0,0 +1,102 @@
+use std::time::SystemTime;
+
+use crate::index::ObjectIndex;
+use crate::io::check_hashkey;
+use crate::{db, Container, Error};
+
+/// Expire the objects of ``hashkeys`` at ``at`` (to the second), replacing the expiry they had.
+/// Expired objects are deleted by ``maintain::expire``, until then they are still read.
+///
+/// NOTE: an object is stored once whoever inserts it, inserting it again clears its expiry so
+/// that the content is kept for the later insert. Set the expiry of an object inserted to expire
+/// right after inserting it.
+pub fn set_expiry<S: AsRef<str>>(
+    cnt: &Container,
+    hashkeys: &[S],
+    at: SystemTime,
+) -> Result<(), Error> {
+    cnt.valid()?;
+    hashkeys
+        .iter()
+        .try_for_each(|h| check_hashkey(h.as_ref()))?;
+
+    let hashkeys: Vec<String> = hashkeys.iter().map(|h| h.as_ref().to_string()).collect();
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    tx.set_expiry(&hashkeys, at)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Keep the objects of ``hashkeys`` forever again and return how many had an expiry.
+pub fn clear_expiry<S: AsRef<str>>(cnt: &Container, hashkeys: &[S]) -> Result<u64, Error> {
+    cnt.valid()?;
+
+    let hashkeys: Vec<String> = hashkeys.iter().map(|h| h.as_ref().to_string()).collect();
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    let cleared = tx.clear_expiry(&hashkeys)?;
+    tx.commit()?;
+    Ok(cleared)
+}
+
+/// When the object ``hashkey`` expires, ``None`` if it is kept forever.
+pub fn expiry(cnt: &Container, hashkey: &str) -> Result<Option<SystemTime>, Error> {
+    cnt.valid()?;
+    check_hashkey(hashkey)?;
+
+    cnt.index()?.expiry(hashkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io_loose::insert as loose_insert;
+    use crate::io_packs;
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn expiry_set_and_clear() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = loose_insert(b"test".to_vec(), &cnt).unwrap().hashkey;
+        assert_eq!(expiry(&cnt, &hashkey).unwrap(), None);
+
+        let at = UNIX_EPOCH + Duration::from_secs(1000);
+        set_expiry(&cnt, &[&hashkey], at).unwrap();
+        assert_eq!(expiry(&cnt, &hashkey).unwrap(), Some(at));
+        let index = cnt.index().unwrap();
+        assert!(index
+            .expired(at - Duration::from_secs(1))
+            .unwrap()
+            .is_empty());
+        assert_eq!(index.expired(at).unwrap(), vec![hashkey.clone()]);
+
+        // replaced
+        set_expiry(&cnt, &[&hashkey], at + Duration::from_secs(10)).unwrap();
+        assert!(index.expired(at).unwrap().is_empty());
+
+        assert_eq!(clear_expiry(&cnt, &[&hashkey, &hashkey]).unwrap(), 1);
+        assert_eq!(expiry(&cnt, &hashkey).unwrap(), None);
+    }
+
+    #[test]
+    fn expiry_cleared_by_insert() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let at = UNIX_EPOCH + Duration::from_secs(1000);
+        let loose = loose_insert(b"loose".to_vec(), &cnt).unwrap().hashkey;
+        let packed = io_packs::insert(b"packed".to_vec(), &cnt).unwrap().hashkey;
+        let named = loose_insert(b"named".to_vec(), &cnt).unwrap().hashkey;
+        set_expiry(&cnt, &[&loose, &packed, &named], at).unwrap();
+
+        loose_insert(b"loose".to_vec(), &cnt).unwrap();
+        io_packs::insert(b"packed".to_vec(), &cnt).unwrap();
+        cnt.insert_to_namespace(b"named".to_vec(), "raw").unwrap();
+        assert_eq!(expiry(&cnt, &loose).unwrap(), None);
+        assert_eq!(expiry(&cnt, &named).unwrap(), None);
+        assert_eq!(expiry(&cnt, &packed).unwrap(), None);
+    }
+}
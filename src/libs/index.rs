@@ -0,0 +1,520 @@
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use crate::bloom::BloomFilter;
+use crate::db::{self, EntryFilter, PackEntry, PackStats, PooledConnection};
+use crate::Error;
+
+/// Hashkeys queried together with ``IN (..)``, below the default limit of SQLite variables.
+const SELECT_BATCH_SIZE: usize = 950;
+
+/// Store the pack index of a container is kept in, recorded as ``Config::index_backend``.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexBackend {
+    /// The ``packs.idx`` SQLite database, the layout of legacy disk-objectstore.
+    #[default]
+    Sqlite,
+}
+
+/// Count and sizes of the indexed objects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    pub count: u64,
+    /// Bytes of the objects when not compressed.
+    pub raw_size: u64,
+    /// Bytes the objects take in packs.
+    pub size: u64,
+}
+
+/// Everything a container keeps about its objects besides their content: where every packed
+/// object is (the ``PackEntry`` of its hashkey), the manifests of the chunked objects, the
+/// namespaces and expiries of the objects and the loose counters.
+///
+/// Each index backend implements it, so the container does not depend on the backend it uses,
+/// see ``Container::index``. Writes go to the transaction the backend is in, if any, e.g. a SQLite
+/// ``Transaction`` derefs to the ``Connection`` implementing it: pack writes and ``maintain`` add,
+/// dedup and list entries through it in their write transaction.
+pub trait ObjectIndex: Send {
+    /// Add ``entries``, the entries of hashkeys already indexed are ignored.
+    fn insert(&self, entries: &[PackEntry]) -> Result<(), Error>;
+
+    /// Entry of ``hashkey``, ``None`` if it is not indexed.
+    fn select(&self, hashkey: &str) -> Result<Option<PackEntry>, Error>;
+
+    /// Entries of the indexed ``hashkeys`` in pack order, i.e. by ``pack_id`` and then
+    /// ``offset``. Hashkeys not indexed are skipped.
+    fn select_many(&self, hashkeys: &[String]) -> Result<Vec<PackEntry>, Error>;
+
+    fn stats(&self) -> Result<IndexStats, Error>;
+
+    /// Call ``f`` with every entry, stopping at the first error.
+    fn iterate(&self, f: &mut dyn FnMut(PackEntry) -> Result<(), Error>) -> Result<(), Error>;
+
+    /// Call ``f`` with the entries matching ``filter`` in the order they were inserted, stopping
+    /// at the first error.
+    fn iterate_filtered(
+        &self,
+        filter: &EntryFilter,
+        f: &mut dyn FnMut(PackEntry) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+
+    /// Point the entry of ``entry.hashkey`` to where ``entry`` is now, e.g. once it is rewritten
+    /// to another pack. Its raw size is kept.
+    fn relocate(&self, entry: &PackEntry) -> Result<(), Error>;
+
+    /// End of the last indexed entry of pack ``pack_id``, 0 if none of its entries is indexed.
+    /// The pack must never be written before it.
+    fn pack_end(&self, pack_id: u64) -> Result<u64, Error>;
+
+    /// Count and sizes of the indexed objects of every pack, ordered by ``pack_id``.
+    fn pack_stats(&self) -> Result<Vec<PackStats>, Error>;
+
+    /// Bloom filter over the hashkeys of the packed and the chunked objects.
+    fn bloom(&self) -> Result<BloomFilter, Error>;
+
+    /// Drop all that is indexed of ``hashkeys``: pack entries, chunked records, namespaces and
+    /// expiries.
+    fn remove(&self, hashkeys: &[String]) -> Result<(), Error>;
+
+    /// Record the chunked object ``hashkey`` of ``raw_size`` bytes read through ``manifest``.
+    fn insert_chunked(&self, hashkey: &str, raw_size: u64, manifest: &str) -> Result<(), Error>;
+
+    /// Raw size and manifest hashkey of the chunked object ``hashkey``.
+    fn select_chunked(&self, hashkey: &str) -> Result<Option<(u64, String)>, Error>;
+
+    fn list_chunked(&self) -> Result<Vec<String>, Error>;
+
+    /// Count and size of the loose objects, ``None`` until they are counted.
+    fn loose_counters(&self) -> Result<Option<(u64, u64)>, Error>;
+
+    fn set_loose_counters(&self, count: u64, size: u64) -> Result<(), Error>;
+
+    /// Add ``count`` objects of ``size`` bytes (negative when removed) to the loose counters,
+    /// nothing is done while they are not counted yet.
+    fn add_loose(&self, count: i64, size: i64) -> Result<(), Error>;
+
+    /// Add ``hashkeys`` to ``namespace``, the ones already in it are ignored.
+    fn add_to_namespace(&self, namespace: &str, hashkeys: &[String]) -> Result<(), Error>;
+
+    /// Add the ``hashkeys`` that are in no namespace yet to ``namespace``.
+    fn add_untagged(&self, namespace: &str, hashkeys: &[String]) -> Result<(), Error>;
+
+    /// Remove ``hashkeys`` from ``namespace``, returns how many were in it.
+    fn remove_from_namespace(&self, namespace: &str, hashkeys: &[String]) -> Result<u64, Error>;
+
+    /// Forget ``namespace``, its objects are left as they are.
+    fn delete_namespace(&self, namespace: &str) -> Result<(), Error>;
+
+    /// Hashkeys in ``namespace``, sorted.
+    fn namespace_hashkeys(&self, namespace: &str) -> Result<Vec<String>, Error>;
+
+    /// Hashkeys in ``namespace`` and in no other namespace.
+    fn namespace_only(&self, namespace: &str) -> Result<Vec<String>, Error>;
+
+    /// Namespaces ``hashkey`` is in, sorted, ``namespace::UNSCOPED`` aside.
+    fn namespaces_of(&self, hashkey: &str) -> Result<Vec<String>, Error>;
+
+    /// Call ``f`` with the namespace, the hashkey and the indexed size of every object in a
+    /// namespace (``namespace::UNSCOPED`` aside) ordered by namespace, stopping at the first
+    /// error. The size is ``None`` for loose objects.
+    fn for_each_in_namespaces(
+        &self,
+        f: &mut dyn FnMut(String, String, Option<u64>) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+
+    /// Expire ``hashkeys`` at ``at``, replacing the expiry they had.
+    fn set_expiry(&self, hashkeys: &[String], at: SystemTime) -> Result<(), Error>;
+
+    /// Clear the expiry of ``hashkeys``, returns how many had one.
+    fn clear_expiry(&self, hashkeys: &[String]) -> Result<u64, Error>;
+
+    /// When ``hashkey`` expires, ``None`` if it has no expiry.
+    fn expiry(&self, hashkey: &str) -> Result<Option<SystemTime>, Error>;
+
+    /// Hashkeys expired at ``now``.
+    fn expired(&self, now: SystemTime) -> Result<Vec<String>, Error>;
+}
+
+impl ObjectIndex for Connection {
+    fn insert(&self, entries: &[PackEntry]) -> Result<(), Error> {
+        db::insert_batch(self, entries)
+    }
+
+    fn select(&self, hashkey: &str) -> Result<Option<PackEntry>, Error> {
+        db::select(self, hashkey)
+    }
+
+    fn select_many(&self, hashkeys: &[String]) -> Result<Vec<PackEntry>, Error> {
+        let mut entries = vec![];
+        for batch in hashkeys.chunks(SELECT_BATCH_SIZE) {
+            let placeholders = vec!["?"; batch.len()].join(",");
+            let mut stmt = self
+                .prepare_cached(&format!(
+                    "SELECT {} FROM db_object WHERE hashkey IN ({placeholders}) ORDER BY pack_id, offset",
+                    db::ENTRY_COLUMNS
+                ))
+                .map_err(|err| Error::SQLiteSelectError { source: err })?;
+            let rows = stmt
+                .query_map(params_from_iter(batch), db::pack_entry)
+                .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+                .map_err(|err| Error::SQLiteSelectError { source: err })?;
+            entries.extend(rows);
+        }
+        if hashkeys.len() > SELECT_BATCH_SIZE {
+            entries.sort_by_key(|entry| (entry.pack_id, entry.offset));
+        }
+        Ok(entries)
+    }
+
+    fn stats(&self) -> Result<IndexStats, Error> {
+        self.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0), COALESCE(SUM(length), 0) FROM db_object",
+            [],
+            |row| {
+                Ok(IndexStats {
+                    count: row.get(0)?,
+                    raw_size: row.get(1)?,
+                    size: row.get(2)?,
+                })
+            },
+        )
+        .map_err(|err| Error::SQLiteSelectError { source: err })
+    }
+
+    fn iterate(&self, f: &mut dyn FnMut(PackEntry) -> Result<(), Error>) -> Result<(), Error> {
+        db::iterate(self).try_for_each(|entry| f(entry?))
+    }
+
+    fn iterate_filtered(
+        &self,
+        filter: &EntryFilter,
+        f: &mut dyn FnMut(PackEntry) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        db::for_each_filtered(self, filter, f)
+    }
+
+    fn relocate(&self, entry: &PackEntry) -> Result<(), Error> {
+        db::relocate(self, entry)
+    }
+
+    fn pack_end(&self, pack_id: u64) -> Result<u64, Error> {
+        db::pack_end(self, pack_id)
+    }
+
+    fn pack_stats(&self) -> Result<Vec<PackStats>, Error> {
+        db::stats_per_pack(self)
+    }
+
+    fn bloom(&self) -> Result<BloomFilter, Error> {
+        db::build_bloom(self)
+    }
+
+    fn remove(&self, hashkeys: &[String]) -> Result<(), Error> {
+        db::remove(self, hashkeys)
+    }
+
+    fn insert_chunked(&self, hashkey: &str, raw_size: u64, manifest: &str) -> Result<(), Error> {
+        db::insert_chunked(self, hashkey, raw_size, manifest)
+    }
+
+    fn select_chunked(&self, hashkey: &str) -> Result<Option<(u64, String)>, Error> {
+        db::select_chunked(self, hashkey)
+    }
+
+    fn list_chunked(&self) -> Result<Vec<String>, Error> {
+        db::list_chunked(self)
+    }
+
+    fn loose_counters(&self) -> Result<Option<(u64, u64)>, Error> {
+        db::loose_counters(self)
+    }
+
+    fn set_loose_counters(&self, count: u64, size: u64) -> Result<(), Error> {
+        db::set_loose_counters(self, count, size)
+    }
+
+    fn add_loose(&self, count: i64, size: i64) -> Result<(), Error> {
+        db::add_loose(self, count, size)
+    }
+
+    fn add_to_namespace(&self, namespace: &str, hashkeys: &[String]) -> Result<(), Error> {
+        db::add_to_namespace(self, namespace, hashkeys)
+    }
+
+    fn add_untagged(&self, namespace: &str, hashkeys: &[String]) -> Result<(), Error> {
+        db::add_untagged(self, namespace, hashkeys)
+    }
+
+    fn remove_from_namespace(&self, namespace: &str, hashkeys: &[String]) -> Result<u64, Error> {
+        db::remove_from_namespace(self, namespace, hashkeys)
+    }
+
+    fn delete_namespace(&self, namespace: &str) -> Result<(), Error> {
+        db::delete_namespace(self, namespace)
+    }
+
+    fn namespace_hashkeys(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        db::namespace_hashkeys(self, namespace)
+    }
+
+    fn namespace_only(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        db::namespace_only(self, namespace)
+    }
+
+    fn namespaces_of(&self, hashkey: &str) -> Result<Vec<String>, Error> {
+        db::namespaces_of(self, hashkey)
+    }
+
+    fn for_each_in_namespaces(
+        &self,
+        f: &mut dyn FnMut(String, String, Option<u64>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        db::for_each_in_namespaces(self, f)
+    }
+
+    fn set_expiry(&self, hashkeys: &[String], at: SystemTime) -> Result<(), Error> {
+        db::set_expiry(self, hashkeys, at)
+    }
+
+    fn clear_expiry(&self, hashkeys: &[String]) -> Result<u64, Error> {
+        db::clear_expiry(self, hashkeys)
+    }
+
+    fn expiry(&self, hashkey: &str) -> Result<Option<SystemTime>, Error> {
+        db::expiry(self, hashkey)
+    }
+
+    fn expired(&self, now: SystemTime) -> Result<Vec<String>, Error> {
+        db::expired(self, now)
+    }
+}
+
+impl ObjectIndex for PooledConnection<'_> {
+    fn insert(&self, entries: &[PackEntry]) -> Result<(), Error> {
+        (**self).insert(entries)
+    }
+
+    fn select(&self, hashkey: &str) -> Result<Option<PackEntry>, Error> {
+        (**self).select(hashkey)
+    }
+
+    fn select_many(&self, hashkeys: &[String]) -> Result<Vec<PackEntry>, Error> {
+        (**self).select_many(hashkeys)
+    }
+
+    fn stats(&self) -> Result<IndexStats, Error> {
+        (**self).stats()
+    }
+
+    fn iterate(&self, f: &mut dyn FnMut(PackEntry) -> Result<(), Error>) -> Result<(), Error> {
+        (**self).iterate(f)
+    }
+
+    fn iterate_filtered(
+        &self,
+        filter: &EntryFilter,
+        f: &mut dyn FnMut(PackEntry) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        (**self).iterate_filtered(filter, f)
+    }
+
+    fn relocate(&self, entry: &PackEntry) -> Result<(), Error> {
+        (**self).relocate(entry)
+    }
+
+    fn pack_end(&self, pack_id: u64) -> Result<u64, Error> {
+        (**self).pack_end(pack_id)
+    }
+
+    fn pack_stats(&self) -> Result<Vec<PackStats>, Error> {
+        (**self).pack_stats()
+    }
+
+    fn bloom(&self) -> Result<BloomFilter, Error> {
+        (**self).bloom()
+    }
+
+    fn remove(&self, hashkeys: &[String]) -> Result<(), Error> {
+        (**self).remove(hashkeys)
+    }
+
+    fn insert_chunked(&self, hashkey: &str, raw_size: u64, manifest: &str) -> Result<(), Error> {
+        (**self).insert_chunked(hashkey, raw_size, manifest)
+    }
+
+    fn select_chunked(&self, hashkey: &str) -> Result<Option<(u64, String)>, Error> {
+        (**self).select_chunked(hashkey)
+    }
+
+    fn list_chunked(&self) -> Result<Vec<String>, Error> {
+        (**self).list_chunked()
+    }
+
+    fn loose_counters(&self) -> Result<Option<(u64, u64)>, Error> {
+        (**self).loose_counters()
+    }
+
+    fn set_loose_counters(&self, count: u64, size: u64) -> Result<(), Error> {
+        (**self).set_loose_counters(count, size)
+    }
+
+    fn add_loose(&self, count: i64, size: i64) -> Result<(), Error> {
+        (**self).add_loose(count, size)
+    }
+
+    fn add_to_namespace(&self, namespace: &str, hashkeys: &[String]) -> Result<(), Error> {
+        (**self).add_to_namespace(namespace, hashkeys)
+    }
+
+    fn add_untagged(&self, namespace: &str, hashkeys: &[String]) -> Result<(), Error> {
+        (**self).add_untagged(namespace, hashkeys)
+    }
+
+    fn remove_from_namespace(&self, namespace: &str, hashkeys: &[String]) -> Result<u64, Error> {
+        (**self).remove_from_namespace(namespace, hashkeys)
+    }
+
+    fn delete_namespace(&self, namespace: &str) -> Result<(), Error> {
+        (**self).delete_namespace(namespace)
+    }
+
+    fn namespace_hashkeys(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        (**self).namespace_hashkeys(namespace)
+    }
+
+    fn namespace_only(&self, namespace: &str) -> Result<Vec<String>, Error> {
+        (**self).namespace_only(namespace)
+    }
+
+    fn namespaces_of(&self, hashkey: &str) -> Result<Vec<String>, Error> {
+        (**self).namespaces_of(hashkey)
+    }
+
+    fn for_each_in_namespaces(
+        &self,
+        f: &mut dyn FnMut(String, String, Option<u64>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        (**self).for_each_in_namespaces(f)
+    }
+
+    fn set_expiry(&self, hashkeys: &[String], at: SystemTime) -> Result<(), Error> {
+        (**self).set_expiry(hashkeys, at)
+    }
+
+    fn clear_expiry(&self, hashkeys: &[String]) -> Result<u64, Error> {
+        (**self).clear_expiry(hashkeys)
+    }
+
+    fn expiry(&self, hashkey: &str) -> Result<Option<SystemTime>, Error> {
+        (**self).expiry(hashkey)
+    }
+
+    fn expired(&self, now: SystemTime) -> Result<Vec<String>, Error> {
+        (**self).expired(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn sqlite_index_roundtrip() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let index = cnt.index().unwrap();
+        let entry = |i: u64| PackEntry {
+            hashkey: format!("{i:064}"),
+            compressed: false,
+            raw_size: 10,
+            size: 10,
+            offset: 10 * (2000 - i),
+            pack_id: 0,
+        };
+        let entries: Vec<_> = (0..2000).map(entry).collect();
+        index.insert(&entries).unwrap();
+        // already indexed, ignored
+        index.insert(&entries[..1]).unwrap();
+
+        assert_eq!(
+            index.stats().unwrap(),
+            IndexStats {
+                count: 2000,
+                raw_size: 20000,
+                size: 20000
+            }
+        );
+        assert_eq!(
+            index.select(&entries[7].hashkey).unwrap().unwrap().offset,
+            19930
+        );
+        assert!(index.select(&"f".repeat(64)).unwrap().is_none());
+
+        let mut hashkeys: Vec<_> = entries.iter().map(|e| e.hashkey.clone()).collect();
+        hashkeys.push("f".repeat(64));
+        let found = index.select_many(&hashkeys).unwrap();
+        assert_eq!(found.len(), 2000);
+        assert!(found.windows(2).all(|w| w[0].offset < w[1].offset));
+
+        let mut n = 0;
+        index
+            .iterate(&mut |_| {
+                n += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(n, 2000);
+    }
+
+    #[test]
+    fn sqlite_index_relocate_and_remove() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let index = cnt.index().unwrap();
+        let entry = PackEntry {
+            hashkey: "a".repeat(64),
+            compressed: false,
+            raw_size: 10,
+            size: 10,
+            offset: 0,
+            pack_id: 0,
+        };
+        index.insert(std::slice::from_ref(&entry)).unwrap();
+        index
+            .insert_chunked(&"b".repeat(64), 100, &"c".repeat(64))
+            .unwrap();
+        let hashkeys = vec![entry.hashkey.clone(), "b".repeat(64)];
+        index.add_to_namespace("raw", &hashkeys).unwrap();
+        index.set_expiry(&hashkeys, SystemTime::now()).unwrap();
+
+        index
+            .relocate(&PackEntry {
+                compressed: true,
+                size: 7,
+                offset: 20,
+                pack_id: 3,
+                ..entry.clone()
+            })
+            .unwrap();
+        let moved = index.select(&entry.hashkey).unwrap().unwrap();
+        assert_eq!(
+            (
+                moved.compressed,
+                moved.raw_size,
+                moved.size,
+                moved.offset,
+                moved.pack_id
+            ),
+            (true, 10, 7, 20, 3)
+        );
+        assert_eq!(index.pack_end(3).unwrap(), 27);
+
+        index.remove(&hashkeys).unwrap();
+        assert!(index.select(&entry.hashkey).unwrap().is_none());
+        assert!(index.select_chunked(&hashkeys[1]).unwrap().is_none());
+        assert!(index.namespace_hashkeys("raw").unwrap().is_empty());
+        assert!(index.expired(SystemTime::now()).unwrap().is_empty());
+    }
+}
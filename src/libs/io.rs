@@ -1,9 +1,207 @@
+use crate::config::{hash_algorithm, CompressionPolicy};
+use crate::db::PackEntry;
+use crate::io_loose::LObject;
+use crate::io_packs::PObject;
 use crate::Error;
 use bytes::Buf;
-use ring::digest::{Algorithm, Context, Digest};
+use ring::digest::{self, Algorithm, Context, Digest};
+use serde::Serialize;
 use std::fs;
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Outcome of inserting one source of a batch, ``Ok`` once the object is durably written.
+pub type InsertOutcome<T> = Result<T, Error>;
+
+/// Store an inserted object is kept in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Store {
+    Loose,
+    Packs,
+    Chunked,
+}
+
+/// An inserted object, also returned when the object was already in the store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InsertResult {
+    pub hashkey: String,
+    /// Size of the content.
+    pub raw_size: u64,
+    /// Bytes the object takes in its store, less than ``raw_size`` once compressed. For a
+    /// chunked object it is ``raw_size``, chunks shared with other objects are not accounted.
+    pub stored_size: u64,
+    pub compressed: bool,
+    pub store: Store,
+}
+
+impl InsertResult {
+    pub(crate) fn loose(hashkey: String, size: u64) -> Self {
+        Self {
+            hashkey,
+            raw_size: size,
+            stored_size: size,
+            compressed: false,
+            store: Store::Loose,
+        }
+    }
+
+    pub(crate) fn packed(entry: PackEntry) -> Self {
+        Self {
+            hashkey: entry.hashkey,
+            raw_size: entry.raw_size,
+            stored_size: entry.size,
+            compressed: entry.compressed,
+            store: Store::Packs,
+        }
+    }
+}
+
+/// An object read from loose or packs.
+pub enum Object {
+    Loose(LObject),
+    Packed(PObject),
+}
+
+impl Object {
+    pub fn hashkey(&self) -> &str {
+        match self {
+            Object::Loose(obj) => &obj.id,
+            Object::Packed(obj) => &obj.id,
+        }
+    }
+
+    /// Size of the content, whatever the size it takes in its store.
+    pub fn size(&self) -> u64 {
+        match self {
+            Object::Loose(obj) => obj.expected_size,
+            Object::Packed(obj) => obj.raw_size,
+        }
+    }
+
+    pub fn store(&self) -> Store {
+        match self {
+            Object::Loose(_) => Store::Loose,
+            Object::Packed(_) => Store::Packs,
+        }
+    }
+}
+
+impl Object {
+    /// ``make_reader`` with ``options``, see ``ExtractOptions``.
+    pub fn make_reader_with(
+        &self,
+        options: ExtractOptions,
+    ) -> Result<VerifyingReader<impl Read + '_>, Error> {
+        let rdr = self.make_reader()?;
+        Ok(if options.verify {
+            VerifyingReader::new(rdr, self.hashkey())
+        } else {
+            VerifyingReader::unchecked(rdr)
+        })
+    }
+}
+
+/// How the content of an object is read, see ``Object::make_reader_with``.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtractOptions {
+    /// Re-hash the content as it is read, so a reader that reaches EOF has returned the exact
+    /// content of the hashkey, without a separate validation pass. See ``VerifyingReader``.
+    pub verify: bool,
+}
+
+/// Reader that hashes the content as it is read and, at EOF, fails with an ``io::Error`` of kind
+/// ``InvalidData`` wrapping ``Error::IntegrityError`` if the content does not match its hashkey.
+///
+/// NOTE: the mismatch can only be told once the whole content is read, a caller that must not
+/// use corrupted bytes has to read to EOF before using what it read.
+pub struct VerifyingReader<R>(Verify<R>);
+
+enum Verify<R> {
+    Unchecked(R),
+    Hashing {
+        reader: Box<HashReader<R>>,
+        expected: String,
+    },
+}
+
+impl<R: Read> VerifyingReader<R> {
+    pub fn new(inner: R, hashkey: &str) -> Self {
+        VerifyingReader(Verify::Hashing {
+            reader: Box::new(HashReader::new(inner, &digest::SHA256)),
+            expected: hashkey.to_string(),
+        })
+    }
+
+    /// Read ``inner`` as it is, without verifying it.
+    pub fn unchecked(inner: R) -> Self {
+        VerifyingReader(Verify::Unchecked(inner))
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (reader, expected) = match &mut self.0 {
+            Verify::Unchecked(inner) => return inner.read(buf),
+            Verify::Hashing { reader, expected } => (reader, expected),
+        };
+        let n = reader.read(buf)?;
+        // EOF, unless asked for nothing
+        if n == 0 && !buf.is_empty() {
+            let got = hex::encode(reader.digest());
+            if got != *expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    Error::IntegrityError {
+                        expected: expected.clone(),
+                        got,
+                    },
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl From<LObject> for Object {
+    fn from(obj: LObject) -> Self {
+        Object::Loose(obj)
+    }
+}
+
+impl From<PObject> for Object {
+    fn from(obj: PObject) -> Self {
+        Object::Packed(obj)
+    }
+}
+
+impl ReaderMaker for Object {
+    fn make_reader(&self) -> Result<impl Read, Error> {
+        let rdr: Box<dyn Read> = match self {
+            Object::Loose(obj) => Box::new(obj.make_reader()?),
+            Object::Packed(obj) => Box::new(obj.make_reader()?),
+        };
+        Ok(rdr)
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        match self {
+            Object::Loose(obj) => obj.size_hint(),
+            Object::Packed(obj) => obj.size_hint(),
+        }
+    }
+}
+
+impl TryFrom<Object> for ByteString {
+    type Error = Error;
+
+    fn try_from(obj: Object) -> Result<Self, Self::Error> {
+        match obj {
+            Object::Loose(obj) => obj.try_into(),
+            Object::Packed(obj) => obj.try_into(),
+        }
+    }
+}
 
 pub trait Finishable: Write {
     fn finish(self) -> io::Result<()>;
@@ -58,6 +256,38 @@ where
     }
 }
 
+/// Reader that hashes everything read through it, the counterpart of ``HashWriter`` for when the
+/// content is pulled rather than pushed: hash a source while it is copied into a pack, or while
+/// it is uploaded elsewhere.
+pub struct HashReader<R> {
+    pub reader: R,
+    pub ctx: Context,
+}
+
+impl<R: Read> HashReader<R> {
+    pub fn new(reader: R, algorithm: &'static Algorithm) -> Self {
+        let ctx = Context::new(algorithm);
+        Self { reader, ctx }
+    }
+
+    /// Digest of the bytes read so far, reading can go on.
+    pub fn digest(&self) -> Digest {
+        self.ctx.clone().finish()
+    }
+
+    pub fn finish(self) -> Digest {
+        self.ctx.finish()
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.ctx.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
 /// Copy by chunk (``chunk_size`` in unit bytes) and return a tuple of total bytes read from reader
 /// and total bytes write to writer.
 pub fn copy_by_chunk<R, W>(
@@ -90,7 +320,59 @@ where
     Ok(total_bytes_read as u64)
 }
 
-#[derive(Debug, PartialEq)]
+/// Hashkey the content of ``reader`` would be stored under in a container of ``hash_type`` (its
+/// ``Config::hash_type``), computed without storing anything. E.g. to check a container has an
+/// object before sending it over the network.
+pub fn compute_hashkey<R: Read>(mut reader: R, hash_type: &str) -> Result<String, Error> {
+    let mut hasher = HashWriter::new(io::sink(), hash_algorithm(hash_type)?);
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finish()))
+}
+
+/// Files from this size on are mapped in memory to be hashed by ``compute_file_hashkey``, the
+/// mapping costs more than reading smaller ones.
+pub const MMAP_MIN_SIZE: u64 = 1024 * 1024;
+
+/// ``compute_hashkey`` of the file at ``path``. A file of ``MMAP_MIN_SIZE`` or more is mapped in
+/// memory and hashed in place rather than copied through a buffer.
+///
+/// NOTE: the file must not be written to while it is hashed, the hashkey would be of a content
+/// that never existed as a whole, and a mapped file truncated meanwhile aborts the process.
+pub fn compute_file_hashkey(path: &Path, hash_type: &str) -> Result<String, Error> {
+    let algorithm = hash_algorithm(hash_type)?;
+    let file = fs::File::open(path)?;
+    if file.metadata()?.len() < MMAP_MIN_SIZE {
+        return compute_hashkey(file, hash_type);
+    }
+
+    // SAFETY: the mapping is only read and dropped before returning, the file is not changed
+    // meanwhile as required above
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(hex::encode(digest::digest(algorithm, &mmap)))
+}
+
+/// Whether ``hashkey`` is a well formed sha256 hashkey, 64 hex characters.
+#[must_use]
+pub fn is_hashkey(hashkey: &str) -> bool {
+    hashkey.len() == 64 && hashkey.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// ``Error::InvalidHashKey`` unless ``hashkey`` is well formed, for the lookups that would
+/// otherwise slice it into a loose path or query the index with it.
+pub fn check_hashkey(hashkey: &str) -> Result<(), Error> {
+    if is_hashkey(hashkey) {
+        Ok(())
+    } else {
+        Err(Error::InvalidHashKey {
+            hashkey: hashkey.to_string(),
+        })
+    }
+}
+
+/// Bytes read from the start of a content to guess its format.
+pub const SNIFF_LEN: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MaybeContentFormat {
     MaybeLargeText,
     SmallContent,
@@ -98,12 +380,34 @@ pub enum MaybeContentFormat {
     ZFile([u8; 4]),
 }
 
+impl MaybeContentFormat {
+    /// Algorithm a ``ZFile`` is already compressed with, guessed from its magic: ``zlib``,
+    /// ``zstd`` or else the hex of the magic (e.g. a ``skip_magic_prefixes`` of the policy).
+    #[must_use]
+    pub fn precompression(&self) -> Option<String> {
+        match self {
+            MaybeContentFormat::ZFile([0x78, ..]) => Some("zlib".to_string()),
+            MaybeContentFormat::ZFile([0x28, 0xB5, 0x2F, 0xFD]) => Some("zstd".to_string()),
+            MaybeContentFormat::ZFile(magic) => Some(hex::encode(magic)),
+            _ => None,
+        }
+    }
+}
+
 pub trait ReaderMaker {
     fn make_reader(&self) -> Result<impl Read, Error>;
 
-    fn maybe_content_format(&self) -> Result<MaybeContentFormat, Error> {
+    fn maybe_content_format(
+        &self,
+        _policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, Error> {
         Ok(MaybeContentFormat::MaybeLargeText)
     }
+
+    /// Size of the content if it is known without reading it, used to pick the pack tier.
+    fn size_hint(&self) -> Option<u64> {
+        None
+    }
 }
 
 impl ReaderMaker for PathBuf {
@@ -113,38 +417,126 @@ impl ReaderMaker for PathBuf {
     }
 
     /// The method on ``PathBuf`` will estimate whether it is worth to compress.
-    /// Here is the decision making flow:
-    /// - If it is a file (`SmallContent`) < 850 bytes don't compress. For why 850 bytes see: https://developer.att.com/video-optimizer/docs/best-practices/text-file-compression
-    /// - Read 2 header bytes if it is a zilb or a zstd(which is 4 bytes in header) (`ZFile([u8; 4])`), don't compress. (this will be override if recompress was on and different compression algorithm is assigned.)
-    /// - Read 512 bytes and check if it is a binary (`MaybeBinary`) (by checking null bytes which is a heuristic for it is a binary data)
-    /// - none of above is true, regard it as "worth to compress!" (`MabyLargeText`)
+    /// - If the file extension is one of ``force_compress_extensions``, compress (`MaybeLargeText`)
+    /// - otherwise guess from the size and the first bytes, see ``guess_content_format``.
     ///
     /// This avoid to run actuall compress which bring overhead.
     /// XXX: rename to maybe_text_format, content is a bit vague
-    fn maybe_content_format(&self) -> Result<MaybeContentFormat, Error> {
-        let mut f = fs::OpenOptions::new().read(true).open(self)?;
-        if f.metadata().unwrap().len() <= 850 {
+    fn maybe_content_format(
+        &self,
+        policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, Error> {
+        if self
+            .extension()
+            .is_some_and(|ext| policy.forces_extension(&ext.to_string_lossy()))
+        {
+            return Ok(MaybeContentFormat::MaybeLargeText);
+        }
+
+        let f = fs::OpenOptions::new().read(true).open(self)?;
+        let size = f.metadata()?.len();
+        if size <= policy.min_compress_size {
             return Ok(MaybeContentFormat::SmallContent);
         }
+        let rdr = PeekReader::new(f)?;
+        Ok(guess_content_format(rdr.head(), size, policy))
+    }
 
-        // if it is zlib/zstd
-        let mut buf = [0u8; 4];
-        f.read_exact(&mut buf)?;
+    fn size_hint(&self) -> Option<u64> {
+        fs::metadata(self).ok().map(|meta| meta.len())
+    }
+}
 
-        if buf[0] == 0x78 || buf == [0x28, 0xB5, 0x2F, 0xFD] {
-            return Ok(MaybeContentFormat::ZFile(buf));
-        }
+/// Guess whether a content of ``size`` bytes is worth to compress from ``head``, its first
+/// ``SNIFF_LEN`` bytes (or all of it when shorter). Here is the decision making flow, the
+/// thresholds are from ``policy``:
+/// - If it is a content (`SmallContent`) <= ``min_compress_size`` bytes don't compress. For why
+///   850 bytes by default see: https://developer.att.com/video-optimizer/docs/best-practices/text-file-compression
+/// - If the header starts with one of ``skip_magic_prefixes`` (zlib, zstd by default)
+///   (`ZFile([u8; 4])`), don't compress. (this will be override if recompress was on and
+///   different compression algorithm is assigned.)
+/// - If ``head`` has null bytes, which is a heuristic for binary data (`MaybeBinary`), don't
+///   compress.
+/// - none of above is true, regard it as "worth to compress!" (`MaybeLargeText`)
+#[must_use]
+pub fn guess_content_format(
+    head: &[u8],
+    size: u64,
+    policy: &CompressionPolicy,
+) -> MaybeContentFormat {
+    if size <= policy.min_compress_size {
+        return MaybeContentFormat::SmallContent;
+    }
 
-        let mut buf = [0u8; 512];
-        f.seek(SeekFrom::Start(0))?;
-        f.read_exact(&mut buf)?;
+    // if it is zlib/zstd
+    if policy.skips_magic(head) {
+        return MaybeContentFormat::ZFile(magic_of(head));
+    }
 
-        // if find any null bytes then it is maybe binary
-        if buf.contains(&0x00) {
-            return Ok(MaybeContentFormat::MaybeBinary);
-        }
+    // if find any null bytes then it is maybe binary
+    if head[..head.len().min(SNIFF_LEN)].contains(&0x00) {
+        return MaybeContentFormat::MaybeBinary;
+    }
 
-        Ok(MaybeContentFormat::MaybeLargeText)
+    MaybeContentFormat::MaybeLargeText
+}
+
+/// First 4 bytes of ``head``, zero-padded when shorter.
+fn magic_of(head: &[u8]) -> [u8; 4] {
+    let mut magic = [0u8; 4];
+    let n = head.len().min(4);
+    magic[..n].copy_from_slice(&head[..n]);
+    magic
+}
+
+/// Reader that reads the first ``SNIFF_LEN`` bytes ahead, so the format of a content only
+/// readable once (a socket, a Python stream) can be guessed before it is consumed. Reading from
+/// it yields the whole content, the peeked bytes included.
+pub struct PeekReader<R> {
+    head: io::Cursor<Vec<u8>>,
+    inner: R,
+}
+
+impl<R: Read> PeekReader<R> {
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        (&mut inner).take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+        Ok(PeekReader {
+            head: io::Cursor::new(head),
+            inner,
+        })
+    }
+
+    /// The peeked bytes, shorter than ``SNIFF_LEN`` only if that is the whole content.
+    #[must_use]
+    pub fn head(&self) -> &[u8] {
+        self.head.get_ref()
+    }
+
+    /// Guess the format of the content, ``size`` if known else it is taken from the peeked bytes
+    /// when they are the whole content.
+    #[must_use]
+    pub fn content_format(
+        &self,
+        size: Option<u64>,
+        policy: &CompressionPolicy,
+    ) -> MaybeContentFormat {
+        let head = self.head();
+        let size = size.unwrap_or(if head.len() < SNIFF_LEN {
+            head.len() as u64
+        } else {
+            u64::MAX
+        });
+        guess_content_format(head, size, policy)
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.head.read(buf)? {
+            0 => self.inner.read(buf),
+            n => Ok(n),
+        }
     }
 }
 
@@ -155,10 +547,119 @@ impl ReaderMaker for ByteString {
     fn make_reader(&self) -> Result<impl Read, Error> {
         Ok(self.reader())
     }
+
+    fn maybe_content_format(
+        &self,
+        policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, Error> {
+        let head = &self[..self.len().min(SNIFF_LEN)];
+        Ok(guess_content_format(head, self.len() as u64, policy))
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
 }
 
-#[cfg(test)]
+/// A source whose pack entry is compressed, or not, whatever ``CompressionPolicy`` guesses for
+/// it. The entry is still written with the compression of the insert, ``always`` has no effect
+/// when it is uncompressed. NOTE: ``io_packs::insert`` guesses from its sandbox copy of the
+/// source, pass the override to ``io_packs::insert_many``.
+#[derive(Debug, Clone)]
+pub struct CompressOverride<R> {
+    source: R,
+    compress: bool,
+}
+
+impl<R> CompressOverride<R> {
+    #[must_use]
+    pub fn always(source: R) -> Self {
+        CompressOverride {
+            source,
+            compress: true,
+        }
+    }
+
+    #[must_use]
+    pub fn never(source: R) -> Self {
+        CompressOverride {
+            source,
+            compress: false,
+        }
+    }
+}
 
+impl<R: ReaderMaker> ReaderMaker for CompressOverride<R> {
+    fn make_reader(&self) -> Result<impl Read, Error> {
+        self.source.make_reader()
+    }
+
+    fn maybe_content_format(
+        &self,
+        _policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, Error> {
+        if self.compress {
+            Ok(MaybeContentFormat::MaybeLargeText)
+        } else {
+            Ok(MaybeContentFormat::MaybeBinary)
+        }
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        self.source.size_hint()
+    }
+}
+
+/// A source whose format is guessed once, when wrapped, instead of every time it is asked.
+///
+/// Unlike ``guess_content_format`` a small content already compressed is taken as a ``ZFile``,
+/// so that it is stored as it is rather than compressed again with a zstd dictionary.
+#[derive(Debug, Clone)]
+pub struct Sniffed<R> {
+    source: R,
+    format: MaybeContentFormat,
+}
+
+impl<R: ReaderMaker> Sniffed<R> {
+    pub fn new(source: R, policy: &CompressionPolicy) -> Result<Self, Error> {
+        let mut format = source.maybe_content_format(policy)?;
+        if format == MaybeContentFormat::SmallContent {
+            let mut head = Vec::with_capacity(SNIFF_LEN);
+            source
+                .make_reader()?
+                .take(SNIFF_LEN as u64)
+                .read_to_end(&mut head)?;
+            if policy.skips_magic(&head) {
+                format = MaybeContentFormat::ZFile(magic_of(&head));
+            }
+        }
+        Ok(Sniffed { source, format })
+    }
+
+    #[must_use]
+    pub fn format(&self) -> &MaybeContentFormat {
+        &self.format
+    }
+}
+
+impl<R: ReaderMaker> ReaderMaker for Sniffed<R> {
+    fn make_reader(&self) -> Result<impl Read, Error> {
+        self.source.make_reader()
+    }
+
+    fn maybe_content_format(
+        &self,
+        _policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, Error> {
+        Ok(self.format.clone())
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        self.source.size_hint()
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use core::panic;
 
@@ -168,13 +669,14 @@ mod tests {
 
     #[test]
     fn io_maybe_content_format_guess() {
+        let policy = CompressionPolicy::default();
         // small text
         let mut f = tempfile::NamedTempFile::new().unwrap();
         write!(&mut f, "a small text").unwrap();
 
         let p = f.path().to_path_buf();
         assert_eq!(
-            p.maybe_content_format().unwrap(),
+            p.maybe_content_format(&policy).unwrap(),
             MaybeContentFormat::SmallContent
         );
 
@@ -187,7 +689,7 @@ mod tests {
 
         let p = f.path().to_path_buf();
         assert_eq!(
-            p.maybe_content_format().unwrap(),
+            p.maybe_content_format(&policy).unwrap(),
             MaybeContentFormat::SmallContent
         );
 
@@ -202,7 +704,7 @@ mod tests {
 
         let p = f.path().to_path_buf();
         assert_eq!(
-            p.maybe_content_format().unwrap(),
+            p.maybe_content_format(&policy).unwrap(),
             MaybeContentFormat::MaybeBinary
         );
 
@@ -215,7 +717,7 @@ mod tests {
 
         let p = f.path().to_path_buf();
         assert_eq!(
-            p.maybe_content_format().unwrap(),
+            p.maybe_content_format(&policy).unwrap(),
             MaybeContentFormat::MaybeLargeText
         );
 
@@ -224,6 +726,7 @@ mod tests {
 
     #[test]
     fn io_maybe_content_format_guess_zfile() {
+        let policy = CompressionPolicy::default();
         // large zlib file
         let mut f = tempfile::NamedTempFile::new().unwrap();
         let mut encoder = ZlibEncoder::new(&f, Compression::default());
@@ -236,7 +739,7 @@ mod tests {
         let p = f.path().to_path_buf();
         assert!(std::fs::metadata(f.path()).unwrap().len() > 850);
 
-        match p.maybe_content_format().unwrap() {
+        match p.maybe_content_format(&policy).unwrap() {
             MaybeContentFormat::ZFile([b0, _, _, _]) => assert_eq!(b0, 0x78),
             _ => panic!("should be a ZFile compressed with zlib"),
         };
@@ -254,11 +757,198 @@ mod tests {
 
         assert!(std::fs::metadata(f.path()).unwrap().len() > 850);
 
-        match p.maybe_content_format().unwrap() {
+        match p.maybe_content_format(&policy).unwrap() {
             MaybeContentFormat::ZFile(b) => assert_eq!(b, [0x28, 0xB5, 0x2F, 0xFD]),
             _ => panic!("should be a ZFile compressed with zlib"),
         };
 
         f.close().unwrap();
     }
+
+    #[test]
+    fn io_maybe_content_format_policy() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let text = tmp_dir.path().join("small.txt");
+        std::fs::write(&text, "a small text").unwrap();
+        let png = tmp_dir.path().join("image.png");
+        std::fs::write(&png, [b"\x89PNG".as_slice(), &[0u8; 1000]].concat()).unwrap();
+
+        let default = CompressionPolicy::default();
+        assert_eq!(
+            text.maybe_content_format(&default).unwrap(),
+            MaybeContentFormat::SmallContent
+        );
+        assert_eq!(
+            png.maybe_content_format(&default).unwrap(),
+            MaybeContentFormat::MaybeBinary
+        );
+
+        let policy = CompressionPolicy {
+            min_compress_size: 0,
+            skip_magic_prefixes: vec!["89504e47".to_string()],
+            force_compress_extensions: vec!["TXT".to_string()],
+        };
+        assert_eq!(
+            text.maybe_content_format(&policy).unwrap(),
+            MaybeContentFormat::MaybeLargeText
+        );
+        assert_eq!(
+            png.maybe_content_format(&policy).unwrap(),
+            MaybeContentFormat::ZFile(*b"\x89PNG")
+        );
+
+        // the per-insert override wins over the policy
+        assert_eq!(
+            CompressOverride::never(text.clone())
+                .maybe_content_format(&policy)
+                .unwrap(),
+            MaybeContentFormat::MaybeBinary
+        );
+        assert_eq!(
+            CompressOverride::always(png.clone())
+                .maybe_content_format(&policy)
+                .unwrap(),
+            MaybeContentFormat::MaybeLargeText
+        );
+        assert_eq!(CompressOverride::always(png).size_hint(), Some(1004));
+    }
+
+    #[test]
+    fn io_maybe_content_format_guess_bytes() {
+        let policy = CompressionPolicy::default();
+
+        let small = b"a small text".to_vec();
+        assert_eq!(
+            small.maybe_content_format(&policy).unwrap(),
+            MaybeContentFormat::SmallContent
+        );
+
+        let text = "📝 Repeat me 200 times".repeat(200).into_bytes();
+        assert_eq!(
+            text.maybe_content_format(&policy).unwrap(),
+            MaybeContentFormat::MaybeLargeText
+        );
+
+        let zstd_bytes = zstd::encode_all(&text[..], 0).unwrap();
+        let zstd_bytes = [zstd_bytes.clone(), vec![0u8; 1000]].concat();
+        assert_eq!(
+            zstd_bytes.maybe_content_format(&policy).unwrap(),
+            MaybeContentFormat::ZFile([0x28, 0xB5, 0x2F, 0xFD])
+        );
+
+        let mut binary = text.clone();
+        binary.insert(100, 0x00);
+        assert_eq!(
+            binary.maybe_content_format(&policy).unwrap(),
+            MaybeContentFormat::MaybeBinary
+        );
+    }
+
+    #[test]
+    fn io_peek_reader() {
+        let policy = CompressionPolicy::default();
+        let text = "📝 Repeat me 200 times".repeat(200).into_bytes();
+
+        let mut rdr = PeekReader::new(text.as_slice()).unwrap();
+        assert_eq!(rdr.head(), &text[..SNIFF_LEN]);
+        assert_eq!(
+            rdr.content_format(None, &policy),
+            MaybeContentFormat::MaybeLargeText
+        );
+        let mut content = vec![];
+        rdr.read_to_end(&mut content).unwrap();
+        assert_eq!(content, text);
+
+        // a short content is all peeked, its size is known
+        let rdr = PeekReader::new(&b"short"[..]).unwrap();
+        assert_eq!(
+            rdr.content_format(None, &policy),
+            MaybeContentFormat::SmallContent
+        );
+    }
+
+    #[test]
+    fn io_hash_reader() {
+        let content = "📝 Repeat me 200 times".repeat(200).into_bytes();
+        let mut hreader = HashReader::new(content.as_slice(), &digest::SHA256);
+        let mut head = [0u8; 10];
+        hreader.read_exact(&mut head).unwrap();
+        assert_eq!(
+            hreader.digest().as_ref(),
+            digest::digest(&digest::SHA256, &head).as_ref()
+        );
+
+        let mut rest = vec![];
+        hreader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, content[10..]);
+        assert_eq!(
+            hreader.finish().as_ref(),
+            digest::digest(&digest::SHA256, &content).as_ref()
+        );
+    }
+
+    #[test]
+    fn io_compute_hashkey() {
+        let (_tmp_dir, cnt) = crate::test_utils::new_container(1024, "zstd:+3");
+        let content = "📝 Repeat me 200 times".repeat(200).into_bytes();
+        let hashkey = cnt.compute_hashkey(content.as_slice()).unwrap();
+        assert!(!cnt.has_object(&hashkey).unwrap());
+        assert_eq!(
+            crate::io_loose::insert(content.clone(), &cnt)
+                .unwrap()
+                .hashkey,
+            hashkey
+        );
+
+        // read or mapped in memory, the same hashkey
+        let small = tempfile::NamedTempFile::new().unwrap();
+        fs::write(small.path(), &content).unwrap();
+        assert_eq!(
+            compute_file_hashkey(small.path(), "sha256").unwrap(),
+            hashkey
+        );
+        let large = tempfile::NamedTempFile::new().unwrap();
+        let large_content = vec![7u8; MMAP_MIN_SIZE as usize + 1];
+        fs::write(large.path(), &large_content).unwrap();
+        assert_eq!(
+            compute_file_hashkey(large.path(), "sha256").unwrap(),
+            compute_hashkey(large_content.as_slice(), "sha256").unwrap()
+        );
+
+        assert!(matches!(
+            compute_hashkey(content.as_slice(), "md5"),
+            Err(Error::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn io_verify_on_read() {
+        let (_tmp_dir, cnt) = crate::test_utils::new_container(1024, "none");
+        let hashkey = crate::io_loose::insert(b"test".to_vec(), &cnt)
+            .unwrap()
+            .hashkey;
+        let obj = cnt.get(&hashkey).unwrap().unwrap();
+        let read = |options: ExtractOptions| {
+            let mut content = vec![];
+            obj.make_reader_with(options)
+                .unwrap()
+                .read_to_end(&mut content)
+                .map(|_| content)
+        };
+        let verify = ExtractOptions { verify: true };
+        assert_eq!(read(verify).unwrap(), b"test");
+
+        // same size, other content
+        let Object::Loose(lobj) = &obj else {
+            panic!("expect a loose object")
+        };
+        fs::write(&lobj.loc, b"tost").unwrap();
+        assert_eq!(read(ExtractOptions::default()).unwrap(), b"tost");
+        let err = read(verify).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(
+            err.into_inner().unwrap().downcast_ref::<Error>(),
+            Some(Error::IntegrityError { expected, .. }) if *expected == hashkey
+        ));
+    }
 }
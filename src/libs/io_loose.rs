@@ -1,11 +1,17 @@
 use ring::digest;
 use std::fs;
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-use crate::io::{copy_by_chunk, ByteString, HashWriter, ReaderMaker};
-use crate::Container;
+use crate::io::{
+    check_hashkey, copy_by_chunk, ByteString, HashWriter, InsertOutcome, InsertResult, ReaderMaker,
+};
+use crate::namespace::{self, Scope};
+use crate::utils::rename_replace;
 use crate::Error;
+use crate::{db, Container};
+
+const CHUNK_SIZE: usize = 524_288; // 512 KiB TODO: make it configurable??
 
 #[derive(Debug)]
 pub struct LObject {
@@ -47,9 +53,35 @@ impl ReaderMaker for LObject {
     fn make_reader(&self) -> Result<impl Read, Error> {
         Ok(fs::OpenOptions::new().read(true).open(&self.loc)?)
     }
+
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.expected_size)
+    }
+}
+
+impl LObject {
+    /// Reader over ``len`` bytes of the content from ``offset``, the range is clamped to the
+    /// object.
+    pub fn make_range_reader(&self, offset: u64, len: u64) -> Result<impl Read, Error> {
+        let mut f = fs::OpenOptions::new().read(true).open(&self.loc)?;
+        f.seek(SeekFrom::Start(offset.min(self.expected_size)))?;
+        Ok(f.take(len))
+    }
+}
+
+pub fn insert_many<I>(sources: I, cnt: &Container) -> Result<Vec<InsertResult>, Error>
+where
+    I: IntoIterator,
+    I::Item: ReaderMaker,
+{
+    sources.into_iter().map(|s| insert(s, cnt)).collect()
 }
 
-pub fn insert_many<I>(sources: I, cnt: &Container) -> Result<Vec<(u64, String)>, Error>
+/// Insert every source to loose and return one ``InsertOutcome`` per source, in order.
+///
+/// A failing source does not abort the batch, every ``Ok`` object is already in place in loose,
+/// so callers only need to retry the failed sources.
+pub fn try_insert_many<I>(sources: I, cnt: &Container) -> Vec<InsertOutcome<InsertResult>>
 where
     I: IntoIterator,
     I::Item: ReaderMaker,
@@ -57,15 +89,23 @@ where
     sources.into_iter().map(|s| insert(s, cnt)).collect()
 }
 
-pub fn insert<T>(source: T, cnt: &Container) -> Result<(u64, String), Error>
+pub fn insert<T>(source: T, cnt: &Container) -> Result<InsertResult, Error>
+where
+    T: ReaderMaker,
+{
+    insert_in(source, cnt, Scope::Unscoped)
+}
+
+/// Insert to loose for ``scope``, see ``namespace::Scope``.
+pub(crate) fn insert_in<T>(source: T, cnt: &Container, scope: Scope) -> Result<InsertResult, Error>
 where
     T: ReaderMaker,
 {
     cnt.valid()?;
 
-    // <cnt_path>/sandbox/<uuid> as dst
+    // <cnt_path>/sandbox/<uuid> (or <sandbox_dir>/<uuid>) as dst
     let dst = format!("{}.tmp", uuid::Uuid::new_v4());
-    let dst = cnt.sandbox().join(dst);
+    let dst = cnt.insert_sandbox()?.join(dst);
     let mut writer = fs::File::create(&dst)?;
 
     let mut hwriter = HashWriter::new(&mut writer, &digest::SHA256);
@@ -78,32 +118,283 @@ where
     // NOTE: this chunk_size is the upbound of the buf, which in order to control the size of
     // memory usage when coping large file. 512 KiB is way larger then the default buffer size in rust
     // (4KiB). Large buffer may increase chance of loosing data.
-    let chunk_size = 524_288; // 512 KiB TODO: make it configurable??
-                              //
-    let mut stream = source.make_reader()?;
-    let bytes_read = copy_by_chunk(&mut stream, &mut hwriter, chunk_size)
-        .map_err(|err| Error::ChunkCopyError { source: err })?;
+    let copied = source.make_reader().and_then(|mut stream| {
+        copy_by_chunk(&mut stream, &mut hwriter, CHUNK_SIZE)
+            .map_err(|err| Error::ChunkCopyError { source: err })
+    });
+    let bytes_read = match copied {
+        Ok(n) => n,
+        Err(err) => {
+            // do not leave the partial object behind in sandbox
+            let _ = fs::remove_file(&dst);
+            return Err(err);
+        }
+    };
     let hash = hwriter.ctx.finish();
-    let hash_hex = hex::encode(hash);
+    drop(writer);
+
+    finalize(cnt, &dst, hex::encode(hash), bytes_read, scope)
+}
 
+/// Move ``dst``, the temporary file of a new object of ``size`` bytes hashed to ``hash_hex``, to
+/// loose and record it for ``scope``. It is dropped instead if the object is already there.
+fn finalize(
+    cnt: &Container,
+    dst: &Path,
+    hash_hex: String,
+    size: u64,
+    scope: Scope,
+) -> Result<InsertResult, Error> {
     let loose = cnt.loose();
-    fs::create_dir_all(loose.join(format!("{}/", &hash_hex[..2])))?;
-    let loose_dst = loose.join(format!("{}/{}", &hash_hex[..2], &hash_hex[2..]));
+    fs::create_dir_all(loose.join(&hash_hex[..2]))?;
+    let loose_dst = loose.join(&hash_hex[..2]).join(&hash_hex[2..]);
 
     // avoid move if duplicate exist to reduce overhead
-    if !loose_dst.exists() {
-        fs::rename(&dst, &loose_dst)?;
+    if loose_dst.exists() {
+        let _ = fs::remove_file(dst);
+    } else {
+        if let Err(err) = check_quota(cnt, size) {
+            let _ = fs::remove_file(dst);
+            return Err(err);
+        }
+        move_to_loose(cnt, dst, &loose_dst)?;
+        count_new_object(cnt, size);
+    }
+    record_insert(cnt, scope, &hash_hex)?;
+
+    Ok(InsertResult::loose(hash_hex, size))
+}
+
+/// A new loose object written piece by piece, see ``Container::loose_writer``.
+///
+/// The content is streamed to a temporary file of the sandbox and hashed on the way. ``commit``
+/// moves it to loose under its hashkey, ``abort`` (or dropping the writer) deletes it, nothing is
+/// in the container until it is committed.
+pub struct LooseWriter<'a> {
+    cnt: &'a Container,
+    tmp: PathBuf,
+    /// ``None`` once committed.
+    hwriter: Option<HashWriter<BufWriter<fs::File>>>,
+    size: u64,
+}
+
+impl<'a> LooseWriter<'a> {
+    pub(crate) fn new(cnt: &'a Container) -> Result<Self, Error> {
+        cnt.valid()?;
+        let tmp = cnt
+            .insert_sandbox()?
+            .join(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let file = fs::File::create(&tmp)?;
+        Ok(LooseWriter {
+            cnt,
+            tmp,
+            hwriter: Some(HashWriter::new(
+                BufWriter::with_capacity(CHUNK_SIZE, file),
+                &digest::SHA256,
+            )),
+            size: 0,
+        })
+    }
+
+    /// Bytes written so far.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Store what was written as a loose object and return it, the same as inserting the whole
+    /// content at once with ``insert``.
+    pub fn commit(mut self) -> Result<InsertResult, Error> {
+        let HashWriter { mut writer, ctx } = self
+            .hwriter
+            .take()
+            .expect("only taken by commit, which consumes the writer");
+        let flushed = writer.flush();
+        drop(writer);
+        if let Err(err) = flushed {
+            let _ = fs::remove_file(&self.tmp);
+            return Err(err.into());
+        }
+        let hash = ctx.finish();
+
+        finalize(
+            self.cnt,
+            &self.tmp,
+            hex::encode(hash),
+            self.size,
+            Scope::Unscoped,
+        )
+    }
+
+    /// Drop what was written, the same as dropping the writer.
+    pub fn abort(self) {}
+
+    fn hwriter(&mut self) -> &mut HashWriter<BufWriter<fs::File>> {
+        self.hwriter
+            .as_mut()
+            .expect("only taken by commit, which consumes the writer")
+    }
+}
+
+impl Write for LooseWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.hwriter().write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.hwriter().flush()
+    }
+}
+
+impl Drop for LooseWriter<'_> {
+    fn drop(&mut self) {
+        // not committed, the temporary file is closed before it is deleted
+        if self.hwriter.take().is_some() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// Move the temporary file ``tmp`` to ``loose_dst``. A sandbox on another filesystem
+/// (``Config::sandbox_dir``) can not be renamed from, the file is then copied to the container
+/// sandbox first so the object still appears at once.
+fn move_to_loose(cnt: &Container, tmp: &Path, loose_dst: &Path) -> Result<(), Error> {
+    match rename_replace(tmp, loose_dst) {
+        Err(err) if is_cross_device(&err) => {
+            let local = cnt.sandbox().join(format!("{}.tmp", uuid::Uuid::new_v4()));
+            let moved = fs::copy(tmp, &local)
+                .and_then(|_| fs::File::open(&local)?.sync_all())
+                .and_then(|()| rename_replace(&local, loose_dst));
+            if let Err(err) = moved {
+                let _ = fs::remove_file(&local);
+                let _ = fs::remove_file(tmp);
+                return Err(err.into());
+            }
+            fs::remove_file(tmp)?;
+            Ok(())
+        }
+        moved => Ok(moved?),
+    }
+}
+
+/// Whether ``err`` is a rename refused because the paths are on different filesystems.
+fn is_cross_device(err: &io::Error) -> bool {
+    // EXDEV on unix, ERROR_NOT_SAME_DEVICE on windows
+    let code = if cfg!(windows) { 17 } else { 18 };
+    err.raw_os_error() == Some(code)
+}
+
+/// Insert a file to loose by hard-linking it into the container instead of copying its content.
+///
+/// The file is hashed in place and shares its inode with the stored object, so the source must
+/// not be modified afterwards. Falls back to ``insert`` (a plain copy) when the link cannot be
+/// created, e.g. when the file lives on another filesystem than the container.
+pub fn insert_by_link<P>(path: P, cnt: &Container) -> Result<InsertResult, Error>
+where
+    P: AsRef<Path>,
+{
+    insert_by_link_in(path, cnt, Scope::Unscoped)
+}
+
+/// Insert to loose by link for ``scope``, see ``namespace::Scope``.
+pub(crate) fn insert_by_link_in<P>(
+    path: P,
+    cnt: &Container,
+    scope: Scope,
+) -> Result<InsertResult, Error>
+where
+    P: AsRef<Path>,
+{
+    cnt.valid()?;
+
+    let path = path.as_ref();
+    // not ``insert_sandbox``, the link has to be on the filesystem of loose
+    let dst = cnt.sandbox().join(format!("{}.tmp", uuid::Uuid::new_v4()));
+    if fs::hard_link(path, &dst).is_err() {
+        return insert_in(path.to_path_buf(), cnt, scope);
+    }
+
+    // hash from the linked file so the object and the hash come from the same inode
+    let mut hwriter = HashWriter::new(std::io::sink(), &digest::SHA256);
+    let hashed = dst.make_reader().and_then(|mut stream| {
+        copy_by_chunk(&mut stream, &mut hwriter, CHUNK_SIZE)
+            .map_err(|err| Error::ChunkCopyError { source: err })
+    });
+    let bytes_read = match hashed {
+        Ok(n) => n,
+        Err(err) => {
+            let _ = fs::remove_file(&dst);
+            return Err(err);
+        }
+    };
+    let hash_hex = hex::encode(hwriter.ctx.finish());
+
+    let loose = cnt.loose();
+    fs::create_dir_all(loose.join(&hash_hex[..2]))?;
+    let loose_dst = loose.join(&hash_hex[..2]).join(&hash_hex[2..]);
+
+    if loose_dst.exists() {
+        fs::remove_file(&dst)?;
+    } else {
+        if let Err(err) = check_quota(cnt, bytes_read) {
+            let _ = fs::remove_file(&dst);
+            return Err(err);
+        }
+        rename_replace(&dst, &loose_dst)?;
+        count_new_object(cnt, bytes_read);
     }
+    record_insert(cnt, scope, &hash_hex)?;
 
-    Ok((bytes_read as u64, hash_hex))
+    Ok(InsertResult::loose(hash_hex, bytes_read))
 }
 
+/// Record in the index that the loose object ``hashkey`` was inserted for ``scope``.
+///
+/// NOTE: recorded once the object is in place, a ``maintain::gc_namespace`` of a namespace the
+/// object is also in may still delete it in between.
+fn record_insert(cnt: &Container, scope: Scope, hashkey: &str) -> Result<(), Error> {
+    if scope == Scope::Internal {
+        return Ok(());
+    }
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    namespace::record(&*tx, scope, &[hashkey.to_string()])?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Add a new loose object to the loose counters of the index.
+///
+/// NOTE: the object is already in place, failing to count it is not worth failing the insert,
+/// the counters are rebuilt with ``rsdos recount``.
+fn count_new_object(cnt: &Container, size: u64) {
+    if let Ok(index) = cnt.index() {
+        let _ = index.add_loose(1, i64::try_from(size).unwrap_or(i64::MAX));
+    }
+}
+
+/// Check the quotas for a new loose object of ``size`` bytes, the disk usage is only computed
+/// when ``Config::max_total_size`` is set.
+fn check_quota(cnt: &Container, size: u64) -> Result<(), Error> {
+    let config = cnt.config()?;
+    let total_size = if config.max_total_size.is_some() {
+        cnt.disk_usage()? + size
+    } else {
+        size
+    };
+    config.check_quota(size, total_size)
+}
+
+/// Look up a loose object, ``None`` if it is not in loose. Returns ``Error::InvalidHashKey`` for a
+/// malformed ``hashkey``.
 pub fn extract(hashkey: &str, cnt: &Container) -> Result<Option<LObject>, Error> {
     cnt.valid()?;
+    check_hashkey(hashkey)?;
 
-    let loc = cnt
-        .loose()
-        .join(format!("{}/{}", &hashkey[..2], &hashkey[2..]));
+    let loc = cnt.loose().join(&hashkey[..2]).join(&hashkey[2..]);
     if loc.exists() {
         let f = fs::File::open(&loc)?;
         let expected_size = f.metadata()?.len();
@@ -114,6 +405,21 @@ pub fn extract(hashkey: &str, cnt: &Container) -> Result<Option<LObject>, Error>
     }
 }
 
+/// Reader over the ``len`` bytes from ``offset`` of a loose object, ``None`` if it is not in
+/// loose.
+pub fn extract_range(
+    hashkey: &str,
+    offset: u64,
+    len: u64,
+    cnt: &Container,
+) -> Result<Option<impl Read>, Error> {
+    extract(hashkey, cnt)?
+        .map(|obj| obj.make_range_reader(offset, len))
+        .transpose()
+}
+
+/// Loose objects of ``hashkeys``, the ones not in loose are skipped. Returns
+/// ``Error::InvalidHashKey`` before anything is read if one of ``hashkeys`` is malformed.
 pub fn extract_many<'a, I>(
     hashkeys: I,
     cnt: &'a Container,
@@ -122,15 +428,19 @@ where
     I: IntoIterator + 'a,
     I::Item: ToString,
 {
+    let hashkeys: Vec<String> = hashkeys.into_iter().map(|h| h.to_string()).collect();
+    hashkeys.iter().try_for_each(|h| check_hashkey(h))?;
+
     let iter = hashkeys
         .into_iter()
-        .filter_map(|hashkey| extract(&hashkey.to_string(), cnt).ok())
+        .filter_map(|hashkey| extract(&hashkey, cnt).ok())
         .flatten();
     Ok(iter)
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
     use std::collections::HashMap;
 
     use crate::{
@@ -146,7 +456,7 @@ mod tests {
         let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
 
         let bstr: ByteString = b"test 0".to_vec();
-        let (_, hashkey) = insert(bstr, &cnt).unwrap();
+        let hashkey = insert(bstr, &cnt).unwrap().hashkey;
 
         // check packs has `0` and audit has only one pack
         // check content of 0 pack is `test 0`
@@ -160,6 +470,189 @@ mod tests {
         );
     }
 
+    #[test]
+    fn io_loose_writer_commit_and_abort() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let sandbox_is_empty = || fs::read_dir(cnt.sandbox()).unwrap().next().is_none();
+
+        let mut writer = cnt.loose_writer().unwrap();
+        for i in 0..100 {
+            writeln!(writer, "line {i}").unwrap();
+        }
+        assert!(!sandbox_is_empty());
+        let size = writer.size();
+        let inserted = writer.commit().unwrap();
+        let content: String = (0..100).map(|i| format!("line {i}\n")).collect();
+        assert_eq!(inserted.raw_size, size);
+        assert_eq!(
+            inserted,
+            insert(content.clone().into_bytes(), &cnt).unwrap()
+        );
+        let obj = extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), content.into_bytes());
+        assert!(sandbox_is_empty());
+        assert_eq!(stat(&cnt).unwrap().count.loose, 1);
+
+        // aborted or dropped, nothing is left behind
+        let mut writer = cnt.loose_writer().unwrap();
+        writer.write_all(b"aborted").unwrap();
+        writer.abort();
+        let mut writer = cnt.loose_writer().unwrap();
+        writer.write_all(b"dropped").unwrap();
+        drop(writer);
+        assert!(sandbox_is_empty());
+        assert_eq!(stat(&cnt).unwrap().count.loose, 1);
+    }
+
+    #[test]
+    fn io_loose_try_insert_many_continue_on_error() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let src_dir = tempfile::tempdir().unwrap();
+
+        // a directory opens but fails to read
+        let sources: Vec<PathBuf> = (0..5)
+            .map(|i| {
+                let p = src_dir.path().join(format!("source_{i}"));
+                if i == 1 {
+                    fs::create_dir(&p).unwrap();
+                } else {
+                    fs::write(&p, format!("test {i}")).unwrap();
+                }
+                p
+            })
+            .collect();
+
+        let outcomes = try_insert_many(sources, &cnt);
+        assert!(outcomes[1].is_err());
+        assert_eq!(outcomes.iter().filter(|o| o.is_ok()).count(), 4);
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.loose, 4);
+        // the partial object is not left in sandbox
+        assert_eq!(cnt.sandbox().read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn io_loose_insert_by_link() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let src_dir = tempfile::tempdir().unwrap();
+
+        let src = src_dir.path().join("source");
+        fs::write(&src, b"test link").unwrap();
+
+        let inserted = insert_by_link(&src, &cnt).unwrap();
+        assert_eq!(inserted.raw_size, 9);
+        let hashkey = inserted.hashkey;
+
+        // linking the same file again keeps the single object
+        let hashkey_again = insert_by_link(&src, &cnt).unwrap().hashkey;
+        assert_eq!(hashkey, hashkey_again);
+        assert_eq!(cnt.sandbox().read_dir().unwrap().count(), 0);
+
+        // same content through copy gives the same object
+        let hashkey_copy = insert(b"test link".to_vec(), &cnt).unwrap().hashkey;
+        assert_eq!(hashkey, hashkey_copy);
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.loose, 1);
+
+        let obj = extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), b"test link".to_vec());
+    }
+
+    #[test]
+    fn io_loose_insert_to_sandbox_dir() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let sandbox_dir = tempfile::tempdir().unwrap();
+        let mut config = cnt.config().unwrap();
+        config.sandbox_dir = Some(sandbox_dir.path().join("tmp"));
+        cnt.write_config(&config).unwrap();
+
+        let hashkey = insert(b"test sandbox".to_vec(), &cnt).unwrap().hashkey;
+        assert_eq!(
+            sandbox_dir.path().join("tmp").read_dir().unwrap().count(),
+            0
+        );
+        assert_eq!(cnt.sandbox().read_dir().unwrap().count(), 0);
+
+        let obj = extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), b"test sandbox".to_vec());
+    }
+
+    #[test]
+    fn io_loose_insert_quota_exceeded() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let mut config = cnt.config().unwrap();
+        config.max_object_size = Some(8);
+        config.max_total_size = Some(20);
+        cnt.write_config(&config).unwrap();
+
+        let err = insert(b"larger than 8".to_vec(), &cnt).unwrap_err();
+        assert!(matches!(err, Error::QuotaExceeded { limit: 8, .. }));
+
+        insert(b"test 0".to_vec(), &cnt).unwrap();
+        insert(b"test 1".to_vec(), &cnt).unwrap();
+        insert(b"test 2".to_vec(), &cnt).unwrap();
+        let err = insert(b"test 3".to_vec(), &cnt).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::QuotaExceeded {
+                limit: 20,
+                requested: 24,
+                ..
+            }
+        ));
+        assert_eq!(cnt.sandbox().read_dir().unwrap().count(), 0);
+
+        // a duplicate takes no room
+        insert(b"test 0".to_vec(), &cnt).unwrap();
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.loose, 3);
+    }
+
+    #[test]
+    fn io_loose_extract_range() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = insert(b"0123456789".to_vec(), &cnt).unwrap().hashkey;
+
+        let read_range = |offset, len| {
+            let mut buf = String::new();
+            extract_range(&hashkey, offset, len, &cnt)
+                .unwrap()
+                .unwrap()
+                .read_to_string(&mut buf)
+                .unwrap();
+            buf
+        };
+        assert_eq!(read_range(2, 3), "234");
+        assert_eq!(read_range(8, 10), "89");
+        assert_eq!(read_range(20, 10), "");
+    }
+
+    #[test]
+    fn io_loose_extract_invalid_hashkey() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = insert(b"test 0".to_vec(), &cnt).unwrap().hashkey;
+
+        for invalid in ["", "a", &hashkey[..63], &"z".repeat(64), "../config.json"] {
+            let err = extract(invalid, &cnt).unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidHashKey { .. }),
+                "got err: {err}"
+            );
+            let err = crate::io_packs::extract(invalid, &cnt).err().unwrap();
+            assert!(
+                matches!(err, Error::InvalidHashKey { .. }),
+                "got err: {err}"
+            );
+        }
+
+        let hashkeys = [hashkey.as_str(), "a"];
+        assert!(extract_many(hashkeys, &cnt).is_err());
+        assert!(crate::io_packs::extract_many(hashkeys, &cnt).is_err());
+    }
+
     #[test]
     fn io_loose_insert_and_extract_many() {
         let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
@@ -168,7 +661,7 @@ mod tests {
         for i in 0..100 {
             let content = format!("test {i}");
             let buf = content.clone().into_bytes();
-            let (_, hash) = insert(buf, &cnt).unwrap();
+            let hash = insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -197,4 +690,30 @@ mod tests {
         }
         assert_eq!(count + 2, hashkeys.len());
     }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Loose objects read back the same before and after they are packed.
+        #[test]
+        fn io_loose_roundtrip_prop(
+            contents in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..4096), 1..16),
+            algo in prop::sample::select(vec!["none", "zlib:+1", "zstd:+1"]),
+            pack_size_target in 1u64..8192,
+        ) {
+            let (_tmp_dir, cnt) = new_container(pack_size_target, algo);
+
+            let written = insert_many(contents.clone(), &cnt).unwrap();
+            for (content, inserted) in contents.iter().zip(&written) {
+                let obj = extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+                prop_assert_eq!(&ByteString::try_from(obj).unwrap(), content);
+            }
+
+            crate::maintain::pack_loose(&cnt).unwrap();
+            for (content, inserted) in contents.iter().zip(&written) {
+                let obj = crate::io_packs::extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+                prop_assert_eq!(&ByteString::try_from(obj).unwrap(), content);
+            }
+        }
+    }
 }
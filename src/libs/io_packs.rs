@@ -1,19 +1,34 @@
 use flate2;
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
+use flate2::{CrcReader, CrcWriter};
 use ring::digest;
-use rusqlite::{params, params_from_iter, Connection};
+use rusqlite::Connection;
+use std::collections::{HashMap, VecDeque};
 use std::fs::{self, File};
-use std::io::{self, Read, Seek, SeekFrom, Take};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use zstd::stream::read::Decoder as ZstdDecoder;
 use zstd::stream::write::Encoder as ZstdEncoder;
 
-use crate::container::Compression;
+use crate::cache::ObjectCache;
+use crate::config::{CompressionPolicy, Config};
+use crate::container::{Compression, PackLayout, DICTIONARIES};
 use crate::db::PackEntry;
-use crate::io::{copy_by_chunk, ByteString, HashWriter, MaybeContentFormat, ReaderMaker};
+use crate::dictionary::{frame_dictionary_id, Dictionaries, FRAME_HEAD_LEN};
+use crate::index::ObjectIndex;
+use crate::io::{
+    check_hashkey, copy_by_chunk, ByteString, HashReader, HashWriter, InsertOutcome, InsertResult,
+    MaybeContentFormat, PeekReader, ReaderMaker,
+};
+use crate::journal::{self, Journal};
+use crate::namespace::{self, Scope};
+use crate::pack_storage::PackStorage;
+use crate::pipeline::{self, Prepared};
 use crate::{db, Container};
 
-use crate::utils::Dir;
 use crate::Error;
 
 /// ``raw_size`` is the size without compress.
@@ -26,6 +41,10 @@ pub struct PObject {
     pub compressed: bool,
     // pub compress_algo: String, (zstd+22, zlib+3 ...)
     // pub checksum: u64, // CRC32
+    storage: Option<(u64, Arc<dyn PackStorage>)>,
+    cache: Option<Arc<ObjectCache>>,
+    file: Option<Arc<File>>,
+    dictionaries: Option<Arc<Dictionaries>>,
 }
 
 impl PObject {
@@ -44,8 +63,103 @@ impl PObject {
             raw_size,
             size,
             compressed,
+            storage: None,
+            cache: None,
+            file: None,
+            dictionaries: None,
         }
     }
+
+    pub(crate) fn from_entry<P: AsRef<Path>>(entry: &PackEntry, loc: P) -> Self {
+        Self::new(
+            &entry.hashkey,
+            loc,
+            entry.offset,
+            entry.raw_size,
+            entry.size,
+            entry.compressed,
+        )
+    }
+
+    /// Compression of the stored bytes, ``"none"``, ``"zlib"`` or ``"zstd"``.
+    pub fn compression(&self) -> Result<&'static str, Error> {
+        if !self.compressed {
+            return Ok("none");
+        }
+        let head = self.stored_reader(0, self.size.min(ZSTD_MAGIC.len() as u64))?;
+        if Self::peek_zstd_frame(head)?.0.is_some() {
+            Ok("zstd")
+        } else {
+            Ok("zlib")
+        }
+    }
+
+    /// Read the entry of pack ``pack_id`` from ``storage`` when the pack is not in the local
+    /// ``packs`` folder.
+    #[must_use]
+    pub(crate) fn with_storage(
+        mut self,
+        pack_id: u64,
+        storage: Option<Arc<dyn PackStorage>>,
+    ) -> Self {
+        self.storage = storage.map(|storage| (pack_id, storage));
+        self
+    }
+
+    /// Serve the content from ``cache`` if it is a small object read recently, and cache it once
+    /// read otherwise.
+    #[must_use]
+    pub(crate) fn with_cache(mut self, cache: Option<Arc<ObjectCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Read the entry through the already open pack ``file`` instead of opening ``loc``.
+    #[must_use]
+    pub(crate) fn with_file(mut self, file: Option<Arc<File>>) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// Hint the kernel the stored bytes are read soon, see ``extract_many_with_readahead``.
+    fn will_need(&self) {
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+        if let Some(file) = &self.file {
+            use std::os::fd::AsRawFd;
+
+            // SAFETY: ``file`` keeps the descriptor open, the advice does not touch memory.
+            // NOTE: the result is ignored, a failed hint only means no readahead.
+            unsafe {
+                libc::posix_fadvise(
+                    file.as_raw_fd(),
+                    self.offset as libc::off_t,
+                    self.size as libc::off_t,
+                    libc::POSIX_FADV_WILLNEED,
+                );
+            }
+        }
+    }
+
+    /// Look up the zstd dictionaries the entry may be compressed with in ``dictionaries``.
+    #[must_use]
+    pub(crate) fn with_dictionaries(mut self, dictionaries: Arc<Dictionaries>) -> Self {
+        self.dictionaries = Some(dictionaries);
+        self
+    }
+
+    /// The zstd dictionary ``id``. An object not read through a ``Container`` loads it from the
+    /// container folder its pack is in.
+    fn dictionary(&self, id: u32) -> Result<Arc<[u8]>, Error> {
+        if let Some(dictionaries) = &self.dictionaries {
+            return dictionaries.get(id);
+        }
+        let dir = self
+            .loc
+            .parent()
+            .and_then(Path::parent)
+            .ok_or(Error::DictionaryNotFound { id })?;
+        Dictionaries::new(dir.join(DICTIONARIES)).get(id)
+    }
 }
 
 impl TryFrom<PObject> for ByteString {
@@ -71,33 +185,277 @@ impl TryFrom<PObject> for ByteString {
     }
 }
 
+/// Stored bytes of an entry, from the local pack file or fetched from the pack storage.
+type Stored = Box<dyn Read + Send>;
+
+/// Bytes ``pos..end`` of a pack file handle shared with other readers, read at an offset so the
+/// cursor of the handle is never moved.
+struct PackRange {
+    file: Arc<File>,
+    pos: u64,
+    end: u64,
+}
+
+impl Read for PackRange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = usize::try_from(self.end - self.pos)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = read_at(&self.file, &mut buf[..len], self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
 enum PReader {
-    Uncompressed(Take<File>),
-    Zlib(ZlibDecoder<Take<File>>),
+    Uncompressed(Stored),
+    Zlib(ZlibDecoder<Stored>),
+    Zstd(ZstdDecoder<'static, BufReader<Stored>>),
+    Cached(io::Cursor<Arc<[u8]>>),
 }
 
 impl Read for PReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
             PReader::Zlib(inner) => inner.read(buf),
+            PReader::Zstd(inner) => inner.read(buf),
             PReader::Uncompressed(inner) => inner.read(buf),
+            PReader::Cached(inner) => inner.read(buf),
         }
     }
 }
 
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
 impl ReaderMaker for PObject {
     fn make_reader(&self) -> Result<impl Read, crate::Error> {
+        self.open_reader()
+    }
+
+    /// Sniffed from the first decoded bytes of the content, its size is known from the index.
+    fn maybe_content_format(
+        &self,
+        policy: &CompressionPolicy,
+    ) -> Result<MaybeContentFormat, Error> {
+        if self.raw_size <= policy.min_compress_size {
+            return Ok(MaybeContentFormat::SmallContent);
+        }
+        let rdr = PeekReader::new(self.open_stored_reader()?)?;
+        Ok(rdr.content_format(Some(self.raw_size), policy))
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.raw_size)
+    }
+}
+
+impl PObject {
+    /// Reader over ``len`` stored bytes from ``start`` of the entry. Read from the local pack
+    /// file, or with a ranged read from the pack storage once the pack has been offloaded.
+    fn stored_reader(&self, start: u64, len: u64) -> Result<Stored, Error> {
+        if let Some(file) = &self.file {
+            let pos = self.offset + start;
+            return Ok(Box::new(PackRange {
+                file: file.clone(),
+                pos,
+                end: pos + len,
+            }));
+        }
+
+        if let Some((pack_id, storage)) = self.storage.as_ref().filter(|_| !self.loc.exists()) {
+            let bytes = storage.read_range(*pack_id, self.offset + start, len)?;
+            return Ok(Box::new(io::Cursor::new(bytes)));
+        }
+
         let mut f = fs::OpenOptions::new().read(true).open(&self.loc)?;
-        f.seek(SeekFrom::Start(self.offset))?;
-        // NOTE: V2 should add support to zstd
+        f.seek(SeekFrom::Start(self.offset + start))?;
+        Ok(Box::new(f.take(len)))
+    }
+
+    /// The index only records whether the entry is compressed, the algorithm is told from the
+    /// stream header: zstd frames start with a magic number, otherwise it is zlib. Return, for a
+    /// zstd frame, the id of the dictionary it was compressed with if any, and the stored bytes
+    /// with the peeked header put back in front.
+    fn peek_zstd_frame(mut stored: Stored) -> io::Result<(Option<Option<u32>>, Stored)> {
+        let mut head = Vec::with_capacity(FRAME_HEAD_LEN);
+        (&mut stored)
+            .take(FRAME_HEAD_LEN as u64)
+            .read_to_end(&mut head)?;
+        let zstd = head
+            .starts_with(&ZSTD_MAGIC)
+            .then(|| frame_dictionary_id(&head));
+        Ok((zstd, Box::new(io::Cursor::new(head).chain(stored))))
+    }
+
+    fn open_reader(&self) -> Result<PReader, Error> {
+        let Some(cache) = self.cache.as_ref().filter(|c| c.admits(self.raw_size)) else {
+            return self.open_stored_reader();
+        };
+        if let Some(content) = cache.get(&self.id) {
+            return Ok(PReader::Cached(io::Cursor::new(content)));
+        }
+
+        let mut content = Vec::with_capacity(usize::try_from(self.raw_size).unwrap_or_default());
+        self.open_stored_reader()?.read_to_end(&mut content)?;
+        let content: Arc<[u8]> = content.into();
+        cache.put(&self.id, content.clone());
+        Ok(PReader::Cached(io::Cursor::new(content)))
+    }
+
+    /// Reader decoding the stored bytes, bypassing the cache.
+    fn open_stored_reader(&self) -> Result<PReader, Error> {
+        let stored = self.stored_reader(0, self.size)?;
         if self.compressed {
-            let rdr = PReader::Zlib(ZlibDecoder::new(f.take(self.size)));
+            let (zstd, stored) = Self::peek_zstd_frame(stored)?;
+            if let Some(dict_id) = zstd {
+                let rdr = match dict_id {
+                    Some(id) => {
+                        ZstdDecoder::with_dictionary(BufReader::new(stored), &self.dictionary(id)?)?
+                    }
+                    None => ZstdDecoder::new(stored)?,
+                };
+                return Ok(PReader::Zstd(rdr));
+            }
+            let rdr = PReader::Zlib(ZlibDecoder::new(stored));
             Ok(rdr)
         } else {
-            let rdr = PReader::Uncompressed(f.take(self.size));
+            let rdr = PReader::Uncompressed(stored);
             Ok(rdr)
         }
     }
+
+    /// Reader over ``len`` bytes of the content from ``offset``, the range is clamped to the
+    /// object. Uncompressed entries seek straight to the range, compressed ones are decoded from
+    /// the start and the bytes before ``offset`` are skipped.
+    pub fn make_range_reader(&self, offset: u64, len: u64) -> Result<impl Read, Error> {
+        let start = offset.min(self.raw_size);
+        let len = len.min(self.raw_size - start);
+
+        if self.compressed {
+            let mut rdr = self.open_reader()?;
+            io::copy(&mut (&mut rdr).take(start), &mut io::sink())?;
+            Ok(rdr.take(len))
+        } else {
+            Ok(PReader::Uncompressed(self.stored_reader(start, len)?).take(len))
+        }
+    }
+}
+
+/// Magic bytes opening every framed pack entry.
+pub const ENTRY_MAGIC: [u8; 4] = *b"RSDE";
+/// magic(4) + flags(1) + reserved(3) + raw_size(8) + length(8) + crc32(4) + sha256(32)
+pub const ENTRY_HEADER_LEN: usize = 60;
+const ENTRY_FLAG_COMPRESSED: u8 = 0b0000_0001;
+
+/// Header written in front of each payload when ``Config::pack_entry_header`` is on.
+///
+/// The header makes a pack file self-describing: the object boundaries and hashkeys can be
+/// recovered by walking the pack (see ``scan_framed_pack``) and the CRC32 over the stored bytes
+/// detects corruption without decompressing. All integers are little-endian.
+#[derive(Debug, PartialEq)]
+pub struct EntryHeader {
+    pub compressed: bool,
+    pub raw_size: u64,
+    pub length: u64,
+    pub crc: u32,
+    pub hash: [u8; 32],
+}
+
+impl EntryHeader {
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; ENTRY_HEADER_LEN] {
+        let mut buf = [0u8; ENTRY_HEADER_LEN];
+        buf[0..4].copy_from_slice(&ENTRY_MAGIC);
+        buf[4] = if self.compressed {
+            ENTRY_FLAG_COMPRESSED
+        } else {
+            0
+        };
+        buf[8..16].copy_from_slice(&self.raw_size.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.length.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.crc.to_le_bytes());
+        buf[28..60].copy_from_slice(&self.hash);
+        buf
+    }
+
+    /// Parse a header, ``None`` if the bytes do not start with ``ENTRY_MAGIC``.
+    #[must_use]
+    pub fn from_bytes(buf: &[u8; ENTRY_HEADER_LEN]) -> Option<Self> {
+        if buf[0..4] != ENTRY_MAGIC {
+            return None;
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        Some(Self {
+            compressed: buf[4] & ENTRY_FLAG_COMPRESSED != 0,
+            raw_size: u64_at(8),
+            length: u64_at(16),
+            crc: u32::from_le_bytes(buf[24..28].try_into().unwrap()),
+            hash: buf[28..60].try_into().unwrap(),
+        })
+    }
+}
+
+pub(crate) fn hex_to_hash(hash_hex: &str) -> Result<[u8; 32], Error> {
+    hex::decode(hash_hex)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| Error::IntegrityError {
+            expected: "sha256 hex digest".to_string(),
+            got: hash_hex.to_string(),
+        })
+}
+
+/// Walk a pack written with entry headers and return the entries whose payload passes the CRC
+/// check. The walk stops at the first position not starting with a header (e.g. a pack written
+/// before headers were enabled, or a tail left by an interrupted write).
+pub fn scan_framed_pack(pack: &Path, pack_id: u64) -> Result<Vec<PackEntry>, Error> {
+    let mut f = fs::OpenOptions::new().read(true).open(pack)?;
+    let pack_len = f.metadata()?.len();
+    let mut entries = vec![];
+    let mut pos = 0;
+
+    while pos + ENTRY_HEADER_LEN as u64 <= pack_len {
+        let mut buf = [0u8; ENTRY_HEADER_LEN];
+        f.seek(SeekFrom::Start(pos))?;
+        f.read_exact(&mut buf)?;
+        let Some(header) = EntryHeader::from_bytes(&buf) else {
+            break;
+        };
+
+        let payload_offset = pos + ENTRY_HEADER_LEN as u64;
+        if payload_offset + header.length > pack_len {
+            break;
+        }
+
+        let mut rdr = CrcReader::new((&mut f).take(header.length));
+        io::copy(&mut rdr, &mut io::sink())?;
+        if rdr.crc().sum() == header.crc {
+            entries.push(PackEntry {
+                hashkey: hex::encode(header.hash),
+                compressed: header.compressed,
+                raw_size: header.raw_size,
+                size: header.length,
+                offset: payload_offset,
+                pack_id,
+            });
+        }
+        pos = payload_offset + header.length;
+    }
+
+    Ok(entries)
 }
 
 // XXX: how to combine this with using extract_many???
@@ -108,17 +466,34 @@ impl ReaderMaker for PObject {
 // then proceed with write to writer using buffer reader/writer.
 pub fn extract(hashkey: &str, cnt: &Container) -> Result<Option<PObject>, Error> {
     cnt.valid()?;
-    let conn = Connection::open(cnt.packs_db())?;
-    if let Some(pn) = db::select(&conn, hashkey)? {
+    check_hashkey(hashkey)?;
+    if let Some(pn) = cnt.index()?.select(hashkey)? {
         let pack_id = pn.pack_id;
-        let loc = cnt.packs().join(format!("{pack_id}"));
-        let obj = PObject::new(hashkey, loc, pn.offset, pn.raw_size, pn.size, pn.compressed);
+        let loc = cnt.pack_path(pack_id)?;
+        let obj = PObject::new(hashkey, loc, pn.offset, pn.raw_size, pn.size, pn.compressed)
+            .with_storage(pack_id, cnt.pack_storage()?)
+            .with_cache(cnt.object_cache()?)
+            .with_file(cnt.pack_file(pack_id)?)
+            .with_dictionaries(cnt.dictionaries());
         Ok(Some(obj))
     } else {
         Ok(None)
     }
 }
 
+/// Reader over the ``len`` bytes from ``offset`` of a packed object, ``None`` if it is not in
+/// packs. See ``PObject::make_range_reader``.
+pub fn extract_range(
+    hashkey: &str,
+    offset: u64,
+    len: u64,
+    cnt: &Container,
+) -> Result<Option<impl Read>, Error> {
+    extract(hashkey, cnt)?
+        .map(|obj| obj.make_range_reader(offset, len))
+        .transpose()
+}
+
 fn _chunked<I>(mut iter: I, chunk_size: usize) -> impl Iterator<Item = Vec<I::Item>>
 where
     I: Iterator,
@@ -133,78 +508,192 @@ where
     })
 }
 
+/// How ``extract_many_with`` returns the objects.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtractManyOptions {
+    /// Return the objects in the order of the hashkeys asked for (a hashkey asked twice is
+    /// returned twice) instead of in pack order. The objects of a batch of hashkeys are buffered
+    /// to be reordered, and reading them seeks back and forth in the pack files.
+    pub preserve_order: bool,
+}
+
 /// ``extract_many`` fetch an interator of ``PObject`` from given hashkeys
 ///
-/// NOTE: the return type declaration is not fully correct, the return iterator should live as long
-/// as at most of ``hashkeys`` iterator, but the return type means it live as long as at least of
-/// ``hashkeys``. It is subtle because of Rust impl trait problem, and will be changed in the
-/// future. See: the talk of [Impl Trait aka Look ma’, no generics! by Jon Gjengset](https://www.youtube.com/watch?v=CWiz_RtA1Hw&t=2230s) for how to correct
-/// it. I didn't implement the change because this function in this crate is usually called once
-/// therefore less likely hit the edge case that borrow checker will confuse from this.
+/// The objects are not returned in the order of ``hashkeys``: each batch of hashkeys queried
+/// together is returned sorted by pack and offset, so reading them in turn scans the pack files
+/// sequentially instead of seeking back and forth. Use ``extract_many_with`` and
+/// ``ExtractManyOptions::preserve_order`` to get them in the order of ``hashkeys``. Hashkeys not
+/// in packs are skipped. Returns ``Error::InvalidHashKey`` before anything is read if one of
+/// ``hashkeys`` is malformed. A batch whose query fails is yielded as a single error in place of
+/// its objects.
+///
+/// The hashkeys are collected up front, so the iterator only borrows the container and
+/// ``hashkeys`` can be a temporary. See ``extract_many_owned`` for an iterator that can be moved
+/// to another thread.
 pub fn extract_many<'a, I>(
     hashkeys: I,
     cnt: &'a Container,
-) -> Result<impl Iterator<Item = PObject> + 'a, Error>
+) -> Result<impl Iterator<Item = Result<PObject, Error>> + 'a, Error>
+where
+    I: IntoIterator,
+    I::Item: ToString,
+{
+    extract_many_with(hashkeys, cnt, ExtractManyOptions::default())
+}
+
+/// ``extract_many`` with ``options``.
+pub fn extract_many_with<'a, I>(
+    hashkeys: I,
+    cnt: &'a Container,
+    options: ExtractManyOptions,
+) -> Result<impl Iterator<Item = Result<PObject, Error>> + 'a, Error>
 where
-    I: IntoIterator + 'a,
+    I: IntoIterator,
     I::Item: ToString,
 {
     cnt.valid()?;
+    let hashkeys = checked_hashkeys(hashkeys)?;
 
-    // TODO: make chunk size configuable
-    let _max_chunk_iterate_length = 9500;
-    let in_sql_max_length = 950;
-
-    let conn = Connection::open(cnt.packs_db())?;
-    let chunked_iter = _chunked(hashkeys.into_iter(), in_sql_max_length);
-
-    // XXX: why not work??
-    // let chunked_iter = std::iter::from_fn(move || {
-    //     let chunk: Vec<_> = hashkeys.into_iter().by_ref().take(in_sql_max_length).collect();
-    //     if chunk.is_empty() {
-    //         None
-    //     } else {
-    //         Some(chunk)
-    //     }
-    // });
-
-    // NOTE: I believe when yield is available in rust (https://without.boats/blog/a-four-year-plan/)
-    // this can be more straightforward implemented. I was quite struggle with the ownership here
-    // and have to use move for both `chunk` and inner iterator.
-    let iter_vec = chunked_iter.flat_map(move |chunk| {
-        let placeholders: Vec<&str> = (0..chunk.len()).map(|_| "?").collect();
-        let mut stmt = conn.prepare_cached(&format!("SELECT hashkey, compressed, size, offset, length, pack_id FROM db_object WHERE hashkey IN ({})", placeholders.join(","))).unwrap();
-        let chunk = chunk.into_iter().map(|x| x.to_string());
-        let rows = stmt
-            .query_map(params_from_iter(chunk), |row| {
-                let hashkey: String = row.get(0)?;
-                let compressed: bool = row.get(1)?;
-                let raw_size: u64 = row.get(2)?;
-                let offset: u64 = row.get(3)?;
-                let size: u64 = row.get(4)?;
-                let pack_id: u64 = row.get(5)?;
-
-                Ok(PackEntry {
-                    hashkey,
-                    compressed,
-                    raw_size,
-                    size,
-                    offset,
-                    pack_id,
-                })
-            }).expect("query failed");
-        let mut rows = rows
-            .filter_map(Result::ok)
-            .collect::<Vec<_>>();
+    let index = cnt.index()?;
+    let mut lookup = BatchLookup::new(cnt, options)?;
+    let iter = _chunked(hashkeys.into_iter(), EXTRACT_BATCH_SIZE)
+        .flat_map(move |batch| lookup.objects(&*index, cnt, &batch));
+    Ok(iter)
+}
+
+/// ``extract_many_with`` for an iterator that owns the container and its hashkeys, so it can be
+/// moved to a spawned thread. The iterator keeps its own index connection, opened here.
+pub fn extract_many_owned(
+    hashkeys: Vec<String>,
+    cnt: Arc<Container>,
+    options: ExtractManyOptions,
+) -> Result<impl Iterator<Item = Result<PObject, Error>> + Send + 'static, Error> {
+    cnt.valid()?;
+    let hashkeys = checked_hashkeys(hashkeys)?;
 
-        std::iter::from_fn(move || {
-            if let Some(pn) = rows.pop() {
-                let pack_id = pn.pack_id;
-                // XXX: I should not return Result for cnt.<subfolder>, instead better to valitate
-                // the cnt and then just return PathBuf. Then I can get rid of `unwrap` for some
-                // places.
-                let packs_path = cnt.packs();
-                let loc = packs_path.join(format!("{pack_id}"));
+    let index = cnt.owned_index()?;
+    let mut lookup = BatchLookup::new(&cnt, options)?;
+    let iter = _chunked(hashkeys.into_iter(), EXTRACT_BATCH_SIZE)
+        .flat_map(move |batch| lookup.objects(&*index, &cnt, &batch));
+    Ok(iter)
+}
+
+/// Objects ahead of the one being read whose pack ranges ``extract_many_with_readahead`` asks the
+/// kernel to read in.
+pub const READAHEAD_WINDOW: usize = 32;
+
+/// ``extract_many_with`` asking the kernel to read in the pack ranges of the next ``window``
+/// objects (``posix_fadvise(POSIX_FADV_WILLNEED)``) while the current one is consumed, so the disk
+/// reads of a bulk extraction overlap the decompression. The hints are only advisory: where
+/// ``posix_fadvise`` is not available, and for objects not in a local pack file, this is
+/// ``extract_many_with``.
+pub fn extract_many_with_readahead<'a, I>(
+    hashkeys: I,
+    cnt: &'a Container,
+    options: ExtractManyOptions,
+    window: usize,
+) -> Result<impl Iterator<Item = Result<PObject, Error>> + 'a, Error>
+where
+    I: IntoIterator,
+    I::Item: ToString,
+{
+    let objects = extract_many_with(hashkeys, cnt, options)?;
+    Ok(Readahead {
+        objects,
+        ahead: VecDeque::with_capacity(window + 1),
+        window,
+    })
+}
+
+/// Keeps the next ``window`` objects of ``objects`` buffered, their ranges advised.
+struct Readahead<I> {
+    objects: I,
+    ahead: VecDeque<Result<PObject, Error>>,
+    window: usize,
+}
+
+impl<I: Iterator<Item = Result<PObject, Error>>> Iterator for Readahead<I> {
+    type Item = Result<PObject, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ahead.len() <= self.window {
+            let Some(obj) = self.objects.next() else {
+                break;
+            };
+            if let Ok(obj) = &obj {
+                obj.will_need();
+            }
+            self.ahead.push_back(obj);
+        }
+        self.ahead.pop_front()
+    }
+}
+
+/// Hashkeys queried together with ``IN (..)``, below the default limit of SQLite variables.
+// TODO: make chunk size configuable
+pub(crate) const EXTRACT_BATCH_SIZE: usize = 950;
+
+fn checked_hashkeys<I>(hashkeys: I) -> Result<Vec<String>, Error>
+where
+    I: IntoIterator,
+    I::Item: ToString,
+{
+    let hashkeys: Vec<String> = hashkeys.into_iter().map(|h| h.to_string()).collect();
+    hashkeys.iter().try_for_each(|h| check_hashkey(h))?;
+    Ok(hashkeys)
+}
+
+/// Queries the batches of hashkeys of ``extract_many_*`` and makes their ``PObject``.
+struct BatchLookup {
+    packs: PackLayout,
+    storage: Option<Arc<dyn PackStorage>>,
+    cache: Option<Arc<ObjectCache>>,
+    /// pack files opened so far, so each pack is looked up at most once
+    files: HashMap<u64, Option<Arc<File>>>,
+    options: ExtractManyOptions,
+}
+
+impl BatchLookup {
+    fn new(cnt: &Container, options: ExtractManyOptions) -> Result<Self, Error> {
+        Ok(Self {
+            packs: cnt.pack_layout()?,
+            storage: cnt.pack_storage()?,
+            cache: cnt.object_cache()?,
+            files: HashMap::new(),
+            options,
+        })
+    }
+
+    /// Objects of ``batch`` found in packs, in pack order or in the order of ``batch``. A failed
+    /// query is returned as a single error.
+    fn objects(
+        &mut self,
+        index: &dyn ObjectIndex,
+        cnt: &Container,
+        batch: &[String],
+    ) -> Vec<Result<PObject, Error>> {
+        let mut entries = match index.select_many(batch) {
+            Ok(entries) => entries,
+            Err(err) => return vec![Err(err)],
+        };
+        if self.options.preserve_order {
+            let found: HashMap<String, PackEntry> = entries
+                .into_iter()
+                .map(|pn| (pn.hashkey.clone(), pn))
+                .collect();
+            entries = batch.iter().filter_map(|h| found.get(h).cloned()).collect();
+        }
+
+        entries
+            .into_iter()
+            .map(|pn| {
+                // a pack that can not be opened here fails when the object is read
+                let file = self
+                    .files
+                    .entry(pn.pack_id)
+                    .or_insert_with(|| cnt.pack_file(pn.pack_id).ok().flatten())
+                    .clone();
+                let loc = self.packs.path(pn.pack_id);
                 let obj = PObject::new(
                     &pn.hashkey,
                     loc,
@@ -212,24 +701,33 @@ where
                     pn.raw_size,
                     pn.size,
                     pn.compressed,
-                );
-                Some(obj)
-            } else {
-                None
-            }
+                )
+                .with_storage(pn.pack_id, self.storage.clone())
+                .with_cache(self.cache.clone())
+                .with_file(file)
+                .with_dictionaries(cnt.dictionaries());
+                Ok(obj)
+            })
+            .collect()
+    }
+}
 
-        })
-    });
-    Ok(iter_vec)
+pub fn insert<T>(source: T, cnt: &Container) -> Result<InsertResult, Error>
+where
+    T: ReaderMaker,
+{
+    insert_in(source, cnt, Scope::Unscoped)
 }
 
-pub fn insert<T>(source: T, cnt: &Container) -> Result<(u64, u64, String), Error>
+/// Insert to packs for ``scope``, see ``namespace::Scope``.
+pub(crate) fn insert_in<T>(source: T, cnt: &Container, scope: Scope) -> Result<InsertResult, Error>
 where
     T: ReaderMaker,
 {
     // add to sandbox first, same as loose insert
     let dpath_string = format!("{}.tmp", uuid::Uuid::new_v4());
-    let dst = cnt.sandbox().join(&dpath_string);
+    let sandbox = cnt.insert_sandbox()?;
+    let dst = sandbox.join(&dpath_string);
     let mut sandbox_tmp = fs::File::create(&dst)?;
 
     let mut hwriter = HashWriter::new(&mut sandbox_tmp, &digest::SHA256);
@@ -240,38 +738,83 @@ where
     let chunk_size = 524_288; // 512 KiB TODO: make it configurable??
                               //
     let mut stream = source.make_reader()?;
-    let bytes_copied = copy_by_chunk(&mut stream, &mut hwriter, chunk_size)
+    copy_by_chunk(&mut stream, &mut hwriter, chunk_size)
         .map_err(|err| Error::ChunkCopyError { source: err })?;
     let hash = hwriter.ctx.finish();
     let hash_hex = hex::encode(hash);
 
-    let conn = Connection::open(cnt.packs_db())?;
-    if (db::select(&conn, hash_hex.as_str())?).is_some() {
-        eprintln!("{hash_hex} exist");
-        fs::remove_file(&dst)?;
-        return Ok((bytes_copied, bytes_copied, hash_hex));
+    if cnt.may_be_indexed(&hash_hex)? {
+        if let Some(entry) = cnt.index()?.select(&hash_hex)? {
+            fs::remove_file(&dst)?;
+            if scope != Scope::Internal {
+                let conn = cnt.db()?;
+                db::ensure_writable(&conn)?;
+                let tx = db::begin_write(&conn)?;
+                namespace::record(&*tx, scope, std::slice::from_ref(&hash_hex))?;
+                tx.commit()?;
+            }
+            return Ok(InsertResult::packed(entry));
+        }
     }
 
     // from sandbox to pack if not exist in pack
-    let (bytes_read, bytes_write, hash_hex) = insert_many(vec![dst], cnt)?
-        .first()
-        .map(|(n_r, n_w, hash)| (*n_r, *n_w, hash.clone()))
-        .expect("problem when insert many to pack");
+    let written = cnt
+        .compression()
+        .and_then(|compression| _insert_many_outcomes(vec![dst], cnt, &compression, false, scope));
 
     // remove tmp from sandbox
-    fs::remove_file(cnt.sandbox().join(&dpath_string))?;
+    fs::remove_file(sandbox.join(&dpath_string))?;
+
+    let inserted = written?.pop().expect("problem when insert many to pack")?;
 
-    Ok((bytes_read, bytes_write, hash_hex))
+    Ok(inserted)
 }
 
-fn find_current_pack_id(packs: &PathBuf, pack_size_target: u64) -> Result<u64, Error> {
-    // make sure there is a pack if not create 0
-    if Dir(packs).is_empty()? {
-        fs::File::create(packs.join("0"))?;
+/// Pack ids from this one on are packs of the small-object tier, the pack files of both tiers
+/// live side by side in ``packs``.
+pub const SMALL_PACK_ID_BASE: u64 = 1 << 48;
+
+/// Family of packs an object is written to, see ``Config::small_object_threshold``.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackTier {
+    Regular,
+    /// Tiny objects, stored uncompressed in packs of their own.
+    Small,
+}
+
+impl PackTier {
+    #[must_use]
+    pub fn of(pack_id: u64) -> Self {
+        if pack_id >= SMALL_PACK_ID_BASE {
+            PackTier::Small
+        } else {
+            PackTier::Regular
+        }
+    }
+
+    fn first_pack_id(self) -> u64 {
+        match self {
+            PackTier::Regular => 0,
+            PackTier::Small => SMALL_PACK_ID_BASE,
+        }
+    }
+
+    /// Size the packs of this tier are rolled over at.
+    #[must_use]
+    pub fn pack_size_target(self, config: &Config) -> u64 {
+        match self {
+            PackTier::Regular => config.pack_size_target,
+            PackTier::Small => config
+                .small_pack_size_target
+                .unwrap_or(config.pack_size_target),
+        }
     }
-    let mut current_pack_id = 0;
-    for entry in packs.read_dir()? {
-        let path = entry?.path();
+}
+
+/// Id of the last pack of ``tier``, ``None`` if the tier has no pack yet.
+fn last_pack_id(packs: &PackLayout, tier: PackTier) -> Result<Option<u64>, Error> {
+    let mut last = None;
+    for path in packs.files()? {
         if let Some(filename) = path.file_name() {
             let n = filename.to_string_lossy();
             let n = n
@@ -280,170 +823,766 @@ fn find_current_pack_id(packs: &PathBuf, pack_size_target: u64) -> Result<u64, E
                     source: err,
                     n: n.to_string(),
                 })?;
-            current_pack_id = std::cmp::max(current_pack_id, n);
+            if PackTier::of(n) == tier {
+                last = last.max(Some(n));
+            }
         }
     }
+    Ok(last)
+}
+
+pub(crate) fn find_current_pack_id(
+    packs: &PackLayout,
+    tier: PackTier,
+    pack_size_target: u64,
+) -> Result<u64, Error> {
+    // make sure the tier has a pack, if not create its first one
+    let Some(mut current_pack_id) = last_pack_id(packs, tier)? else {
+        let current_pack_id = tier.first_pack_id();
+        start_pack(packs, current_pack_id)?;
+        return Ok(current_pack_id);
+    };
 
     // check if the current pack exceed pack target size
-    let p = Dir(packs).at_path(&format!("{current_pack_id}"));
-    let fpack = fs::OpenOptions::new().read(true).open(p)?;
+    let fpack = fs::OpenOptions::new()
+        .read(true)
+        .open(packs.path(current_pack_id))?;
     if fpack.metadata()?.len() >= pack_size_target {
         current_pack_id += 1;
-        let p = Dir(packs).at_path(&format!("{current_pack_id}"));
-        fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&p)
-            .map_err(|err| Error::IoOpen {
-                source: err,
-                path: p,
-            })?;
+        start_pack(packs, current_pack_id)?;
     }
 
     Ok(current_pack_id)
 }
 
-pub fn insert_many<I>(sources: I, cnt: &Container) -> Result<Vec<(u64, u64, String)>, Error>
+/// Open pack ``pack_id`` to read and write, created if it does not exist yet.
+///
+/// NOTE: never truncated, a pack that already has entries keeps them and is appended to.
+fn open_pack(packs: &PackLayout, pack_id: u64) -> Result<File, Error> {
+    let p = packs.new_path(pack_id)?;
+    fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .read(true)
+        .open(&p)
+        .map_err(|err| Error::IoOpen {
+            source: err,
+            path: p,
+        })
+}
+
+/// Start pack ``pack_id``. It is created exclusively, when another writer rolled over to it at
+/// the same time its pack is opened as it is, never truncated.
+fn start_pack(packs: &PackLayout, pack_id: u64) -> Result<File, Error> {
+    let p = packs.new_path(pack_id)?;
+    match fs::OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .read(true)
+        .open(&p)
+    {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => open_pack(packs, pack_id),
+        opened => opened.map_err(|err| Error::IoOpen {
+            source: err,
+            path: p,
+        }),
+    }
+}
+
+/// Whether a source of ``size_hint`` fills a pack of ``pack_size_target`` on its own, such a
+/// source is written to a pack of its own. Sources without size hint are never taken as
+/// oversized.
+fn oversized(size_hint: Option<u64>, pack_size_target: u64) -> bool {
+    size_hint.is_some_and(|size| size >= pack_size_target)
+}
+
+/// Start a new empty pack in every tier so that subsequent writes never append to the current
+/// ones.
+pub(crate) fn seal_current_pack(packs: &PackLayout) -> Result<(), Error> {
+    for tier in [PackTier::Regular, PackTier::Small] {
+        if tier == PackTier::Small && last_pack_id(packs, tier)?.is_none() {
+            continue;
+        }
+        let current_pack_id = find_current_pack_id(packs, tier, u64::MAX)?;
+        if fs::metadata(packs.path(current_pack_id))?.len() > 0 {
+            let p = packs.new_path(current_pack_id + 1)?;
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&p)
+                .map_err(|err| Error::IoOpen {
+                    source: err,
+                    path: p,
+                })?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop the current pack of every tier from the sorted ``pack_ids``, they are still appended to.
+pub(crate) fn retain_sealed(pack_ids: &mut Vec<u64>) {
+    let current: Vec<u64> = [PackTier::Regular, PackTier::Small]
+        .into_iter()
+        .filter_map(|tier| {
+            pack_ids
+                .iter()
+                .copied()
+                .filter(|id| PackTier::of(*id) == tier)
+                .max()
+        })
+        .collect();
+    pack_ids.retain(|id| !current.contains(id));
+}
+
+/// ``InsertOutcome`` of an object inserted to packs.
+pub type PackInsertOutcome = InsertOutcome<InsertResult>;
+
+pub fn insert_many<I>(sources: I, cnt: &Container) -> Result<Vec<InsertResult>, Error>
 where
     I: IntoIterator,
-    I::Item: ReaderMaker,
+    I::Item: ReaderMaker + Send,
 {
     let compression = cnt.compression()?;
     _insert_many_internal(sources, cnt, &compression)
 }
 
+/// Insert every source to packs and return one ``InsertOutcome`` per source, in order.
+///
+/// Unlike ``insert_many``, a failing source does not abort the batch: its partially written bytes
+/// are dropped from the pack and the next source is inserted. An ``Ok`` outcome means the object
+/// and its index entry are committed, so callers only need to retry the failed sources.
+/// ``Err`` is returned only when the container itself can not be written.
+pub fn try_insert_many<I>(sources: I, cnt: &Container) -> Result<Vec<PackInsertOutcome>, Error>
+where
+    I: IntoIterator,
+    I::Item: ReaderMaker + Send,
+{
+    let compression = cnt.compression()?;
+    _insert_many_outcomes(sources, cnt, &compression, true, Scope::Unscoped)
+}
+
+/// Insert with fail-fast: the objects written before the failing source stay committed.
 pub fn _insert_many_internal<I>(
     sources: I,
     cnt: &Container,
     compression: &Compression,
-) -> Result<Vec<(u64, u64, String)>, Error>
+) -> Result<Vec<InsertResult>, Error>
+where
+    I: IntoIterator,
+    I::Item: ReaderMaker + Send,
+{
+    _insert_many_outcomes(sources, cnt, compression, false, Scope::Unscoped)?
+        .into_iter()
+        .collect()
+}
+
+/// Insert every source to packs for ``scope`` (see ``namespace::Scope``), with
+/// ``continue_on_error`` a failing source does not abort the batch.
+pub(crate) fn _insert_many_outcomes<I>(
+    sources: I,
+    cnt: &Container,
+    compression: &Compression,
+    continue_on_error: bool,
+    scope: Scope,
+) -> Result<Vec<PackInsertOutcome>, Error>
 where
     I: IntoIterator,
-    I::Item: ReaderMaker,
+    I::Item: ReaderMaker + Send,
 {
     cnt.valid()?;
 
-    let mut conn = Connection::open(cnt.packs_db())?;
-    let packs = cnt.packs();
-    let pack_size_target = cnt.config()?.pack_size_target;
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let config = cnt.config()?;
+    let capabilities = config.capabilities();
 
-    // cwp: current working pack
-    let mut cwp_id = find_current_pack_id(&cnt.packs(), pack_size_target)?;
-    let cwp = cnt.packs().join(format!("{cwp_id}"));
-    let mut cwp = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .read(true)
-        .open(cwp)?;
-    let mut offset = cwp.seek(io::SeekFrom::End(0))?;
-    let dig_algo = &digest::SHA256;
+    // drop what interrupted writers left at the end of packs before appending after it
+    journal::recover(cnt, &conn)?;
+    // disk usage grows with every entry written, only needed to check ``max_total_size``
+    let used = if config.max_total_size.is_some() {
+        cnt.disk_usage()?
+    } else {
+        0
+    };
+    let mut writer = PackWriter {
+        cnt,
+        conn: &conn,
+        journal: Journal::open(cnt)?,
+        framed: capabilities.entry_header,
+        continue_on_error,
+        scope,
+        used,
+        failed: false,
+        dictionary: match config.zstd_dictionary {
+            Some(id) => Some(cnt.dictionaries().get(id)?),
+            None => None,
+        },
+        config,
+    };
 
-    let mut nbytes_hash = Vec::new();
-    let mut sources = sources.into_iter().peekable();
-
-    // outer loop control the increment of pack id
-    loop {
-        // transaction for every pack writing
-        let tx = conn.transaction()?;
-        if offset >= pack_size_target {
-            // reset when move to new pack
-            cwp_id += 1;
-            offset = 0;
-            let p = Dir(&packs).at_path(&format!("{cwp_id}"));
-            cwp = fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(p)?;
-        }
+    let Some(threshold) = writer.config.small_object_threshold else {
+        return writer.write(PackTier::Regular, compression, sources);
+    };
 
-        if sources.peek().is_none() {
-            break;
-        }
+    // split by size, the position of every source is kept to give the outcomes back in order
+    let (small, regular): (Vec<_>, Vec<_>) = sources
+        .into_iter()
+        .enumerate()
+        .partition(|(_, source)| source.size_hint().is_some_and(|size| size < threshold));
+    let (small_pos, small): (Vec<_>, Vec<_>) = small.into_iter().unzip();
+    let (regular_pos, regular): (Vec<_>, Vec<_>) = regular.into_iter().unzip();
+
+    // tiny objects gain little from compression, they are stored as they are
+    let mut outcomes: Vec<_> = small_pos
+        .into_iter()
+        .zip(writer.write(PackTier::Small, &Compression::Uncompressed, small)?)
+        .collect();
+    if !writer.failed {
+        let regular = writer.write(PackTier::Regular, compression, regular)?;
+        outcomes.extend(regular_pos.into_iter().zip(regular));
+    }
+    outcomes.sort_by_key(|(pos, _)| *pos);
 
-        for rmaker in sources.by_ref() {
-            // NOTE: Using small chunk_size can be fast in terms of benchmark.
-            // Ideally should accept a hint for buffer size (loose -> packs)
-            // 64 KiB from legacy dos  TODO: make it configurable??
-            let chunk_size = 65_536;
+    Ok(outcomes.into_iter().map(|(_, outcome)| outcome).collect())
+}
 
-            // XXX: for if need to do the valitation for the hash, the idea is to having an object
-            // encapsulate the pre-computed hash (better with cheap checksum). For Readers that has no pre-compute hash it return
-            // None. The method is from ReaderMaker and calling rmaker.expected_hash(). If the hash
-            // already exist and do not need to run validation, the writer can be normal writer without
-            // hash.
+/// Appends entries to the current pack of a tier, one index transaction per pack.
+struct PackWriter<'a> {
+    cnt: &'a Container,
+    conn: &'a Connection,
+    journal: Journal,
+    config: Config,
+    framed: bool,
+    continue_on_error: bool,
+    /// Recorded for every object inserted, written or already stored.
+    scope: Scope<'a>,
+    /// Bytes the container takes on disk, kept up to date to check ``max_total_size``.
+    used: u64,
+    /// A source failed without ``continue_on_error``, nothing more is written.
+    failed: bool,
+    /// zstd dictionary of ``Config::zstd_dictionary``.
+    dictionary: Option<Arc<[u8]>>,
+}
 
-            let mut stream = rmaker.make_reader()?;
+impl PackWriter<'_> {
+    /// Write ``sources`` to the packs of ``tier``, read and compressed ahead by a ``Pipeline``
+    /// unless ``Config::pack_compressors`` is ``0`` or there is a single source.
+    fn write<I>(
+        &mut self,
+        tier: PackTier,
+        compression: &Compression,
+        sources: I,
+    ) -> Result<Vec<PackInsertOutcome>, Error>
+    where
+        I: IntoIterator,
+        I::Item: ReaderMaker + Send,
+    {
+        let dictionary = match compression {
+            Compression::Zstd(_) => self.dictionary.clone(),
+            _ => None,
+        };
+        let dictionary = dictionary.as_deref();
+        let sources = sources.into_iter();
+
+        // no more threads than sources, a single source gains nothing from them
+        let (readers, compressors) = self.config.pack_workers();
+        let count = sources.size_hint().1.unwrap_or(usize::MAX);
+        let (readers, compressors) = (readers.clamp(1, count.max(1)), compressors.min(count));
+        if compressors == 0 || count < 2 {
+            let sources = sources.map(Prepared::Source);
+            return self.append(tier, compression, dictionary, sources);
+        }
 
-            let (bytes_read, hash_hex, compressed) =
-                match (compression, rmaker.maybe_content_format()) {
-                    (Compression::Zlib(level), Ok(MaybeContentFormat::MaybeLargeText)) => {
-                        let writer = ZlibEncoder::new(&mut cwp, flate2::Compression::new(*level));
-                        let mut hwriter = HashWriter::new(writer, dig_algo);
-                        let bytes_copied = copy_by_chunk(&mut stream, &mut hwriter, chunk_size)?;
+        let policy = self.config.compression_policy.clone();
+        thread::scope(|scope| {
+            let sources = pipeline::spawn(
+                scope,
+                sources,
+                readers,
+                compressors,
+                compression,
+                &policy,
+                dictionary,
+            );
+            self.append(tier, compression, dictionary, sources)
+        })
+    }
 
-                        let hash = hwriter.finish();
-                        let hash_hex = hex::encode(hash);
+    fn append<R, I>(
+        &mut self,
+        tier: PackTier,
+        compression: &Compression,
+        dictionary: Option<&[u8]>,
+        sources: I,
+    ) -> Result<Vec<PackInsertOutcome>, Error>
+    where
+        R: ReaderMaker,
+        I: Iterator<Item = Prepared<R>>,
+    {
+        let packs = self.cnt.pack_layout()?;
+        let pack_size_target = tier.pack_size_target(&self.config);
+
+        // cwp: current working pack
+        // NOTE: not opened in append mode since the entry header is written back in front of the
+        // payload once its hash is known.
+        let mut cwp_id = find_current_pack_id(&packs, tier, pack_size_target)?;
+        let mut cwp = open_pack(&packs, cwp_id)?;
+
+        let mut outcomes = Vec::new();
+        let mut sources = sources.peekable();
+
+        // outer loop control the increment of pack id
+        loop {
+            // transaction for every pack writing
+            let tx = db::begin_write(self.conn)?;
+            // another writer (thread or process) may have appended to the pack or started a new
+            // one since the last batch, only trust what is on disk once the write lock is held
+            let current_id = find_current_pack_id(&packs, tier, u64::MAX)?;
+            if current_id != cwp_id {
+                cwp_id = current_id;
+                cwp = fs::OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .open(packs.path(cwp_id))?;
+            }
+            let mut offset = cwp.seek(io::SeekFrom::End(0))?;
+            // an oversized source starts a new pack unless the current one is still empty
+            let oversized_next = sources
+                .peek()
+                .is_some_and(|prepared| oversized(prepared.size_hint(), pack_size_target));
+            if offset >= pack_size_target || (offset > 0 && oversized_next) {
+                // move to new pack
+                cwp_id += 1;
+                cwp = start_pack(&packs, cwp_id)?;
+                offset = cwp.seek(io::SeekFrom::End(0))?;
+            }
 
-                        (bytes_copied, hash_hex, true)
+            if self.failed || sources.peek().is_none() {
+                break;
+            }
+            // packs are append-only, a pack shorter than its index was cut by someone else
+            let indexed_end = tx.pack_end(cwp_id)?;
+            if offset < indexed_end {
+                return Err(Error::PackNotAppendOnly {
+                    pack_id: cwp_id,
+                    offset,
+                    indexed_end,
+                });
+            }
+            self.journal.intent(cwp_id, offset)?;
+
+            // outcomes of this pack, only durable once the transaction is committed
+            let mut pending = Vec::new();
+            // their index entries, inserted together right before the commit
+            let mut entries: Vec<PackEntry> = Vec::new();
+            // position in ``entries`` of every object written to this pack so far
+            let mut written_here: HashMap<String, usize> = HashMap::new();
+            while let Some(prepared) = sources.next_if(|prepared| {
+                offset == 0 || !oversized(prepared.size_hint(), pack_size_target)
+            }) {
+                let written = match prepared {
+                    Prepared::Encoded(encoded) => {
+                        encoded.and_then(|encoded| encoded.append_to(&mut cwp, self.framed, cwp_id))
                     }
-                    (Compression::Zstd(lv), Ok(MaybeContentFormat::MaybeLargeText)) => {
-                        let mut writer = ZstdEncoder::new(&mut cwp, *lv)?;
-                        let mut hwriter = HashWriter::new(&mut writer, dig_algo);
-                        let bytes_copied = copy_by_chunk(&mut stream, &mut hwriter, chunk_size)?;
-
-                        let hash = hwriter.finish();
-                        let hash_hex = hex::encode(hash);
+                    Prepared::Source(rmaker) => write_entry(
+                        &mut cwp,
+                        &rmaker,
+                        entry_compression(
+                            &rmaker,
+                            compression,
+                            &self.config.compression_policy,
+                            dictionary.is_some(),
+                        ),
+                        dictionary,
+                        self.framed,
+                        cwp_id,
+                        offset,
+                    ),
+                };
 
-                        (bytes_copied, hash_hex, true)
+                // the hashkey is only known once the payload is written, the payload of an object
+                // already stored is cut off again so that duplicates never grow the pack
+                let stored = match &written {
+                    Ok(entry) => match written_here.get(&entry.hashkey) {
+                        Some(&i) => Some(entries[i].clone()),
+                        None => self.indexed(&tx, &entry.hashkey)?,
+                    },
+                    Err(_) => None,
+                };
+                if let Some(stored) = stored {
+                    cwp.set_len(offset)?;
+                    cwp.seek(SeekFrom::End(0))?;
+                    pending.push(Ok(InsertResult::packed(stored)));
+                    continue;
+                }
+
+                let written = written.and_then(|entry| {
+                    self.config.check_quota(
+                        entry.raw_size,
+                        self.used + entry.offset + entry.size - offset,
+                    )?;
+                    Ok(entry)
+                });
+
+                match written {
+                    Ok(entry) => {
+                        self.journal.written(
+                            entry.pack_id,
+                            entry.offset,
+                            entry.size,
+                            &entry.hashkey,
+                        )?;
+                        self.used += entry.offset + entry.size - offset;
+                        offset = entry.offset + entry.size;
+                        pending.push(Ok(InsertResult::packed(entry.clone())));
+                        written_here.insert(entry.hashkey.clone(), entries.len());
+                        entries.push(entry);
                     }
-                    _ => {
-                        let mut hwriter = HashWriter::new(&mut cwp, dig_algo);
-                        let bytes_copied = copy_by_chunk(&mut stream, &mut hwriter, chunk_size)?;
-                        let hash = hwriter.ctx.finish();
-                        let hash_hex = hex::encode(hash);
-
-                        (bytes_copied, hash_hex, false)
+                    Err(err) => {
+                        // drop what was partially written for this source
+                        cwp.set_len(offset)?;
+                        cwp.seek(SeekFrom::End(0))?;
+                        pending.push(Err(err));
+                        if !self.continue_on_error {
+                            self.failed = true;
+                            break;
+                        }
                     }
-                };
+                }
 
-            // look at the end of cwp compute how many bytes had been written
-            let bytes_write = cwp.stream_position()? - offset;
-
-            let mut stmt = tx.prepare_cached("INSERT OR IGNORE INTO db_object (hashkey, compressed, size, offset, length, pack_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
-            stmt.execute(params![
-                &hash_hex,
-                compressed,
-                bytes_read,
-                offset,
-                bytes_write,
-                cwp_id,
-            ])
-            .map_err(|err| Error::SQLiteInsertError { source: err })?;
-            offset += bytes_write;
-
-            // TODO: Should be removed bytes_write with considering using cheap checksum for PObject.
-            nbytes_hash.push((bytes_read, bytes_write, hash_hex));
-
-            if offset >= pack_size_target {
-                break;
+                if offset >= pack_size_target {
+                    break;
+                }
             }
+
+            // the index must never refer to bytes that are not on disk
+            cwp.sync_data()?;
+            let inserted: Vec<String> = pending
+                .iter()
+                .flatten()
+                .map(|inserted| inserted.hashkey.clone())
+                .collect();
+            let committed = tx
+                .insert(&entries)
+                .and_then(|()| namespace::record(&*tx, self.scope, &inserted))
+                .and_then(|()| tx.commit().map_err(Error::from));
+            if let Err(err) = committed {
+                let cause = err.to_string();
+                outcomes.extend(pending.into_iter().map(|outcome| {
+                    outcome.and_then(|inserted| {
+                        Err(Error::NotCommitted {
+                            hashkey: inserted.hashkey,
+                            cause: cause.clone(),
+                        })
+                    })
+                }));
+                if !self.continue_on_error {
+                    self.failed = true;
+                    break;
+                }
+            } else {
+                self.journal.commit()?;
+                for inserted in pending.iter().flatten() {
+                    self.cnt.note_indexed(&inserted.hashkey);
+                }
+                outcomes.extend(pending);
+            }
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Entry of ``hashkey`` if the object is already in packs, queried in the write transaction.
+    ///
+    /// NOTE: the bloom filter is not consulted, it misses the objects packed by other processes
+    /// and only the index read under the write lock tells whether the object is there.
+    fn indexed(&self, tx: &Connection, hashkey: &str) -> Result<Option<PackEntry>, Error> {
+        tx.select(hashkey)
+    }
+}
+
+/// Compression of the entry of ``rmaker``, ``Compression::Uncompressed`` unless ``policy``
+/// guesses its content is worth compressing. With a zstd dictionary small contents are compressed
+/// as well, the dictionary is what makes it worth it.
+pub(crate) fn entry_compression<'a, R>(
+    rmaker: &R,
+    compression: &'a Compression,
+    policy: &CompressionPolicy,
+    dictionary: bool,
+) -> &'a Compression
+where
+    R: ReaderMaker,
+{
+    match rmaker.maybe_content_format(policy) {
+        Ok(MaybeContentFormat::MaybeLargeText) => compression,
+        Ok(MaybeContentFormat::SmallContent) if dictionary => compression,
+        _ => &Compression::Uncompressed,
+    }
+}
+
+/// Write one source at ``offset`` of the current working pack with ``compression`` and return its
+/// index entry.
+pub(crate) fn write_entry<R>(
+    cwp: &mut File,
+    rmaker: &R,
+    compression: &Compression,
+    dictionary: Option<&[u8]>,
+    framed: bool,
+    pack_id: u64,
+    offset: u64,
+) -> Result<PackEntry, Error>
+where
+    R: ReaderMaker,
+{
+    // reserve room for the header, it is filled after the payload is written
+    if framed {
+        cwp.write_all(&[0u8; ENTRY_HEADER_LEN])?;
+    }
+    let payload_offset = cwp.stream_position()?;
+    let encoding = encode(rmaker.make_reader()?, &mut *cwp, compression, dictionary)?;
+
+    // look at the end of cwp compute how many bytes had been written
+    let bytes_write = cwp.stream_position()? - payload_offset;
+
+    if framed {
+        let header = encoding.header(bytes_write)?;
+        cwp.seek(SeekFrom::Start(offset))?;
+        cwp.write_all(&header.to_bytes())?;
+        cwp.seek(SeekFrom::End(0))?;
+    }
+
+    Ok(encoding.into_entry(pack_id, payload_offset, bytes_write))
+}
+
+/// What ``encode`` learnt of a content while writing it.
+#[derive(Debug)]
+pub(crate) struct Encoding {
+    pub hashkey: String,
+    pub compressed: bool,
+    pub raw_size: u64,
+    /// CRC32 of the bytes written.
+    pub crc: u32,
+}
+
+impl Encoding {
+    /// Entry header of the content written in ``length`` bytes.
+    pub(crate) fn header(&self, length: u64) -> Result<EntryHeader, Error> {
+        Ok(EntryHeader {
+            compressed: self.compressed,
+            raw_size: self.raw_size,
+            length,
+            crc: self.crc,
+            hash: hex_to_hash(&self.hashkey)?,
+        })
+    }
+
+    pub(crate) fn into_entry(self, pack_id: u64, offset: u64, size: u64) -> PackEntry {
+        PackEntry {
+            hashkey: self.hashkey,
+            compressed: self.compressed,
+            raw_size: self.raw_size,
+            size,
+            offset,
+            pack_id,
         }
+    }
+}
+
+/// Copy the content of ``reader`` to ``sink`` with ``compression``, hashing the bytes read and
+/// checksumming the bytes written.
+pub(crate) fn encode<R, W>(
+    reader: R,
+    sink: W,
+    compression: &Compression,
+    dictionary: Option<&[u8]>,
+) -> Result<Encoding, Error>
+where
+    R: Read,
+    W: Write,
+{
+    // NOTE: Using small chunk_size can be fast in terms of benchmark.
+    // Ideally should accept a hint for buffer size (loose -> packs)
+    // 64 KiB from legacy dos  TODO: make it configurable??
+    let chunk_size = 65_536;
+    let dig_algo = &digest::SHA256;
+
+    // XXX: for if need to do the valitation for the hash, the idea is to having an object
+    // encapsulate the pre-computed hash (better with cheap checksum). For Readers that has no pre-compute hash it return
+    // None. The method is from ReaderMaker and calling rmaker.expected_hash(). If the hash
+    // already exist and do not need to run validation, the writer can be normal writer without
+    // hash.
+
+    // hash while copying, on the reading side so the hash is of the exact bytes read whatever
+    // the encoder consumes of its buffers
+    let mut stream = HashReader::new(reader, dig_algo);
+    let mut sink = CrcWriter::new(sink);
+
+    let (bytes_read, compressed) = match compression {
+        Compression::Zlib(level) => {
+            let mut writer = ZlibEncoder::new(&mut sink, flate2::Compression::new(*level));
+            let bytes_copied = copy_by_chunk(&mut stream, &mut writer, chunk_size)?;
+            writer.finish()?;
 
-        tx.commit()?;
+            (bytes_copied, true)
+        }
+        Compression::Zstd(lv) => {
+            let mut writer = match dictionary {
+                Some(dict) => ZstdEncoder::with_dictionary(&mut sink, *lv, dict)?,
+                None => ZstdEncoder::new(&mut sink, *lv)?,
+            };
+            let bytes_copied = copy_by_chunk(&mut stream, &mut writer, chunk_size)?;
+            // write the frame epilogue, flush alone leaves the frame unterminated
+            writer.finish()?;
+
+            (bytes_copied, true)
+        }
+        Compression::Uncompressed => {
+            let bytes_copied = copy_by_chunk(&mut stream, &mut sink, chunk_size)?;
+
+            (bytes_copied, false)
+        }
+    };
+
+    Ok(Encoding {
+        hashkey: hex::encode(stream.finish()),
+        compressed,
+        raw_size: bytes_read,
+        crc: sink.crc().sum(),
+    })
+}
+
+/// Counts the bytes read through it, to tell where a compressed stream ended in a pack.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
     }
+}
 
-    Ok(nbytes_hash)
+/// Whether ``head`` may be the start of a zlib stream as written by ``ZlibEncoder``: deflate with
+/// a 32 KiB window, no preset dictionary and a valid header checksum.
+fn is_zlib_header(head: &[u8]) -> bool {
+    head.len() >= 2
+        && head[0] == 0x78
+        && head[1] & 0x20 == 0
+        && (u16::from(head[0]) << 8 | u16::from(head[1])) % 31 == 0
+}
+
+/// Decode the compressed stream starting at ``offset`` of ``pack`` and not going past ``end``.
+///
+/// Returns the raw size, the size in the pack and the hashkey of the content, or ``None`` if no
+/// complete stream can be decoded there. A zstd frame compressed with a dictionary is decoded
+/// with it, from ``dictionaries``.
+fn decode_stream_at(
+    pack: &Path,
+    offset: u64,
+    end: u64,
+    zstd: bool,
+    dictionaries: &Dictionaries,
+) -> Result<Option<(u64, u64, String)>, Error> {
+    let mut f = File::open(pack)?;
+    f.seek(SeekFrom::Start(offset))?;
+    let mut counter = CountingReader {
+        inner: f.take(end - offset),
+        count: 0,
+    };
+    let mut rdr = BufReader::new(&mut counter);
+    let mut hwriter = HashWriter::new(io::sink(), &digest::SHA256);
+    let decoded = if zstd {
+        let dict = match frame_dictionary_id(rdr.fill_buf()?) {
+            Some(id) => match dictionaries.get(id) {
+                Ok(dict) => Some(dict),
+                // the frame can not be decoded without its dictionary
+                Err(Error::DictionaryNotFound { .. }) => return Ok(None),
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
+        match dict {
+            Some(dict) => ZstdDecoder::with_dictionary(&mut rdr, &dict),
+            None => ZstdDecoder::with_buffer(&mut rdr),
+        }
+        .and_then(|decoder| io::copy(&mut decoder.single_frame(), &mut hwriter))
+    } else {
+        io::copy(&mut flate2::bufread::ZlibDecoder::new(&mut rdr), &mut hwriter)
+    };
+    let Ok(raw_size) = decoded else {
+        return Ok(None);
+    };
+    // the decoders only consume the bytes of their stream, the rest is left in the buffer
+    let pending = rdr.buffer().len() as u64;
+    drop(rdr);
+    let size = counter.count - pending;
+    Ok(Some((raw_size, size, hex::encode(hwriter.finish()))))
+}
+
+/// Recover the compressed entries stored between ``start`` and ``end`` of a pack.
+///
+/// Packs are plain concatenations of payloads, but zlib and zstd streams carry their own start
+/// and end. The range is walked byte by byte for a stream header, every stream decoding to the
+/// end becomes an entry. Uncompressed payloads have no boundary to find and are skipped.
+pub(crate) fn scan_compressed_entries(
+    pack: &Path,
+    pack_id: u64,
+    start: u64,
+    end: u64,
+    dictionaries: &Dictionaries,
+) -> Result<Vec<PackEntry>, Error> {
+    let mut entries = vec![];
+    let mut rdr = BufReader::new(File::open(pack)?);
+    rdr.seek(SeekFrom::Start(start))?;
+    let mut offset = start;
+    while offset + 2 <= end {
+        let mut head = [0u8; 4];
+        let n = usize::try_from((end - offset).min(4)).unwrap_or(4);
+        rdr.read_exact(&mut head[..n])?;
+        let zstd = head[..n] == ZSTD_MAGIC;
+        let found = if zstd || is_zlib_header(&head[..n]) {
+            decode_stream_at(pack, offset, end, zstd, dictionaries)?
+        } else {
+            None
+        };
+        match found {
+            Some((raw_size, size, hashkey)) => {
+                entries.push(PackEntry {
+                    hashkey,
+                    compressed: true,
+                    raw_size,
+                    offset,
+                    size,
+                    pack_id,
+                });
+                offset += size;
+                rdr.seek(SeekFrom::Start(offset))?;
+            }
+            None => {
+                offset += 1;
+                rdr.seek_relative(1 - n as i64)?;
+            }
+        }
+    }
+    Ok(entries)
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
     use rstest::*;
-    use std::{collections::HashMap, io::Write};
+    use std::{
+        collections::{HashMap, HashSet},
+        io::Write,
+    };
 
     use crate::{
-        io::ByteString,
+        container::traverse_packs,
+        io::{ByteString, CompressOverride, Store},
         stat,
         test_utils::{new_container, PACK_TARGET_SIZE},
     };
@@ -455,7 +1594,7 @@ mod tests {
         let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
 
         let bstr: ByteString = b"test 0".to_vec();
-        let (_, _, hash) = insert(bstr, &cnt).unwrap();
+        let hash = insert(bstr, &cnt).unwrap().hashkey;
 
         // check packs has `0` and audit has only one pack
         // check content of 0 pack is `test 0`
@@ -472,7 +1611,7 @@ mod tests {
 
         // subsquent add will still goes to pack 0 (since pack_target_size is 4 GiB)
         let bstr: ByteString = b"test 1".to_vec();
-        let (_, _, hash) = insert(bstr, &cnt).unwrap();
+        let hash = insert(bstr, &cnt).unwrap().hashkey;
 
         // check packs has `0` and audit has only one pack
         // check content of 0 pack is `test 0`
@@ -487,6 +1626,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn io_packs_entry_header_roundtrip() {
+        let header = EntryHeader {
+            compressed: true,
+            raw_size: 1024,
+            length: 42,
+            crc: 0xDEAD_BEEF,
+            hash: [7u8; 32],
+        };
+        let buf = header.to_bytes();
+        assert_eq!(EntryHeader::from_bytes(&buf), Some(header));
+
+        let mut buf = buf;
+        buf[0] = b'X';
+        assert_eq!(EntryHeader::from_bytes(&buf), None);
+    }
+
+    #[test]
+    fn io_packs_insert_result() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+1");
+
+        let content = "test ".repeat(200).into_bytes();
+        let inserted = insert(content.clone(), &cnt).unwrap();
+        assert_eq!(inserted.raw_size, 1000);
+        assert!(inserted.stored_size < inserted.raw_size);
+        assert!(inserted.compressed);
+        assert_eq!(inserted.store, Store::Packs);
+
+        // the existing object is reported when inserted again
+        assert_eq!(insert(content, &cnt).unwrap(), inserted);
+    }
+
     #[test]
     fn io_packs_insert_1_when_1_exist() {
         let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
@@ -498,7 +1669,7 @@ mod tests {
         fs::File::create(packs.join("1")).unwrap();
 
         let bstr: ByteString = b"test 0".to_vec();
-        let (_, _, hash) = insert(bstr, &cnt).unwrap();
+        let hash = insert(bstr, &cnt).unwrap().hashkey;
 
         // check packs has 2 packs
         // check content of 1 pack is `test 0`
@@ -527,12 +1698,12 @@ mod tests {
 
         // more bytes
         // let mut buf = b"test 0".reader();
-        // let (_, hash) = insert(&mut buf, &cnt).unwrap();
+        // let hash = insert(&mut buf, &cnt).unwrap().hashkey;
         let mut hash_content_map: HashMap<String, String> = HashMap::new();
         for i in 0..100 {
             let content = format!("test {i}");
             let buf = content.clone().into_bytes();
-            let (_, _, hash) = insert(buf, &cnt).unwrap();
+            let hash = insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -548,6 +1719,129 @@ mod tests {
         }
     }
 
+    #[rstest]
+    // smaller than the target, shares the pack with its neighbours
+    #[case(1000, vec![0, 0, 0])]
+    #[case(1023, vec![0, 0, 1])]
+    // fills a pack of its own
+    #[case(1024, vec![0, 1, 2])]
+    #[case(4096, vec![0, 1, 2])]
+    fn io_packs_object_around_pack_size_target(#[case] size: usize, #[case] packs: Vec<u64>) {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        let contents = vec![b"small 0".to_vec(), vec![1u8; size], b"small 1".to_vec()];
+        let written = insert_many(contents.clone(), &cnt).unwrap();
+
+        let got: Vec<u64> = written
+            .iter()
+            .map(|inserted| {
+                let obj = extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+                obj.loc
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(got, packs);
+        for (inserted, content) in written.iter().zip(contents) {
+            let obj = extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+            assert_eq!(Vec::try_from(obj).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn io_packs_oversized_object_keeps_pack() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        let small = insert(b"small 0".to_vec(), &cnt).unwrap();
+        let pack_0 = fs::read(cnt.packs().join("0")).unwrap();
+
+        // one by one, every oversized object rolls over to a new pack without truncating any
+        for i in 0..3u8 {
+            insert(vec![i; 2048], &cnt).unwrap();
+        }
+        assert_eq!(fs::read(cnt.packs().join("0")).unwrap(), pack_0);
+        for i in 1..=3 {
+            assert_eq!(
+                fs::metadata(cnt.packs().join(format!("{i}")))
+                    .unwrap()
+                    .len(),
+                2048
+            );
+        }
+        let obj = extract(&small.hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(Vec::try_from(obj).unwrap(), b"small 0");
+    }
+
+    #[test]
+    fn io_packs_concurrent_rollover() {
+        let (_tmp_dir, cnt) = new_container(64, "none");
+
+        // every writer has its own container, as separate processes would, and they all roll
+        // over to new packs of 64 bytes at the same time
+        let written: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|t| {
+                    let path = cnt.path.clone();
+                    s.spawn(move || {
+                        let cnt = Container::new(&path);
+                        let mut written = Vec::new();
+                        for i in 0..10 {
+                            let content = format!("thread {t} object {i:02} ").repeat(2);
+                            let sources = vec![content.clone().into_bytes()];
+                            let hashkey = insert_many(sources, &cnt).unwrap()[0].hashkey.clone();
+                            written.push((hashkey, content));
+                        }
+                        written
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+
+        let conn = cnt.db().unwrap();
+        for pack in traverse_packs(&cnt).unwrap() {
+            let pack_id = pack.file_name().unwrap().to_str().unwrap().parse().unwrap();
+            let len = fs::metadata(&pack).unwrap().len();
+            assert!(len >= db::pack_end(&conn, pack_id).unwrap());
+        }
+        assert_eq!(stat(&cnt).unwrap().count.packs, 80);
+        for (hashkey, content) in written {
+            let obj = extract(&hashkey, &cnt).unwrap().unwrap();
+            assert_eq!(String::from_utf8(obj.try_into().unwrap()).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn io_packs_refuse_write_before_indexed_end() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        insert(b"test 0".to_vec(), &cnt).unwrap();
+
+        // the current pack is cut behind the back of the index
+        let pack = fs::OpenOptions::new()
+            .write(true)
+            .open(cnt.packs().join("0"))
+            .unwrap();
+        pack.set_len(3).unwrap();
+
+        let err = insert(b"test 1".to_vec(), &cnt).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::PackNotAppendOnly {
+                    pack_id: 0,
+                    offset: 3,
+                    indexed_end: 6
+                }
+            ),
+            "got err: {err}"
+        );
+    }
+
     #[test]
     fn io_packs_extract_from_any_single() {
         let (_tmp_dir, cnt) = new_container(6400, "none");
@@ -557,7 +1851,7 @@ mod tests {
         for i in 0..n {
             let content = format!("test {i}");
             let buf = content.clone().into_bytes();
-            let (_, _, hash) = insert(buf, &cnt).unwrap();
+            let hash = insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -581,7 +1875,7 @@ mod tests {
         for i in 0..100 {
             let content = format!("test {i}").repeat(i);
             let buf = content.clone().into_bytes();
-            let (_, _, hash) = insert(buf, &cnt).unwrap();
+            let hash = insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -589,10 +1883,301 @@ mod tests {
         assert_eq!(info.count.packs, 100);
     }
 
+    #[rstest]
+    #[case::uncompressed("none", false)]
+    #[case::zstd_framed("zstd:3", true)]
+    fn io_packs_pipeline_same_as_sequential(#[case] compression: &str, #[case] framed: bool) {
+        // duplicates, and a source too large to be read ahead which is streamed to its own pack
+        let mut sources: Vec<ByteString> = (0..100)
+            .map(|i| format!("test {}", i % 80).repeat(i % 80 + 1).into_bytes())
+            .collect();
+        sources.insert(50, vec![b'x'; crate::pipeline::BUFFERED_MAX as usize + 1]);
+
+        let inserted: Vec<_> = [Some(0), Some(4)]
+            .into_iter()
+            .map(|compressors| {
+                let (_tmp_dir, cnt) = new_container(4096, compression);
+                let mut config = cnt.config().unwrap();
+                config.pack_entry_header = framed;
+                config.pack_compressors = compressors;
+                cnt.write_config(&config).unwrap();
+
+                let outcomes = try_insert_many(sources.clone(), &cnt).unwrap();
+                let written: Vec<_> = outcomes.into_iter().map(Result::unwrap).collect();
+                let mut packs: Vec<_> = fs::read_dir(cnt.packs())
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .collect();
+                packs.sort();
+                let packs: Vec<_> = packs.iter().map(|p| fs::read(p).unwrap()).collect();
+                (written, packs)
+            })
+            .collect();
+
+        // same entries at the same place, byte for byte
+        assert_eq!(inserted[0].0.len(), sources.len());
+        assert!(inserted[0].1.len() > 2);
+        assert_eq!(inserted[0], inserted[1]);
+    }
+
+    #[test]
+    fn io_packs_extract_zlib_and_zstd() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        let zlib = insert(vec![b'a'; 1000], &cnt).unwrap();
+
+        let mut config = cnt.config().unwrap();
+        config.compression_algorithm = "zstd:+1".to_string();
+        fs::write(cnt.config_file(), serde_json::to_string(&config).unwrap()).unwrap();
+        let zstd = insert(vec![b'b'; 1000], &cnt).unwrap();
+        assert!(zlib.compressed && zstd.compressed);
+
+        // the index only says both are compressed, the algorithm is told from the stream
+        let read = |hashkey: &str| -> Vec<u8> {
+            extract(hashkey, &cnt).unwrap().unwrap().try_into().unwrap()
+        };
+        assert_eq!(read(&zlib.hashkey), vec![b'a'; 1000]);
+        assert_eq!(read(&zstd.hashkey), vec![b'b'; 1000]);
+    }
+
+    #[test]
+    fn io_packs_try_insert_many_continue_on_error() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        let src_dir = tempfile::tempdir().unwrap();
+
+        // a missing file fails to open, a directory opens but fails to read
+        let sources: Vec<PathBuf> = (0..6)
+            .map(|i| {
+                let p = src_dir.path().join(format!("source_{i}"));
+                match i {
+                    2 => {}
+                    4 => fs::create_dir(&p).unwrap(),
+                    _ => fs::write(&p, format!("test {i}")).unwrap(),
+                }
+                p
+            })
+            .collect();
+
+        let outcomes = try_insert_many(sources, &cnt).unwrap();
+        assert_eq!(outcomes.len(), 6);
+        assert!(outcomes[2].is_err());
+        assert!(outcomes[4].is_err());
+
+        let mut length = 0;
+        for (i, outcome) in outcomes.into_iter().enumerate() {
+            if let Ok(inserted) = outcome {
+                let obj = extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+                let content: ByteString = obj.try_into().unwrap();
+                assert_eq!(content, format!("test {i}").into_bytes());
+                length += inserted.stored_size;
+            }
+        }
+
+        // nothing of the failed sources is left in the pack
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.packs, 4);
+        assert_eq!(info.size.packs_file, length);
+    }
+
+    #[test]
+    fn io_packs_insert_many_quota_exceeded() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let mut config = cnt.config().unwrap();
+        config.max_object_size = Some(8);
+        config.max_total_size = Some(20);
+        cnt.write_config(&config).unwrap();
+
+        let sources: Vec<ByteString> = vec![
+            b"test 0".to_vec(),
+            b"larger than 8".to_vec(),
+            b"test 1".to_vec(),
+            b"test 2".to_vec(),
+            b"test 3".to_vec(),
+        ];
+        let outcomes = try_insert_many(sources, &cnt).unwrap();
+        assert!(matches!(
+            outcomes[1],
+            Err(Error::QuotaExceeded { limit: 8, .. })
+        ));
+        assert!(matches!(
+            outcomes[4],
+            Err(Error::QuotaExceeded { limit: 20, .. })
+        ));
+        assert_eq!(outcomes.iter().filter(|o| o.is_ok()).count(), 3);
+
+        // the refused objects are dropped from the pack
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.packs, 3);
+        assert_eq!(info.size.packs_file, 18);
+
+        // fail-fast insert stops at the first refused object
+        let err = insert_many(vec![b"test 4".to_vec()], &cnt).unwrap_err();
+        assert!(matches!(err, Error::QuotaExceeded { .. }));
+    }
+
+    #[rstest]
+    #[case("none")]
+    #[case("zlib:+1")]
+    #[case("zstd:+1")]
+    fn io_packs_extract_range(#[case] algo: &str) {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, algo);
+
+        let content = (0..1000).map(|i| format!("{i:04}")).collect::<String>();
+        insert(b"test 0".to_vec(), &cnt).unwrap();
+        let hash = insert(content.clone().into_bytes(), &cnt).unwrap().hashkey;
+
+        let read_range = |offset, len| {
+            let mut buf = String::new();
+            extract_range(&hash, offset, len, &cnt)
+                .unwrap()
+                .unwrap()
+                .read_to_string(&mut buf)
+                .unwrap();
+            buf
+        };
+        assert_eq!(read_range(0, 8), "00000001");
+        assert_eq!(read_range(2000, 12), "050005010502");
+        // clamped to the object
+        assert_eq!(read_range(3992, 100), "09980999");
+        assert_eq!(read_range(5000, 100), "");
+
+        assert!(extract_range(&"0".repeat(64), 0, 1, &cnt)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn io_packs_extract_reuses_pack_file() {
+        let (_tmp_dir, cnt) = new_container(64, "zlib:+1");
+
+        // every other object is dropped from the index, as a delete would, leaving dead bytes
+        let contents: Vec<_> = (0..20)
+            .map(|i| format!("test {i}").repeat(4).into_bytes())
+            .collect();
+        let hashkeys: Vec<_> = insert_many(contents.clone(), &cnt)
+            .unwrap()
+            .into_iter()
+            .map(|inserted| inserted.hashkey)
+            .collect();
+        let conn = cnt.db().unwrap();
+        for hashkey in hashkeys.iter().skip(1).step_by(2) {
+            conn.execute(
+                "DELETE FROM db_object WHERE hashkey = ?1",
+                rusqlite::params![hashkey],
+            )
+            .unwrap();
+        }
+        let hashkeys: Vec<_> = hashkeys.into_iter().step_by(2).collect();
+        let contents: Vec<_> = contents.into_iter().step_by(2).collect();
+
+        let read_all = || {
+            extract_many(&hashkeys, &cnt)
+                .unwrap()
+                .map(|obj| ByteString::try_from(obj.unwrap()).unwrap())
+                .collect::<HashSet<_>>()
+        };
+        let expected: HashSet<_> = contents.iter().cloned().collect();
+        assert_eq!(read_all(), expected);
+        let open_packs = || {
+            (0..)
+                .map_while(|pack_id| cnt.pack_file(pack_id).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let files = open_packs();
+        assert!(files.len() > 1);
+        #[cfg(unix)]
+        assert!(files
+            .iter()
+            .zip(open_packs())
+            .all(|(a, b)| Arc::ptr_eq(a, &b)));
+
+        // a pack file written again under the same id is opened again
+        let pack_0 = cnt.pack_path(0).unwrap();
+        let copy = cnt.sandbox().join("0.copy");
+        fs::copy(&pack_0, &copy).unwrap();
+        fs::remove_file(&pack_0).unwrap();
+        fs::rename(&copy, &pack_0).unwrap();
+        #[cfg(unix)]
+        assert!(!Arc::ptr_eq(&files[0], &cnt.pack_file(0).unwrap().unwrap()));
+        assert_eq!(read_all(), expected);
+
+        // the rewritten packs move to new ids, the files of the old ones are gone
+        assert!(crate::maintain::repack(&cnt, false).unwrap().bytes > 0);
+        assert!(cnt.pack_file(0).unwrap().is_none());
+        assert_eq!(read_all(), expected);
+        assert!(cnt.pack_file(999).unwrap().is_none());
+    }
+
+    #[test]
+    fn io_packs_duplicates_not_written() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        let contents: Vec<_> = (0..10)
+            .map(|i| format!("test {i}").repeat(4).into_bytes())
+            .collect();
+
+        // duplicated in the batch
+        let sources: Vec<_> = contents.iter().chain(contents.iter()).cloned().collect();
+        let written = insert_many(sources, &cnt).unwrap();
+        assert_eq!(written[..10], written[10..]);
+        let pack_len = || fs::metadata(cnt.packs().join("0")).unwrap().len();
+        let len = pack_len();
+
+        // already in packs
+        assert_eq!(insert_many(contents.clone(), &cnt).unwrap(), written[..10]);
+        assert_eq!(insert(contents[0].clone(), &cnt).unwrap(), written[0]);
+        assert_eq!(pack_len(), len);
+        assert_eq!(stat(&cnt).unwrap().count.packs, 10);
+        assert_eq!(crate::maintain::repack(&cnt, true).unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn io_packs_duplicates_of_other_container_not_written() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zlib:+1");
+        cnt.update_config(|config| config.set("bloom_filter", "true"))
+            .unwrap();
+        // the bloom filter of ``cnt`` is built before the other container packs the object
+        let content = b"packed by another container".to_vec();
+        let hashkey = cnt.compute_hashkey(content.as_slice()).unwrap();
+        assert!(!cnt.has_object(&hashkey).unwrap());
+
+        let other = Container::open(&cnt.path).unwrap();
+        let written = insert_many(vec![content.clone()], &other).unwrap();
+        let pack_len = || fs::metadata(cnt.packs().join("0")).unwrap().len();
+        let len = pack_len();
+
+        assert_eq!(insert_many(vec![content], &cnt).unwrap(), written);
+        assert_eq!(pack_len(), len);
+        assert_eq!(stat(&cnt).unwrap().count.packs, 1);
+    }
+
+    #[test]
+    fn io_packs_extract_cached() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+1");
+        let mut config = cnt.config().unwrap();
+        config.object_cache_size = Some(1024);
+        cnt.write_config(&config).unwrap();
+
+        let small = insert(b"small content".to_vec(), &cnt).unwrap().hashkey;
+        let large = insert(vec![b'x'; 1000], &cnt).unwrap().hashkey;
+
+        let read = |hashkey: &str| -> Vec<u8> {
+            extract(hashkey, &cnt).unwrap().unwrap().try_into().unwrap()
+        };
+        assert_eq!(read(&small), b"small content");
+        assert_eq!(read(&small), b"small content");
+        // too large to be cached, always decoded from the pack
+        assert_eq!(read(&large), vec![b'x'; 1000]);
+        assert_eq!(read(&large), vec![b'x'; 1000]);
+
+        let stats = cnt.object_cache().unwrap().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!((stats.objects, stats.bytes), (1, 13));
+    }
+
     #[rstest]
     #[case("none")]
     #[case("zlib+1")]
     #[case("zlib:+9")]
+    #[case("zstd:-1")]
     fn io_packs_extract_many(#[case] algo: &str) {
         let (_tmp_dir, cnt) = new_container(64, algo);
 
@@ -600,7 +2185,7 @@ mod tests {
         for i in 0..100 {
             let content = format!("test {i}").repeat(i);
             let buf = content.clone().into_bytes();
-            let (_, _, hash) = insert(buf, &cnt).unwrap();
+            let hash = insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -617,11 +2202,24 @@ mod tests {
         hashkeys
             .push("7e76df6ac7d08a837f7212e765edd07333c8159ffa0484bc26394e7ffd898817".to_string());
 
-        let objs = extract_many(&hashkeys, &cnt).unwrap();
+        let objs = extract_many(&hashkeys, &cnt).unwrap().map(Result::unwrap);
 
         let mut count = 0;
+        let mut last_position = None;
         for obj in objs {
             count += 1;
+            // in pack order
+            let pack_id: u64 = obj
+                .loc
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(last_position <= Some((pack_id, obj.offset)));
+            last_position = Some((pack_id, obj.offset));
+
             let content = hash_content_map.get(&obj.id).unwrap();
             assert_eq!(
                 String::from_utf8(obj.try_into().unwrap()).unwrap(),
@@ -631,6 +2229,185 @@ mod tests {
         assert_eq!(count + 2, hashkeys.len());
     }
 
+    #[test]
+    fn io_packs_compression_policy() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+1");
+        let src_dir = tempfile::tempdir().unwrap();
+        let mut config = cnt.config().unwrap();
+        config.compression_policy.min_compress_size = 0;
+        config.compression_policy.force_compress_extensions = vec!["bin".to_string()];
+        cnt.write_config(&config).unwrap();
+
+        let small = src_dir.path().join("small.txt");
+        fs::write(&small, "a small text").unwrap();
+        let binary = src_dir.path().join("data.bin");
+        fs::write(&binary, [0u8; 2000]).unwrap();
+        let text = src_dir.path().join("large.txt");
+        fs::write(&text, "large text ".repeat(200)).unwrap();
+
+        let conn = cnt.db().unwrap();
+        let compressed = |hashkey: &str| db::select(&conn, hashkey).unwrap().unwrap().compressed;
+
+        // small text is worth it with no minimum size, binary forced by its extension
+        let inserted = insert_many(vec![small, binary], &cnt).unwrap();
+        assert!(inserted.iter().all(|obj| compressed(&obj.hashkey)));
+
+        // the per-insert override wins over the policy
+        let inserted = insert_many(vec![CompressOverride::never(text.clone())], &cnt).unwrap();
+        assert!(!compressed(&inserted[0].hashkey));
+        let obj = extract(&inserted[0].hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), fs::read(&text).unwrap());
+    }
+
+    #[test]
+    fn io_packs_small_object_tier() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+1");
+        let mut config = cnt.config().unwrap();
+        config.small_object_threshold = Some(100);
+        config.small_pack_size_target = Some(64);
+        cnt.write_config(&config).unwrap();
+
+        // small and large sources interleaved
+        let contents: Vec<_> = (0..20)
+            .map(|i| {
+                let n = if i % 2 == 0 { 1 } else { 100 };
+                format!("object {i:02} ").repeat(n).into_bytes()
+            })
+            .collect();
+        let inserted = insert_many(contents.clone(), &cnt).unwrap();
+
+        let conn = cnt.db().unwrap();
+        for (content, inserted) in contents.iter().zip(&inserted) {
+            let entry = db::select(&conn, &inserted.hashkey).unwrap().unwrap();
+            if content.len() < 100 {
+                assert_eq!(PackTier::of(entry.pack_id), PackTier::Small);
+                assert!(!entry.compressed);
+            } else {
+                assert_eq!(PackTier::of(entry.pack_id), PackTier::Regular);
+                assert!(entry.compressed);
+            }
+            let obj = extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+            assert_eq!(&ByteString::try_from(obj).unwrap(), content);
+        }
+
+        // the 10 small objects of 10 bytes roll over the small packs at their own target
+        let mut pack_ids: Vec<u64> = crate::container::traverse_packs(&cnt)
+            .unwrap()
+            .filter_map(|p| p.file_name()?.to_str()?.parse().ok())
+            .collect();
+        pack_ids.sort_unstable();
+        assert_eq!(pack_ids, [0, SMALL_PACK_ID_BASE, SMALL_PACK_ID_BASE + 1]);
+
+        // loose objects are split the same way when packed
+        let small = crate::io_loose::insert(b"tiny".to_vec(), &cnt).unwrap();
+        crate::maintain::pack_loose(&cnt).unwrap();
+        let entry = db::select(&conn, &small.hashkey).unwrap().unwrap();
+        assert_eq!(PackTier::of(entry.pack_id), PackTier::Small);
+    }
+
+    #[test]
+    fn io_packs_extract_many_preserve_order() {
+        let (_tmp_dir, cnt) = new_container(64, "none");
+        let sources = (0..2000).map(|i| format!("test {i}").into_bytes());
+        let mut hashkeys = insert_many(sources, &cnt)
+            .unwrap()
+            .into_iter()
+            .map(|inserted| inserted.hashkey)
+            .collect::<Vec<_>>();
+        // across the batches of the query, with a missing and a repeated hashkey
+        hashkeys.reverse();
+        hashkeys.insert(1000, "0".repeat(64));
+        hashkeys.push(hashkeys[0].clone());
+
+        let options = ExtractManyOptions {
+            preserve_order: true,
+        };
+        let ids = extract_many_with(&hashkeys, &cnt, options)
+            .unwrap()
+            .map(|obj| obj.unwrap().id)
+            .collect::<Vec<_>>();
+        let expected = hashkeys
+            .iter()
+            .filter(|h| **h != "0".repeat(64))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn io_packs_extract_many_with_readahead() {
+        let (_tmp_dir, cnt) = new_container(64, "zlib:+1");
+        let sources = (0..100).map(|i| format!("test {i}").into_bytes());
+        let hashkeys = insert_many(sources, &cnt)
+            .unwrap()
+            .into_iter()
+            .map(|inserted| inserted.hashkey)
+            .collect::<Vec<_>>();
+
+        let options = ExtractManyOptions {
+            preserve_order: true,
+        };
+        for window in [0, 1, READAHEAD_WINDOW, 1000] {
+            let contents = extract_many_with_readahead(&hashkeys, &cnt, options, window)
+                .unwrap()
+                .map(Result::unwrap)
+                .map(|obj| (obj.id.clone(), Vec::<u8>::try_from(obj).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(contents.len(), 100);
+            for (i, (id, content)) in contents.into_iter().enumerate() {
+                assert_eq!(id, hashkeys[i]);
+                assert_eq!(content, format!("test {i}").into_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn io_packs_extract_many_owned_in_thread() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkeys = (0..10)
+            .map(|i| {
+                insert(format!("test {i}").into_bytes(), &cnt)
+                    .unwrap()
+                    .hashkey
+            })
+            .collect::<Vec<_>>();
+
+        let cnt = Arc::new(cnt);
+        let options = ExtractManyOptions {
+            preserve_order: true,
+        };
+        let objs = extract_many_owned(hashkeys.clone(), cnt.clone(), options).unwrap();
+        let contents = std::thread::spawn(move || {
+            objs.map(|obj| ByteString::try_from(obj.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .join()
+        .unwrap();
+
+        let expected = (0..10)
+            .map(|i| format!("test {i}").into_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn io_packs_extract_many_owned_query_error() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = insert(b"test".to_vec(), &cnt).unwrap().hashkey;
+
+        let cnt = Arc::new(cnt);
+        let mut objs =
+            extract_many_owned(vec![hashkey], cnt.clone(), ExtractManyOptions::default()).unwrap();
+        cnt.db()
+            .unwrap()
+            .execute_batch("DROP TABLE db_object")
+            .unwrap();
+
+        // the failed query of the batch is returned instead of panicking
+        assert!(matches!(objs.next(), Some(Err(Error::SQLiteSelectError { .. }))));
+        assert!(objs.next().is_none());
+    }
+
     #[rstest]
     #[case("none")]
     #[case("zlib+1")]
@@ -644,7 +2421,7 @@ mod tests {
             // create a 800 KiB data
             let content = format!("8bytes{i}").repeat(100 * 1024);
             let buf = content.clone().into_bytes();
-            let (_, _, hash) = insert(buf, &cnt).unwrap();
+            let hash = insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -661,7 +2438,7 @@ mod tests {
         hashkeys
             .push("7e76df6ac7d08a837f7212e765edd07333c8159ffa0484bc26394e7ffd898817".to_string());
 
-        let objs = extract_many(&hashkeys, &cnt).unwrap();
+        let objs = extract_many(&hashkeys, &cnt).unwrap().map(Result::unwrap);
 
         let mut count = 0;
         for obj in objs {
@@ -674,4 +2451,41 @@ mod tests {
         }
         assert_eq!(count + 2, hashkeys.len());
     }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Objects are sized around ``pack_size_target`` so batches often roll over to a new pack
+        /// in the middle or right at the end.
+        #[test]
+        fn io_packs_roundtrip_prop(
+            contents in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..512), 1..24),
+            algo in prop::sample::select(vec!["none", "zlib:+1", "zstd:+1", "zstd:-1"]),
+            pack_size_target in 1u64..1024,
+            batch_size in 1usize..8,
+        ) {
+            let (_tmp_dir, cnt) = new_container(pack_size_target, algo);
+
+            let mut hashkeys = vec![];
+            for batch in contents.chunks(batch_size) {
+                let written = insert_many(batch.to_vec(), &cnt).unwrap();
+                hashkeys.extend(written.into_iter().map(|inserted| inserted.hashkey));
+            }
+
+            for (content, hashkey) in contents.iter().zip(&hashkeys) {
+                let obj = extract(hashkey, &cnt).unwrap().unwrap();
+                prop_assert_eq!(&ByteString::try_from(obj).unwrap(), content);
+            }
+
+            // every pack but the last one is filled up to the target, unless it was left for an
+            // oversized object which then fills the next pack on its own
+            let pack_lens: Vec<_> = (0..)
+                .map_while(|pack_id| fs::metadata(cnt.packs().join(format!("{pack_id}"))).ok())
+                .map(|stat| stat.len())
+                .collect();
+            prop_assert!(pack_lens
+                .windows(2)
+                .all(|lens| lens[0] >= pack_size_target || lens[1] >= pack_size_target));
+        }
+    }
 }
@@ -0,0 +1,248 @@
+use rusqlite::Connection;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::index::ObjectIndex;
+use crate::{db, Container, Error};
+
+/// Write-ahead intent log of the appends to pack files.
+///
+/// Before the first byte of a batch is appended, an intent record ``I <pack_id> <offset>`` is
+/// written and synced, and every entry written is followed by ``W <pack_id> <offset> <length>
+/// <hashkey>`` (length and hashkey are only known once the entry is written). The journal is
+/// emptied once the batch is committed to the index, so a non-empty journal outside of a write
+/// transaction means a writer was interrupted and the pack may end with bytes no index entry
+/// refers to. ``recover`` truncates them.
+///
+/// All records are written while holding the index write lock (``db::begin_write``), so writers of
+/// other processes never interleave in the journal.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+/// A record read back from the journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// Bytes will be appended to ``pack_id`` from ``offset``.
+    Intent { pack_id: u64, offset: u64 },
+    /// An entry was written, ``offset`` is where its payload starts.
+    Written {
+        pack_id: u64,
+        offset: u64,
+        length: u64,
+        hashkey: String,
+    },
+}
+
+impl Record {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next()?;
+        let pack_id = fields.next()?.parse().ok()?;
+        let offset = fields.next()?.parse().ok()?;
+        match kind {
+            "I" => Some(Record::Intent { pack_id, offset }),
+            "W" => Some(Record::Written {
+                pack_id,
+                offset,
+                length: fields.next()?.parse().ok()?,
+                hashkey: fields.next()?.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Journal {
+    pub fn open(cnt: &Container) -> Result<Self, Error> {
+        let path = cnt.journal();
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| Error::IoOpen { source: err, path })?;
+        Ok(Self { file })
+    }
+
+    /// Record that bytes are about to be appended to ``pack_id`` at ``offset``, synced before
+    /// returning so the intent is on disk before the data.
+    pub fn intent(&mut self, pack_id: u64, offset: u64) -> Result<(), Error> {
+        writeln!(self.file, "I {pack_id} {offset}")?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Record an entry written to a pack. Not synced, the intent is what recovery relies on.
+    pub fn written(
+        &mut self,
+        pack_id: u64,
+        offset: u64,
+        length: u64,
+        hashkey: &str,
+    ) -> Result<(), Error> {
+        writeln!(self.file, "W {pack_id} {offset} {length} {hashkey}")?;
+        Ok(())
+    }
+
+    /// Empty the journal once the batch is committed to the index.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Records left in the journal, empty if no write was interrupted (or one is in progress).
+/// Lines cut short by a crash are skipped.
+pub fn records(cnt: &Container) -> Result<Vec<Record>, Error> {
+    let file = match File::open(cnt.journal()) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut records = vec![];
+    for line in BufReader::new(file).lines() {
+        records.extend(Record::parse(&line?));
+    }
+    Ok(records)
+}
+
+/// Truncate the bytes appended by interrupted writes and empty the journal, returns the number
+/// of bytes dropped.
+///
+/// A pack of an intent record is cut back to the intent offset, or to the end of its last
+/// indexed entry if the batch was committed before the journal could be emptied. The index
+/// write lock is taken first, so a batch still being written by another writer is waited for
+/// instead of being cut.
+pub fn recover(cnt: &Container, conn: &Connection) -> Result<u64, Error> {
+    if fs::metadata(cnt.journal()).map_or(true, |meta| meta.len() == 0) {
+        return Ok(0);
+    }
+
+    let tx = db::begin_write(conn)?;
+    let mut dropped = 0;
+    for record in records(cnt)? {
+        let Record::Intent { pack_id, offset } = record else {
+            continue;
+        };
+        let end = offset.max(tx.pack_end(pack_id)?);
+        dropped += truncate_pack(&cnt.pack_path(pack_id)?, end)?;
+    }
+    Journal::open(cnt)?.commit()?;
+    tx.commit()?;
+
+    Ok(dropped)
+}
+
+/// Cut the pack at ``path`` to ``len`` bytes, returns the number of bytes dropped.
+fn truncate_pack(path: &Path, len: u64) -> Result<u64, Error> {
+    let pack = match fs::OpenOptions::new().write(true).open(path) {
+        Ok(pack) => pack,
+        // offloaded or never created
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(Error::IoOpen {
+                source: err,
+                path: path.to_path_buf(),
+            })
+        }
+    };
+    let pack_len = pack.metadata()?.len();
+    if pack_len <= len {
+        return Ok(0);
+    }
+    pack.set_len(len)?;
+    pack.sync_data()?;
+    Ok(pack_len - len)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::ByteString;
+    use crate::io_packs;
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn journal_emptied_after_commit() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        io_packs::insert(b"test 0".to_vec(), &cnt).unwrap();
+
+        assert!(records(&cnt).unwrap().is_empty());
+        assert_eq!(recover(&cnt, &cnt.db().unwrap()).unwrap(), 0);
+    }
+
+    #[test]
+    fn journal_recover_interrupted_write() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let hashkey = io_packs::insert(b"test 0".to_vec(), &cnt).unwrap().hashkey;
+        let pack = cnt.packs().join("0");
+        let pack_len = fs::metadata(&pack).unwrap().len();
+
+        // a writer appended bytes and died before committing them
+        let mut journal = Journal::open(&cnt).unwrap();
+        journal.intent(0, pack_len).unwrap();
+        journal.written(0, pack_len, 6, &"0".repeat(64)).unwrap();
+        let mut f = fs::OpenOptions::new().append(true).open(&pack).unwrap();
+        f.write_all(b"test 1").unwrap();
+        // and the last record is cut short
+        write!(journal.file, "W 0 {pack_len}").unwrap();
+
+        assert_eq!(
+            records(&cnt).unwrap(),
+            vec![
+                Record::Intent {
+                    pack_id: 0,
+                    offset: pack_len
+                },
+                Record::Written {
+                    pack_id: 0,
+                    offset: pack_len,
+                    length: 6,
+                    hashkey: "0".repeat(64)
+                },
+            ]
+        );
+        assert_eq!(recover(&cnt, &cnt.db().unwrap()).unwrap(), 6);
+        assert_eq!(fs::metadata(&pack).unwrap().len(), pack_len);
+        assert!(records(&cnt).unwrap().is_empty());
+
+        let obj = io_packs::extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), b"test 0");
+    }
+
+    #[test]
+    fn journal_open_does_not_wait_for_writers() {
+        let (tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        io_packs::insert(b"test 0".to_vec(), &cnt).unwrap();
+        let pack_len = fs::metadata(cnt.packs().join("0")).unwrap().len();
+        Journal::open(&cnt).unwrap().intent(0, pack_len).unwrap();
+
+        // another writer holds the write lock for its whole batch
+        let conn = cnt.db().unwrap();
+        let tx = db::begin_write(&conn).unwrap();
+        let opened = Container::open(tmp_dir.path()).unwrap();
+        assert_eq!(records(&opened).unwrap().len(), 1);
+        drop(tx);
+
+        // the next writer recovers
+        io_packs::insert(b"test 1".to_vec(), &opened).unwrap();
+        assert!(records(&opened).unwrap().is_empty());
+    }
+
+    #[test]
+    fn journal_recover_keeps_committed_entries() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+
+        // the batch was committed to the index but the journal was not emptied
+        let hashkey = io_packs::insert(b"test 0".to_vec(), &cnt).unwrap().hashkey;
+        Journal::open(&cnt).unwrap().intent(0, 0).unwrap();
+
+        assert_eq!(recover(&cnt, &cnt.db().unwrap()).unwrap(), 0);
+        let obj = io_packs::extract(&hashkey, &cnt).unwrap().unwrap();
+        assert_eq!(ByteString::try_from(obj).unwrap(), b"test 0");
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Container, Error};
+
+/// A maintenance lock older than this is stale whatever its process, whose PID may have been
+/// reused since.
+pub const MAINTENANCE_LOCK_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Exclusive right to run a maintenance operation that rewrites or deletes stored objects
+/// (``repack_with``, ``prune_packs``, ``clean_loose``...) on a container, so two of them never run
+/// at the same time. Released when dropped.
+///
+/// The lock is the file ``Container::maintenance_lock`` holding ``<pid> <since> <operation>``, the
+/// process that took it, when (seconds since the epoch) and for what. A lock whose process is not
+/// running anymore, or older than ``MAINTENANCE_LOCK_TTL``, was left by a crashed process and is
+/// taken over.
+///
+/// NOTE: the process of a lock can only be checked on unix and on the same host, elsewhere a lock
+/// left by a crash is only taken over once it is older than ``MAINTENANCE_LOCK_TTL``.
+#[derive(Debug)]
+pub struct MaintenanceLock {
+    path: PathBuf,
+}
+
+/// Who holds a maintenance lock, as written in the lock file.
+#[derive(Debug, PartialEq, Eq)]
+struct Holder {
+    pid: u32,
+    since: u64,
+    operation: String,
+}
+
+impl Holder {
+    fn parse(content: &str) -> Option<Self> {
+        let mut fields = content.trim().splitn(3, ' ');
+        Some(Holder {
+            pid: fields.next()?.parse().ok()?,
+            since: fields.next()?.parse().ok()?,
+            operation: fields.next().unwrap_or_default().to_string(),
+        })
+    }
+
+    fn is_stale(&self, now: u64) -> bool {
+        now.saturating_sub(self.since) > MAINTENANCE_LOCK_TTL.as_secs() || !process_alive(self.pid)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Whether process ``pid`` is running.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 is not sent, it only checks the process exists
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // it exists but belongs to another user
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether process ``pid`` is running, always assumed where it can not be checked.
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+impl MaintenanceLock {
+    /// Take the maintenance lock of ``cnt`` for ``operation``, ``Error::MaintenanceInProgress``
+    /// if another operation holds it. A stale lock is taken over.
+    pub fn acquire(cnt: &Container, operation: &str) -> Result<Self, Error> {
+        let path = cnt.maintenance_lock();
+        // the lock file appears with its content or not at all, so a lock being taken is never
+        // read half written and taken as stale
+        let tmp = cnt.sandbox().join(format!("{}.tmp", uuid::Uuid::new_v4()));
+        fs::write(
+            &tmp,
+            format!("{} {} {operation}", std::process::id(), now()),
+        )?;
+        let taken = Self::link(&tmp, &path);
+        let _ = fs::remove_file(&tmp);
+        taken?;
+        Ok(Self { path })
+    }
+
+    fn link(tmp: &Path, path: &Path) -> Result<(), Error> {
+        // NOTE: a stale lock is taken over once, a process that takes it over first wins
+        for _ in 0..2 {
+            match fs::hard_link(tmp, path) {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    return Err(Error::IoWrite {
+                        source: err,
+                        path: path.to_path_buf(),
+                    })
+                }
+            }
+            let holder = match fs::read_to_string(path) {
+                // released meanwhile
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                content => content.ok().as_deref().and_then(Holder::parse),
+            };
+            match holder {
+                Some(holder) if !holder.is_stale(now()) => {
+                    return Err(Error::MaintenanceInProgress {
+                        path: path.to_path_buf(),
+                        pid: holder.pid,
+                        operation: holder.operation,
+                    });
+                }
+                _ => {
+                    if let Err(err) = fs::remove_file(path) {
+                        if err.kind() != io::ErrorKind::NotFound {
+                            return Err(err.into());
+                        }
+                    }
+                }
+            }
+        }
+        Err(Error::MaintenanceInProgress {
+            path: path.to_path_buf(),
+            pid: 0,
+            operation: "unknown".to_string(),
+        })
+    }
+}
+
+impl Drop for MaintenanceLock {
+    fn drop(&mut self) {
+        // a lock that can not be removed is taken over once this process is gone
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn maintenance_lock_exclusive() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let lock = MaintenanceLock::acquire(&cnt, "repack").unwrap();
+        assert!(cnt.valid().is_ok());
+        assert!(matches!(
+            MaintenanceLock::acquire(&cnt, "clean"),
+            Err(Error::MaintenanceInProgress { pid, operation, .. })
+                if pid == std::process::id() && operation == "repack"
+        ));
+        drop(lock);
+        assert!(!cnt.maintenance_lock().exists());
+        MaintenanceLock::acquire(&cnt, "clean").unwrap();
+    }
+
+    #[test]
+    fn maintenance_lock_take_over_stale() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let old = now() - MAINTENANCE_LOCK_TTL.as_secs() - 1;
+        for stale in [
+            format!("{} {old} repack", std::process::id()),
+            // not a running process
+            format!("{} {} repack", u32::MAX, now()),
+            "garbage".to_string(),
+        ] {
+            fs::write(cnt.maintenance_lock(), stale).unwrap();
+            let lock = MaintenanceLock::acquire(&cnt, "clean").unwrap();
+            let content = fs::read_to_string(cnt.maintenance_lock()).unwrap();
+            assert_eq!(
+                Holder::parse(&content).unwrap().pid,
+                std::process::id(),
+                "{content}"
+            );
+            drop(lock);
+        }
+    }
+}
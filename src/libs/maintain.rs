@@ -1,42 +1,210 @@
-use rusqlite::Connection;
+use flate2::{Crc, CrcReader};
+use ring::digest;
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-use crate::container::{traverse_loose, Compression, Container};
-use crate::{io_packs, Error};
+use crate::cancel::{self, CancellationToken};
+use crate::config::{Config, CONTAINER_VERSION};
+use crate::container::{
+    loose_hashkey, remove_stale_tmp, traverse_loose, traverse_packs, Compression, Container,
+    PackLayout, DICTIONARIES,
+};
+use crate::db::PackEntry;
+use crate::index::{IndexBackend, ObjectIndex};
+use crate::io::{is_hashkey, ByteString, HashWriter, MaybeContentFormat, ReaderMaker, Sniffed};
+use crate::io_loose::LObject;
+use crate::io_packs::{EntryHeader, PObject, PackTier, EXTRACT_BATCH_SIZE};
+use crate::journal::{self, Journal};
+use crate::lock::MaintenanceLock;
+use crate::namespace::Scope;
+use crate::throttle::IoThrottle;
+use crate::utils::{open_shared, rename_replace};
+use crate::{chunked, db, io_loose, io_packs, namespace, Error};
 
-pub fn pack_loose(cnt: &Container) -> Result<(), Error> {
+/// What a maintenance operation changed, or would change when run with ``dry_run``.
+#[derive(Debug, Default, Serialize)]
+pub struct MaintainReport {
+    /// Number of objects packed, deleted or rewritten.
+    pub objects: u64,
+    /// Bytes packed (raw size), deleted from loose or freed from packs.
+    pub bytes: u64,
+    /// The operation was cancelled before it was over, the report tells what was done up to then.
+    pub interrupted: bool,
+}
+
+/// What ``pack_loose`` packed, or would pack when run with ``dry_run``.
+#[derive(Debug, Default, Serialize)]
+pub struct PackReport {
+    /// Loose objects written to packs.
+    pub objects_packed: u64,
+    /// Raw bytes of the objects packed.
+    pub bytes_read: u64,
+    /// Bytes the packed objects take in packs once compressed, 0 in a dry run.
+    pub bytes_written: u64,
+    /// Pack files started while packing, 0 in a dry run.
+    pub packs_created: u64,
+    /// Loose objects left alone since they are already in packs.
+    pub skipped_already_packed: u64,
+    /// Objects already compressed, by the algorithm they are compressed with (see
+    /// ``MaybeContentFormat::precompression``), copied to packs as they are instead of compressed
+    /// again.
+    pub precompressed: BTreeMap<String, u64>,
+    /// Packing was cancelled before every object was packed, the objects packed so far are.
+    pub interrupted: bool,
+}
+
+impl PackReport {
+    fn count_precompressed(&mut self, format: &MaybeContentFormat) {
+        if let Some(algorithm) = format.precompression() {
+            *self.precompressed.entry(algorithm).or_default() += 1;
+        }
+    }
+}
+
+/// Objects processed between two reports to a ``ProgressObserver``.
+const PROGRESS_STEP: usize = 1000;
+
+/// Told how far a long operation (packing, validation) went, e.g. to draw a progress bar.
+pub trait ProgressObserver: Sync {
+    /// ``done`` out of ``total`` objects processed so far. Called with ``done == 0`` when the
+    /// operation starts and ``done == total`` when it is over.
+    fn progress(&self, done: u64, total: u64);
+}
+
+/// No progress reported.
+impl ProgressObserver for () {
+    fn progress(&self, _done: u64, _total: u64) {}
+}
+
+pub fn pack_loose(cnt: &Container) -> Result<PackReport, Error> {
     let compression = cnt.compression()?;
-    _pack_loose_internal(cnt, &compression)
+    _pack_loose_internal(cnt, &compression, None, false, &(), None, None)
 }
 
 // XXX: flag to set if do the validate, if no, use reguler writer not hash writer.
-pub fn _pack_loose_internal(cnt: &Container, compression: &Compression) -> Result<(), Error> {
+/// Pack loose objects that are not yet in packs. With ``older_than`` only the loose objects
+/// written at least that long ago (by mtime) are packed, the recently written ones are the most
+/// read and stay loose. With ``dry_run`` only report what would be packed. ``progress`` is told
+/// the number of objects packed every ``PROGRESS_STEP`` objects, ``throttle`` caps the rate the
+/// loose objects are read at.
+///
+/// ``cancel`` is checked between objects, once cancelled the objects written so far are committed
+/// and the report tells it was ``interrupted``.
+///
+/// NOTE: with ``Config::small_object_threshold`` the objects are sorted by size before any is
+/// written, then a cancel is only noticed once the run of objects being packed is done.
+pub fn _pack_loose_internal(
+    cnt: &Container,
+    compression: &Compression,
+    older_than: Option<Duration>,
+    dry_run: bool,
+    progress: &dyn ProgressObserver,
+    throttle: Option<&IoThrottle>,
+    cancel: Option<&CancellationToken>,
+) -> Result<PackReport, Error> {
     cnt.valid()?;
 
-    let loose_objs = traverse_loose(cnt)?;
-
-    // if objs in packs, remove it from Vec
     // Only objects that not yet pack will be packed.
-    // NOTE: for large packed DB this operation can be performance bottleneck
-    let conn = Connection::open(cnt.packs_db())?;
-    let mut stmt = conn.prepare("SELECT hashkey FROM db_object")?;
-    let rows: Vec<_> = stmt
-        .query([])?
-        .mapped(|row| row.get::<_, String>(0))
-        .filter_map(std::result::Result::ok)
-        .collect();
+    // NOTE: the index is queried for a batch of loose hashkeys at a time, so the check does not
+    // load the whole index, see the ``pack_loose_skip_packed`` bench.
+    let index = cnt.index()?;
 
-    let sources = loose_objs.filter(|obj| {
-        let hash = obj.parent().and_then(|p| p.file_name()).and_then(|parent| {
-            obj.file_name().map(|filename| {
-                format!("{}{}", parent.to_str().unwrap(), filename.to_str().unwrap())
-            })
+    let mut report = PackReport::default();
+    let mut sources = Vec::new();
+    for_each_loose_packed(cnt, &*index, |obj, packed| {
+        if packed {
+            report.skipped_already_packed += 1;
+        } else {
+            sources.push(obj);
+        }
+        Ok(())
+    })?;
+    if let Some(age) = older_than {
+        let now = SystemTime::now();
+        sources.retain(|obj| {
+            // an object removed meanwhile or written in the future (clock skew) is not packed
+            fs::metadata(obj)
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|d| d >= age))
         });
-        hash.map_or(false, |h| !rows.contains(&h))
-    });
+    }
+
+    let policy = cnt.config()?.compression_policy;
+    if dry_run {
+        report.objects_packed = sources.len() as u64;
+        report.bytes_read = sources
+            .iter()
+            .filter_map(|p| fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum();
+        for obj in &sources {
+            // an object removed meanwhile would not be packed either
+            if let Ok(sniffed) = Sniffed::new(obj.clone(), &policy) {
+                report.count_precompressed(sniffed.format());
+            }
+        }
+        return Ok(report);
+    }
+
+    let count_packs = || -> Result<u64, Error> { Ok(traverse_packs(cnt)?.count() as u64) };
+    let packs_before = count_packs()?;
 
     // race may happened during packing, I pass path as iterator which can be modified or doesn't
     // catch newly added objects to loose folder.
-    io_packs::_insert_many_internal(sources, cnt, compression)?;
+    let total = sources.len() as u64;
+    let mut written = Vec::with_capacity(sources.len());
+    let mut gone = 0;
+    progress.progress(0, total);
+    'batches: for batch in sources.chunks(PROGRESS_STEP) {
+        for (run, bytes) in throttled_runs(batch, throttle) {
+            if let Some(throttle) = throttle {
+                throttle.consume(bytes);
+            }
+            if cancel::is_cancelled(cancel) {
+                break 'batches;
+            }
+            // the format of every source is guessed once, to store the already compressed ones as
+            // they are and report them
+            let mut sniffed = Vec::with_capacity(run.len());
+            for obj in run {
+                match Sniffed::new(obj.clone(), &policy) {
+                    Err(err) if vanished(&err) => gone += 1,
+                    res => sniffed.push(res?),
+                }
+            }
+            // the writer stops pulling sources once cancelled and commits what it wrote, the
+            // sources it already read ahead included
+            let outcomes = io_packs::_insert_many_outcomes(
+                sniffed
+                    .iter()
+                    .take_while(|_| !cancel::is_cancelled(cancel))
+                    .cloned(),
+                cnt,
+                compression,
+                true,
+                Scope::Internal,
+            )?;
+            for (sniffed, outcome) in sniffed.iter().zip(outcomes) {
+                match outcome {
+                    Ok(inserted) => {
+                        report.count_precompressed(sniffed.format());
+                        written.push(inserted);
+                    }
+                    Err(err) if vanished(&err) => gone += 1,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        progress.progress(written.len() as u64, total);
+    }
 
     // XXX: the goal is unclear in legacy dos, there are following reasons that can cause the hash
     // mismatched:
@@ -55,46 +223,3143 @@ pub fn _pack_loose_internal(cnt: &Container, compression: &Compression) -> Resul
     //     }
     // }
 
+    // packed and cleaned by another process meanwhile
+    report.skipped_already_packed += gone;
+    report.interrupted = written.len() as u64 + gone < total;
+    report.objects_packed = written.len() as u64;
+    report.bytes_read = written.iter().map(|inserted| inserted.raw_size).sum();
+    report.bytes_written = written.iter().map(|inserted| inserted.stored_size).sum();
+    report.packs_created = count_packs()?.saturating_sub(packs_before);
+    Ok(report)
+}
+
+/// Whether ``err`` is a loose object removed while being packed, by a concurrent ``clean_loose``
+/// once another process packed it.
+fn vanished(err: &Error) -> bool {
+    matches!(err, Error::StdIO(err) | Error::IoOpen { source: err, .. } if err.kind() == io::ErrorKind::NotFound)
+}
+
+/// Split ``sources`` in runs of about a second of reads at the rate of ``throttle``, with the
+/// bytes of each run, so a throttled ``pack_loose`` waits between runs instead of reading a whole
+/// batch in one burst.
+fn throttled_runs<'a>(
+    sources: &'a [PathBuf],
+    throttle: Option<&IoThrottle>,
+) -> Vec<(&'a [PathBuf], u64)> {
+    let limit = match throttle.map(IoThrottle::rate) {
+        None => return vec![(sources, 0)],
+        Some(0) => u64::MAX,
+        Some(rate) => rate,
+    };
+    let mut runs = vec![];
+    let (mut start, mut bytes) = (0, 0);
+    for (i, path) in sources.iter().enumerate() {
+        let size = fs::metadata(path).map_or(0, |meta| meta.len());
+        if i > start && bytes + size > limit {
+            runs.push((&sources[start..i], bytes));
+            (start, bytes) = (i, 0);
+        }
+        bytes += size;
+    }
+    if start < sources.len() {
+        runs.push((&sources[start..], bytes));
+    }
+    runs
+}
+
+/// The ``MaintenanceLock`` of ``cnt`` for ``operation``, a dry run changes nothing and runs
+/// without it.
+fn lock_unless_dry_run(
+    cnt: &Container,
+    operation: &str,
+    dry_run: bool,
+) -> Result<Option<MaintenanceLock>, Error> {
+    (!dry_run)
+        .then(|| MaintenanceLock::acquire(cnt, operation))
+        .transpose()
+}
+
+/// Delete loose objects that are already packed and vacuum the index. With ``dry_run`` only
+/// report what would be deleted.
+pub fn clean_loose(cnt: &Container, dry_run: bool) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    let _lock = lock_unless_dry_run(cnt, "clean", dry_run)?;
+
+    let conn = cnt.db()?;
+
+    let mut report = MaintainReport::default();
+    for_each_loose_packed(cnt, &conn, |path, packed| {
+        if !packed {
+            return Ok(());
+        }
+        report.objects += 1;
+        report.bytes += fs::metadata(&path)?.len();
+        if !dry_run {
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    })?;
+
+    if !dry_run {
+        conn.add_loose(
+            -i64::try_from(report.objects).unwrap_or(i64::MAX),
+            -i64::try_from(report.bytes).unwrap_or(i64::MAX),
+        )?;
+        conn.execute_batch("VACUUM")?;
+    }
+
+    Ok(report)
+}
+
+/// Delete ``namespace`` and the objects that are in no other namespace, e.g. once the dataset it
+/// groups is not needed anymore. Loose objects are deleted, packed objects are dropped from the
+/// index and the bytes they take in packs are freed by the next ``prune_packs``. Objects never put
+/// in a namespace, also inserted outside of any namespace (see ``Container::insert_to_namespace``)
+/// or still needed to read a chunked object are not touched. With ``dry_run`` only report what
+/// would be deleted.
+///
+/// NOTE: a chunked object is dropped from the index but its chunks are loose objects of their
+/// own, possibly shared with other objects, they are left in place.
+///
+/// NOTE: the index is updated first, a crash before the loose objects are deleted leaves loose
+/// objects in no namespace, which are still readable.
+pub fn gc_namespace(
+    cnt: &Container,
+    namespace: &str,
+    dry_run: bool,
+) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    namespace::check_namespace(namespace)?;
+    let _lock = lock_unless_dry_run(cnt, "gc-namespace", dry_run)?;
+
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    let hashkeys = unreferenced(cnt, &*tx, tx.namespace_only(namespace)?)?;
+
+    let (report, loose) = drop_objects(cnt, &*tx, &hashkeys, dry_run)?;
+    if dry_run {
+        return Ok(report);
+    }
+    tx.delete_namespace(namespace)?;
+    tx.commit()?;
+    remove_loose(loose)?;
+
+    Ok(report)
+}
+
+/// Delete the objects whose expiry (see ``expiry::set_expiry``) is at or before ``now``: expired
+/// loose objects are deleted, expired packed objects are dropped from the index, a tombstone the
+/// next ``prune_packs`` frees the bytes of. Objects without an expiry are kept forever, so are
+/// the manifests and chunks still needed to read a chunked object.
+///
+/// NOTE: the index is updated first, a crash before the loose objects are deleted leaves them
+/// without an expiry, they are then kept.
+pub fn expire(cnt: &Container, now: SystemTime) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    let _lock = MaintenanceLock::acquire(cnt, "expire")?;
+
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    let hashkeys = unreferenced(cnt, &*tx, tx.expired(now)?)?;
+    let (report, loose) = drop_objects(cnt, &*tx, &hashkeys, false)?;
+    tx.commit()?;
+    remove_loose(loose)?;
+
+    Ok(report)
+}
+
+/// ``hashkeys`` but the manifests and chunks of the chunked objects not in ``hashkeys``, which
+/// are still needed to read those objects.
+fn unreferenced(
+    cnt: &Container,
+    index: &dyn ObjectIndex,
+    mut hashkeys: Vec<String>,
+) -> Result<Vec<String>, Error> {
+    if hashkeys.is_empty() {
+        return Ok(hashkeys);
+    }
+    let dropped: HashSet<&String> = hashkeys.iter().collect();
+    let mut referenced = HashSet::new();
+    for hashkey in index.list_chunked()? {
+        if dropped.contains(&hashkey) {
+            continue;
+        }
+        if let Some(obj) = chunked::extract(&hashkey, cnt)? {
+            referenced.insert(obj.manifest);
+            referenced.extend(obj.chunks);
+        }
+    }
+    hashkeys.retain(|hashkey| !referenced.contains(hashkey));
+    Ok(hashkeys)
+}
+
+/// Drop ``hashkeys`` from the index in ``tx``, their pack entries, chunked records, namespaces
+/// and expiry, and return what is deleted with the loose objects to delete once ``tx`` is
+/// committed. With ``dry_run`` ``tx`` is not written to.
+fn drop_objects(
+    cnt: &Container,
+    tx: &dyn ObjectIndex,
+    hashkeys: &[String],
+    dry_run: bool,
+) -> Result<(MaintainReport, Vec<LObject>), Error> {
+    let mut report = MaintainReport::default();
+    let mut loose = vec![];
+    for hashkey in hashkeys {
+        let mut deleted = false;
+        if let Some(obj) = io_loose::extract(hashkey, cnt)? {
+            report.bytes += obj.expected_size;
+            loose.push(obj);
+            deleted = true;
+        }
+        if let Some(entry) = tx.select(hashkey)? {
+            report.bytes += entry.size;
+            deleted = true;
+        }
+        if tx.select_chunked(hashkey)?.is_some() {
+            deleted = true;
+        }
+        report.objects += u64::from(deleted);
+    }
+    if dry_run {
+        return Ok((report, loose));
+    }
+
+    tx.remove(hashkeys)?;
+    let loose_bytes: u64 = loose.iter().map(|obj| obj.expected_size).sum();
+    tx.add_loose(
+        -i64::try_from(loose.len()).unwrap_or(i64::MAX),
+        -i64::try_from(loose_bytes).unwrap_or(i64::MAX),
+    )?;
+    Ok((report, loose))
+}
+
+/// Delete the files of ``loose`` objects, the ones already gone are skipped.
+fn remove_loose(loose: Vec<LObject>) -> Result<(), Error> {
+    for obj in loose {
+        match fs::remove_file(&obj.loc) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Walk the loose store and reset the loose counters kept in the index to what it holds, for
+/// when they drifted (loose objects added or removed by hand, or a best-effort update that
+/// failed). Reports the loose objects and their bytes.
+pub fn recount(cnt: &Container) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
 
-    use crate::io_loose::insert as loose_insert;
-    use crate::io_packs::extract as packs_extract;
-    use crate::stat;
-    use crate::test_utils::new_container;
-    use std::collections::HashMap;
+    let (objects, bytes) = cnt.scan_loose()?;
+    cnt.index()?.set_loose_counters(objects, bytes)?;
 
-    #[test]
-    fn pack_loose_default() {
-        let (_tmp_dir, cnt) = new_container(1024, "none");
-        let n = 200;
+    Ok(MaintainReport {
+        objects,
+        bytes,
+        ..MaintainReport::default()
+    })
+}
 
-        // add 10 obj to loose
-        let mut hash_content_map: HashMap<String, String> = HashMap::new();
-        for i in 0..n {
-            let content = format!("test {i:03}"); // 8 bytes each
-            let buf = content.clone().into_bytes();
-            let (_, hash) = loose_insert(buf, &cnt).unwrap();
-            hash_content_map.insert(hash, content);
+/// Remove the temporary files left in the sandboxes (``sandbox`` and ``Config::sandbox_dir``) by
+/// crashed inserts, those not modified for ``older_than``. Reports the files and bytes removed.
+///
+/// NOTE: a temporary file younger than ``older_than`` may belong to an insert still running, so
+/// do not pass a duration shorter than the longest insert.
+pub fn clean_sandbox(cnt: &Container, older_than: Duration) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+
+    let mut dirs = vec![cnt.sandbox()];
+    if cnt.config()?.sandbox_dir.is_some() {
+        dirs.push(cnt.insert_sandbox()?);
+    }
+    dirs.dedup();
+
+    let mut report = MaintainReport::default();
+    for dir in dirs {
+        let (objects, bytes) = remove_stale_tmp(&dir, older_than)?;
+        report.objects += objects;
+        report.bytes += bytes;
+    }
+    Ok(report)
+}
+
+/// Largest objects sampled by ``train_dictionary``, a dictionary only pays off for small ones.
+const DICTIONARY_SAMPLE_MAX_SIZE: u64 = 128 * 1024;
+
+/// Outcome of ``train_dictionary``.
+#[derive(Debug, Serialize)]
+pub struct TrainedDictionary {
+    /// Id the dictionary is stored with and recorded in the frames compressed with it.
+    pub id: u32,
+    /// Number of objects the dictionary was trained on.
+    pub samples: u64,
+    /// Size of the dictionary in bytes.
+    pub size: u64,
+}
+
+/// Train a zstd dictionary on up to ``max_samples`` small objects of the container (spread over
+/// all of them), store it and make it ``Config::zstd_dictionary``, so pack entries written from
+/// now on with zstd are compressed with it. Containers of many similar small objects (e.g. JSON
+/// documents) compress several times better this way.
+///
+/// The entries already written are left as they are, every zstd frame records the dictionary it
+/// was compressed with so older dictionaries stay readable.
+pub fn train_dictionary(
+    cnt: &Container,
+    max_samples: usize,
+    dict_size: usize,
+) -> Result<TrainedDictionary, Error> {
+    cnt.valid()?;
+
+    let mut candidates: Vec<String> = read_index_entries(cnt)?
+        .into_iter()
+        .filter(|entry| entry.raw_size <= DICTIONARY_SAMPLE_MAX_SIZE)
+        .map(|entry| entry.hashkey)
+        .collect();
+    for path in traverse_loose(cnt)? {
+        let path = path?;
+        if fs::metadata(&path)?.len() <= DICTIONARY_SAMPLE_MAX_SIZE {
+            candidates.extend(loose_hashkey(&path));
         }
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
 
-        let info = stat(&cnt).unwrap();
-        assert_eq!(info.count.loose, n);
+    let step = (candidates.len() / max_samples.max(1)).max(1);
+    let mut samples = Vec::new();
+    for hashkey in candidates.iter().step_by(step).take(max_samples) {
+        if let Some(obj) = cnt.get(hashkey)? {
+            samples.push(ByteString::try_from(obj)?);
+        }
+    }
 
-        pack_loose(&cnt).unwrap();
+    let dict =
+        zstd::dict::from_samples(&samples, dict_size).map_err(|err| Error::InvalidDictionary {
+            cause: format!("training on {} objects failed: {err}", samples.len()),
+        })?;
+    let id = cnt.dictionaries().add(&dict)?;
+    cnt.update_config(|config| {
+        config.zstd_dictionary = Some(id);
+        Ok(())
+    })?;
 
-        let info = stat(&cnt).unwrap();
-        assert_eq!(info.count.packs, n);
-        assert_eq!(info.count.packs_file, n * 8 / 1024 + 1);
+    Ok(TrainedDictionary {
+        id,
+        samples: samples.len() as u64,
+        size: dict.len() as u64,
+    })
+}
 
-        // read from packs
-        for (hash, content) in hash_content_map {
-            let obj = packs_extract(&hash, &cnt).unwrap().unwrap();
-            assert_eq!(String::from_utf8(obj.try_into().unwrap()).unwrap(), content);
+/// Order the live entries of a pack are written in by ``repack_with``.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RepackStrategy {
+    /// Keep the order the objects were written in.
+    #[default]
+    Preserve,
+    /// Sort by hashkey, objects are then found in the pack in the order they are listed.
+    SortByHash,
+    /// Sort by raw size, small objects end up next to each other at the start of the pack.
+    SortBySize,
+}
+
+impl RepackStrategy {
+    fn sort(self, live: &mut [(PackEntry, u64)]) {
+        match self {
+            RepackStrategy::Preserve => {}
+            RepackStrategy::SortByHash => live.sort_by(|(a, _), (b, _)| a.hashkey.cmp(&b.hashkey)),
+            RepackStrategy::SortBySize => live.sort_by_key(|(e, _)| (e.raw_size, e.offset)),
+        }
+    }
+}
+
+impl FromStr for RepackStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "preserve" => Ok(RepackStrategy::Preserve),
+            "sort-by-hash" => Ok(RepackStrategy::SortByHash),
+            "sort-by-size" => Ok(RepackStrategy::SortBySize),
+            _ => Err(Error::ParseRepackStrategyError { s: s.to_string() }),
+        }
+    }
+}
+
+/// Dead space of a local pack file, the bytes no index entry covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackFragmentation {
+    pub pack_id: u64,
+    pub file_size: u64,
+    /// Bytes of the indexed entries of the pack together with their entry headers.
+    pub live_bytes: u64,
+    /// Bytes left by deleted objects, interrupted writes or objects written twice, what
+    /// ``repack`` reclaims.
+    pub dead_bytes: u64,
+}
+
+/// Dead space of every pack file in the local ``packs`` folder, ordered by ``pack_id``. A pack
+/// without any indexed entry is all dead space. Offloaded packs are not listed.
+pub fn fragmentation(cnt: &Container) -> Result<Vec<PackFragmentation>, Error> {
+    cnt.valid()?;
+
+    let mut entries_by_pack: BTreeMap<u64, Vec<PackEntry>> = BTreeMap::new();
+    for entry in read_index_entries(cnt)? {
+        entries_by_pack
+            .entry(entry.pack_id)
+            .or_default()
+            .push(entry);
+    }
+
+    let mut pack_ids: Vec<u64> = traverse_packs(cnt)?
+        .filter_map(|p| p.file_name()?.to_str()?.parse::<u64>().ok())
+        .collect();
+    pack_ids.sort_unstable();
+
+    let mut packs = Vec::with_capacity(pack_ids.len());
+    for pack_id in pack_ids {
+        let mut src = open_shared(&cnt.pack_path(pack_id)?)?;
+        let file_size = src.metadata()?.len();
+        let mut live_bytes = 0;
+        for entry in entries_by_pack.remove(&pack_id).unwrap_or_default() {
+            live_bytes += entry.size + stored_header_len(&mut src, &entry)?;
+        }
+        packs.push(PackFragmentation {
+            pack_id,
+            file_size,
+            live_bytes,
+            dead_bytes: file_size.saturating_sub(live_bytes),
+        });
+    }
+    Ok(packs)
+}
+
+/// Rewrite packs to drop the bytes no index entry refers to (left by duplicated or interrupted
+/// writes). Live entries are copied as they are stored, together with their entry header if
+/// they have one. With ``dry_run`` only report the objects that would be moved and the bytes
+/// that would be freed.
+///
+/// The current pack is skipped since it is still appended to. The packs with the most dead bytes
+/// (see ``fragmentation``) are rewritten first.
+///
+/// NOTE: the live entries are written to a new pack and the old pack is deleted only after the
+/// index update is committed, a crash in between leaves a pack nothing refers to, which the next
+/// ``prune_packs`` deletes. A crash before the commit leaves the new pack to ``journal::recover``.
+pub fn repack(cnt: &Container, dry_run: bool) -> Result<MaintainReport, Error> {
+    repack_with(cnt, RepackStrategy::Preserve, None, dry_run, None, None)
+}
+
+/// ``repack`` writing the live entries of every pack in the order of ``strategy``. A pack whose
+/// entries are out of that order is rewritten even if it has no dead bytes. The objects of a pack
+/// are kept together, they move to a new pack of the same tier that replaces it.
+///
+/// With ``recompress`` every entry is re-evaluated against the compression policy of the
+/// container, and the entries not stored with the algorithm that gives (e.g. ``none`` or ``zlib``
+/// after switching to ``zstd``) are decoded and written again with ``recompress``, checked against
+/// their hashkey. The bytes reported are then the bytes saved, a dry run only knows the dead
+/// bytes.
+///
+/// ``throttle`` caps the rate the live entries are read at while rewritten. ``cancel`` is checked
+/// between entries, the pack being rewritten when cancelled is left as it was.
+pub fn repack_with(
+    cnt: &Container,
+    strategy: RepackStrategy,
+    recompress: Option<&Compression>,
+    dry_run: bool,
+    throttle: Option<&IoThrottle>,
+    cancel: Option<&CancellationToken>,
+) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    let _lock = lock_unless_dry_run(cnt, "repack", dry_run)?;
+
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let config = cnt.config()?;
+    let dictionary = match (recompress, config.zstd_dictionary) {
+        (Some(Compression::Zstd(_)), Some(id)) => Some(cnt.dictionaries().get(id)?),
+        _ => None,
+    };
+
+    let mut entries_by_pack: BTreeMap<u64, Vec<PackEntry>> = BTreeMap::new();
+    for entry in read_index_entries(cnt)? {
+        entries_by_pack
+            .entry(entry.pack_id)
+            .or_default()
+            .push(entry);
+    }
+
+    let mut pack_ids: Vec<u64> = traverse_packs(cnt)?
+        .filter_map(|p| p.file_name()?.to_str()?.parse::<u64>().ok())
+        .collect();
+    pack_ids.sort_unstable();
+    // keep the current packs as they are
+    io_packs::retain_sealed(&mut pack_ids);
+    // the packs with the most dead space first, they gain the most from a repack cut short
+    let dead_bytes: HashMap<u64, u64> = fragmentation(cnt)?
+        .into_iter()
+        .map(|pack| (pack.pack_id, pack.dead_bytes))
+        .collect();
+    pack_ids.sort_by_key(|id| Reverse(dead_bytes.get(id).copied().unwrap_or_default()));
+
+    let mut report = MaintainReport::default();
+    for pack_id in pack_ids {
+        if cancel::is_cancelled(cancel) {
+            report.interrupted = true;
+            break;
+        }
+        let pack = cnt.pack_path(pack_id)?;
+        let mut entries = entries_by_pack.remove(&pack_id).unwrap_or_default();
+        entries.sort_by_key(|e| e.offset);
+
+        let mut src = open_shared(&pack)?;
+        let mut live = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let header_len = stored_header_len(&mut src, &entry)?;
+            live.push((entry, header_len));
+        }
+
+        let mut targets = HashMap::new();
+        if let Some(compression) = recompress {
+            for (entry, _) in &live {
+                let obj = PObject::from_entry(entry, &pack).with_dictionaries(cnt.dictionaries());
+                let target = io_packs::entry_compression(
+                    &obj,
+                    compression,
+                    &config.compression_policy,
+                    dictionary.is_some(),
+                );
+                if obj.compression()? != target.algorithm() {
+                    targets.insert(entry.hashkey.clone(), target);
+                }
+            }
+        }
+
+        let pack_len = src.metadata()?.len();
+        let live_bytes: u64 = live.iter().map(|(e, h)| e.size + h).sum();
+        let dead_bytes = pack_len.saturating_sub(live_bytes);
+        let written_order: Vec<_> = live.iter().map(|(e, _)| e.offset).collect();
+        strategy.sort(&mut live);
+        let reordered = live
+            .iter()
+            .zip(&written_order)
+            .any(|((e, _), offset)| e.offset != *offset);
+        if dead_bytes == 0 && !reordered && targets.is_empty() {
+            continue;
+        }
+        let objects = live.len() as u64;
+        if dry_run {
+            report.objects += objects;
+            report.bytes += dead_bytes;
+            continue;
+        }
+
+        // the rewritten pack is a new one after the last pack of the tier, the writers append to
+        // it from now on like to any other current pack
+        let tx = db::begin_write(&conn)?;
+        let packs = cnt.pack_layout()?;
+        let new_id = io_packs::find_current_pack_id(&packs, PackTier::of(pack_id), u64::MAX)? + 1;
+        let new_pack = packs.new_path(new_id)?;
+        let mut journal = Journal::open(cnt)?;
+        journal.intent(new_id, 0)?;
+        let mut dst = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&new_pack)
+            .map_err(|err| Error::IoOpen {
+                source: err,
+                path: new_pack.clone(),
+            })?;
+        let mut offset = 0;
+        for (entry, header_len) in live {
+            if cancel::is_cancelled(cancel) {
+                report.interrupted = true;
+                break;
+            }
+            if let Some(throttle) = throttle {
+                throttle.consume(header_len + entry.size);
+            }
+            if let Some(target) = targets.get(&entry.hashkey) {
+                let obj = PObject::from_entry(&entry, &pack).with_dictionaries(cnt.dictionaries());
+                let written = io_packs::write_entry(
+                    &mut dst,
+                    &obj,
+                    target,
+                    dictionary.as_deref(),
+                    header_len > 0,
+                    new_id,
+                    offset,
+                )?;
+                if written.hashkey != entry.hashkey {
+                    return Err(Error::IntegrityError {
+                        expected: entry.hashkey,
+                        got: written.hashkey,
+                    });
+                }
+                tx.relocate(&written)?;
+                offset = written.offset + written.size;
+                continue;
+            }
+
+            src.seek(SeekFrom::Start(entry.offset - header_len))?;
+            io::copy(&mut (&mut src).take(header_len + entry.size), &mut dst)?;
+            tx.relocate(&PackEntry {
+                offset: offset + header_len,
+                pack_id: new_id,
+                ..entry
+            })?;
+            offset += header_len + entry.size;
+        }
+        if report.interrupted {
+            // removed while the write lock is held, no writer appends to it in the meantime,
+            // the index updates are then rolled back and the old pack stays in use
+            drop(dst);
+            fs::remove_file(&new_pack)?;
+            journal.commit()?;
+            drop(tx);
+            break;
+        }
+        dst.sync_all()?;
+        tx.commit()?;
+        journal.commit()?;
+        // windows does not delete a file still open here
+        drop(src);
+        fs::remove_file(&pack)?;
+        report.objects += objects;
+        report.bytes += pack_len.saturating_sub(offset);
+    }
+
+    Ok(report)
+}
+
+/// Move the objects of sparse packs into the current pack and delete the old pack files.
+///
+/// A pack is pruned when its live bytes (indexed entries with their headers) fall below
+/// ``min_live_ratio`` of its file size, a pack no entry refers to anymore is always pruned. The
+/// stored bytes are copied as they are, without recompressing, and get an entry header if the
+/// current pack is framed. With ``dry_run`` only report the objects that would be moved and the
+/// bytes that would be freed.
+///
+/// The current pack of every tier is skipped since it is the destination of the objects moved out
+/// of the sealed packs of that tier.
+///
+/// NOTE: the old pack is deleted only after the index update is committed, a crash in between
+/// leaves a pack nothing refers to, which the next prune deletes.
+pub fn prune_packs(
+    cnt: &Container,
+    min_live_ratio: f64,
+    dry_run: bool,
+) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    let _lock = lock_unless_dry_run(cnt, "prune", dry_run)?;
+
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    if !dry_run {
+        // the objects are appended after what interrupted writers left in the current packs
+        journal::recover(cnt, &conn)?;
+    }
+    let config = cnt.config()?;
+
+    let mut entries_by_pack: BTreeMap<u64, Vec<PackEntry>> = BTreeMap::new();
+    for entry in read_index_entries(cnt)? {
+        entries_by_pack
+            .entry(entry.pack_id)
+            .or_default()
+            .push(entry);
+    }
+
+    let mut pack_ids: Vec<u64> = traverse_packs(cnt)?
+        .filter_map(|p| p.file_name()?.to_str()?.parse::<u64>().ok())
+        .collect();
+    pack_ids.sort_unstable();
+    // the current packs receive the moved objects
+    io_packs::retain_sealed(&mut pack_ids);
+
+    let mut report = MaintainReport::default();
+    for pack_id in pack_ids {
+        let pack = cnt.pack_path(pack_id)?;
+        let mut entries = entries_by_pack.remove(&pack_id).unwrap_or_default();
+        entries.sort_by_key(|e| e.offset);
+
+        let mut src = open_shared(&pack)?;
+        let pack_len = src.metadata()?.len();
+        let mut live_bytes = 0;
+        for entry in &entries {
+            live_bytes += entry.size + stored_header_len(&mut src, entry)?;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let sparse = (live_bytes as f64) < min_live_ratio * pack_len as f64;
+        if pack_len == 0 || !(entries.is_empty() || sparse) {
+            continue;
+        }
+        report.objects += entries.len() as u64;
+        report.bytes += pack_len - live_bytes;
+        if dry_run {
+            continue;
+        }
+
+        let tx = db::begin_write(&conn)?;
+        let mut journal = Journal::open(cnt)?;
+        move_to_current_pack(
+            cnt,
+            &*tx,
+            &mut journal,
+            &config,
+            &mut src,
+            PackTier::of(pack_id),
+            entries,
+        )?;
+        tx.commit()?;
+        journal.commit()?;
+        drop(src);
+        fs::remove_file(&pack)?;
+    }
+
+    Ok(report)
+}
+
+/// Sealed packs whose live bytes are this many times over the pack size target are split, and
+/// under it this many times are merged, by ``rebalance_packs``.
+const REBALANCE_FACTOR: u64 = 2;
+
+/// Split the sealed packs far over the pack size target and merge those far under it, e.g. after
+/// ``pack_size_target`` was changed, which only applies to the packs written from then on.
+///
+/// A pack with more than one object whose live bytes are over ``REBALANCE_FACTOR`` times the
+/// target of its tier, or a pack under the target divided by ``REBALANCE_FACTOR``, has its objects
+/// moved to the current pack of its tier, rolled over at the target, then it is deleted. A single
+/// object larger than the target can not be split and its pack is left alone. The stored bytes
+/// are copied as they are, as ``prune_packs`` does. With ``dry_run`` only report the objects and
+/// the bytes that would be moved.
+///
+/// The current pack of every tier is skipped since it is the destination of the objects, it is
+/// rebalanced by a later run once sealed.
+pub fn rebalance_packs(cnt: &Container, dry_run: bool) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    let _lock = lock_unless_dry_run(cnt, "rebalance", dry_run)?;
+
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    if !dry_run {
+        // the objects are appended after what interrupted writers left in the current packs
+        journal::recover(cnt, &conn)?;
+    }
+    let config = cnt.config()?;
+
+    let mut entries_by_pack: BTreeMap<u64, Vec<PackEntry>> = BTreeMap::new();
+    for entry in read_index_entries(cnt)? {
+        entries_by_pack
+            .entry(entry.pack_id)
+            .or_default()
+            .push(entry);
+    }
+
+    let mut pack_ids: Vec<u64> = traverse_packs(cnt)?
+        .filter_map(|p| p.file_name()?.to_str()?.parse::<u64>().ok())
+        .collect();
+    pack_ids.sort_unstable();
+    // the current packs receive the moved objects
+    io_packs::retain_sealed(&mut pack_ids);
+
+    let mut report = MaintainReport::default();
+    for pack_id in pack_ids {
+        let pack = cnt.pack_path(pack_id)?;
+        let mut entries = entries_by_pack.remove(&pack_id).unwrap_or_default();
+        entries.sort_by_key(|e| e.offset);
+
+        let mut src = open_shared(&pack)?;
+        let mut live_bytes = 0;
+        for entry in &entries {
+            live_bytes += entry.size + stored_header_len(&mut src, entry)?;
+        }
+        let tier = PackTier::of(pack_id);
+        let target = tier.pack_size_target(&config);
+        let oversized = entries.len() > 1 && live_bytes > target.saturating_mul(REBALANCE_FACTOR);
+        let undersized = live_bytes < target / REBALANCE_FACTOR;
+        if !(oversized || undersized) {
+            continue;
+        }
+        report.objects += entries.len() as u64;
+        report.bytes += live_bytes;
+        if dry_run {
+            continue;
+        }
+
+        let tx = db::begin_write(&conn)?;
+        let mut journal = Journal::open(cnt)?;
+        move_to_current_pack(cnt, &*tx, &mut journal, &config, &mut src, tier, entries)?;
+        tx.commit()?;
+        journal.commit()?;
+        drop(src);
+        fs::remove_file(&pack)?;
+    }
+
+    Ok(report)
+}
+
+/// Append the stored bytes of ``entries`` of the pack ``src`` to the current pack of ``tier``,
+/// rolled over at the pack size target of the tier, and point the index to them in ``tx``. The
+/// entries get an entry header if the container writes them.
+///
+/// The appends are recorded in ``journal``, which the caller empties once ``tx`` is committed.
+fn move_to_current_pack(
+    cnt: &Container,
+    tx: &dyn ObjectIndex,
+    journal: &mut Journal,
+    config: &Config,
+    src: &mut fs::File,
+    tier: PackTier,
+    entries: Vec<PackEntry>,
+) -> Result<(), Error> {
+    let framed = config.capabilities().entry_header;
+    let pack_size_target = tier.pack_size_target(config);
+    let packs = cnt.pack_layout()?;
+    let mut cwp_id = io_packs::find_current_pack_id(&packs, tier, pack_size_target)?;
+    let mut cwp = fs::OpenOptions::new()
+        .append(true)
+        .open(packs.path(cwp_id))?;
+    let mut offset = cwp.metadata()?.len();
+    journal.intent(cwp_id, offset)?;
+    for entry in entries {
+        if offset >= pack_size_target {
+            cwp.sync_all()?;
+            cwp_id += 1;
+            offset = 0;
+            journal.intent(cwp_id, offset)?;
+            cwp = fs::OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(packs.new_path(cwp_id)?)?;
+        }
+        let start = offset;
+
+        let mut stored = vec![];
+        src.seek(SeekFrom::Start(entry.offset))?;
+        (&mut *src).take(entry.size).read_to_end(&mut stored)?;
+        if framed {
+            let mut crc = Crc::new();
+            crc.update(&stored);
+            let header = EntryHeader {
+                compressed: entry.compressed,
+                raw_size: entry.raw_size,
+                length: entry.size,
+                crc: crc.sum(),
+                hash: io_packs::hex_to_hash(&entry.hashkey)?,
+            };
+            cwp.write_all(&header.to_bytes())?;
+            offset += io_packs::ENTRY_HEADER_LEN as u64;
+        }
+        cwp.write_all(&stored)?;
+        journal.written(cwp_id, start, offset + entry.size - start, &entry.hashkey)?;
+        tx.relocate(&PackEntry {
+            offset,
+            pack_id: cwp_id,
+            ..entry
+        })?;
+        offset += entry.size;
+    }
+    cwp.sync_all()?;
+    Ok(())
+}
+
+/// Upload the sealed packs to the pack storage of the container and delete the local copies,
+/// reads of their objects are then served from the pack storage. A local copy is only deleted
+/// once the uploaded pack has the same size. With ``dry_run`` only report the objects and the
+/// bytes that would be offloaded.
+///
+/// The current pack is skipped since it is still appended to.
+pub fn offload_packs(cnt: &Container, dry_run: bool) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+    let _lock = lock_unless_dry_run(cnt, "offload", dry_run)?;
+
+    let Some(storage) = cnt.pack_storage()? else {
+        return Err(Error::PackStorage {
+            url: String::new(),
+            cause: "no pack storage configured, set `pack_storage_url`".to_string(),
+        });
+    };
+
+    let mut n_entries: BTreeMap<u64, u64> = BTreeMap::new();
+    for entry in read_index_entries(cnt)? {
+        *n_entries.entry(entry.pack_id).or_default() += 1;
+    }
+
+    let mut pack_ids: Vec<u64> = traverse_packs(cnt)?
+        .filter_map(|p| p.file_name()?.to_str()?.parse::<u64>().ok())
+        .collect();
+    pack_ids.sort_unstable();
+    // keep the current packs as they are
+    io_packs::retain_sealed(&mut pack_ids);
+
+    let mut report = MaintainReport::default();
+    for pack_id in pack_ids {
+        let pack = cnt.pack_path(pack_id)?;
+        let pack_len = fs::metadata(&pack)?.len();
+        report.objects += n_entries.get(&pack_id).copied().unwrap_or_default();
+        report.bytes += pack_len;
+        if dry_run {
+            continue;
+        }
+
+        storage.upload(pack_id, &pack)?;
+        match storage.size(pack_id)? {
+            Some(size) if size == pack_len => fs::remove_file(&pack)?,
+            got => {
+                return Err(Error::PackStorage {
+                    url: cnt.config()?.pack_storage_url.unwrap_or_default(),
+                    cause: format!(
+                        "pack {pack_id} uploaded with {} bytes, expected {pack_len}",
+                        got.unwrap_or_default()
+                    ),
+                })
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Copy the objects of ``src`` that ``dst`` does not have into ``dst``. The hashkeys of both
+/// containers are compared through their indexes, only the missing objects are read and they are
+/// streamed into the loose store of ``dst``, where each is re-hashed and refused if it does not
+/// match its hashkey. Chunked objects are copied as their chunks and manifest. With ``dry_run``
+/// only report the objects and the bytes that would be copied.
+///
+/// NOTE: an object failing its check aborts the sync, the objects copied before it are kept so a
+/// later sync picks up where this one stopped.
+pub fn sync(
+    src: &Container,
+    dst: &Container,
+    dry_run: bool,
+    cancel: Option<&CancellationToken>,
+) -> Result<MaintainReport, Error> {
+    src.valid()?;
+    dst.valid()?;
+
+    let (src_plain, src_chunked) = object_hashkeys(src)?;
+    let (dst_plain, dst_chunked) = object_hashkeys(dst)?;
+
+    let mut report = MaintainReport::default();
+    for hashkey in src_plain.difference(&dst_plain) {
+        if cancel::is_cancelled(cancel) {
+            report.interrupted = true;
+            return Ok(report);
+        }
+        let Some(obj) = src.get(hashkey)? else {
+            continue;
+        };
+        let size = obj.size();
+        if !dry_run {
+            copy_object(obj, hashkey, dst, &dst_plain)?;
+        }
+        report.objects += 1;
+        report.bytes += size;
+    }
+
+    let src_index = src.index()?;
+    let dst_index = dst.index()?;
+    if !dry_run {
+        db::ensure_writable(&*dst.db()?)?;
+    }
+    for hashkey in src_chunked.difference(&dst_chunked) {
+        if cancel::is_cancelled(cancel) {
+            report.interrupted = true;
+            break;
+        }
+        let Some((raw_size, manifest)) = src_index.select_chunked(hashkey)? else {
+            continue;
+        };
+        report.objects += 1;
+        if !dry_run {
+            dst_index.insert_chunked(hashkey, raw_size, &manifest)?;
+            dst.note_indexed(hashkey);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Copy the container to the new folder ``dest`` as a snapshot that opens cleanly even while the
+/// container is being written to. Report the objects and the bytes copied.
+///
+/// Loose objects are copied first and re-hashed on the way. The index is then checkpointed and
+/// copied with the SQLite backup API, which sees a single committed state. Packs are append-only,
+/// so the pack files copied afterwards hold every entry of that index, which are then verified in
+/// the copy. An object packed and cleaned from loose in the meantime is in the copied index, an
+/// object added in the meantime may or may not be in the snapshot.
+///
+/// NOTE: ``repack`` and ``prune_packs`` rewrite packs, running them during a backup fails the
+/// verification of the moved entries.
+pub fn backup(cnt: &Container, dest: &Path) -> Result<MaintainReport, Error> {
+    cnt.valid()?;
+
+    fs::create_dir_all(dest)?;
+    let dst = Container::new(dest);
+    dst.initialize(&cnt.config()?)
+        .map_err(|err| Error::StoreComponentError {
+            path: dest.to_path_buf(),
+            cause: err.to_string(),
+        })?;
+
+    let mut report = MaintainReport::default();
+    for path in traverse_loose(cnt)? {
+        let path = path?;
+        let Some(hashkey) = loose_hashkey(&path) else {
+            continue;
+        };
+        let dir = dst.loose().join(&hashkey[..2]);
+        fs::create_dir_all(&dir)?;
+        let mut hwriter =
+            HashWriter::new(fs::File::create(dir.join(&hashkey[2..]))?, &digest::SHA256);
+        report.bytes += io::copy(&mut fs::File::open(&path)?, &mut hwriter)?;
+        let got = hex::encode(hwriter.finish());
+        if got != hashkey {
+            return Err(Error::IntegrityError {
+                expected: hashkey,
+                got,
+            });
+        }
+        report.objects += 1;
+    }
+
+    let conn = cnt.db()?;
+    // move the committed transactions into the index file, busy readers only make it partial
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    let mut dst_conn = Connection::open(dst.packs_db())?;
+    let step = Backup::new(&conn, &mut dst_conn)?.step(-1)?;
+    if step != StepResult::Done {
+        return Err(Error::StoreComponentError {
+            path: cnt.packs_db(),
+            cause: format!("index backup did not complete: {step:?}"),
+        });
+    }
+    // the copied index carries the counters of the source, which may have drifted
+    dst_conn.set_loose_counters(report.objects, report.bytes)?;
+    drop(dst_conn);
+    drop(conn);
+
+    let dst_packs = dst.pack_layout()?;
+    for path in traverse_packs(cnt)? {
+        let Some(pack_id) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        report.bytes += fs::copy(&path, dst_packs.new_path(pack_id)?)?;
+    }
+    // the packed entries compressed with a dictionary can not be read without it
+    let dictionaries = cnt.path.join(DICTIONARIES);
+    if dictionaries.is_dir() {
+        fs::create_dir_all(dst.path.join(DICTIONARIES))?;
+        for entry in fs::read_dir(&dictionaries)? {
+            let path = entry?.path();
+            if let Some(name) = path.file_name().filter(|_| path.is_file()) {
+                fs::copy(&path, dst.path.join(DICTIONARIES).join(name))?;
+            }
+        }
+    }
+    for entry in read_index_entries(&dst)? {
+        if !verify_entry(&dst, &entry) {
+            return Err(Error::StoreComponentError {
+                path: dst_packs.path(entry.pack_id),
+                cause: format!("copied entry {} does not verify", entry.hashkey),
+            });
+        }
+        report.objects += 1;
+    }
+
+    Ok(report)
+}
+
+/// Hashkeys of the plain objects (loose and packs) and of the chunked objects of a container.
+fn object_hashkeys(cnt: &Container) -> Result<(BTreeSet<String>, BTreeSet<String>), Error> {
+    let mut plain = BTreeSet::new();
+    for path in traverse_loose(cnt)? {
+        plain.extend(loose_hashkey(&path?));
+    }
+    let index = cnt.index()?;
+    plain.extend(packed_hashkeys(&*index)?);
+    let chunked = index.list_chunked()?.into_iter().collect();
+    Ok((plain, chunked))
+}
+
+/// Insert ``source`` as a loose object of ``dst`` and check it arrived as ``hashkey``, the
+/// ``existing`` objects of ``dst`` are kept if the content turns out to be one of them.
+fn copy_object<T: ReaderMaker>(
+    source: T,
+    hashkey: &str,
+    dst: &Container,
+    existing: &BTreeSet<String>,
+) -> Result<(), Error> {
+    let got = io_loose::insert(source, dst)?.hashkey;
+    if got != hashkey {
+        // do not keep the corrupted copy under the hashkey of its content
+        if !existing.contains(&got) {
+            let loc = dst.loose().join(&got[..2]).join(&got[2..]);
+            let size = fs::metadata(&loc)?.len();
+            fs::remove_file(loc)?;
+            dst.index()?
+                .add_loose(-1, -i64::try_from(size).unwrap_or(i64::MAX))?;
+        }
+        return Err(Error::IntegrityError {
+            expected: hashkey.to_string(),
+            got,
+        });
+    }
+    Ok(())
+}
+
+/// Length of the entry header stored in front of ``entry``, 0 if it has none.
+fn stored_header_len(pack: &mut fs::File, entry: &PackEntry) -> Result<u64, Error> {
+    let header_len = io_packs::ENTRY_HEADER_LEN as u64;
+    if entry.offset < header_len {
+        return Ok(0);
+    }
+
+    let mut buf = [0u8; io_packs::ENTRY_HEADER_LEN];
+    pack.seek(SeekFrom::Start(entry.offset - header_len))?;
+    pack.read_exact(&mut buf)?;
+    match EntryHeader::from_bytes(&buf) {
+        Some(header)
+            if header.length == entry.size && hex::encode(header.hash) == entry.hashkey =>
+        {
+            Ok(header_len)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Call ``f`` with every loose object of ``cnt`` and whether it is already in packs.
+///
+/// The index is queried for ``EXTRACT_BATCH_SIZE`` loose hashkeys at a time, so the memory used
+/// is bounded by the batch whatever the number of packed objects.
+pub(crate) fn for_each_loose_packed<F>(
+    cnt: &Container,
+    index: &dyn ObjectIndex,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(PathBuf, bool) -> Result<(), Error>,
+{
+    let mut batch = Vec::with_capacity(EXTRACT_BATCH_SIZE);
+    let mut flush = |batch: &mut Vec<(PathBuf, String)>| -> Result<(), Error> {
+        let hashkeys: Vec<String> = batch.iter().map(|(_, h)| h.clone()).collect();
+        let packed: HashSet<String> = index
+            .select_many(&hashkeys)?
+            .into_iter()
+            .map(|entry| entry.hashkey)
+            .collect();
+        for (path, hashkey) in batch.drain(..) {
+            f(path, packed.contains(&hashkey))?;
+        }
+        Ok(())
+    };
+    for path in traverse_loose(cnt)? {
+        let path = path?;
+        let Some(hashkey) = loose_hashkey(&path) else {
+            continue;
+        };
+        batch.push((path, hashkey));
+        if batch.len() == EXTRACT_BATCH_SIZE {
+            flush(&mut batch)?;
+        }
+    }
+    if !batch.is_empty() {
+        flush(&mut batch)?;
+    }
+    Ok(())
+}
+
+fn packed_hashkeys(index: &dyn ObjectIndex) -> Result<HashSet<String>, Error> {
+    let mut hashkeys = HashSet::new();
+    index.iterate(&mut |entry| {
+        hashkeys.insert(entry.hashkey);
+        Ok(())
+    })?;
+    Ok(hashkeys)
+}
+
+/// Upgrade a container in place to ``CONTAINER_VERSION``, returns the version it had before.
+///
+/// Version 2 is a superset of version 1: loose objects, packs and the index stay valid as they
+/// are, only ``config.json`` is rewritten. With ``entry_header`` the packs written from now on get
+/// entry headers; the current pack is sealed first so a pack is never half framed. With
+/// ``sharded_packs`` the pack files are moved into shards, see ``shard_packs``.
+pub fn upgrade(cnt: &Container, entry_header: bool, sharded_packs: bool) -> Result<u32, Error> {
+    // refuse layouts and versions this build does not understand
+    Container::open(&cnt.path)?;
+    cnt.reload();
+    let config = cnt.config()?;
+    let from_version = config.container_version;
+
+    // bring the index schema up to date as well
+    cnt.db()?;
+
+    let enable_header = entry_header && !config.pack_entry_header;
+    // also run on a sharded container, to finish a move that was interrupted
+    if from_version == CONTAINER_VERSION && !enable_header && !sharded_packs {
+        return Ok(from_version);
+    }
+
+    if enable_header {
+        io_packs::seal_current_pack(&cnt.pack_layout()?)?;
+    }
+    let update = |config: &mut Config| {
+        config.pack_entry_header |= enable_header;
+        config.container_version = CONTAINER_VERSION;
+        Ok(())
+    };
+    if sharded_packs {
+        shard_packs(cnt, update)?;
+    } else {
+        cnt.update_config(update)?;
+    }
+
+    Ok(from_version)
+}
+
+/// Move the pack files from ``packs`` into their shards and set ``Config::sharded_packs``, along
+/// with the other changes of ``update``.
+///
+/// Every pack is first hard linked into its shard, so the packs are complete in both layouts
+/// while the config is switched, and only then unlinked from ``packs``. Interrupted, the
+/// container is left in one of the layouts with extra links, a new run starts over or removes
+/// them.
+///
+/// NOTE: run it while no other process uses the container, a process that read the config
+/// before the switch still looks for the packs in ``packs``.
+fn shard_packs<F>(cnt: &Container, update: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Config) -> Result<(), Error>,
+{
+    let _lock = MaintenanceLock::acquire(cnt, "upgrade")?;
+    // the links of an interrupted run are only trusted once the config was switched
+    let switched = cnt.config()?.sharded_packs;
+    let flat = PackLayout {
+        root: cnt.packs(),
+        sharded: false,
+    };
+    let sharded = PackLayout {
+        sharded: true,
+        ..flat.clone()
+    };
+    let mut packs = vec![];
+    for path in flat.files()? {
+        let Some(pack_id) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if !switched {
+            let dst = sharded.new_path(pack_id)?;
+            match fs::remove_file(&dst) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => fs::hard_link(&path, &dst)?,
+            }
+        }
+        packs.push(path);
+    }
+    cnt.update_config(|config| {
+        update(config)?;
+        config.sharded_packs = true;
+        Ok(())
+    })?;
+    for path in packs {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Outcome of ``rebuild_index``.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Whether the previous index could be read at all.
+    pub index_readable: bool,
+    /// Entries verified against the pack content and written to the rebuilt index.
+    pub entries_kept: u64,
+    /// Entries dropped because their pack is missing, out of range or the content mismatch.
+    pub entries_dropped: u64,
+    /// Entries missing from the old index, recovered from the pack entry headers or by decoding
+    /// the compressed streams of packs.
+    pub entries_recovered: u64,
+    /// Bytes in pack files that are not referenced by any kept entry.
+    pub unreferenced_bytes: u64,
+    /// Rows of the tables not derived from the packs (see ``KEPT_TABLES``) copied from the
+    /// previous index.
+    pub rows_copied: u64,
+}
+
+const REBUILD_DB_SUFFIX: &str = "rebuild";
+/// Tables of the index that can not be derived from the packs, ``rebuild_index`` copies them from
+/// the previous index.
+const KEPT_TABLES: &[&str] = &["db_chunked", "db_namespace", "db_expiry"];
+const BACKUP_DB_SUFFIX: &str = "bak";
+
+/// Rebuild the pack index from the pack files.
+///
+/// Every entry of the old index (if it can still be read) is re-verified by re-hashing the content
+/// it points to. The bytes of the packs no verified entry covers are then walked to recover the
+/// entries the old index lost: packs written with entry headers are read header by header,
+/// elsewhere zlib and zstd streams carry their own boundaries. Verified entries are written to a
+/// fresh index which then replaces ``packs.idx``. The old index is kept next to it as
+/// ``packs.idx.bak``.
+///
+/// The records of the index that are not about packs (``KEPT_TABLES``: the manifests of the
+/// chunked objects, the namespaces and the expiries) are copied over from the old index when it can be read.
+///
+/// NOTE: uncompressed payloads written without entry headers are stored without any boundary,
+/// those lost from the index cannot be re-derived from the packs alone, their bytes are reported
+/// as ``unreferenced_bytes``.
+pub fn rebuild_index(cnt: &Container) -> Result<FsckReport, Error> {
+    // the index may be missing or unreadable, that is what is fixed here
+    cnt.valid_layout()?;
+    let config = cnt.config()?;
+    config.check_version()?;
+    if let Some(url) = config.pack_storage_url {
+        return Err(Error::PackStorage {
+            url,
+            cause: "the index can not be rebuilt from offloaded packs".to_string(),
+        });
+    }
+    let _lock = MaintenanceLock::acquire(cnt, "fsck")?;
+
+    let mut report = FsckReport::default();
+    let entries = match read_index_entries(cnt) {
+        Ok(entries) => {
+            report.index_readable = true;
+            entries
+        }
+        Err(_) => vec![],
+    };
+
+    let packs_db = cnt.packs_db();
+    let rebuild_db = packs_db.with_extension(format!("idx.{REBUILD_DB_SUFFIX}"));
+    if rebuild_db.exists() {
+        fs::remove_file(&rebuild_db)?;
+    }
+    db::create(&rebuild_db).map_err(|err| Error::StoreComponentError {
+        path: rebuild_db.clone(),
+        cause: err.to_string(),
+    })?;
+
+    let mut conn = db::open(&rebuild_db)?;
+    // attached out of the transaction, SQLite refuses to attach in one
+    let attached = packs_db.exists()
+        && conn
+            .execute(
+                "ATTACH DATABASE ?1 AS old",
+                params![packs_db.to_string_lossy()],
+            )
+            .is_ok();
+    let tx = conn.transaction()?;
+    if attached {
+        report.rows_copied = copy_kept_tables(&tx);
+    }
+    let mut referenced_bytes = 0;
+    let mut kept = HashSet::new();
+    let mut covered: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+    let mut rebuilt = vec![];
+    for entry in entries {
+        if verify_entry(cnt, &entry) {
+            referenced_bytes += entry.size;
+            report.entries_kept += 1;
+            covered
+                .entry(entry.pack_id)
+                .or_default()
+                .push((entry.offset, entry.offset + entry.size));
+            kept.insert(entry.hashkey.clone());
+            rebuilt.push(entry);
+        } else {
+            report.entries_dropped += 1;
+        }
+    }
+
+    let dictionaries = cnt.dictionaries();
+    for pack in traverse_packs(cnt)? {
+        let Some(pack_id) = pack
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        let mut ranges = covered.remove(&pack_id).unwrap_or_default();
+        for entry in io_packs::scan_framed_pack(&pack, pack_id)? {
+            if kept.contains(&entry.hashkey) || !verify_entry(cnt, &entry) {
+                continue;
+            }
+            referenced_bytes += entry.size;
+            report.entries_recovered += 1;
+            ranges.push((
+                entry.offset - io_packs::ENTRY_HEADER_LEN as u64,
+                entry.offset + entry.size,
+            ));
+            kept.insert(entry.hashkey.clone());
+            rebuilt.push(entry);
+        }
+        for (start, end) in uncovered(ranges, fs::metadata(&pack)?.len()) {
+            let scanned =
+                io_packs::scan_compressed_entries(&pack, pack_id, start, end, &dictionaries)?;
+            for entry in scanned {
+                if !kept.insert(entry.hashkey.clone()) {
+                    continue;
+                }
+                referenced_bytes += entry.size;
+                report.entries_recovered += 1;
+                rebuilt.push(entry);
+            }
+        }
+    }
+    tx.insert(&rebuilt)
+        .map_err(|err| Error::StoreComponentError {
+            path: rebuild_db.clone(),
+            cause: err.to_string(),
+        })?;
+    tx.commit()?;
+    drop(conn);
+
+    let packs_bytes: u64 = traverse_packs(cnt)?
+        .filter_map(|p| fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum();
+    report.unreferenced_bytes = packs_bytes.saturating_sub(referenced_bytes);
+
+    // swap in the rebuilt index, the WAL files of the old index must not be applied to the new one
+    cnt.close_db();
+    if packs_db.exists() {
+        rename_replace(
+            &packs_db,
+            &packs_db.with_extension(format!("idx.{BACKUP_DB_SUFFIX}")),
+        )?;
+    }
+    for suffix in ["idx-wal", "idx-shm"] {
+        let p = packs_db.with_extension(suffix);
+        if p.exists() {
+            fs::remove_file(p)?;
+        }
+    }
+    rename_replace(&rebuild_db, &packs_db)?;
+
+    Ok(report)
+}
+
+/// The ranges of a pack of ``len`` bytes that none of the ``(start, end)`` ``ranges`` covers.
+fn uncovered(mut ranges: Vec<(u64, u64)>, len: u64) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut gaps = vec![];
+    let mut cursor = 0;
+    for (start, end) in ranges {
+        if start > cursor {
+            gaps.push((cursor, start));
+        }
+        cursor = cursor.max(end);
+    }
+    if len > cursor {
+        gaps.push((cursor, len));
+    }
+    gaps
+}
+
+/// Copy the rows of ``KEPT_TABLES`` from the index attached as ``old`` in ``tx`` and return how
+/// many were copied. A table the old index does not have or that can not be read is skipped.
+fn copy_kept_tables(tx: &Connection) -> u64 {
+    let mut copied = 0;
+    for table in KEPT_TABLES {
+        let n = tx
+            .execute(
+                &format!("INSERT OR IGNORE INTO main.{table} SELECT * FROM old.{table}"),
+                [],
+            )
+            .unwrap_or(0);
+        copied += n as u64;
+    }
+    copied
+}
+
+/// An index entry ``check_index`` found inconsistent with the pack files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IndexAnomaly {
+    /// The pack of the entry is neither in the ``packs`` folder nor in the pack storage, the
+    /// object is lost unless the pack is restored.
+    MissingPack { hashkey: String, pack_id: u64 },
+    /// The entry ends past the end of its pack of ``pack_size`` bytes, the pack was cut.
+    OutOfRange {
+        hashkey: String,
+        pack_id: u64,
+        offset: u64,
+        length: u64,
+        pack_size: u64,
+    },
+    /// The entry starts before the end of ``other``, the entry of its pack right before it, at
+    /// ``other_end``. At most one of them has its content where the index says.
+    Overlap {
+        hashkey: String,
+        other: String,
+        pack_id: u64,
+        offset: u64,
+        other_end: u64,
+    },
+}
+
+/// Outcome of ``check_index``.
+#[derive(Debug, Default, Serialize)]
+pub struct IndexCheckReport {
+    /// Number of index entries checked.
+    pub entries_checked: u64,
+    /// Number of packs the entries are in.
+    pub packs_checked: u64,
+    pub anomalies: Vec<IndexAnomaly>,
+}
+
+impl IndexCheckReport {
+    #[must_use]
+    pub fn is_consistent(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+}
+
+/// Check the index against the sizes of the pack files, without reading their content: every
+/// entry must be in a pack that exists, locally or in the pack storage, end within it and not
+/// overlap another entry of its pack. Much faster than ``validate``, which re-hashes every object.
+///
+/// Nothing is changed, ``rebuild_index`` repairs the index from the pack files.
+pub fn check_index(cnt: &Container) -> Result<IndexCheckReport, Error> {
+    cnt.valid()?;
+
+    let mut entries_by_pack: BTreeMap<u64, Vec<PackEntry>> = BTreeMap::new();
+    for entry in read_index_entries(cnt)? {
+        entries_by_pack
+            .entry(entry.pack_id)
+            .or_default()
+            .push(entry);
+    }
+
+    let storage = cnt.pack_storage()?;
+    let mut report = IndexCheckReport::default();
+    for (pack_id, mut entries) in entries_by_pack {
+        report.packs_checked += 1;
+        report.entries_checked += entries.len() as u64;
+        let pack_size = match fs::metadata(cnt.pack_path(pack_id)?) {
+            Ok(meta) => Some(meta.len()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => match &storage {
+                Some(storage) => storage.size(pack_id)?,
+                None => None,
+            },
+            Err(err) => return Err(err.into()),
+        };
+        let Some(pack_size) = pack_size else {
+            report
+                .anomalies
+                .extend(entries.into_iter().map(|entry| IndexAnomaly::MissingPack {
+                    hashkey: entry.hashkey,
+                    pack_id,
+                }));
+            continue;
+        };
+
+        entries.sort_by_key(|e| (e.offset, e.size));
+        let mut previous: Option<(String, u64)> = None;
+        for entry in entries {
+            let end = entry.offset + entry.size;
+            if end > pack_size {
+                report.anomalies.push(IndexAnomaly::OutOfRange {
+                    hashkey: entry.hashkey.clone(),
+                    pack_id,
+                    offset: entry.offset,
+                    length: entry.size,
+                    pack_size,
+                });
+            }
+            match previous {
+                Some((other, other_end)) if entry.offset < other_end => {
+                    report.anomalies.push(IndexAnomaly::Overlap {
+                        hashkey: entry.hashkey.clone(),
+                        other: other.clone(),
+                        pack_id,
+                        offset: entry.offset,
+                        other_end,
+                    });
+                    // the entry ending last is the one later entries may overlap
+                    if end > other_end {
+                        previous = Some((entry.hashkey, end));
+                    } else {
+                        previous = Some((other, other_end));
+                    }
+                }
+                _ => previous = Some((entry.hashkey, end)),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Outcome of ``validate``.
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationReport {
+    /// Number of loose objects checked.
+    pub loose_checked: u64,
+    /// Number of packed objects checked.
+    pub packs_checked: u64,
+    /// Loose objects whose content does not hash to their hashkey.
+    pub invalid_loose: Vec<String>,
+    /// Packed objects that are out of their pack range or whose content does not hash to their
+    /// hashkey.
+    pub invalid_packs: Vec<String>,
+    /// Validation was cancelled before every object was checked.
+    pub interrupted: bool,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid_loose.is_empty() && self.invalid_packs.is_empty()
+    }
+}
+
+/// Re-hash every loose and packed object and report those whose content mismatch their hashkey.
+/// The container is not modified.
+pub fn validate(cnt: &Container) -> Result<ValidationReport, Error> {
+    validate_with_progress(cnt, &(), None, None)
+}
+
+/// ``validate`` telling ``progress`` the number of objects checked every ``PROGRESS_STEP``
+/// objects, the objects are read at most at the rate of ``throttle``. ``cancel`` is checked between
+/// objects, the report then covers the objects checked so far.
+pub fn validate_with_progress(
+    cnt: &Container,
+    progress: &dyn ProgressObserver,
+    throttle: Option<&IoThrottle>,
+    cancel: Option<&CancellationToken>,
+) -> Result<ValidationReport, Error> {
+    cnt.valid()?;
+
+    let loose = traverse_loose(cnt)?
+        .filter_map(|path| path.map(|p| loose_hashkey(&p).map(|h| (p, h))).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+    let entries = read_index_entries(cnt)?;
+    let total = (loose.len() + entries.len()) as u64;
+    let mut done = 0;
+    let mut step = || {
+        done += 1;
+        if done % PROGRESS_STEP as u64 == 0 && done < total {
+            progress.progress(done, total);
+        }
+    };
+    progress.progress(0, total);
+
+    let mut report = ValidationReport::default();
+    for (path, hashkey) in loose {
+        report.interrupted |= cancel::is_cancelled(cancel);
+        if report.interrupted {
+            break;
+        }
+        if let Some(throttle) = throttle {
+            throttle.consume(fs::metadata(&path).map_or(0, |meta| meta.len()));
+        }
+        report.loose_checked += 1;
+        if !verify_loose(&path, &hashkey) {
+            report.invalid_loose.push(hashkey);
+        }
+        step();
+    }
+
+    for entry in entries {
+        report.interrupted |= cancel::is_cancelled(cancel);
+        if report.interrupted {
+            break;
+        }
+        if let Some(throttle) = throttle {
+            throttle.consume(entry.size);
+        }
+        report.packs_checked += 1;
+        if !verify_entry(cnt, &entry) {
+            report.invalid_packs.push(entry.hashkey);
+        }
+        step();
+    }
+    progress.progress(done, total);
+
+    Ok(report)
+}
+
+/// Outcome of ``verify``, ``None`` for the store the object is not in.
+#[derive(Debug, Serialize)]
+pub struct ObjectVerification {
+    pub hashkey: String,
+    pub loose: Option<bool>,
+    pub packs: Option<bool>,
+}
+
+impl ObjectVerification {
+    pub fn is_valid(&self) -> bool {
+        self.loose.unwrap_or(true) && self.packs.unwrap_or(true)
+    }
+}
+
+/// Re-hash a single object, in loose and in packs if it is in both, and compare with its
+/// hashkey. Packed entries with an entry header also get their CRC32 checked. Returns ``None``
+/// if the object is not in the container. The container is not modified.
+pub fn verify(cnt: &Container, hashkey: &str) -> Result<Option<ObjectVerification>, Error> {
+    cnt.valid()?;
+
+    if !is_hashkey(hashkey) {
+        return Ok(None);
+    }
+
+    let loc = cnt.loose().join(&hashkey[..2]).join(&hashkey[2..]);
+    let loose = loc.is_file().then(|| verify_loose(&loc, hashkey));
+
+    let packs = cnt
+        .index()?
+        .select(hashkey)?
+        .map(|entry| verify_entry(cnt, &entry));
+
+    if loose.is_none() && packs.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(ObjectVerification {
+        hashkey: hashkey.to_string(),
+        loose,
+        packs,
+    }))
+}
+
+/// Check the loose object at ``path`` hashes to ``hashkey``.
+fn verify_loose(path: &Path, hashkey: &str) -> bool {
+    let mut hwriter = HashWriter::new(io::sink(), &digest::SHA256);
+    fs::File::open(path)
+        .and_then(|mut f| io::copy(&mut f, &mut hwriter))
+        .is_ok_and(|_| hex::encode(hwriter.finish()) == hashkey)
+}
+
+fn read_index_entries(cnt: &Container) -> Result<Vec<PackEntry>, Error> {
+    let index: Box<dyn ObjectIndex> = match cnt.config()?.index_backend {
+        // read-only, a broken index is not migrated (see ``rebuild_index``)
+        IndexBackend::Sqlite => Box::new(Connection::open_with_flags(
+            cnt.packs_db(),
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?),
+    };
+    let mut entries = vec![];
+    index.iterate(&mut |entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Check the entry lies inside its pack file, matches the CRC32 of its entry header if it has one
+/// and its content hashes to its hashkey.
+fn verify_entry(cnt: &Container, entry: &PackEntry) -> bool {
+    let Ok(loc) = cnt.pack_path(entry.pack_id) else {
+        return false;
+    };
+    let Ok(mut f) = fs::File::open(&loc) else {
+        return verify_offloaded_entry(cnt, entry, &loc);
+    };
+    match f.metadata() {
+        Ok(stat) if entry.offset + entry.size <= stat.len() => {}
+        _ => return false,
+    }
+    if !verify_entry_crc(&mut f, entry).unwrap_or(false) {
+        return false;
+    }
+
+    let obj = PObject::from_entry(entry, loc);
+    hashes_to_entry(&obj, entry)
+}
+
+/// Check an entry of an offloaded pack by fetching and re-hashing its content, its entry header
+/// (if any) is not fetched.
+fn verify_offloaded_entry(cnt: &Container, entry: &PackEntry, loc: &Path) -> bool {
+    let Ok(Some(storage)) = cnt.pack_storage() else {
+        return false;
+    };
+    let obj = PObject::from_entry(entry, loc).with_storage(entry.pack_id, Some(storage));
+    hashes_to_entry(&obj, entry)
+}
+
+fn hashes_to_entry(obj: &PObject, entry: &PackEntry) -> bool {
+    let Ok(mut rdr) = obj.make_reader() else {
+        return false;
+    };
+    let mut hwriter = HashWriter::new(io::sink(), &digest::SHA256);
+    match io::copy(&mut rdr, &mut hwriter) {
+        Ok(n) if n == entry.raw_size => hex::encode(hwriter.finish()) == entry.hashkey,
+        _ => false,
+    }
+}
+
+/// Check the stored bytes of ``entry`` against the CRC32 of its entry header, true if it has none.
+fn verify_entry_crc(pack: &mut fs::File, entry: &PackEntry) -> Result<bool, Error> {
+    let header_len = stored_header_len(pack, entry)?;
+    if header_len == 0 {
+        return Ok(true);
+    }
+
+    let mut buf = [0u8; io_packs::ENTRY_HEADER_LEN];
+    pack.seek(SeekFrom::Start(entry.offset - header_len))?;
+    pack.read_exact(&mut buf)?;
+    let Some(header) = EntryHeader::from_bytes(&buf) else {
+        return Ok(false);
+    };
+    let mut rdr = CrcReader::new(pack.take(entry.size));
+    io::copy(&mut rdr, &mut io::sink())?;
+    Ok(rdr.crc().sum() == header.crc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    use crate::expiry;
+    use crate::io_loose::insert as loose_insert;
+    use crate::io_packs::extract as packs_extract;
+    use crate::stat;
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    #[test]
+    fn pack_loose_default() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        let n = 200;
+
+        // add 10 obj to loose
+        let mut hash_content_map: HashMap<String, String> = HashMap::new();
+        for i in 0..n {
+            let content = format!("test {i:03}"); // 8 bytes each
+            let buf = content.clone().into_bytes();
+            let hash = loose_insert(buf, &cnt).unwrap().hashkey;
+            hash_content_map.insert(hash, content);
+        }
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.loose, n);
+
+        pack_loose(&cnt).unwrap();
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.packs, n);
+        assert_eq!(info.count.packs_file, n * 8 / 1024 + 1);
+
+        // read from packs
+        for (hash, content) in hash_content_map {
+            let obj = packs_extract(&hash, &cnt).unwrap().unwrap();
+            assert_eq!(String::from_utf8(obj.try_into().unwrap()).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn pack_and_clean_loose_dry_run() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        for i in 0..20 {
+            loose_insert(format!("test {i:03}").into_bytes(), &cnt).unwrap();
+        }
+
+        let report = _pack_loose_internal(
+            &cnt,
+            &Compression::Uncompressed,
+            None,
+            true,
+            &(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.objects_packed, 20);
+        assert_eq!(report.bytes_read, 20 * 8);
+        assert_eq!((report.bytes_written, report.packs_created), (0, 0));
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.packs, 0);
+
+        let report = pack_loose(&cnt).unwrap();
+        assert_eq!(report.objects_packed, 20);
+        assert_eq!(report.bytes_read, 20 * 8);
+        assert_eq!(report.bytes_written, 20 * 8);
+        // 160 bytes in packs of 1024 bytes
+        assert_eq!(report.packs_created, 1);
+        assert_eq!(report.skipped_already_packed, 0);
+
+        // nothing left to pack
+        let report = pack_loose(&cnt).unwrap();
+        assert_eq!(
+            (report.objects_packed, report.skipped_already_packed),
+            (0, 20)
+        );
+
+        // packed objects are still in loose until cleaned
+        let report = clean_loose(&cnt, true).unwrap();
+        assert_eq!(report.objects, 20);
+        assert_eq!(stat(&cnt).unwrap().count.loose, 20);
+
+        let report = clean_loose(&cnt, false).unwrap();
+        assert_eq!(report.objects, 20);
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.loose, 0);
+        assert_eq!(info.count.packs, 20);
+        assert_eq!(cnt.loose_counters().unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn pack_loose_skip_packed_batches() {
+        // more loose objects than hashkeys queried at once, half of them already in packs
+        let n = EXTRACT_BATCH_SIZE + 100;
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let content = |i: usize| format!("test {i:05}").into_bytes();
+        crate::io_packs::insert_many((0..n).step_by(2).map(content), &cnt).unwrap();
+        for i in 0..n {
+            loose_insert(content(i), &cnt).unwrap();
+        }
+
+        let report = pack_loose(&cnt).unwrap();
+        assert_eq!(report.skipped_already_packed, n.div_ceil(2) as u64);
+        assert_eq!(report.objects_packed, (n / 2) as u64);
+
+        let report = clean_loose(&cnt, false).unwrap();
+        assert_eq!(report.objects, n as u64);
+        let info = stat(&cnt).unwrap();
+        assert_eq!((info.count.loose, info.count.packs), (0, n as u64));
+    }
+
+    #[test]
+    fn pack_loose_throttled() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        for i in 0..10 {
+            loose_insert(format!("test {i:03}").into_bytes(), &cnt).unwrap();
+        }
+        let sources = traverse_loose(&cnt)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // two objects of 8 bytes a run
+        let runs = throttled_runs(&sources, Some(&IoThrottle::new(20)));
+        assert_eq!(runs.len(), 5);
+        assert!(runs
+            .iter()
+            .all(|(run, bytes)| run.len() == 2 && *bytes == 16));
+        assert_eq!(throttled_runs(&sources, Some(&IoThrottle::new(0))).len(), 1);
+
+        let throttle = IoThrottle::new(1000);
+        let report = _pack_loose_internal(
+            &cnt,
+            &Compression::Uncompressed,
+            None,
+            false,
+            &(),
+            Some(&throttle),
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.objects_packed, 10);
+        let report = validate_with_progress(&cnt, &(), Some(&throttle), None).unwrap();
+        assert_eq!((report.loose_checked, report.packs_checked), (10, 10));
+        assert!(report.is_valid());
+    }
+
+    #[test]
+    fn pack_loose_cancelled() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        for i in 0..10 {
+            loose_insert(format!("test {i:03}").into_bytes(), &cnt).unwrap();
+        }
+
+        struct CancelAt(CancellationToken);
+        impl ProgressObserver for CancelAt {
+            fn progress(&self, _done: u64, _total: u64) {
+                self.0.cancel();
+            }
+        }
+        // cancelled as soon as packing starts, nothing is written
+        let cancel = CancellationToken::new();
+        let report = _pack_loose_internal(
+            &cnt,
+            &Compression::Uncompressed,
+            None,
+            false,
+            &CancelAt(cancel.clone()),
+            None,
+            Some(&cancel),
+        )
+        .unwrap();
+        assert!(report.interrupted);
+        assert_eq!(report.objects_packed, 0);
+        assert_eq!(stat(&cnt).unwrap().count.packs, 0);
+
+        let report = pack_loose(&cnt).unwrap();
+        assert!(!report.interrupted);
+        assert_eq!(report.objects_packed, 10);
+    }
+
+    #[test]
+    fn pack_loose_older_than() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        let day = Duration::from_secs(24 * 3600);
+        let mut old = Vec::new();
+        for i in 0..10 {
+            let hashkey = loose_insert(format!("test {i:03}").into_bytes(), &cnt)
+                .unwrap()
+                .hashkey;
+            if i % 2 == 0 {
+                let path = cnt.loose().join(&hashkey[..2]).join(&hashkey[2..]);
+                fs::File::options()
+                    .write(true)
+                    .open(path)
+                    .unwrap()
+                    .set_modified(SystemTime::now() - 2 * day)
+                    .unwrap();
+                old.push(hashkey);
+            }
+        }
+
+        let report = _pack_loose_internal(
+            &cnt,
+            &Compression::Uncompressed,
+            Some(day),
+            true,
+            &(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!((report.objects_packed, report.bytes_read), (5, 5 * 8));
+        let report = _pack_loose_internal(
+            &cnt,
+            &Compression::Uncompressed,
+            Some(day),
+            false,
+            &(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.objects_packed, 5);
+
+        let conn = cnt.db().unwrap();
+        let mut packed: Vec<_> = packed_hashkeys(&conn).unwrap().into_iter().collect();
+        packed.sort();
+        old.sort();
+        assert_eq!(packed, old);
+    }
+
+    #[test]
+    fn clean_sandbox_stale_tmp() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        let hour = Duration::from_secs(3600);
+        for name in ["crashed.tmp", "other"] {
+            let mut f = fs::File::create(cnt.sandbox().join(name)).unwrap();
+            f.write_all(b"test 0").unwrap();
+            f.set_modified(std::time::SystemTime::now() - hour).unwrap();
+        }
+
+        let report = clean_sandbox(&cnt, 2 * hour).unwrap();
+        assert_eq!((report.objects, report.bytes), (0, 0));
+
+        let report = clean_sandbox(&cnt, hour / 2).unwrap();
+        assert_eq!((report.objects, report.bytes), (1, 6));
+        assert!(!cnt.sandbox().join("crashed.tmp").exists());
+        assert!(cnt.sandbox().join("other").exists());
+    }
+
+    #[test]
+    fn recount_drifted_counters() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        for i in 0..10 {
+            loose_insert(format!("test {i:03}").into_bytes(), &cnt).unwrap();
+        }
+        assert_eq!(cnt.loose_counters().unwrap(), (10, 10 * 8));
+
+        // a loose object removed behind the back of the container
+        let path = traverse_loose(&cnt).unwrap().next().unwrap().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(cnt.loose_counters().unwrap(), (10, 10 * 8));
+
+        let report = recount(&cnt).unwrap();
+        assert_eq!((report.objects, report.bytes), (9, 9 * 8));
+        assert_eq!(cnt.loose_counters().unwrap(), (9, 9 * 8));
+    }
+
+    /// Insert twenty objects to packs and drop ten of them from the index, interleaved.
+    fn drop_after_insert(cnt: &Container) {
+        let sources: Vec<_> = (0..10)
+            .flat_map(|i| [format!("test {i}"), format!("dead {i}")])
+            .map(|c| c.repeat(4).into_bytes())
+            .collect();
+        let written = crate::io_packs::insert_many(sources, cnt).unwrap();
+        let conn = cnt.db().unwrap();
+        for inserted in written.iter().skip(1).step_by(2) {
+            conn.execute(
+                "DELETE FROM db_object WHERE hashkey = ?1",
+                params![inserted.hashkey],
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn repack_drop_dead_bytes() {
+        let (_tmp_dir, cnt) = new_container(64, "zlib:+1");
+
+        // ten objects dropped from the index, as a delete would, leave dead bytes
+        drop_after_insert(&cnt);
+
+        let before = stat(&cnt).unwrap();
+        let report = repack(&cnt, true).unwrap();
+        assert!(report.bytes > 0);
+        assert_eq!(stat(&cnt).unwrap().size.packs_file, before.size.packs_file);
+
+        let dry_run = report;
+        let report = repack(&cnt, false).unwrap();
+        assert_eq!(report.objects, dry_run.objects);
+        assert_eq!(report.bytes, dry_run.bytes);
+
+        let after = stat(&cnt).unwrap();
+        assert_eq!(after.size.packs_file, before.size.packs_file - report.bytes);
+        assert_eq!(after.count.packs, 10);
+        assert!(validate(&cnt).unwrap().is_valid());
+
+        // the rewritten packs come after the pack that was current, it is sealed now and
+        // rewritten by the next repack
+        repack(&cnt, false).unwrap();
+        assert!(validate(&cnt).unwrap().is_valid());
+        assert_eq!(repack(&cnt, true).unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn maintenance_cancelled() {
+        let (_tmp_dir, cnt) = new_container(64, "zlib:+1");
+        drop_after_insert(&cnt);
+        let (_dst_dir, dst) = new_container(64, "none");
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let before = stat(&cnt).unwrap();
+        let report = repack_with(
+            &cnt,
+            RepackStrategy::Preserve,
+            None,
+            false,
+            None,
+            Some(&cancel),
+        )
+        .unwrap();
+        assert!(report.interrupted);
+        assert_eq!((report.objects, report.bytes), (0, 0));
+        assert_eq!(stat(&cnt).unwrap().size.packs_file, before.size.packs_file);
+
+        let report = validate_with_progress(&cnt, &(), None, Some(&cancel)).unwrap();
+        assert!(report.interrupted);
+        assert_eq!(report.packs_checked, 0);
+
+        let report = sync(&cnt, &dst, false, Some(&cancel)).unwrap();
+        assert!(report.interrupted);
+        assert_eq!(stat(&dst).unwrap().count.packs, 0);
+
+        // not cancelled, everything is done
+        let report = repack(&cnt, false).unwrap();
+        assert!(!report.interrupted && report.bytes > 0);
+        let report = validate(&cnt).unwrap();
+        assert!(!report.interrupted && report.is_valid());
+        assert_eq!(report.packs_checked, 10);
+    }
+
+    #[test]
+    fn gc_namespace_keeps_shared_objects() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let shared = cnt
+            .insert_to_namespace(b"shared".to_vec(), "raw")
+            .unwrap()
+            .hashkey;
+        let packed = cnt
+            .insert_to_namespace(b"packed raw".to_vec(), "raw")
+            .unwrap()
+            .hashkey;
+        cnt.insert_to_namespace(b"shared".to_vec(), "processed")
+            .unwrap();
+        pack_loose(&cnt).unwrap();
+        clean_loose(&cnt, false).unwrap();
+        let raw = cnt
+            .insert_to_namespace(b"raw".to_vec(), "raw")
+            .unwrap()
+            .hashkey;
+        let untagged = loose_insert(b"untagged".to_vec(), &cnt).unwrap().hashkey;
+
+        let report = gc_namespace(&cnt, "raw", true).unwrap();
+        assert_eq!((report.objects, report.bytes), (2, 13));
+        assert!(cnt.has_object(&raw).unwrap());
+
+        let report = gc_namespace(&cnt, "raw", false).unwrap();
+        assert_eq!((report.objects, report.bytes), (2, 13));
+        assert!(!cnt.has_object(&raw).unwrap());
+        assert!(!cnt.has_object(&packed).unwrap());
+        assert!(cnt.has_object(&shared).unwrap());
+        assert!(cnt.has_object(&untagged).unwrap());
+        assert!(namespace::list(&cnt, "raw").unwrap().is_empty());
+        assert_eq!(namespace::list(&cnt, "processed").unwrap(), vec![shared]);
+        assert_eq!(cnt.loose_counters().unwrap(), (1, 8));
+    }
+
+    #[test]
+    fn gc_namespace_keeps_objects_inserted_otherwise() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        // the same content added with and without a namespace, in both orders
+        let before = cnt.insert(b"plain first".to_vec()).unwrap().hashkey;
+        cnt.insert_to_namespace(b"plain first".to_vec(), "raw")
+            .unwrap();
+        let after = cnt
+            .insert_to_namespace(b"plain after".to_vec(), "raw")
+            .unwrap()
+            .hashkey;
+        io_packs::insert(b"plain after".to_vec(), &cnt).unwrap();
+        // tagged once inserted
+        let tagged = cnt.insert(b"tagged".to_vec()).unwrap().hashkey;
+        namespace::tag(&cnt, "raw", &[&tagged]).unwrap();
+        // the manifest and a chunk of a chunked object
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let content: ByteString = (0..1_000_000).map(|_| rng.gen()).collect();
+        let chunked = chunked::insert(content.clone(), &cnt).unwrap().hashkey;
+        let obj = chunked::extract(&chunked, &cnt).unwrap().unwrap();
+        for hashkey in [&obj.manifest, &obj.chunks[0]] {
+            let part = ByteString::try_from(cnt.get(hashkey).unwrap().unwrap()).unwrap();
+            cnt.insert_to_namespace(part, "raw").unwrap();
+        }
+        let only = cnt
+            .insert_to_namespace(b"raw only".to_vec(), "raw")
+            .unwrap()
+            .hashkey;
+
+        let report = gc_namespace(&cnt, "raw", false).unwrap();
+        assert_eq!((report.objects, report.bytes), (1, 8));
+        assert!(!cnt.has_object(&only).unwrap());
+        for hashkey in [&before, &after, &tagged, &obj.manifest, &obj.chunks[0]] {
+            assert!(cnt.has_object(hashkey).unwrap(), "{hashkey} deleted");
+        }
+        let mut buf = vec![];
+        obj.reader(&cnt).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+    }
+
+    #[test]
+    fn expire_objects() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let packed = loose_insert(b"packed".to_vec(), &cnt).unwrap().hashkey;
+        pack_loose(&cnt).unwrap();
+        clean_loose(&cnt, false).unwrap();
+        let loose = loose_insert(b"loose".to_vec(), &cnt).unwrap().hashkey;
+        let kept = loose_insert(b"kept".to_vec(), &cnt).unwrap().hashkey;
+        let later = loose_insert(b"later".to_vec(), &cnt).unwrap().hashkey;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let content: ByteString = (0..1_000_000).map(|_| rng.gen()).collect();
+        let chunked = chunked::insert(content.clone(), &cnt).unwrap().hashkey;
+        let obj = chunked::extract(&chunked, &cnt).unwrap().unwrap();
+
+        let now = SystemTime::now();
+        expiry::set_expiry(&cnt, &[&packed, &loose], now).unwrap();
+        expiry::set_expiry(&cnt, &[&obj.manifest, &obj.chunks[0]], now).unwrap();
+        expiry::set_expiry(&cnt, &[&later], now + Duration::from_secs(3600)).unwrap();
+
+        let report = expire(&cnt, now).unwrap();
+        assert_eq!((report.objects, report.bytes), (2, 11));
+        assert!(!cnt.has_object(&packed).unwrap());
+        assert!(!cnt.has_object(&loose).unwrap());
+        assert!(cnt.has_object(&kept).unwrap());
+        assert!(cnt.has_object(&later).unwrap());
+        assert_eq!(expiry::expiry(&cnt, &packed).unwrap(), None);
+        let mut buf = vec![];
+        obj.reader(&cnt).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+
+        // nothing more expired
+        assert_eq!(expire(&cnt, now).unwrap().objects, 0);
+        // tombstoned, its bytes stay in the pack until it is pruned
+        assert_eq!(cnt.index().unwrap().stats().unwrap().count, 0);
+    }
+
+    #[test]
+    fn maintenance_exclusive() {
+        let (_tmp_dir, cnt) = new_container(64, "none");
+        loose_insert(b"test".to_vec(), &cnt).unwrap();
+        pack_loose(&cnt).unwrap();
+
+        let lock = MaintenanceLock::acquire(&cnt, "repack").unwrap();
+        assert!(matches!(
+            clean_loose(&cnt, false),
+            Err(Error::MaintenanceInProgress { .. })
+        ));
+        assert!(matches!(
+            prune_packs(&cnt, 0.5, false),
+            Err(Error::MaintenanceInProgress { .. })
+        ));
+        // a dry run changes nothing, it is not locked out
+        assert_eq!(clean_loose(&cnt, true).unwrap().objects, 1);
+        drop(lock);
+
+        assert_eq!(clean_loose(&cnt, false).unwrap().objects, 1);
+        assert!(!cnt.maintenance_lock().exists());
+    }
+
+    #[test]
+    fn train_dictionary_compress_new_entries() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+3");
+        let json = |i: usize| {
+            format!(
+                r#"{{"node_type": "data.core.dict.Dict.", "uuid": "{i:032x}", "attributes": {{"value": {i}, "label": "sample", "mtime": "2024-01-{:02}"}}}}"#,
+                i % 28 + 1
+            )
+            .into_bytes()
+        };
+        let before = crate::io_packs::insert(json(100_000), &cnt).unwrap();
+        crate::io_packs::insert_many((0..500).map(json), &cnt).unwrap();
+        for i in 500..600 {
+            loose_insert(json(i), &cnt).unwrap();
+        }
+
+        let trained = train_dictionary(&cnt, 400, 2048).unwrap();
+        assert_eq!(trained.samples, 400);
+        assert_eq!(cnt.config().unwrap().zstd_dictionary, Some(trained.id));
+
+        let after = crate::io_packs::insert(json(100_001), &cnt).unwrap();
+        assert!(after.stored_size < before.stored_size);
+        pack_loose(&cnt).unwrap();
+
+        // entries with and without dictionary read back, also through a copy of the container
+        let backup_dir = tempfile::tempdir().unwrap();
+        backup(&cnt, backup_dir.path()).unwrap();
+        for cnt in [&cnt, &Container::open(backup_dir.path()).unwrap()] {
+            for (i, hashkey) in [(100_000, &before.hashkey), (100_001, &after.hashkey)] {
+                let obj = packs_extract(hashkey, cnt).unwrap().unwrap();
+                assert_eq!(ByteString::try_from(obj).unwrap(), json(i));
+            }
+            assert!(validate(cnt).unwrap().is_valid());
+        }
+    }
+
+    #[rstest]
+    fn repack_reorder_within_pack(
+        #[values(RepackStrategy::SortByHash, RepackStrategy::SortBySize)] strategy: RepackStrategy,
+    ) {
+        let (_tmp_dir, cnt) = new_container(4096, "none");
+        let contents: Vec<_> = (0..20).map(|i| "x".repeat(100 - i)).collect();
+        crate::io_packs::insert_many(contents.iter().map(|c| c.clone().into_bytes()), &cnt)
+            .unwrap();
+        // seal it, the current pack is never repacked
+        crate::io_packs::seal_current_pack(&cnt.pack_layout().unwrap()).unwrap();
+
+        // nothing dead, only the order changes
+        let report = repack_with(&cnt, strategy, None, false, None, None).unwrap();
+        assert_eq!((report.objects, report.bytes), (20, 0));
+        assert_eq!(
+            repack_with(&cnt, strategy, None, true, None, None)
+                .unwrap()
+                .objects,
+            0
+        );
+        assert_eq!(repack(&cnt, true).unwrap().objects, 0);
+
+        let mut entries = read_index_entries(&cnt).unwrap();
+        entries.sort_by_key(|e| e.offset);
+        match strategy {
+            RepackStrategy::SortByHash => {
+                assert!(entries.windows(2).all(|w| w[0].hashkey < w[1].hashkey));
+            }
+            _ => assert!(entries.windows(2).all(|w| w[0].raw_size <= w[1].raw_size)),
+        }
+        assert!(validate(&cnt).unwrap().is_valid());
+    }
+
+    #[rstest]
+    fn repack_recompress(
+        #[values("none", "zlib:+1")] from: &str,
+        #[values(false, true)] framed: bool,
+    ) {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, from);
+        let mut config = cnt.config().unwrap();
+        config.pack_entry_header = framed;
+        cnt.write_config(&config).unwrap();
+
+        let mut contents: Vec<_> = (0..10)
+            .map(|i| format!("line {i} of a text worth compressing\n").repeat(100))
+            .collect();
+        contents.push("small".to_string());
+        crate::io_packs::insert_many(contents.iter().map(|c| c.clone().into_bytes()), &cnt)
+            .unwrap();
+        crate::io_packs::seal_current_pack(&cnt.pack_layout().unwrap()).unwrap();
+
+        // switch to zstd, only the large texts are worth rewriting
+        config.compression_algorithm = "zstd:+3".to_string();
+        cnt.write_config(&config).unwrap();
+        let zstd = Compression::Zstd(3);
+        let dry_run = repack_with(
+            &cnt,
+            RepackStrategy::Preserve,
+            Some(&zstd),
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!((dry_run.objects, dry_run.bytes), (11, 0));
+
+        let before = stat(&cnt).unwrap().size.packs_file;
+        let report = repack_with(
+            &cnt,
+            RepackStrategy::Preserve,
+            Some(&zstd),
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.objects, 11);
+        assert_eq!(stat(&cnt).unwrap().size.packs_file, before - report.bytes);
+        assert!(report.bytes > 0);
+
+        let conn = cnt.db().unwrap();
+        for content in &contents {
+            let hashkey = hex::encode(digest::digest(&digest::SHA256, content.as_bytes()));
+            let entry = db::select(&conn, &hashkey).unwrap().unwrap();
+            let obj = PObject::from_entry(&entry, cnt.packs().join(format!("{}", entry.pack_id)));
+            let expected = if content.len() > 850 { "zstd" } else { "none" };
+            assert_eq!(obj.compression().unwrap(), expected);
+            assert_eq!(&ByteString::try_from(obj).unwrap(), content.as_bytes());
+        }
+        assert!(validate(&cnt).unwrap().is_valid());
+
+        // everything is already stored as the policy picks
+        let again = repack_with(
+            &cnt,
+            RepackStrategy::Preserve,
+            Some(&zstd),
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(again.objects, 0);
+    }
+
+    #[test]
+    fn parse_repack_strategy() {
+        assert_eq!(
+            RepackStrategy::from_str("sort-by-size").unwrap(),
+            RepackStrategy::SortBySize
+        );
+        assert!(matches!(
+            RepackStrategy::from_str("random"),
+            Err(Error::ParseRepackStrategyError { .. })
+        ));
+    }
+
+    #[test]
+    fn fragmentation_dead_bytes() {
+        let (_tmp_dir, cnt) = new_container(64, "none");
+        let contents: Vec<_> = (0..6).map(|i| format!("test {i}").repeat(4)).collect();
+        let sources: Vec<_> = contents.iter().map(|c| c.clone().into_bytes()).collect();
+        let written = crate::io_packs::insert_many(sources, &cnt).unwrap();
+        assert!(fragmentation(&cnt)
+            .unwrap()
+            .iter()
+            .all(|pack| pack.dead_bytes == 0 && pack.live_bytes == pack.file_size));
+
+        // a deleted object and a pack left with no entry at all
+        let conn = cnt.db().unwrap();
+        let deleted = db::select(&conn, &written[1].hashkey).unwrap().unwrap();
+        conn.execute(
+            "DELETE FROM db_object WHERE hashkey = ?1",
+            params![deleted.hashkey],
+        )
+        .unwrap();
+        fs::write(cnt.packs().join("50"), b"orphan").unwrap();
+
+        let packs = fragmentation(&cnt).unwrap();
+        let pack = packs
+            .iter()
+            .find(|pack| pack.pack_id == deleted.pack_id)
+            .unwrap();
+        assert_eq!(pack.dead_bytes, deleted.size);
+        let orphan = packs.last().unwrap();
+        assert_eq!((orphan.pack_id, orphan.dead_bytes), (50, 6));
+    }
+
+    #[test]
+    fn prune_sparse_packs() {
+        let (_tmp_dir, cnt) = new_container(64, "zlib:+1");
+
+        let contents: Vec<_> = (0..20).map(|i| format!("test {i}").repeat(4)).collect();
+        let sources: Vec<_> = contents.iter().map(|c| c.clone().into_bytes()).collect();
+        let written = crate::io_packs::insert_many(sources, &cnt).unwrap();
+        let before = stat(&cnt).unwrap();
+        assert!(before.count.packs_file > 2);
+
+        // drop all the entries of pack 0 and all but one of pack 1, as a delete would
+        let conn = cnt.db().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT hashkey FROM db_object WHERE pack_id = 1 ORDER BY offset")
+            .unwrap();
+        let survivor: String = stmt.query_row([], |row| row.get(0)).unwrap();
+        drop(stmt);
+        conn.execute(
+            "DELETE FROM db_object WHERE pack_id IN (0, 1) AND hashkey != ?1",
+            params![survivor],
+        )
+        .unwrap();
+        drop(conn);
+        let n_live = stat(&cnt).unwrap().count.packs;
+
+        let report = prune_packs(&cnt, 0.6, true).unwrap();
+        assert_eq!(report.objects, 1);
+        assert!(cnt.packs().join("0").exists());
+
+        let dry_run = report;
+        let report = prune_packs(&cnt, 0.6, false).unwrap();
+        assert_eq!(report.objects, dry_run.objects);
+        assert_eq!(report.bytes, dry_run.bytes);
+        assert!(!cnt.packs().join("0").exists());
+        assert!(!cnt.packs().join("1").exists());
+
+        let after = stat(&cnt).unwrap();
+        assert_eq!(after.count.packs, n_live);
+        assert_eq!(after.count.packs_file, before.count.packs_file - 2);
+        assert!(validate(&cnt).unwrap().is_valid());
+
+        let content = contents
+            .iter()
+            .zip(&written)
+            .find_map(|(c, inserted)| (inserted.hashkey == survivor).then_some(c))
+            .unwrap();
+        let obj = packs_extract(&survivor, &cnt).unwrap().unwrap();
+        let mut buf = String::new();
+        obj.make_reader().unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(&buf, content);
+        assert_eq!(prune_packs(&cnt, 0.6, true).unwrap().objects, 0);
+    }
+
+    #[test]
+    fn rebalance_to_pack_size_target() {
+        let (_tmp_dir, cnt) = new_container(64, "zlib:+1");
+
+        let contents: Vec<_> = (0..20).map(|i| format!("test {i}").repeat(4)).collect();
+        let sources: Vec<_> = contents.iter().map(|c| c.clone().into_bytes()).collect();
+        let written = crate::io_packs::insert_many(sources, &cnt).unwrap();
+        let before = stat(&cnt).unwrap();
+        assert!(before.count.packs_file > 2);
+        // balanced already
+        assert_eq!(rebalance_packs(&cnt, true).unwrap().objects, 0);
+
+        let read_all = |cnt: &Container| {
+            for (content, inserted) in contents.iter().zip(&written) {
+                let obj = packs_extract(&inserted.hashkey, cnt).unwrap().unwrap();
+                let mut buf = String::new();
+                obj.make_reader().unwrap().read_to_string(&mut buf).unwrap();
+                assert_eq!(&buf, content);
+            }
+            assert!(validate(cnt).unwrap().is_valid());
+        };
+
+        // smaller target, the packs of several objects are split
+        cnt.update_config(|config| {
+            config.pack_size_target = 16;
+            Ok(())
+        })
+        .unwrap();
+        let dry_run = rebalance_packs(&cnt, true).unwrap();
+        assert!(dry_run.objects > 0);
+        assert_eq!(
+            stat(&cnt).unwrap().count.packs_file,
+            before.count.packs_file
+        );
+        let report = rebalance_packs(&cnt, false).unwrap();
+        assert_eq!(report.objects, dry_run.objects);
+        assert_eq!(report.bytes, dry_run.bytes);
+        let split = stat(&cnt).unwrap();
+        assert!(split.count.packs_file > before.count.packs_file);
+        assert_eq!(split.count.packs, before.count.packs);
+        read_all(&cnt);
+
+        // larger target, the packs are merged into the current one
+        cnt.update_config(|config| {
+            config.pack_size_target = 1024 * 1024;
+            Ok(())
+        })
+        .unwrap();
+        rebalance_packs(&cnt, false).unwrap();
+        let merged = stat(&cnt).unwrap();
+        assert_eq!(merged.count.packs_file, 1);
+        assert_eq!(merged.count.packs, before.count.packs);
+        read_all(&cnt);
+        assert_eq!(rebalance_packs(&cnt, true).unwrap().objects, 0);
+    }
+
+    #[test]
+    fn offload_sealed_packs() {
+        let (_tmp_dir, cnt) = new_container(64, "zstd:+1");
+        let storage_dir = tempfile::tempdir().unwrap();
+        assert!(offload_packs(&cnt, true).is_err());
+
+        let mut config = cnt.config().unwrap();
+        config.pack_storage_url = Some(format!("file://{}", storage_dir.path().display()));
+        cnt.write_config(&config).unwrap();
+
+        let contents: Vec<_> = (0..20).map(|i| format!("test {i}").repeat(4)).collect();
+        let sources: Vec<_> = contents.iter().map(|c| c.clone().into_bytes()).collect();
+        let written = crate::io_packs::insert_many(sources, &cnt).unwrap();
+        let n_packs = stat(&cnt).unwrap().count.packs_file;
+        assert!(n_packs > 2);
+
+        let dry_run = offload_packs(&cnt, true).unwrap();
+        assert!(cnt.packs().join("0").exists());
+        let report = offload_packs(&cnt, false).unwrap();
+        assert_eq!(report.objects, dry_run.objects);
+        assert_eq!(report.bytes, dry_run.bytes);
+        assert!(!cnt.packs().join("0").exists());
+        assert!(storage_dir.path().join("0").exists());
+        assert_eq!(stat(&cnt).unwrap().count.packs_file, 1);
+
+        for (content, inserted) in contents.iter().zip(&written) {
+            let obj = packs_extract(&inserted.hashkey, &cnt).unwrap().unwrap();
+            let mut buf = String::new();
+            obj.make_reader().unwrap().read_to_string(&mut buf).unwrap();
+            assert_eq!(&buf, content);
+        }
+        assert!(validate(&cnt).unwrap().is_valid());
+        assert!(rebuild_index(&cnt).is_err());
+        assert_eq!(offload_packs(&cnt, true).unwrap().objects, 0);
+    }
+
+    #[test]
+    fn sync_missing_objects() {
+        let (_src_dir, src) = new_container(1024, "zlib:+1");
+        let (_dst_dir, dst) = new_container(1024, "none");
+
+        let shared = loose_insert(b"shared".to_vec(), &src).unwrap().hashkey;
+        loose_insert(b"shared".to_vec(), &dst).unwrap();
+        let packed = loose_insert(b"packed".to_vec(), &src).unwrap().hashkey;
+        pack_loose(&src).unwrap();
+        clean_loose(&src, false).unwrap();
+        let loose = loose_insert(b"loose".to_vec(), &src).unwrap().hashkey;
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let chunked = crate::chunked::insert(content.clone(), &src)
+            .unwrap()
+            .hashkey;
+
+        let dry_run = sync(&src, &dst, true, None).unwrap();
+        assert!(crate::io_loose::extract(&packed, &dst).unwrap().is_none());
+        let report = sync(&src, &dst, false, None).unwrap();
+        assert_eq!(report.objects, dry_run.objects);
+        assert_eq!(report.bytes, dry_run.bytes);
+        assert!(report.objects >= 4);
+
+        for (hashkey, expected) in [
+            (&shared, &b"shared"[..]),
+            (&packed, b"packed"),
+            (&loose, b"loose"),
+        ] {
+            let obj = crate::io_loose::extract(hashkey, &dst).unwrap().unwrap();
+            let mut buf = vec![];
+            obj.make_reader().unwrap().read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, expected);
+        }
+        let obj = crate::chunked::extract(&chunked, &dst).unwrap().unwrap();
+        let mut buf = vec![];
+        obj.reader(&dst).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+        assert_eq!(sync(&src, &dst, false, None).unwrap().objects, 0);
+
+        // a corrupted object is refused and not kept in the destination
+        let corrupted = loose_insert(b"corrupted".to_vec(), &src).unwrap().hashkey;
+        fs::write(
+            src.loose().join(&corrupted[..2]).join(&corrupted[2..]),
+            b"c0rrupted",
+        )
+        .unwrap();
+        assert!(matches!(
+            sync(&src, &dst, false, None),
+            Err(Error::IntegrityError { .. })
+        ));
+        assert_eq!(
+            stat(&dst).unwrap().count.loose,
+            stat(&src).unwrap().count.loose + 1
+        );
+        assert!(validate(&dst).unwrap().is_valid());
+    }
+
+    #[test]
+    fn backup_while_writing() {
+        let (_tmp_dir, cnt) = new_container(1024, "zlib:+1");
+        let backup_dir = tempfile::tempdir().unwrap();
+        let dest = backup_dir.path().join("backup");
+
+        let mut hashkeys = vec![];
+        for i in 0..50 {
+            let hash = loose_insert(format!("object {i}").into_bytes(), &cnt)
+                .unwrap()
+                .hashkey;
+            hashkeys.push(hash);
+        }
+        pack_loose(&cnt).unwrap();
+        clean_loose(&cnt, false).unwrap();
+        let loose = loose_insert(b"loose".to_vec(), &cnt).unwrap().hashkey;
+        hashkeys.push(loose);
+
+        // keep packing into the container while it is backed up
+        let writer = {
+            let path = cnt.path.clone();
+            std::thread::spawn(move || {
+                let cnt = Container::new(path);
+                for i in 0..50 {
+                    let content = format!("concurrent {i}").into_bytes();
+                    crate::io_packs::insert(content, &cnt).unwrap();
+                }
+            })
+        };
+        let report = backup(&cnt, &dest).unwrap();
+        writer.join().unwrap();
+        assert!(report.objects >= hashkeys.len() as u64);
+
+        let copy = Container::open(&dest).unwrap();
+        assert!(validate(&copy).unwrap().is_valid());
+        for hash in &hashkeys {
+            assert!(verify(&copy, hash).unwrap().unwrap().is_valid());
+        }
+        assert_eq!(
+            copy.config().unwrap().container_id,
+            cnt.config().unwrap().container_id
+        );
+
+        // refuse to overwrite a container
+        assert!(backup(&cnt, &dest).is_err());
+    }
+
+    #[test]
+    fn validate_report_corrupted_objects() {
+        let (_tmp_dir, cnt) = new_container(1024, "zlib:+1");
+
+        let mut loose_hashes = vec![];
+        for i in 0..10 {
+            let hash = loose_insert(format!("loose {i}").into_bytes(), &cnt)
+                .unwrap()
+                .hashkey;
+            loose_hashes.push(hash);
+        }
+        for i in 0..10 {
+            crate::io_packs::insert(format!("packed {i}").into_bytes(), &cnt).unwrap();
+        }
+
+        let report = validate(&cnt).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.loose_checked, 10);
+        assert_eq!(report.packs_checked, 10);
+
+        // corrupt one loose object and the first packed object
+        let corrupted = &loose_hashes[3];
+        let loc = cnt.loose().join(&corrupted[..2]).join(&corrupted[2..]);
+        fs::write(loc, "corrupted").unwrap();
+        let pack0 = cnt.packs().join("0");
+        let mut bytes = fs::read(&pack0).unwrap();
+        bytes[0] ^= 0xFF;
+        fs::write(&pack0, bytes).unwrap();
+
+        let report = validate(&cnt).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.invalid_loose, vec![corrupted.clone()]);
+        assert_eq!(report.invalid_packs.len(), 1);
+    }
+
+    #[test]
+    fn verify_single_object() {
+        let (_tmp_dir, cnt) = new_container(1024, "zlib:+1");
+        let mut config = cnt.config().unwrap();
+        config.pack_entry_header = true;
+        cnt.write_config(&config).unwrap();
+
+        let loose_hash = loose_insert(b"loose".to_vec(), &cnt).unwrap().hashkey;
+        let packed_hash = crate::io_packs::insert(b"packed".to_vec(), &cnt)
+            .unwrap()
+            .hashkey;
+        crate::io_packs::insert(b"loose".to_vec(), &cnt).unwrap();
+
+        let v = verify(&cnt, &loose_hash).unwrap().unwrap();
+        assert_eq!((v.loose, v.packs), (Some(true), Some(true)));
+        let v = verify(&cnt, &packed_hash).unwrap().unwrap();
+        assert_eq!((v.loose, v.packs), (None, Some(true)));
+        assert!(verify(&cnt, &"0".repeat(64)).unwrap().is_none());
+        assert!(verify(&cnt, "not a hashkey").unwrap().is_none());
+
+        // the content still hashes right, only the CRC32 in the header is off
+        let pack0 = cnt.packs().join("0");
+        let mut bytes = fs::read(&pack0).unwrap();
+        bytes[24] ^= 0xFF;
+        fs::write(&pack0, bytes).unwrap();
+
+        let v = verify(&cnt, &packed_hash).unwrap().unwrap();
+        assert!(!v.is_valid());
+        assert!(verify(&cnt, &loose_hash).unwrap().unwrap().is_valid());
+    }
+
+    #[test]
+    fn check_index_anomalies() {
+        let (_tmp_dir, cnt) = new_container(64, "none");
+        let contents: Vec<_> = (0..12).map(|i| format!("test {i}").repeat(4)).collect();
+        let sources: Vec<_> = contents.iter().map(|c| c.clone().into_bytes()).collect();
+        let written = crate::io_packs::insert_many(sources, &cnt).unwrap();
+
+        let report = check_index(&cnt).unwrap();
+        assert!(report.is_consistent(), "{:?}", report.anomalies);
+        assert_eq!(report.entries_checked, 12);
+        assert!(report.packs_checked > 2);
+
+        let conn = cnt.db().unwrap();
+        let entry = |hashkey: &str| db::select(&conn, hashkey).unwrap().unwrap();
+        let (lost, cut, shifted) = (
+            &written[0].hashkey,
+            &written[3].hashkey,
+            &written[7].hashkey,
+        );
+        let (lost_pack, cut_pack) = (entry(lost).pack_id, entry(cut).pack_id);
+        let shifted_entry = entry(shifted);
+        assert_ne!(lost_pack, cut_pack);
+        // the other entries of the pack of ``lost`` go with it
+        conn.execute(
+            "UPDATE db_object SET pack_id = 99 WHERE pack_id = ?1",
+            params![lost_pack],
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE db_object SET length = 10000 WHERE hashkey = ?1",
+            params![cut],
+        )
+        .unwrap();
+        // one byte into the entry before it
+        assert!(shifted_entry.offset > 0);
+        conn.execute(
+            "UPDATE db_object SET offset = offset - 1 WHERE hashkey = ?1",
+            params![shifted],
+        )
+        .unwrap();
+
+        let report = check_index(&cnt).unwrap();
+        assert!(!report.is_consistent());
+        assert!(report.anomalies.contains(&IndexAnomaly::MissingPack {
+            hashkey: lost.clone(),
+            pack_id: 99,
+        }));
+        assert!(report.anomalies.iter().any(|a| matches!(
+            a,
+            IndexAnomaly::OutOfRange { hashkey, length: 10000, .. } if hashkey == cut
+        )));
+        assert!(report.anomalies.iter().any(|a| matches!(
+            a,
+            IndexAnomaly::Overlap { hashkey, offset, other_end, .. }
+                if hashkey == shifted && *other_end == *offset + 1
+        )));
+        // checking changes nothing
+        assert_eq!(check_index(&cnt).unwrap().anomalies, report.anomalies);
+    }
+
+    #[test]
+    fn rebuild_index_drop_corrupted_entry() {
+        let (_tmp_dir, cnt) = new_container(1024, "zstd:+3");
+
+        let mut hash_content_map: HashMap<String, String> = HashMap::new();
+        for i in 0..20 {
+            let content = format!("test {i}").repeat(i + 1);
+            let hash = crate::io_packs::insert(content.clone().into_bytes(), &cnt)
+                .unwrap()
+                .hashkey;
+            hash_content_map.insert(hash, content);
+        }
+
+        // corrupt the first byte of the first object in pack 0
+        let pack0 = cnt.packs().join("0");
+        let mut bytes = fs::read(&pack0).unwrap();
+        bytes[0] ^= 0xFF;
+        fs::write(&pack0, bytes).unwrap();
+
+        let report = rebuild_index(&cnt).unwrap();
+        assert!(report.index_readable);
+        assert_eq!(report.entries_kept, 19);
+        assert_eq!(report.entries_dropped, 1);
+        assert!(report.unreferenced_bytes > 0);
+
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.packs, 19);
+        for (hash, content) in hash_content_map {
+            if let Some(obj) = packs_extract(&hash, &cnt).unwrap() {
+                assert_eq!(String::from_utf8(obj.try_into().unwrap()).unwrap(), content);
+            }
+        }
+    }
+
+    #[test]
+    fn rebuild_index_from_unreadable_index() {
+        let (_tmp_dir, cnt) = new_container(1024, "none");
+        crate::io_packs::insert(b"test 0".to_vec(), &cnt).unwrap();
+
+        fs::write(cnt.packs_db(), b"not a sqlite db").unwrap();
+
+        let report = rebuild_index(&cnt).unwrap();
+        assert!(!report.index_readable);
+        assert_eq!(report.entries_kept, 0);
+        assert_eq!(report.unreferenced_bytes, 6);
+
+        // container is usable again
+        let info = stat(&cnt).unwrap();
+        assert_eq!(info.count.packs, 0);
+        crate::io_packs::insert(b"test 1".to_vec(), &cnt).unwrap();
+    }
+
+    #[rstest::rstest]
+    #[case("zlib:+1")]
+    #[case("zstd:+3")]
+    fn rebuild_index_recover_compressed_entries(#[case] algo: &str) {
+        let (_tmp_dir, cnt) = new_container(256, algo);
+
+        // objects above 850 bytes are compressed, smaller ones are stored as they are
+        let mut compressed: HashMap<String, String> = HashMap::new();
+        let mut stored = vec![];
+        for i in 0..20 {
+            let repeat = if i % 4 == 0 { 1 } else { 200 };
+            let content = format!("test {i}").repeat(repeat);
+            let hash = crate::io_packs::insert(content.clone().into_bytes(), &cnt)
+                .unwrap()
+                .hashkey;
+            if repeat == 1 {
+                stored.push((hash, content.len() as u64));
+            } else {
+                compressed.insert(hash, content);
+            }
+        }
+        assert!(stat(&cnt).unwrap().count.packs_file > 1);
+
+        // index lost completely
+        fs::remove_file(cnt.packs_db()).unwrap();
+
+        let report = rebuild_index(&cnt).unwrap();
+        assert!(!report.index_readable);
+        assert_eq!(report.entries_kept, 0);
+        assert_eq!(report.entries_recovered, 15);
+        // only the uncompressed payloads cannot be told apart
+        assert_eq!(
+            report.unreferenced_bytes,
+            stored.iter().map(|(_, n)| n).sum::<u64>()
+        );
+
+        for (hash, content) in compressed {
+            let obj = packs_extract(&hash, &cnt).unwrap().unwrap();
+            assert_eq!(String::from_utf8(obj.try_into().unwrap()).unwrap(), content);
+        }
+        for (hash, _) in stored {
+            assert!(packs_extract(&hash, &cnt).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn rebuild_index_recover_framed_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut config = crate::Config::new(256, "zlib:+1");
+        config.pack_entry_header = true;
+        let cnt = Container::new(tmp.path());
+        cnt.initialize(&config).unwrap();
+
+        let mut hash_content_map: HashMap<String, String> = HashMap::new();
+        for i in 0..50 {
+            let content = format!("test {i}").repeat(i + 1);
+            let hash = crate::io_packs::insert(content.clone().into_bytes(), &cnt)
+                .unwrap()
+                .hashkey;
+            hash_content_map.insert(hash, content);
+        }
+        assert!(stat(&cnt).unwrap().count.packs_file > 1);
+
+        // index lost completely
+        fs::remove_file(cnt.packs_db()).unwrap();
+
+        let report = rebuild_index(&cnt).unwrap();
+        assert_eq!(report.entries_kept, 0);
+        assert_eq!(report.entries_recovered, 50);
+        // only the headers are not referenced
+        assert_eq!(
+            report.unreferenced_bytes,
+            50 * crate::io_packs::ENTRY_HEADER_LEN as u64
+        );
+
+        for (hash, content) in hash_content_map {
+            let obj = packs_extract(&hash, &cnt).unwrap().unwrap();
+            assert_eq!(String::from_utf8(obj.try_into().unwrap()).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn rebuild_index_recover_dictionary_entries() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+3");
+        let json = |i: usize| {
+            format!(
+                r#"{{"node_type": "data.core.dict.Dict.", "uuid": "{i:032x}", "attributes": {{"value": {i}, "label": "sample"}}}}"#
+            )
+            .into_bytes()
+        };
+        crate::io_packs::insert_many((0..500).map(json), &cnt).unwrap();
+        train_dictionary(&cnt, 400, 2048).unwrap();
+        let mut compressed = vec![];
+        for i in 500..600 {
+            let inserted = crate::io_packs::insert(json(i), &cnt).unwrap();
+            if inserted.compressed {
+                compressed.push((i, inserted.hashkey));
+            }
+        }
+        assert!(!compressed.is_empty());
+
+        // index lost completely, the frames compressed with the dictionary are still found
+        fs::remove_file(cnt.packs_db()).unwrap();
+        rebuild_index(&cnt).unwrap();
+        for (i, hashkey) in compressed {
+            let obj = packs_extract(&hashkey, &cnt).unwrap().unwrap();
+            assert_eq!(ByteString::try_from(obj).unwrap(), json(i));
+        }
+    }
+
+    #[test]
+    fn rebuild_index_keeps_chunked_objects_namespaces_and_expiry() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let content: ByteString = (0..1_000_000).map(|_| rng.gen()).collect();
+        let chunked = chunked::insert(content.clone(), &cnt).unwrap().hashkey;
+        let raw = cnt
+            .insert_to_namespace(b"raw".to_vec(), "raw")
+            .unwrap()
+            .hashkey;
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        expiry::set_expiry(&cnt, &[&raw], at).unwrap();
+        pack_loose(&cnt).unwrap();
+        clean_loose(&cnt, false).unwrap();
+
+        let report = rebuild_index(&cnt).unwrap();
+        assert!(report.index_readable);
+        // the chunked object, its plain insert, ``raw`` in its namespace and its expiry
+        assert_eq!(report.rows_copied, 4);
+
+        assert_eq!(namespace::list(&cnt, "raw").unwrap(), vec![raw.clone()]);
+        assert_eq!(expiry::expiry(&cnt, &raw).unwrap(), Some(at));
+        // still deleted with its namespace, the chunked object is not
+        assert_eq!(gc_namespace(&cnt, "raw", false).unwrap().objects, 1);
+
+        assert!(cnt.has_object(&chunked).unwrap());
+        let obj = chunked::extract(&chunked, &cnt).unwrap().unwrap();
+        let mut buf = vec![];
+        obj.reader(&cnt).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, content);
+    }
+
+    #[test]
+    fn upgrade_v1_to_v2() {
+        let (_tmp_dir, cnt) = new_container(1024, "zstd:+1");
+        let mut config = cnt.config().unwrap();
+        config.container_version = 1;
+        cnt.write_config(&config).unwrap();
+
+        // zstd entries are written to version 1 containers as well
+        let content = "test 0 ".repeat(200).into_bytes();
+        let hash = crate::io_packs::insert(content, &cnt).unwrap().hashkey;
+        let obj = crate::io_packs::extract(&hash, &cnt).unwrap().unwrap();
+        assert!(obj.compressed);
+
+        assert_eq!(upgrade(&cnt, true, false).unwrap(), 1);
+        let config = cnt.config().unwrap();
+        assert_eq!(config.container_version, CONTAINER_VERSION);
+        assert!(cnt.capabilities().unwrap().entry_header);
+
+        // the unframed pack 0 is sealed, framed entries go to pack 1
+        let hash = crate::io_packs::insert(b"test 1".to_vec(), &cnt)
+            .unwrap()
+            .hashkey;
+        assert_eq!(stat(&cnt).unwrap().count.packs_file, 2);
+        let entries = crate::io_packs::scan_framed_pack(&cnt.packs().join("1"), 1).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].hashkey, hash);
+
+        // upgrade again is a no-op
+        assert_eq!(upgrade(&cnt, true, false).unwrap(), CONTAINER_VERSION);
+    }
+
+    #[test]
+    fn upgrade_shard_packs() {
+        // a pack per object, so the packs fill more than one shard
+        let (_tmp_dir, cnt) = new_container(1, "zlib:+1");
+        let contents: Vec<Vec<u8>> = (0..300).map(|i| format!("test {i}").into_bytes()).collect();
+        let hashkeys: Vec<_> = crate::io_packs::insert_many(contents.clone(), &cnt)
+            .unwrap()
+            .into_iter()
+            .map(|result| result.hashkey)
+            .collect();
+        let n_packs = stat(&cnt).unwrap().count.packs_file;
+        assert!(n_packs > crate::container::PACKS_PER_SHARD);
+
+        assert_eq!(upgrade(&cnt, false, true).unwrap(), CONTAINER_VERSION);
+        assert!(cnt.capabilities().unwrap().sharded);
+        assert!(cnt.packs().join("shard-0/0").is_file());
+        assert!(cnt.packs().join("shard-1/256").is_file());
+        assert!(!cnt.packs().join("0").is_file());
+        assert_eq!(stat(&cnt).unwrap().count.packs_file, n_packs);
+        for (hashkey, content) in hashkeys.iter().zip(&contents) {
+            let mut buf = vec![];
+            let obj = packs_extract(hashkey, &cnt).unwrap().unwrap();
+            obj.make_reader().unwrap().read_to_end(&mut buf).unwrap();
+            assert_eq!(&buf, content);
+        }
+        assert!(check_index(&cnt).unwrap().anomalies.is_empty());
+
+        // new packs go to their shard
+        crate::io_packs::insert(b"test 300".to_vec(), &cnt).unwrap();
+        let pack_id = n_packs;
+        assert!(cnt.pack_path(pack_id).unwrap().is_file());
+        assert_eq!(stat(&cnt).unwrap().count.packs_file, n_packs + 1);
+
+        // the flat links left by an interrupted upgrade are removed by the next one
+        fs::hard_link(cnt.packs().join("shard-1/300"), cnt.packs().join("300")).unwrap();
+        assert_eq!(upgrade(&cnt, false, true).unwrap(), CONTAINER_VERSION);
+        assert!(!cnt.packs().join("300").exists());
+        assert!(cnt.packs().join("shard-1/300").is_file());
+    }
+
+    #[test]
+    fn pack_loose_precompressed_verbatim() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+3");
+        let text = "📝 Repeat me 200 times".repeat(200).into_bytes();
+        let numbers: String = (0..2000).map(|i| format!("{i} ")).collect();
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zlib.write_all(numbers.as_bytes()).unwrap();
+        let zlib = zlib.finish().unwrap();
+        // small, stored as it is whatever the dictionary
+        let zstd = zstd::encode_all(&b"a small text"[..], 3).unwrap();
+        assert!(zstd.len() < 850 && zlib.len() > 850);
+        let hashkeys: Vec<_> = [&text, &zlib, &zstd]
+            .into_iter()
+            .map(|content| loose_insert(content.clone(), &cnt).unwrap().hashkey)
+            .collect();
+
+        let expected = BTreeMap::from([("zlib".to_string(), 1), ("zstd".to_string(), 1)]);
+        let compression = cnt.compression().unwrap();
+        let report = _pack_loose_internal(&cnt, &compression, None, true, &(), None, None).unwrap();
+        assert_eq!(report.precompressed, expected);
+        let report = pack_loose(&cnt).unwrap();
+        assert_eq!(report.objects_packed, 3);
+        assert_eq!(report.precompressed, expected);
+
+        let conn = cnt.db().unwrap();
+        for (hashkey, content) in hashkeys.iter().zip([&text, &zlib, &zstd]) {
+            let entry = db::select(&conn, hashkey).unwrap().unwrap();
+            assert_eq!(entry.compressed, *content == text);
+            let obj = packs_extract(hashkey, &cnt).unwrap().unwrap();
+            assert_eq!(&ByteString::try_from(obj).unwrap(), content);
         }
     }
 
@@ -107,7 +3372,7 @@ mod tests {
         for i in 0..100 {
             let content = format!("test {i}").repeat(i); // 8 bytes each
             let buf = content.clone().into_bytes();
-            let (_, hash) = loose_insert(buf, &cnt).unwrap();
+            let hash = loose_insert(buf, &cnt).unwrap().hashkey;
             hash_content_map.insert(hash, content);
         }
 
@@ -0,0 +1,381 @@
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, Request,
+};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::cli::{list_objects, object_size};
+use crate::io::is_hashkey;
+use crate::{chunked, io_loose, io_packs, Container, Error};
+
+const ROOT_INO: u64 = 1;
+/// How long the kernel may cache attributes and lookups, objects never change once written.
+const TTL: Duration = Duration::from_secs(60);
+
+/// An open object, read sequentially through ``reader`` which is at ``pos`` of the content.
+struct Handle<'a> {
+    hashkey: String,
+    reader: Box<dyn Read + 'a>,
+    pos: u64,
+}
+
+/// Read-only view of a container: a single folder with one file per object, named by its
+/// hashkey. Inodes are given out as objects are first listed or looked up.
+struct ContainerFs<'a> {
+    cnt: &'a Container,
+    hashkeys: Vec<String>,
+    inodes: HashMap<String, u64>,
+    handles: HashMap<u64, Handle<'a>>,
+    next_fh: u64,
+    uid: u32,
+    gid: u32,
+    mounted_at: SystemTime,
+}
+
+/// Mount the container read-only at ``mountpoint`` and serve it until it is unmounted.
+///
+/// Objects are decompressed lazily: opening a file reads nothing, sequential reads stream the
+/// content and only a read before the current position starts over from the beginning.
+pub fn mount<P: AsRef<Path>>(cnt: &Container, mountpoint: P) -> Result<(), Error> {
+    cnt.valid()?;
+
+    let fs = ContainerFs::new(cnt)?;
+    let options = [
+        MountOption::RO,
+        MountOption::NoExec,
+        MountOption::FSName(format!("rsdos:{}", cnt.path.display())),
+        MountOption::Subtype("rsdos".to_string()),
+    ];
+    fuser::mount2(fs, mountpoint, &options)?;
+    Ok(())
+}
+
+/// Reader over the content of an object from ``offset``, ``None`` if it is not in the container.
+fn open_at<'a>(
+    hashkey: &str,
+    offset: u64,
+    cnt: &'a Container,
+) -> Result<Option<Box<dyn Read + 'a>>, Error> {
+    if let Some(rdr) = io_loose::extract_range(hashkey, offset, u64::MAX, cnt)? {
+        return Ok(Some(Box::new(rdr)));
+    }
+    if let Some(rdr) = io_packs::extract_range(hashkey, offset, u64::MAX, cnt)? {
+        return Ok(Some(Box::new(rdr)));
+    }
+    if let Some(obj) = chunked::extract(hashkey, cnt)? {
+        let mut rdr = obj.reader(cnt);
+        io::copy(&mut (&mut rdr).take(offset), &mut io::sink())?;
+        return Ok(Some(Box::new(rdr)));
+    }
+    Ok(None)
+}
+
+impl<'a> ContainerFs<'a> {
+    fn new(cnt: &'a Container) -> Result<Self, Error> {
+        let stat = std::fs::metadata(&cnt.path)?;
+        let mut fs = ContainerFs {
+            cnt,
+            hashkeys: vec![],
+            inodes: HashMap::new(),
+            handles: HashMap::new(),
+            next_fh: 1,
+            uid: stat.uid(),
+            gid: stat.gid(),
+            mounted_at: SystemTime::now(),
+        };
+        fs.refresh()?;
+        Ok(fs)
+    }
+
+    /// Give inodes to the objects added since the last listing.
+    fn refresh(&mut self) -> Result<(), Error> {
+        let mut hashkeys: Vec<_> = list_objects(self.cnt)?
+            .into_iter()
+            .map(|obj| obj.hashkey)
+            .collect();
+        hashkeys.extend(self.cnt.index()?.list_chunked()?);
+        for hashkey in hashkeys {
+            self.inode(hashkey);
+        }
+        Ok(())
+    }
+
+    fn inode(&mut self, hashkey: String) -> u64 {
+        if let Some(ino) = self.inodes.get(&hashkey) {
+            return *ino;
+        }
+        self.hashkeys.push(hashkey.clone());
+        let ino = self.hashkeys.len() as u64 + ROOT_INO;
+        self.inodes.insert(hashkey, ino);
+        ino
+    }
+
+    fn hashkey(&self, ino: u64) -> Option<&String> {
+        let idx = usize::try_from(ino.checked_sub(ROOT_INO + 1)?).ok()?;
+        self.hashkeys.get(idx)
+    }
+
+    fn attr(&self, ino: u64, kind: FileType, size: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mounted_at,
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            crtime: self.mounted_at,
+            kind,
+            perm: if kind == FileType::Directory {
+                0o555
+            } else {
+                0o444
+            },
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    /// Attributes of inode ``ino``, ``None`` if there is no such object.
+    fn getattr_of(&self, ino: u64) -> Result<Option<FileAttr>, Error> {
+        if ino == ROOT_INO {
+            return Ok(Some(self.attr(ino, FileType::Directory, 0)));
+        }
+        let Some(hashkey) = self.hashkey(ino) else {
+            return Ok(None);
+        };
+        let size = object_size(hashkey, self.cnt)?;
+        Ok(size.map(|size| self.attr(ino, FileType::RegularFile, size)))
+    }
+
+    /// Attributes of the object named ``name``, objects added after the last listing are found
+    /// as well.
+    fn lookup_of(&mut self, name: &str) -> Result<Option<FileAttr>, Error> {
+        if !is_hashkey(name) {
+            return Ok(None);
+        }
+        let Some(size) = object_size(name, self.cnt)? else {
+            return Ok(None);
+        };
+        let ino = self.inode(name.to_string());
+        Ok(Some(self.attr(ino, FileType::RegularFile, size)))
+    }
+
+    /// Open inode ``ino`` and return the file handle, ``None`` if there is no such object.
+    fn open_of(&mut self, ino: u64) -> Result<Option<u64>, Error> {
+        let Some(hashkey) = self.hashkey(ino).cloned() else {
+            return Ok(None);
+        };
+        let Some(reader) = open_at(&hashkey, 0, self.cnt)? else {
+            return Ok(None);
+        };
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.handles.insert(
+            fh,
+            Handle {
+                hashkey,
+                reader,
+                pos: 0,
+            },
+        );
+        Ok(Some(fh))
+    }
+
+    /// Read up to ``size`` bytes from ``offset`` of the object open as ``fh``.
+    fn read_of(&mut self, fh: u64, offset: u64, size: u32) -> Result<Option<Vec<u8>>, Error> {
+        let Some(handle) = self.handles.get_mut(&fh) else {
+            return Ok(None);
+        };
+        if offset < handle.pos {
+            let Some(reader) = open_at(&handle.hashkey, offset, self.cnt)? else {
+                return Ok(None);
+            };
+            handle.reader = reader;
+            handle.pos = offset;
+        } else if offset > handle.pos {
+            handle.pos += io::copy(
+                &mut (&mut handle.reader).take(offset - handle.pos),
+                &mut io::sink(),
+            )?;
+        }
+
+        let mut buf = Vec::with_capacity(size as usize);
+        (&mut handle.reader)
+            .take(u64::from(size))
+            .read_to_end(&mut buf)?;
+        handle.pos += buf.len() as u64;
+        Ok(Some(buf))
+    }
+}
+
+impl Filesystem for ContainerFs<'_> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if parent != ROOT_INO {
+            return reply.error(libc::ENOENT);
+        }
+        match self.lookup_of(&name.to_string_lossy()) {
+            Ok(Some(attr)) => reply.entry(&TTL, &attr, 0),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.getattr_of(ino) {
+            Ok(Some(attr)) => reply.attr(&TTL, &attr),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+        match self.open_of(ino) {
+            Ok(Some(fh)) => reply.opened(fh, 0),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Ok(offset) = u64::try_from(offset) else {
+            return reply.error(libc::EINVAL);
+        };
+        match self.read_of(fh, offset, size) {
+            Ok(Some(buf)) => reply.data(&buf),
+            Ok(None) => reply.error(libc::EBADF),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.handles.remove(&fh);
+        reply.ok();
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        if ino != ROOT_INO {
+            return reply.error(libc::ENOTDIR);
+        }
+        // pick up the objects added since the folder was last listed
+        if offset == 0 && self.refresh().is_err() {
+            return reply.error(libc::EIO);
+        }
+
+        let entries = [
+            (ROOT_INO, FileType::Directory, "."),
+            (ROOT_INO, FileType::Directory, ".."),
+        ]
+        .into_iter()
+        .chain(self.hashkeys.iter().enumerate().map(|(idx, hashkey)| {
+            (
+                idx as u64 + ROOT_INO + 1,
+                FileType::RegularFile,
+                hashkey.as_str(),
+            )
+        }));
+        let skip = usize::try_from(offset).unwrap_or_default();
+        for (i, (ino, kind, name)) in entries.enumerate().skip(skip) {
+            // the offset given back is where the next listing starts
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::maintain::pack_loose;
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn mount_fs_lookup_and_read() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "zstd:+1");
+
+        let packed: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let packed_hash = io_loose::insert(packed.clone(), &cnt).unwrap().hashkey;
+        pack_loose(&cnt).unwrap();
+        crate::maintain::clean_loose(&cnt, false).unwrap();
+        let loose_hash = io_loose::insert(b"loose object".to_vec(), &cnt)
+            .unwrap()
+            .hashkey;
+
+        let mut fs = ContainerFs::new(&cnt).unwrap();
+        assert!(fs.getattr_of(ROOT_INO).unwrap().is_some());
+        let attr = fs.lookup_of(&packed_hash).unwrap().unwrap();
+        assert_eq!(attr.size, packed.len() as u64);
+        assert_eq!(fs.hashkey(attr.ino), Some(&packed_hash));
+        assert!(fs.lookup_of(&"0".repeat(64)).unwrap().is_none());
+        assert!(fs.lookup_of("not-a-hashkey").unwrap().is_none());
+
+        // sequential reads, a skip forward and a read back from the start
+        let fh = fs.open_of(attr.ino).unwrap().unwrap();
+        assert_eq!(fs.read_of(fh, 0, 10).unwrap().unwrap(), packed[..10]);
+        assert_eq!(fs.read_of(fh, 10, 10).unwrap().unwrap(), packed[10..20]);
+        assert_eq!(
+            fs.read_of(fh, 50_000, 100).unwrap().unwrap(),
+            packed[50_000..50_100]
+        );
+        assert_eq!(fs.read_of(fh, 5, 10).unwrap().unwrap(), packed[5..15]);
+        assert_eq!(
+            fs.read_of(fh, 99_990, 100).unwrap().unwrap(),
+            packed[99_990..]
+        );
+
+        let ino = fs.lookup_of(&loose_hash).unwrap().unwrap().ino;
+        let fh = fs.open_of(ino).unwrap().unwrap();
+        assert_eq!(fs.read_of(fh, 6, 100).unwrap().unwrap(), b"object");
+
+        // chunked objects are listed too
+        let chunked_hash = chunked::insert(packed.clone(), &cnt).unwrap().hashkey;
+        fs.refresh().unwrap();
+        assert!(fs.hashkeys.contains(&chunked_hash));
+        let ino = fs.inodes[&chunked_hash];
+        let fh = fs.open_of(ino).unwrap().unwrap();
+        assert_eq!(
+            fs.read_of(fh, 70_000, 10).unwrap().unwrap(),
+            packed[70_000..70_010]
+        );
+    }
+}
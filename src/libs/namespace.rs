@@ -0,0 +1,220 @@
+use serde::Serialize;
+use std::fs;
+
+use crate::index::ObjectIndex;
+use crate::io::check_hashkey;
+use crate::{db, Container, Error};
+
+/// Reserved namespace of the objects inserted outside of any namespace, so that
+/// ``maintain::gc_namespace`` never deletes them. Not a valid namespace name, it is not listed.
+pub(crate) const UNSCOPED: &str = "";
+
+/// What an object is inserted for, recorded with the object so that ``maintain::gc_namespace``
+/// only deletes the objects nothing but its namespace holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scope<'a> {
+    /// A plain insert, the object is kept whatever happens to the namespaces it is also in.
+    Unscoped,
+    /// An insert to a namespace, the object goes with the namespace unless held otherwise.
+    Namespace(&'a str),
+    /// An object moved from a store to another or written as part of another object (the chunks
+    /// and manifest of a chunked object), nothing is recorded.
+    Internal,
+}
+
+/// Record in ``index`` that the objects of ``hashkeys`` were inserted for ``scope``. Their
+/// expiry is cleared, an object inserted again without expiry is kept forever (see
+/// ``expiry::set_expiry``).
+pub(crate) fn record(
+    index: &dyn ObjectIndex,
+    scope: Scope,
+    hashkeys: &[String],
+) -> Result<(), Error> {
+    let namespace = match scope {
+        Scope::Unscoped => UNSCOPED,
+        Scope::Namespace(namespace) => namespace,
+        Scope::Internal => return Ok(()),
+    };
+    index.clear_expiry(hashkeys)?;
+    index.add_to_namespace(namespace, hashkeys)
+}
+
+/// Objects of a namespace and the bytes of their content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NamespaceStats {
+    pub namespace: String,
+    pub count: u64,
+    /// Size of the content of the objects, whatever the store they are in. An object also in
+    /// other namespaces is counted in each of them.
+    pub size: u64,
+}
+
+/// ``Error::InvalidNamespace`` unless ``namespace`` is a non-empty name of ASCII letters, digits,
+/// ``-``, ``_`` or ``.``, so it can be passed around on the command line as it is.
+pub fn check_namespace(namespace: &str) -> Result<(), Error> {
+    let valid = !namespace.is_empty()
+        && namespace
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidNamespace {
+            namespace: namespace.to_string(),
+        })
+    }
+}
+
+/// Add the objects of ``hashkeys`` to ``namespace``, e.g. once inserted. An object can be in
+/// several namespaces, its content is still stored once. Objects already in ``namespace`` are
+/// ignored.
+///
+/// An object in no namespace yet is taken as inserted plainly and ``maintain::gc_namespace``
+/// keeps it, only the objects inserted with ``Container::insert_to_namespace`` go with their
+/// namespace.
+///
+/// NOTE: the objects are not checked to be in the container, tag them after inserting them.
+pub fn tag<S: AsRef<str>>(cnt: &Container, namespace: &str, hashkeys: &[S]) -> Result<(), Error> {
+    cnt.valid()?;
+    check_namespace(namespace)?;
+    hashkeys
+        .iter()
+        .try_for_each(|h| check_hashkey(h.as_ref()))?;
+
+    let hashkeys: Vec<String> = hashkeys.iter().map(|h| h.as_ref().to_string()).collect();
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    tx.add_untagged(UNSCOPED, &hashkeys)?;
+    record(&*tx, Scope::Namespace(namespace), &hashkeys)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Remove the objects of ``hashkeys`` from ``namespace`` and return how many were in it. The
+/// objects stay in the container, see ``maintain::gc_namespace`` to delete them.
+pub fn untag<S: AsRef<str>>(
+    cnt: &Container,
+    namespace: &str,
+    hashkeys: &[S],
+) -> Result<u64, Error> {
+    cnt.valid()?;
+    check_namespace(namespace)?;
+
+    let hashkeys: Vec<String> = hashkeys.iter().map(|h| h.as_ref().to_string()).collect();
+    let conn = cnt.db()?;
+    db::ensure_writable(&conn)?;
+    let tx = db::begin_write(&conn)?;
+    let removed = tx.remove_from_namespace(namespace, &hashkeys)?;
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// Hashkeys of the objects of ``namespace``, sorted. Empty for a namespace nothing was tagged
+/// with.
+pub fn list(cnt: &Container, namespace: &str) -> Result<Vec<String>, Error> {
+    cnt.valid()?;
+    check_namespace(namespace)?;
+    cnt.index()?.namespace_hashkeys(namespace)
+}
+
+/// Namespaces the object ``hashkey`` is in, sorted.
+pub fn namespaces_of(cnt: &Container, hashkey: &str) -> Result<Vec<String>, Error> {
+    cnt.valid()?;
+    check_hashkey(hashkey)?;
+
+    cnt.index()?.namespaces_of(hashkey)
+}
+
+/// Count and size of the objects of every namespace, sorted by namespace. Objects tagged but
+/// not in the container anymore are not counted.
+pub fn stats(cnt: &Container) -> Result<Vec<NamespaceStats>, Error> {
+    cnt.valid()?;
+
+    let mut stats: Vec<NamespaceStats> = vec![];
+    // sizes of packed and chunked objects are in the index, the loose ones are looked up
+    cnt.index()?
+        .for_each_in_namespaces(&mut |namespace, hashkey, size| {
+            let size = match size {
+                Some(size) => size,
+                None => {
+                    let loc = cnt.loose().join(&hashkey[..2]).join(&hashkey[2..]);
+                    match fs::metadata(loc) {
+                        Ok(meta) => meta.len(),
+                        Err(_) => return Ok(()),
+                    }
+                }
+            };
+            match stats.last_mut() {
+                Some(last) if last.namespace == namespace => {
+                    last.count += 1;
+                    last.size += size;
+                }
+                _ => stats.push(NamespaceStats {
+                    namespace,
+                    count: 1,
+                    size,
+                }),
+            }
+            Ok(())
+        })?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io_loose::insert as loose_insert;
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+
+    use super::*;
+
+    #[test]
+    fn namespace_tag_list_untag() {
+        let (_tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let shared = loose_insert(b"shared".to_vec(), &cnt).unwrap().hashkey;
+        let raw = loose_insert(b"raw only".to_vec(), &cnt).unwrap().hashkey;
+
+        tag(&cnt, "raw", &[&shared, &raw]).unwrap();
+        tag(&cnt, "processed", &[&shared]).unwrap();
+        // already tagged, ignored
+        tag(&cnt, "raw", &[&raw]).unwrap();
+
+        let mut expected = vec![shared.clone(), raw.clone()];
+        expected.sort();
+        assert_eq!(list(&cnt, "raw").unwrap(), expected);
+        assert_eq!(list(&cnt, "processed").unwrap(), vec![shared.clone()]);
+        assert!(list(&cnt, "other").unwrap().is_empty());
+        assert_eq!(
+            namespaces_of(&cnt, &shared).unwrap(),
+            vec!["processed", "raw"]
+        );
+        assert_eq!(
+            stats(&cnt).unwrap(),
+            vec![
+                NamespaceStats {
+                    namespace: "processed".to_string(),
+                    count: 1,
+                    size: 6,
+                },
+                NamespaceStats {
+                    namespace: "raw".to_string(),
+                    count: 2,
+                    size: 14,
+                },
+            ]
+        );
+
+        assert_eq!(untag(&cnt, "raw", &[&shared, &shared]).unwrap(), 1);
+        assert_eq!(list(&cnt, "raw").unwrap(), vec![raw]);
+        // the object is still in the container and its other namespaces
+        assert!(cnt.has_object(&shared).unwrap());
+        assert_eq!(namespaces_of(&cnt, &shared).unwrap(), vec!["processed"]);
+
+        for namespace in ["", "a b", "../x"] {
+            assert!(matches!(
+                tag(&cnt, namespace, &[&shared]),
+                Err(Error::InvalidNamespace { .. })
+            ));
+        }
+    }
+}
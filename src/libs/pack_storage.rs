@@ -0,0 +1,220 @@
+use std::fmt::Debug;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utils::{open_shared, rename_replace};
+use crate::Error;
+
+/// Where sealed pack files are kept once offloaded from the container, see
+/// ``maintain::offload_packs``.
+///
+/// Packs are always written to the local ``packs`` folder; only packs that are no longer appended
+/// to are uploaded. Reads of an offloaded pack only fetch the stored bytes of the requested entry.
+pub trait PackStorage: Debug + Send + Sync {
+    /// Read ``len`` stored bytes from ``offset`` of pack ``pack_id``.
+    fn read_range(&self, pack_id: u64, offset: u64, len: u64) -> Result<Vec<u8>, Error>;
+
+    /// Upload the local pack file ``src`` as pack ``pack_id``, replacing an earlier upload.
+    fn upload(&self, pack_id: u64, src: &Path) -> Result<(), Error>;
+
+    /// Size of pack ``pack_id``, ``None`` if it was not uploaded.
+    fn size(&self, pack_id: u64) -> Result<Option<u64>, Error>;
+}
+
+/// Open the pack storage at ``url``: ``file:///<dir>`` for a folder (e.g. another mounted
+/// disk), ``s3://<bucket>/<prefix>`` or ``gs://<bucket>/<prefix>`` with the ``s3`` feature.
+pub fn open(url: &str) -> Result<Arc<dyn PackStorage>, Error> {
+    if let Some(dir) = url.strip_prefix("file://") {
+        return Ok(Arc::new(LocalPackStorage::new(dir)));
+    }
+
+    #[cfg(feature = "s3")]
+    return Ok(Arc::new(ObjectStorePackStorage::from_url(url)?));
+
+    #[cfg(not(feature = "s3"))]
+    Err(Error::PackStorage {
+        url: url.to_string(),
+        cause: "remote pack storage needs rsdos built with the `s3` feature".to_string(),
+    })
+}
+
+/// Pack storage in a local folder, one file per pack named by its id as in ``packs``.
+#[derive(Debug)]
+pub struct LocalPackStorage {
+    dir: PathBuf,
+}
+
+impl LocalPackStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl PackStorage for LocalPackStorage {
+    fn read_range(&self, pack_id: u64, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        let mut f = open_shared(&self.dir.join(format!("{pack_id}")))?;
+        f.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![];
+        f.take(len).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn upload(&self, pack_id: u64, src: &Path) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        // copy next to the destination first so a partial upload never looks like a pack
+        let tmp = self.dir.join(format!("{pack_id}.tmp"));
+        fs::copy(src, &tmp)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        rename_replace(&tmp, &self.dir.join(format!("{pack_id}")))?;
+        Ok(())
+    }
+
+    fn size(&self, pack_id: u64) -> Result<Option<u64>, Error> {
+        match fs::metadata(self.dir.join(format!("{pack_id}"))) {
+            Ok(stat) => Ok(Some(stat.len())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(feature = "s3")]
+pub use object_storage::ObjectStorePackStorage;
+
+#[cfg(feature = "s3")]
+mod object_storage {
+    use object_store::path::Path as StorePath;
+    use object_store::{ObjectStore, WriteMultipart};
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::PackStorage;
+    use crate::Error;
+
+    /// Part size of multipart uploads, S3 requires at least 5 MiB.
+    const PART_SIZE: usize = 8 * 1024 * 1024;
+    /// Parts uploaded concurrently.
+    const MAX_CONCURRENCY: usize = 4;
+
+    /// Pack storage in an S3/GCS bucket, packs are read with ranged GETs and written with multipart
+    /// uploads. Credentials are taken from the usual ``AWS_*``/``GOOGLE_*`` environment variables.
+    #[derive(Debug)]
+    pub struct ObjectStorePackStorage {
+        store: Arc<dyn ObjectStore>,
+        prefix: StorePath,
+        rt: tokio::runtime::Runtime,
+    }
+
+    impl ObjectStorePackStorage {
+        pub fn new(store: Arc<dyn ObjectStore>, prefix: StorePath) -> Result<Self, Error> {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            Ok(Self { store, prefix, rt })
+        }
+
+        pub fn from_url(url: &str) -> Result<Self, Error> {
+            let storage_error = |cause: String| Error::PackStorage {
+                url: url.to_string(),
+                cause,
+            };
+            let parsed = url::Url::parse(url).map_err(|err| storage_error(err.to_string()))?;
+            let options = std::env::vars().map(|(k, v)| (k.to_ascii_lowercase(), v));
+            let (store, prefix) = object_store::parse_url_opts(&parsed, options)
+                .map_err(|err| storage_error(err.to_string()))?;
+            Self::new(Arc::from(store), prefix)
+        }
+
+        fn location(&self, pack_id: u64) -> StorePath {
+            self.prefix.child(pack_id.to_string())
+        }
+
+        fn storage_error(&self, err: &object_store::Error) -> Error {
+            Error::PackStorage {
+                url: format!("{}/{}", self.store, self.prefix),
+                cause: err.to_string(),
+            }
+        }
+    }
+
+    impl PackStorage for ObjectStorePackStorage {
+        fn read_range(&self, pack_id: u64, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+            if len == 0 {
+                return Ok(vec![]);
+            }
+            let location = self.location(pack_id);
+            let bytes = self
+                .rt
+                .block_on(self.store.get_range(&location, offset..offset + len))
+                .map_err(|err| self.storage_error(&err))?;
+            Ok(bytes.to_vec())
+        }
+
+        fn upload(&self, pack_id: u64, src: &Path) -> Result<(), Error> {
+            let location = self.location(pack_id);
+            let mut f = std::fs::File::open(src)?;
+            self.rt
+                .block_on(async {
+                    let upload = self.store.put_multipart(&location).await?;
+                    let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+                    let mut buf = vec![0u8; PART_SIZE];
+                    loop {
+                        let n = f
+                            .read(&mut buf)
+                            .map_err(|err| object_store::Error::Generic {
+                                store: "local pack",
+                                source: Box::new(err),
+                            })?;
+                        if n == 0 {
+                            break;
+                        }
+                        writer.wait_for_capacity(MAX_CONCURRENCY).await?;
+                        writer.write(&buf[..n]);
+                    }
+                    writer.finish().await?;
+                    Ok(())
+                })
+                .map_err(|err: object_store::Error| self.storage_error(&err))
+        }
+
+        fn size(&self, pack_id: u64) -> Result<Option<u64>, Error> {
+            let location = self.location(pack_id);
+            match self.rt.block_on(self.store.head(&location)) {
+                Ok(meta) => Ok(Some(meta.size)),
+                Err(object_store::Error::NotFound { .. }) => Ok(None),
+                Err(err) => Err(self.storage_error(&err)),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use object_store::memory::InMemory;
+
+        use super::*;
+
+        #[test]
+        fn object_store_upload_and_read_range() {
+            let storage =
+                ObjectStorePackStorage::new(Arc::new(InMemory::new()), StorePath::from("packs"))
+                    .unwrap();
+            let src_dir = tempfile::tempdir().unwrap();
+            let src = src_dir.path().join("3");
+            let content: Vec<u8> = (0..3 * PART_SIZE / 2).map(|i| (i % 251) as u8).collect();
+            std::fs::write(&src, &content).unwrap();
+
+            assert_eq!(storage.size(3).unwrap(), None);
+            storage.upload(3, &src).unwrap();
+            assert_eq!(storage.size(3).unwrap(), Some(content.len() as u64));
+            assert_eq!(
+                storage.read_range(3, PART_SIZE as u64 - 2, 4).unwrap(),
+                content[PART_SIZE - 2..PART_SIZE + 2]
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crossbeam_channel::{bounded, Receiver, SendError, Sender};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::iter::Fuse;
+use std::thread::Scope;
+
+use crate::config::CompressionPolicy;
+use crate::container::Compression;
+use crate::db::PackEntry;
+use crate::io::ReaderMaker;
+use crate::io_packs::{encode, entry_compression, Encoding};
+use crate::Error;
+
+/// Largest source (bytes) read and compressed ahead by the pipeline, a larger one is streamed to
+/// the pack by the pack writer itself.
+pub const BUFFERED_MAX: u64 = 4 * 1024 * 1024;
+
+/// Bytes of sources the pipeline reads ahead of the pack writer at most, their compressed
+/// payload is held as well until appended.
+pub const BUFFERED_BUDGET: u64 = 64 * 1024 * 1024;
+
+/// A source read and compressed in memory, only left to be appended to a pack.
+#[derive(Debug)]
+pub(crate) struct Encoded {
+    encoding: Encoding,
+    payload: Vec<u8>,
+}
+
+impl Encoded {
+    fn new(
+        content: &[u8],
+        compression: &Compression,
+        dictionary: Option<&[u8]>,
+    ) -> Result<Self, Error> {
+        let mut payload = Vec::new();
+        let encoding = encode(content, &mut payload, compression, dictionary)?;
+        Ok(Self { encoding, payload })
+    }
+
+    /// Append the entry to the end of the current working pack and return its index entry.
+    pub(crate) fn append_to(
+        self,
+        cwp: &mut File,
+        framed: bool,
+        pack_id: u64,
+    ) -> Result<PackEntry, Error> {
+        let size = self.payload.len() as u64;
+        if framed {
+            cwp.write_all(&self.encoding.header(size)?.to_bytes())?;
+        }
+        let payload_offset = cwp.stream_position()?;
+        cwp.write_all(&self.payload)?;
+        Ok(self.encoding.into_entry(pack_id, payload_offset, size))
+    }
+}
+
+/// A source as the pack writer gets it from ``Pipeline``.
+pub(crate) enum Prepared<R> {
+    /// Read and compressed by the workers, or the error that stopped them.
+    Encoded(Result<Encoded, Error>),
+    /// Larger than ``BUFFERED_MAX`` or of unknown size, written by the pack writer.
+    Source(R),
+}
+
+impl<R> Prepared<R>
+where
+    R: ReaderMaker,
+{
+    pub(crate) fn size_hint(&self) -> Option<u64> {
+        match self {
+            Prepared::Encoded(encoded) => encoded.as_ref().ok().map(|e| e.encoding.raw_size),
+            Prepared::Source(rmaker) => rmaker.size_hint(),
+        }
+    }
+}
+
+/// Sources of a pack insert read and compressed by threads of ``scope``, given back in their
+/// order: the sources are pulled on the calling thread and handed over to ``readers`` threads
+/// that read them in memory, then to ``compressors`` threads that compress them, while the pack
+/// writer appends the previous ones. The channels between the stages are bounded to the sources
+/// in flight, no thread runs more than ``BUFFERED_BUDGET`` ahead of the pack writer.
+///
+/// NOTE: the index entries of the sources appended are still inserted by the pack writer in the
+/// transaction of their pack, it holds the write lock of the pack anyway.
+///
+/// NOTE: sources are pulled ahead of the pack writer, a source pulled before the writer stopped
+/// (e.g. on error) is read for nothing.
+pub(crate) struct Pipeline<I>
+where
+    I: Iterator,
+{
+    sources: Fuse<I>,
+    jobs: Sender<(usize, I::Item)>,
+    done: Receiver<(usize, Result<Encoded, Error>)>,
+    /// Sources given back out of order or not handed over, by position.
+    ready: HashMap<usize, Prepared<I::Item>>,
+    /// Bytes of every source in flight, from the next one to give back.
+    in_flight: VecDeque<u64>,
+    in_flight_bytes: u64,
+    /// Position of the next source to give back.
+    next: usize,
+    window: usize,
+}
+
+/// Start the threads of a ``Pipeline`` over ``sources`` in ``scope``, ``compression`` is the one
+/// of the insert, each source is compressed as ``entry_compression`` decides.
+pub(crate) fn spawn<'scope, I>(
+    scope: &'scope Scope<'scope, '_>,
+    sources: I,
+    readers: usize,
+    compressors: usize,
+    compression: &'scope Compression,
+    policy: &'scope CompressionPolicy,
+    dictionary: Option<&'scope [u8]>,
+) -> Pipeline<I>
+where
+    I: Iterator,
+    I::Item: ReaderMaker + Send + 'scope,
+{
+    // enough sources in flight to keep every thread busy, every channel has room for all of
+    // them so no stage ever waits on the next one
+    let window = 2 * (readers + compressors);
+    let (jobs, job_rx) = bounded::<(usize, I::Item)>(window);
+    let (read_tx, read_rx) = bounded(window);
+    let (done_tx, done) = bounded(window);
+
+    for _ in 0..readers {
+        let (job_rx, read_tx) = (job_rx.clone(), read_tx.clone());
+        scope.spawn(move || {
+            for (pos, rmaker) in job_rx {
+                let read = read_source(&rmaker, compression, policy, dictionary.is_some());
+                if read_tx.send((pos, read)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    for _ in 0..compressors {
+        let (read_rx, done_tx) = (read_rx.clone(), done_tx.clone());
+        scope.spawn(move || {
+            for (pos, read) in read_rx {
+                let encoded = read.and_then(|(content, compression)| {
+                    Encoded::new(&content, compression, dictionary)
+                });
+                if done_tx.send((pos, encoded)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Pipeline {
+        sources: sources.fuse(),
+        jobs,
+        done,
+        ready: HashMap::new(),
+        in_flight: VecDeque::new(),
+        in_flight_bytes: 0,
+        next: 0,
+        window,
+    }
+}
+
+/// Content of ``rmaker`` and the compression of its entry.
+fn read_source<'a, R>(
+    rmaker: &R,
+    compression: &'a Compression,
+    policy: &CompressionPolicy,
+    dictionary: bool,
+) -> Result<(Vec<u8>, &'a Compression), Error>
+where
+    R: ReaderMaker,
+{
+    let compression = entry_compression(rmaker, compression, policy, dictionary);
+    let mut content = Vec::with_capacity(rmaker.size_hint().unwrap_or(0) as usize);
+    rmaker.make_reader()?.read_to_end(&mut content)?;
+    Ok((content, compression))
+}
+
+impl<I> Pipeline<I>
+where
+    I: Iterator,
+    I::Item: ReaderMaker,
+{
+    /// Pull sources until ``window`` of them or ``BUFFERED_BUDGET`` bytes are in flight.
+    fn fill(&mut self) {
+        while self.in_flight.len() < self.window
+            && (self.in_flight_bytes < BUFFERED_BUDGET || self.in_flight.is_empty())
+        {
+            let Some(rmaker) = self.sources.next() else {
+                break;
+            };
+            let pos = self.next + self.in_flight.len();
+            let buffered = rmaker.size_hint().filter(|size| *size <= BUFFERED_MAX);
+            let rmaker = match buffered {
+                Some(_) => self
+                    .jobs
+                    .send((pos, rmaker))
+                    .err()
+                    .map(|SendError((_, rmaker))| rmaker),
+                None => Some(rmaker),
+            };
+            // a source not handed over, or whose workers are gone, is written by the pack writer
+            if let Some(rmaker) = rmaker {
+                self.ready.insert(pos, Prepared::Source(rmaker));
+            }
+            let bytes = buffered.unwrap_or(0);
+            self.in_flight.push_back(bytes);
+            self.in_flight_bytes += bytes;
+        }
+    }
+}
+
+impl<I> Iterator for Pipeline<I>
+where
+    I: Iterator,
+    I::Item: ReaderMaker,
+{
+    type Item = Prepared<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill();
+        let bytes = self.in_flight.pop_front()?;
+        self.in_flight_bytes -= bytes;
+        let pos = self.next;
+        self.next += 1;
+        while !self.ready.contains_key(&pos) {
+            // the workers only stop before every source is done if one panicked, the panic is
+            // raised again when the scope ends
+            let (done, encoded) = self.done.recv().ok()?;
+            self.ready.insert(done, Prepared::Encoded(encoded));
+        }
+        self.ready.remove(&pos)
+    }
+}
@@ -0,0 +1,385 @@
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use futures_util::TryStreamExt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+
+use crate::cli::{extract, object_size, show_object, StoreType};
+use crate::io::{is_hashkey, ExtractOptions, InsertResult};
+use crate::{Container, Error};
+
+/// Bytes of an object buffered between the thread reading it and the response being sent.
+const STREAM_BUFFER: usize = 256 * 1024;
+
+/// How ``serve`` answers requests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServeOptions {
+    /// Refuse inserts with ``405 Method Not Allowed``.
+    pub read_only: bool,
+    /// Largest object accepted by ``PUT /objects``, larger bodies are refused with
+    /// ``413 Payload Too Large``. Without it objects are only limited by the container quotas.
+    pub max_object_size: Option<u64>,
+}
+
+struct AppState {
+    cnt: Container,
+    options: ServeOptions,
+}
+
+/// Serve the container over HTTP at ``addr`` until the process is stopped:
+///
+/// - ``GET /objects/<hash>``: content of the object, ``Range: bytes=<start>-<end>`` is honoured
+/// - ``HEAD /objects/<hash>``: existence and ``Content-Length`` without reading the content
+/// - ``PUT /objects``: insert the request body, respond with its hashkey
+/// - ``GET /status``: ``rsdos status`` as JSON
+///
+/// Objects are streamed in both directions, a request body is written to the sandbox of the
+/// container before it is inserted with ``Container::insert``.
+pub fn serve(cnt: Container, addr: SocketAddr, options: ServeOptions) -> Result<(), Error> {
+    cnt.valid()?;
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        eprintln!(
+            "Serving {} on http://{}",
+            cnt.path.display(),
+            listener.local_addr()?
+        );
+        axum::serve(listener, router(cnt, options)).await?;
+        Ok::<_, Error>(())
+    })
+}
+
+fn router(cnt: Container, options: ServeOptions) -> Router {
+    let state = Arc::new(AppState { cnt, options });
+    Router::new()
+        .route("/objects/{hash}", get(get_object).head(head_object))
+        .route("/objects", put(put_object))
+        .route("/status", get(status))
+        .with_state(state)
+}
+
+/// Map a failed container operation to ``500``, the error is sent as the body.
+fn internal_error(err: impl std::fmt::Display) -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+}
+
+/// Parse a single ``bytes=<start>-<end>`` range into ``(start, len)``, ``None`` for anything else.
+fn parse_range(headers: &HeaderMap, size: u64) -> Option<(u64, u64)> {
+    let range = headers.get(header::RANGE)?.to_str().ok()?;
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let start: u64 = start.parse().ok()?;
+    let end: u64 = match end {
+        "" => size.checked_sub(1)?,
+        end => end.parse::<u64>().ok()?.min(size.checked_sub(1)?),
+    };
+    (start <= end).then_some((start, end - start + 1))
+}
+
+/// Size of the object and whether a range of it can be read, ``None`` if it is not in the
+/// container. Chunked objects are always sent whole.
+fn object_head(hash: &str, cnt: &Container) -> Result<Option<(u64, bool)>, Error> {
+    if let Some(info) = show_object(hash, cnt)? {
+        let size = info
+            .loose
+            .map(|loose| loose.size)
+            .or(info.packs.map(|packs| packs.raw_size));
+        return Ok(size.map(|size| (size, true)));
+    }
+    Ok(crate::chunked::extract(hash, cnt)?.map(|obj| (obj.raw_size, false)))
+}
+
+/// Copy ``len`` bytes from ``start`` of the object, or all of it, to ``to``.
+fn send_object(
+    hash: &str,
+    range: Option<(u64, u64)>,
+    cnt: &Container,
+    mut to: impl Write,
+) -> Result<(), Error> {
+    if let Some((start, len)) = range {
+        if let Some(mut rdr) = crate::io_loose::extract_range(hash, start, len, cnt)? {
+            io::copy(&mut rdr, &mut to)?;
+        } else if let Some(mut rdr) = crate::io_packs::extract_range(hash, start, len, cnt)? {
+            io::copy(&mut rdr, &mut to)?;
+        } else {
+            return Err(Error::MissingObject {
+                hashkey: hash.to_string(),
+            });
+        }
+    } else if extract(
+        hash,
+        cnt,
+        &StoreType::Auto,
+        ExtractOptions::default(),
+        &mut to,
+    )?
+    .is_none()
+    {
+        return Err(Error::MissingObject {
+            hashkey: hash.to_string(),
+        });
+    }
+    to.flush()?;
+    Ok(())
+}
+
+async fn get_object(
+    State(state): State<Arc<AppState>>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_hashkey(&hash) {
+        return (StatusCode::BAD_REQUEST, "not a sha256 hashkey").into_response();
+    }
+
+    let head = {
+        let state = state.clone();
+        let hash = hash.clone();
+        tokio::task::spawn_blocking(move || object_head(&hash, &state.cnt)).await
+    };
+    let (size, rangeable) = match head {
+        Ok(Ok(Some(head))) => head,
+        Ok(Ok(None)) => return StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(err)) => return internal_error(err),
+        Err(err) => return internal_error(err),
+    };
+    let range = parse_range(&headers, size).filter(|_| rangeable);
+
+    // the content is read on a blocking thread and streamed as it is read, the headers are sent
+    // already so a failure midway cuts the body short of its ``Content-Length``
+    let (rx, tx) = tokio::io::duplex(STREAM_BUFFER);
+    let to = SyncIoBridge::new(tx);
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = send_object(&hash, range, &state.cnt, to) {
+            eprintln!("Could not send {hash}: {err}");
+        }
+    });
+    let body = Body::from_stream(ReaderStream::new(rx));
+
+    match range {
+        Some((start, len)) => {
+            let content_range = format!("bytes {}-{}/{size}", start, start + len - 1);
+            (
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    (header::CONTENT_RANGE, content_range),
+                    (header::CONTENT_LENGTH, len.to_string()),
+                ],
+                body,
+            )
+                .into_response()
+        }
+        None => (
+            StatusCode::OK,
+            [(header::CONTENT_LENGTH, size.to_string())],
+            body,
+        )
+            .into_response(),
+    }
+}
+
+async fn head_object(State(state): State<Arc<AppState>>, Path(hash): Path<String>) -> Response {
+    if !is_hashkey(&hash) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    match tokio::task::spawn_blocking(move || object_size(&hash, &state.cnt)).await {
+        Ok(Ok(Some(size))) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_LENGTH, size.to_string()),
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+            ],
+        )
+            .into_response(),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(err)) => internal_error(err),
+        Err(err) => internal_error(err),
+    }
+}
+
+/// Write ``body`` to the sandbox and insert it with ``Container::insert``, so it is routed by its
+/// size like any other insert. ``None`` if ``body`` is larger than ``max_size``.
+fn insert_body(
+    cnt: &Container,
+    body: impl Read,
+    max_size: Option<u64>,
+) -> Result<Option<InsertResult>, Error> {
+    let tmp = cnt
+        .insert_sandbox()?
+        .join(format!("{}.tmp", uuid::Uuid::new_v4()));
+    let received = fs::File::create(&tmp).map_err(Error::from).and_then(|mut file| {
+        // one byte more than the limit tells a body over it
+        let limit = max_size.map_or(u64::MAX, |max_size| max_size + 1);
+        let n = io::copy(&mut body.take(limit), &mut file)?;
+        Ok(n)
+    });
+    let inserted = match received {
+        Ok(n) if max_size.is_some_and(|max_size| n > max_size) => Ok(None),
+        Ok(_) => cnt.insert(tmp.clone()).map(Some),
+        Err(err) => Err(err),
+    };
+    let _ = fs::remove_file(&tmp);
+    inserted
+}
+
+async fn put_object(State(state): State<Arc<AppState>>, body: Body) -> Response {
+    if state.options.read_only {
+        return (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "container is served read-only",
+        )
+            .into_response();
+    }
+
+    let stream = body.into_data_stream().map_err(io::Error::other);
+    let body = SyncIoBridge::new(StreamReader::new(stream));
+    let inserted = tokio::task::spawn_blocking(move || {
+        insert_body(&state.cnt, body, state.options.max_object_size)
+    })
+    .await;
+
+    match inserted {
+        Ok(Ok(Some(inserted))) => (StatusCode::CREATED, inserted.hashkey).into_response(),
+        Ok(Ok(None)) => (StatusCode::PAYLOAD_TOO_LARGE, "object too large").into_response(),
+        Ok(Err(err @ crate::Error::QuotaExceeded { .. })) => {
+            (StatusCode::INSUFFICIENT_STORAGE, err.to_string()).into_response()
+        }
+        Ok(Err(err)) => internal_error(err),
+        Err(err) => internal_error(err),
+    }
+}
+
+async fn status(State(state): State<Arc<AppState>>) -> Response {
+    match tokio::task::spawn_blocking(move || crate::stat(&state.cnt)).await {
+        Ok(Ok(info)) => Json(info).into_response(),
+        Ok(Err(err)) => internal_error(err),
+        Err(err) => internal_error(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    use crate::test_utils::{new_container, PACK_TARGET_SIZE};
+
+    use super::*;
+
+    /// Send a raw HTTP/1.1 request and return the status code, headers and body.
+    fn request(addr: SocketAddr, method: &str, path: &str, extra: &str, body: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n{extra}\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    /// Serve ``cnt`` on a free local port, the server stops when the runtime is dropped.
+    fn spawn_server(cnt: Container, options: ServeOptions) -> (tokio::runtime::Runtime, SocketAddr) {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let listener = rt
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(cnt, options);
+        rt.spawn(async move { axum::serve(listener, app).await });
+        (rt, addr)
+    }
+
+    #[test]
+    fn server_put_get_head_status() {
+        let (tmp_dir, _cnt) = new_container(PACK_TARGET_SIZE, "none");
+        let (_rt, addr) = spawn_server(Container::new(tmp_dir.path()), ServeOptions::default());
+
+        let resp = request(addr, "PUT", "/objects", "", b"0123456789");
+        assert!(resp.starts_with("HTTP/1.1 201"), "{resp}");
+        let hash = resp.rsplit("\r\n\r\n").next().unwrap().to_string();
+        assert!(is_hashkey(&hash));
+
+        let resp = request(addr, "GET", &format!("/objects/{hash}"), "", b"");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+        assert!(resp.ends_with("\r\n\r\n0123456789"), "{resp}");
+
+        let resp = request(
+            addr,
+            "GET",
+            &format!("/objects/{hash}"),
+            "Range: bytes=2-4\r\n",
+            b"",
+        );
+        assert!(resp.starts_with("HTTP/1.1 206"), "{resp}");
+        assert!(resp.contains("content-range: bytes 2-4/10"), "{resp}");
+        assert!(resp.ends_with("\r\n\r\n234"), "{resp}");
+
+        let resp = request(addr, "HEAD", &format!("/objects/{hash}"), "", b"");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+        assert!(resp.contains("content-length: 10"), "{resp}");
+
+        let missing = "0".repeat(64);
+        let resp = request(addr, "HEAD", &format!("/objects/{missing}"), "", b"");
+        assert!(resp.starts_with("HTTP/1.1 404"), "{resp}");
+
+        let resp = request(addr, "GET", "/status", "", b"");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+        assert!(resp.contains("\"loose\":1"), "{resp}");
+    }
+
+    #[test]
+    fn server_put_routed_and_limited() {
+        let (tmp_dir, cnt) = new_container(PACK_TARGET_SIZE, "none");
+        cnt.update_config(|config| config.set("auto_pack_threshold", "1KiB"))
+            .unwrap();
+        let options = ServeOptions {
+            read_only: false,
+            max_object_size: Some(64 * 1024),
+        };
+        let (_rt, addr) = spawn_server(Container::new(tmp_dir.path()), options);
+
+        // large objects go to packs, as with ``Container::insert``
+        let content: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let resp = request(addr, "PUT", "/objects", "", &content);
+        assert!(resp.starts_with("HTTP/1.1 201"), "{resp}");
+        let hash = resp.rsplit("\r\n\r\n").next().unwrap().to_string();
+        assert!(crate::io_packs::extract(&hash, &cnt).unwrap().is_some());
+
+        let resp = request(addr, "PUT", "/objects", "", &[0; 64 * 1024 + 1]);
+        assert!(resp.starts_with("HTTP/1.1 413"), "{resp}");
+        assert_eq!(fs::read_dir(cnt.sandbox()).unwrap().count(), 0);
+
+        // the content is streamed back whole
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /objects/{hash} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut resp = vec![];
+        stream.read_to_end(&mut resp).unwrap();
+        let head_len = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&resp[..head_len]);
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+        assert!(head.contains("content-length: 65536"), "{head}");
+        assert_eq!(&resp[head_len..], content.as_slice());
+    }
+}
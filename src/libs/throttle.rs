@@ -0,0 +1,168 @@
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::ByteSize;
+use crate::Error;
+
+/// Time a throttle left idle still counts as credit, so a throttle does not burst after a pause
+/// or a slow stretch.
+const MAX_IDLE_CREDIT: Duration = Duration::from_secs(1);
+
+/// Caps the bytes per second a maintenance operation (``pack_loose``, ``repack_with``,
+/// ``validate_with_progress``) reads, so it can run on production storage without starving the
+/// foreground workload.
+///
+/// It is shared with the operation by reference and can be paused, resumed and given another
+/// rate from another thread. The operation waits while paused at the next object it reads.
+#[derive(Debug)]
+pub struct IoThrottle {
+    state: Mutex<State>,
+    resumed: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    /// bytes per second, 0 for no cap
+    rate: u64,
+    paused: bool,
+    /// start of the window ``bytes`` were read in
+    since: Instant,
+    bytes: u64,
+}
+
+impl IoThrottle {
+    /// Throttle to ``rate`` bytes per second, 0 for no cap (it then only pauses).
+    #[must_use]
+    pub fn new(rate: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                rate,
+                paused: false,
+                since: Instant::now(),
+                bytes: 0,
+            }),
+            resumed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.lock().rate
+    }
+
+    pub fn set_rate(&self, rate: u64) {
+        let mut state = self.lock();
+        state.rate = rate;
+        state.since = Instant::now();
+        state.bytes = 0;
+    }
+
+    pub fn pause(&self) {
+        self.lock().paused = true;
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.lock();
+        state.paused = false;
+        state.since = Instant::now();
+        state.bytes = 0;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /// Account ``bytes`` about to be read. Blocks while the throttle is paused, then for as long
+    /// as it takes to keep the bytes read under the rate.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn consume(&self, bytes: u64) {
+        let mut state = self.lock();
+        while state.paused {
+            state = self
+                .resumed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if state.rate == 0 {
+            return;
+        }
+
+        let due = |state: &State| Duration::from_secs_f64(state.bytes as f64 / state.rate as f64);
+        if state.since.elapsed() > due(&state) + MAX_IDLE_CREDIT {
+            state.since = Instant::now();
+            state.bytes = 0;
+        }
+        state.bytes += bytes;
+        let wait = due(&state).saturating_sub(state.since.elapsed());
+        drop(state);
+        thread::sleep(wait);
+    }
+}
+
+/// Parse a rate, bytes per second or a size with a unit and an optional ``/s``, ``"50MB/s"``,
+/// ``"1GiB"``. See ``ByteSize`` for the units.
+pub fn parse_rate(s: &str) -> Result<u64, Error> {
+    let size = s.trim();
+    let size = size.strip_suffix("/s").unwrap_or(size);
+    size.parse::<ByteSize>()
+        .map(ByteSize::as_u64)
+        .map_err(|_| Error::ParseRateError { s: s.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn parse_throttle_rate() {
+        assert_eq!(parse_rate("50MB/s").unwrap(), 50_000_000);
+        assert_eq!(parse_rate(" 1KiB ").unwrap(), 1024);
+        assert_eq!(parse_rate("100").unwrap(), 100);
+        for s in ["", "/s", "fast", "1.5MB/s", "1MB/h"] {
+            assert!(parse_rate(s).is_err(), "{s} should not parse");
+        }
+    }
+
+    #[test]
+    fn throttle_caps_rate() {
+        let throttle = IoThrottle::new(1000);
+        let start = Instant::now();
+        for _ in 0..5 {
+            throttle.consume(100);
+        }
+        // 500 bytes at 1000 bytes/s
+        assert!(start.elapsed() >= Duration::from_millis(450));
+
+        let unlimited = IoThrottle::new(0);
+        let start = Instant::now();
+        unlimited.consume(u64::MAX);
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn throttle_pause_and_resume() {
+        let throttle = Arc::new(IoThrottle::new(0));
+        throttle.pause();
+        assert!(throttle.is_paused());
+
+        let waiting = {
+            let throttle = throttle.clone();
+            thread::spawn(move || {
+                let start = Instant::now();
+                throttle.consume(1);
+                start.elapsed()
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        throttle.resume();
+        assert!(waiting.join().unwrap() >= Duration::from_millis(50));
+        assert!(!throttle.is_paused());
+    }
+}
@@ -1,5 +1,7 @@
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::Error;
 
@@ -11,6 +13,45 @@ pub fn create_dir(p: &Path) -> Result<(), Error> {
     })
 }
 
+/// Open ``path`` for reading while letting other handles write, rename or delete it.
+///
+/// NOTE: on windows a file can only be replaced or deleted while open if every handle shares it,
+/// these are the share modes of ``std`` already and are set explicitly because pack readers rely
+/// on them: a reader must not block the appends of a writer nor the delete of a repacked pack.
+pub fn open_shared(path: &Path) -> io::Result<fs::File> {
+    let mut opts = fs::OpenOptions::new();
+    opts.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE
+        opts.share_mode(0x1 | 0x2 | 0x4);
+    }
+    opts.open(path)
+}
+
+/// Rename ``from`` to ``to`` atomically, replacing ``to`` if it exists.
+///
+/// On windows the replace is refused while another process holds ``to`` open without sharing
+/// it (e.g. a virus scanner or an indexer), it is retried for a short while before giving up.
+pub fn rename_replace(from: &Path, to: &Path) -> io::Result<()> {
+    const ATTEMPTS: u64 = 10;
+    let mut attempt = 0;
+    loop {
+        match fs::rename(from, to) {
+            Err(err)
+                if cfg!(windows)
+                    && err.kind() == io::ErrorKind::PermissionDenied
+                    && attempt < ATTEMPTS =>
+            {
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(20 * attempt));
+            }
+            renamed => return renamed,
+        }
+    }
+}
+
 pub struct Dir<'a>(pub &'a PathBuf);
 
 impl<'a> Dir<'a> {
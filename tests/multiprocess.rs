@@ -0,0 +1,139 @@
+//! Several `rsdos` processes adding and packing objects in the same container at once, the
+//! index and the packs must stay consistent.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use rsdos::{maintain, Config, Container};
+
+const WRITERS: usize = 4;
+const PACKERS: usize = 2;
+const BATCHES: usize = 6;
+const FILES_PER_BATCH: usize = 5;
+
+fn rsdos(cnt: &Path, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_rsdos"))
+        .arg("--path")
+        .arg(cnt)
+        .args(args)
+        .output()
+        .expect("fail to run rsdos")
+}
+
+fn assert_success(output: &std::process::Output) {
+    assert!(
+        output.status.success(),
+        "rsdos failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Source files of one writer, every writer also adds a few contents shared with the others.
+fn write_sources(dir: &Path, writer: usize) -> Vec<PathBuf> {
+    (0..BATCHES * FILES_PER_BATCH)
+        .map(|i| {
+            let content = if i % 10 == 0 {
+                format!("shared object {i} ").repeat(40)
+            } else {
+                format!("writer {writer} object {i} ").repeat(20 + i)
+            };
+            let path = dir.join(format!("{writer}-{i}"));
+            fs::write(&path, content).unwrap();
+            path
+        })
+        .collect()
+}
+
+#[test]
+fn concurrent_writers_and_packers() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let cnt_path = tmp_dir.path().join("container");
+    let src_dir = tmp_dir.path().join("sources");
+    fs::create_dir_all(&src_dir).unwrap();
+    fs::create_dir_all(&cnt_path).unwrap();
+    // small packs so that writers race on rolling over to new packs as well
+    Container::new(&cnt_path)
+        .initialize(&Config::new(8 * 1024, "zstd:+1"))
+        .unwrap();
+
+    let sources: Vec<_> = (0..WRITERS)
+        .map(|writer| write_sources(&src_dir, writer))
+        .collect();
+
+    let writers: Vec<_> = sources
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(writer, paths)| {
+            let cnt_path = cnt_path.clone();
+            thread::spawn(move || {
+                let to = if writer % 2 == 0 { "packs" } else { "loose" };
+                for batch in paths.chunks(FILES_PER_BATCH) {
+                    let mut args = vec!["add-files", "--to", to];
+                    args.extend(batch.iter().map(|path| path.to_str().unwrap()));
+                    assert_success(&rsdos(&cnt_path, &args));
+                }
+            })
+        })
+        .collect();
+    // a single packer cleans, the loose objects others are packing disappear under them, two
+    // cleans at once would refuse each other the maintenance lock
+    let packers: Vec<_> = (0..PACKERS)
+        .map(|packer| {
+            let cnt_path = cnt_path.clone();
+            thread::spawn(move || {
+                let mut args = vec!["optimize", "pack"];
+                if packer > 0 {
+                    args.push("--no-clean");
+                }
+                for _ in 0..BATCHES {
+                    assert_success(&rsdos(&cnt_path, &args));
+                }
+            })
+        })
+        .collect();
+    for handle in writers.into_iter().chain(packers) {
+        handle.join().unwrap();
+    }
+    assert_success(&rsdos(&cnt_path, &["optimize", "pack"]));
+    assert_success(&rsdos(&cnt_path, &["validate"]));
+
+    let cnt = Container::open(&cnt_path).unwrap();
+    let report = maintain::validate(&cnt).unwrap();
+    assert!(report.is_valid(), "{report:?}");
+    assert_eq!(report.loose_checked, 0);
+
+    // every object added is there, with its content
+    let mut expected = HashMap::new();
+    for path in sources.iter().flatten() {
+        let content = fs::read(path).unwrap();
+        let hashkey = hex::encode(ring::digest::digest(&ring::digest::SHA256, &content));
+        expected.insert(hashkey, content);
+    }
+    assert_eq!(report.packs_checked, expected.len() as u64);
+    for (hashkey, content) in &expected {
+        let obj = cnt.get(hashkey).unwrap().expect("object lost");
+        assert_eq!(&rsdos::io::ByteString::try_from(obj).unwrap(), content);
+    }
+
+    // no two index entries share bytes of a pack
+    let conn = cnt.db().unwrap();
+    let mut stmt = conn
+        .prepare("SELECT pack_id, offset, length FROM db_object ORDER BY pack_id, offset")
+        .unwrap();
+    let entries: Vec<(u64, u64, u64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    for pair in entries.windows(2) {
+        let ((pack_a, offset_a, length_a), (pack_b, offset_b, _)) = (pair[0], pair[1]);
+        assert!(
+            pack_a != pack_b || offset_a + length_a <= offset_b,
+            "overlapping entries in pack {pack_a} at {offset_a} and {offset_b}"
+        );
+    }
+}